@@ -0,0 +1,109 @@
+//! Drain mode: stop accepting new work, finish in-flight work, then exit
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use tokio::sync::Notify;
+use tracing::info;
+
+/// Tracks whether the agent is draining and how much work is still running
+#[derive(Default)]
+pub struct DrainState {
+    draining: AtomicBool,
+    in_flight: AtomicUsize,
+    changed: Notify,
+}
+
+impl DrainState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Enter drain mode. Returns false if the agent was already draining.
+    pub fn start(&self) -> bool {
+        let started = !self.draining.swap(true, Ordering::SeqCst);
+        if started {
+            info!(
+                "Entering drain mode ({} tasks in flight)",
+                self.in_flight.load(Ordering::SeqCst)
+            );
+            self.changed.notify_waiters();
+        }
+        started
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// Number of tasks still running
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Register a unit of new work. Returns `None` while draining; otherwise
+    /// the work counts as in flight until the guard is dropped.
+    pub fn begin_work(self: &Arc<Self>) -> Option<WorkGuard> {
+        // Count first so a concurrent `start` cannot miss this task
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        let guard = WorkGuard(Arc::clone(self));
+        if self.is_draining() {
+            return None;
+        }
+        Some(guard)
+    }
+
+    /// Resolve once drain mode is on and no work is in flight
+    pub async fn drained(&self) {
+        loop {
+            let notified = self.changed.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            if self.is_draining() && self.in_flight() == 0 {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// Marks a unit of work as in flight for as long as it is alive
+pub struct WorkGuard(Arc<DrainState>);
+
+impl Drop for WorkGuard {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+        self.0.changed.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_drained_waits_for_in_flight_work() {
+        let drain = Arc::new(DrainState::new());
+        let guard = drain.begin_work().unwrap();
+
+        assert!(drain.start());
+        assert!(!drain.start());
+        assert!(drain.begin_work().is_none());
+        assert_eq!(drain.in_flight(), 1);
+
+        let waiter = tokio::spawn({
+            let drain = drain.clone();
+            async move { drain.drained().await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiter.is_finished());
+
+        drop(guard);
+        tokio::time::timeout(Duration::from_secs(1), waiter)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+}
@@ -1,5 +1,7 @@
 //! Application lifecycle management
 
+pub mod drain;
 pub mod options;
 pub mod run;
 pub mod state;
+pub mod watchdog;
@@ -1,10 +1,33 @@
 //! Application configuration options
 
+use std::path::PathBuf;
 use std::time::Duration;
 
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::app::watchdog::WatchdogOptions;
+use crate::authn::command_signing::validate_signed_commands;
+use crate::cache::limits::WorkflowLimits;
+use crate::clock::ClockOptions;
 use crate::deploy::fsm::FsmSettings;
+use crate::deploy::limits::ResourceLimits;
+use crate::deploy::registry::DEFAULT_MAX_CONCURRENT_EXECUTIONS;
+use crate::errors::AgentError;
+use crate::http::client::HttpClientOptions;
+use crate::http::retry::RetryPolicy;
+use crate::logs::LogRetention;
+use crate::mqtt::client::{ClientIdOptions, MqttAddress};
+use crate::scanner::ScanOptions;
 use crate::storage::layout::StorageLayout;
-use crate::workers::{mqtt, poller, token_refresh, deployer, relay};
+use crate::storage::settings::Settings;
+use crate::storage::space::SpaceOptions;
+use crate::filesys::relay::FileOptions;
+use crate::terminal::exec::ExecOptions;
+use crate::terminal::TerminalOptions;
+use crate::utils::CooldownOptions;
+use crate::workers::{mqtt, poller, token_refresh, deployer, relay, heartbeat};
+use crate::workers::relay::{RelayEncoding, RelayTransport};
 
 /// Main application options
 #[derive(Debug, Clone)]
@@ -33,6 +56,9 @@ pub struct AppOptions {
     /// Enable deployer worker
     pub enable_deployer: bool,
 
+    /// Enable heartbeat worker
+    pub enable_heartbeat: bool,
+
     /// Server configuration
     pub server: ServerOptions,
 
@@ -51,8 +77,41 @@ pub struct AppOptions {
     /// Token refresh worker options
     pub token_refresh_worker: token_refresh::Options,
 
+    /// Heartbeat worker options
+    pub heartbeat: heartbeat::Options,
+
     /// FSM deployment settings
     pub fsm_settings: FsmSettings,
+
+    /// Timeouts, pooling and retries of backend requests
+    pub http_client: HttpClientOptions,
+
+    /// Backoff after failed workflow syncs
+    pub sync_cooldown: CooldownOptions,
+
+    /// Largest workflows accepted from the backend or the cache
+    pub workflow_limits: WorkflowLimits,
+
+    /// Hardware features
+    pub hardware: HardwareOptions,
+
+    /// Workflows allowed to run at once
+    pub max_concurrent_executions: usize,
+
+    /// Independent nodes of one workflow run at once
+    pub max_concurrent_nodes: usize,
+
+    /// Limits on on-device log files
+    pub log_retention: LogRetention,
+
+    /// System metrics sampling interval; zero collects them on demand
+    pub metrics_interval: Duration,
+
+    /// Detection of stalled workers
+    pub watchdog: WatchdogOptions,
+
+    /// Clock synchronization checks
+    pub clock: ClockOptions,
 }
 
 impl Default for AppOptions {
@@ -66,14 +125,591 @@ impl Default for AppOptions {
             enable_relay_worker: true,
             enable_poller: true,
             enable_deployer: true,
+            enable_heartbeat: true,
             server: ServerOptions::default(),
             mqtt_worker: mqtt::Options::default(),
             relay_worker: relay::Options::default(),
             poller: poller::Options::default(),
             deployer: deployer::Options::default(),
             token_refresh_worker: token_refresh::Options::default(),
+            heartbeat: heartbeat::Options::default(),
             fsm_settings: FsmSettings::default(),
+            http_client: HttpClientOptions::default(),
+            sync_cooldown: CooldownOptions::default(),
+            workflow_limits: WorkflowLimits::default(),
+            hardware: HardwareOptions::default(),
+            max_concurrent_executions: DEFAULT_MAX_CONCURRENT_EXECUTIONS,
+            max_concurrent_nodes: 1,
+            log_retention: LogRetention::default(),
+            metrics_interval: Duration::from_secs(30),
+            watchdog: WatchdogOptions::default(),
+            clock: ClockOptions::default(),
+        }
+    }
+}
+
+impl AppOptions {
+    /// Start building options from the defaults
+    pub fn builder() -> AppOptionsBuilder {
+        AppOptionsBuilder::default()
+    }
+
+    /// The options `settings` configure, with relay file commands and
+    /// terminals confined to directories of `layout` unless set otherwise
+    pub fn from_settings(settings: Settings, layout: &StorageLayout) -> Result<Self, AgentError> {
+        if settings.deployer.max_attempts == 0 {
+            return Err(AgentError::ConfigError(
+                "deployer.max_attempts must be at least 1".to_string(),
+            ));
+        }
+        Self::builder()
+            .backend_base_url(settings.backend.base_url.clone())
+            .persistent(settings.is_persistent)
+            .shutdown_order(settings.shutdown_order.clone())
+            .enable_socket_server(settings.enable_socket_server)
+            .enable_mqtt_worker(settings.enable_mqtt_worker)
+            .enable_poller(settings.enable_poller)
+            .enable_deployer(settings.enable_deployer)
+            .enable_relay_worker(settings.enable_relay_worker)
+            .poller_interval(Duration::from_secs(settings.polling_interval_secs))
+            .log_retention(LogRetention {
+                max_total_bytes: settings.logs.max_total_mb * 1024 * 1024,
+                max_files: settings.logs.max_files,
+                max_age: Duration::from_secs(settings.logs.max_age_days * 24 * 3600),
+                check_interval: Duration::from_secs(settings.logs.retention_check_interval_secs),
+            })
+            .storage_space(SpaceOptions {
+                min_free_bytes: settings.storage.min_free_mb * 1024 * 1024,
+                check_interval: Duration::from_secs(settings.storage.space_check_interval_secs),
+            })
+            .compress_workflow_cache(settings.storage.compress_workflow_cache)
+            .enable_heartbeat(settings.enable_heartbeat)
+            .heartbeat_interval(Duration::from_secs(settings.heartbeat_interval_secs))
+            .metrics_interval(Duration::from_secs(settings.metrics_interval_secs))
+            .max_concurrent_executions(settings.max_concurrent_executions)
+            .max_concurrent_nodes(settings.max_concurrent_nodes)
+            .watchdog(WatchdogOptions {
+                stall_timeout: Duration::from_secs(settings.watchdog.stall_timeout_secs),
+                check_interval: Duration::from_secs(settings.watchdog.check_interval_secs),
+                restart_on_stall: settings.watchdog.restart_on_stall,
+            })
+            .clock(ClockOptions {
+                max_skew: Duration::from_secs(settings.clock.max_skew_secs),
+                check_interval: Duration::from_secs(settings.clock.check_interval_secs),
+            })
+            .mqtt_broker(MqttAddress {
+                host: settings.mqtt_broker.host.clone(),
+                port: settings.mqtt_broker.port,
+                use_tls: settings.mqtt_broker.tls,
+                ca_cert_path: settings.mqtt_broker.ca_cert_path.clone(),
+            })
+            .mqtt_client_id(ClientIdOptions {
+                override_id: settings.mqtt_broker.client_id.clone(),
+                suffix: settings.mqtt_broker.client_id_suffix.clone(),
+                random_suffix: settings.mqtt_broker.random_client_id_suffix,
+            })
+            .mqtt_ca_reload_interval(Duration::from_secs(settings.mqtt_broker.ca_reload_interval_secs))
+            .relay_transport(settings.relay.transport)
+            .relay_encoding(settings.relay.encoding)
+            .relay_ws_retry_interval(Duration::from_secs(settings.relay.ws_retry_interval_secs))
+            .relay_reconnect_delay(Duration::from_secs(settings.relay.reconnect_delay_secs))
+            .relay_heartbeat_interval(Duration::from_secs(settings.relay.heartbeat_interval_secs))
+            .relay_require_signed_commands(settings.relay.require_signed_commands)
+            .relay_signed_commands(settings.relay.signed_commands)
+            .relay_signature_max_age(Duration::from_secs(settings.relay.signature_max_age_secs))
+            .relay_files(FileOptions {
+                root: settings
+                    .relay
+                    .files_root
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|| layout.files_dir().path().to_path_buf()),
+                max_file_size: settings.relay.max_file_size_mb.saturating_mul(1024 * 1024),
+                max_transfer_rate: settings.relay.file_transfer_bytes_per_sec,
+            })
+            .terminal(TerminalOptions {
+                working_dir: settings
+                    .terminal
+                    .working_dir
+                    .as_ref()
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|| layout.terminal_dir().path().to_path_buf()),
+                isolate_home: settings.terminal.isolate_home,
+                env_allowlist: settings.terminal.env_allowlist.clone(),
+                env: settings.terminal.env.clone(),
+                max_output_rate: settings.terminal.max_output_bytes_per_sec,
+                close_grace: Duration::from_secs(settings.terminal.close_grace_secs),
+                max_sessions: settings.terminal.max_sessions,
+                idle_timeout: Duration::from_secs(settings.terminal.idle_timeout_mins * 60),
+                reconnect_grace: Duration::from_secs(settings.terminal.reconnect_grace_secs),
+                reconnect_buffer: settings.terminal.reconnect_buffer_kb.saturating_mul(1024),
+                restrict_shell: settings.terminal.restrict_shell,
+                allowed_shells: settings.terminal.allowed_shells.iter().map(PathBuf::from).collect(),
+            })
+            .command_exec(ExecOptions {
+                allowed_commands: settings.terminal.exec_allowed_commands.clone(),
+                default_timeout: Duration::from_secs(settings.terminal.exec_timeout_secs),
+                ..Default::default()
+            })
+            .network_scan(ScanOptions {
+                max_host_bits: settings.relay.scan_max_host_bits,
+                max_timeout: Duration::from_millis(settings.relay.scan_max_timeout_ms),
+                ..Default::default()
+            })
+            .deployer_interval(Duration::from_secs(settings.deployer.interval_secs))
+            .deployment_retry(
+                settings.deployer.max_attempts - 1,
+                Duration::from_secs(settings.deployer.retry_delay_secs),
+            )
+            .retry_jitter(Duration::from_secs(settings.deployer.retry_jitter_secs))
+            .container_verify_timeout(Duration::from_secs(
+                settings.deployer.container_verify_timeout_secs,
+            ))
+            .deployment_resource_limits(settings.deployer.resource_limits.clone())
+            .deployment_attempt_timeout(Duration::from_secs(settings.deployer.attempt_timeout_secs))
+            .token_reactivation(settings.token_refresh.reactivate_after_failures)
+            .http_client(HttpClientOptions {
+                request_timeout: Duration::from_secs(settings.backend.request_timeout_secs),
+                connect_timeout: Some(settings.backend.connect_timeout_secs)
+                    .filter(|secs| *secs > 0)
+                    .map(Duration::from_secs),
+                pool_idle_timeout: Duration::from_secs(settings.backend.pool_idle_timeout_secs),
+                pool_max_idle_per_host: settings.backend.pool_max_idle_per_host,
+                retry: RetryPolicy {
+                    max_retries: settings.backend.max_retries,
+                    backoff: CooldownOptions {
+                        base_delay: Duration::from_millis(settings.backend.retry_base_delay_ms),
+                        max_delay: Duration::from_secs(settings.backend.retry_max_delay_secs),
+                        multiplier: 2.0,
+                    },
+                    retry_non_idempotent: settings.backend.retry_non_idempotent,
+                },
+            })
+            .sync_cooldown(CooldownOptions {
+                base_delay: Duration::from_secs(settings.sync.cooldown_base_secs),
+                max_delay: Duration::from_secs(settings.sync.cooldown_max_secs),
+                multiplier: settings.sync.cooldown_multiplier,
+            })
+            .workflow_limits(WorkflowLimits {
+                max_nodes: settings.sync.max_workflow_nodes,
+                max_edges: settings.sync.max_workflow_edges,
+                max_json_bytes: settings.sync.max_workflow_size_kb.saturating_mul(1024),
+            })
+            .hardware(HardwareOptions {
+                enable_camera: settings.hardware.enable_camera,
+                enable_gpio: settings.hardware.enable_gpio,
+                camera_device: settings.hardware.camera_device.clone(),
+            })
+            .build()
+    }
+
+    /// Check the options for contradictions
+    pub fn validate(&self) -> Result<(), AgentError> {
+        match Url::parse(&self.backend_base_url) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+            _ => {
+                return Err(AgentError::ConfigError(format!(
+                    "Backend URL must be an http:// or https:// URL, got '{}'",
+                    self.backend_base_url
+                )))
+            }
+        }
+
+        if self.enable_mqtt_worker && self.mqtt_worker.broker_address.host.trim().is_empty() {
+            return Err(AgentError::ConfigError(
+                "MQTT worker is enabled but no broker host is set".to_string(),
+            ));
+        }
+        if self.mqtt_worker.broker_address.ca_cert_path.is_some()
+            && !self.mqtt_worker.broker_address.use_tls
+        {
+            return Err(AgentError::ConfigError(
+                "MQTT CA certificate is set but TLS is disabled".to_string(),
+            ));
         }
+
+        let intervals = [
+            ("poller interval", self.enable_poller, self.poller.interval),
+            ("deployer interval", self.enable_deployer, self.deployer.interval),
+            (
+                "deployment attempt timeout",
+                self.enable_deployer,
+                self.deployer.attempt_timeout,
+            ),
+            ("heartbeat interval", self.enable_heartbeat, self.heartbeat.interval),
+            ("log retention check interval", true, self.log_retention.check_interval),
+            ("storage space check interval", true, self.storage.space.check_interval),
+            ("MQTT status interval", self.enable_mqtt_worker, self.mqtt_worker.status_interval),
+            (
+                "relay reconnect delay",
+                self.enable_relay_worker,
+                self.relay_worker.reconnect_delay,
+            ),
+            (
+                "relay heartbeat interval",
+                self.enable_relay_worker,
+                self.relay_worker.heartbeat_interval,
+            ),
+            (
+                "relay WebSocket retry interval",
+                self.enable_relay_worker,
+                self.relay_worker.ws_retry_interval,
+            ),
+        ];
+        for (name, enabled, interval) in intervals {
+            if enabled && interval.is_zero() {
+                return Err(AgentError::ConfigError(format!(
+                    "The {} must be greater than zero",
+                    name
+                )));
+            }
+        }
+
+        if self.max_concurrent_executions == 0 {
+            return Err(AgentError::ConfigError(
+                "max_concurrent_executions must be at least 1".to_string(),
+            ));
+        }
+
+        if self.max_concurrent_nodes == 0 {
+            return Err(AgentError::ConfigError(
+                "max_concurrent_nodes must be at least 1".to_string(),
+            ));
+        }
+
+        self.deployer.resource_limits.validate()?;
+        self.relay_worker.terminal.validate()?;
+        validate_signed_commands(&self.relay_worker.signed_commands)?;
+        self.storage.cache_capacities.validate()?;
+        self.http_client.validate()?;
+        self.sync_cooldown.validate()?;
+        ShutdownStage::resolve_order(&self.lifecycle.shutdown_order)?;
+        Ok(())
+    }
+
+    /// Non-fatal configuration problems of the enabled workers, each phrased
+    /// with the setting that fixes it
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+
+        if !(self.enable_socket_server
+            || self.enable_mqtt_worker
+            || self.enable_relay_worker
+            || self.enable_poller
+            || self.enable_deployer)
+        {
+            warnings.push(
+                "All workers are disabled; the agent will only refresh its token. \
+                 Enable at least one of enable_poller, enable_deployer, enable_mqtt_worker \
+                 or enable_relay_worker"
+                    .to_string(),
+            );
+        }
+
+        if self.enable_mqtt_worker {
+            if let Some(path) = &self.mqtt_worker.broker_address.ca_cert_path {
+                if !std::path::Path::new(path).is_file() {
+                    warnings.push(format!(
+                        "MQTT CA certificate '{}' does not exist; the broker connection will \
+                         fail until mqtt_broker.ca_cert_path points to a PEM file",
+                        path
+                    ));
+                }
+            }
+            if !self.mqtt_worker.broker_address.use_tls {
+                warnings.push(
+                    "MQTT TLS is disabled; set mqtt_broker.tls to true unless the broker \
+                     is on a trusted network"
+                        .to_string(),
+                );
+            }
+        }
+
+        if self.enable_relay_worker && self.backend_base_url.starts_with("http://") {
+            warnings.push(
+                "The relay (including remote terminals) is unencrypted because the backend \
+                 URL uses http://; switch backend.base_url to https://"
+                    .to_string(),
+            );
+        }
+
+        if self.enable_deployer
+            && !(self.enable_poller || self.enable_mqtt_worker || self.enable_relay_worker)
+        {
+            warnings.push(format!(
+                "No poller, MQTT or relay worker is enabled, so deployments are only picked \
+                 up every {:?} by the deployer; enable one of them for faster delivery",
+                self.deployer.interval
+            ));
+        }
+
+        warnings
+    }
+}
+
+/// Builder for [`AppOptions`]; unset fields keep their defaults
+#[derive(Debug, Clone, Default)]
+pub struct AppOptionsBuilder {
+    options: AppOptions,
+}
+
+impl AppOptionsBuilder {
+    pub fn backend_base_url(mut self, url: impl Into<String>) -> Self {
+        self.options.backend_base_url = url.into();
+        self
+    }
+
+    pub fn persistent(mut self, is_persistent: bool) -> Self {
+        self.options.lifecycle.is_persistent = is_persistent;
+        self
+    }
+
+    pub fn shutdown_order(mut self, order: Vec<ShutdownStage>) -> Self {
+        self.options.lifecycle.shutdown_order = order;
+        self
+    }
+
+    pub fn storage(mut self, storage: StorageOptions) -> Self {
+        self.options.storage = storage;
+        self
+    }
+
+    pub fn storage_space(mut self, space: SpaceOptions) -> Self {
+        self.options.storage.space = space;
+        self
+    }
+
+    pub fn compress_workflow_cache(mut self, compress: bool) -> Self {
+        self.options.storage.compress_workflow_cache = compress;
+        self
+    }
+
+    pub fn server(mut self, server: ServerOptions) -> Self {
+        self.options.server = server;
+        self
+    }
+
+    pub fn enable_socket_server(mut self, enabled: bool) -> Self {
+        self.options.enable_socket_server = enabled;
+        self
+    }
+
+    pub fn enable_mqtt_worker(mut self, enabled: bool) -> Self {
+        self.options.enable_mqtt_worker = enabled;
+        self
+    }
+
+    pub fn enable_relay_worker(mut self, enabled: bool) -> Self {
+        self.options.enable_relay_worker = enabled;
+        self
+    }
+
+    pub fn enable_poller(mut self, enabled: bool) -> Self {
+        self.options.enable_poller = enabled;
+        self
+    }
+
+    pub fn enable_deployer(mut self, enabled: bool) -> Self {
+        self.options.enable_deployer = enabled;
+        self
+    }
+
+    pub fn enable_heartbeat(mut self, enabled: bool) -> Self {
+        self.options.enable_heartbeat = enabled;
+        self
+    }
+
+    pub fn mqtt_broker(mut self, address: MqttAddress) -> Self {
+        self.options.mqtt_worker.broker_address = address;
+        self
+    }
+
+    pub fn mqtt_client_id(mut self, client_id: ClientIdOptions) -> Self {
+        self.options.mqtt_worker.client_id = client_id;
+        self
+    }
+
+    pub fn mqtt_ca_reload_interval(mut self, interval: Duration) -> Self {
+        self.options.mqtt_worker.ca_reload_interval = interval;
+        self
+    }
+
+    pub fn mqtt_status_interval(mut self, interval: Duration) -> Self {
+        self.options.mqtt_worker.status_interval = interval;
+        self
+    }
+
+    pub fn relay_transport(mut self, transport: RelayTransport) -> Self {
+        self.options.relay_worker.transport = transport;
+        self
+    }
+
+    pub fn relay_encoding(mut self, encoding: RelayEncoding) -> Self {
+        self.options.relay_worker.encoding = encoding;
+        self
+    }
+
+    pub fn relay_ws_retry_interval(mut self, interval: Duration) -> Self {
+        self.options.relay_worker.ws_retry_interval = interval;
+        self
+    }
+
+    pub fn relay_reconnect_delay(mut self, delay: Duration) -> Self {
+        self.options.relay_worker.reconnect_delay = delay;
+        self
+    }
+
+    pub fn relay_heartbeat_interval(mut self, interval: Duration) -> Self {
+        self.options.relay_worker.heartbeat_interval = interval;
+        self
+    }
+
+    pub fn relay_require_signed_commands(mut self, require: bool) -> Self {
+        self.options.relay_worker.require_signed_commands = require;
+        self
+    }
+
+    pub fn relay_signed_commands(mut self, commands: Vec<String>) -> Self {
+        self.options.relay_worker.signed_commands = commands;
+        self
+    }
+
+    pub fn relay_signature_max_age(mut self, max_age: Duration) -> Self {
+        self.options.relay_worker.signature_max_age = max_age;
+        self
+    }
+
+    pub fn terminal(mut self, terminal: TerminalOptions) -> Self {
+        self.options.relay_worker.terminal = terminal;
+        self
+    }
+
+    pub fn relay_files(mut self, files: FileOptions) -> Self {
+        self.options.relay_worker.files = files;
+        self
+    }
+
+    pub fn command_exec(mut self, exec: ExecOptions) -> Self {
+        self.options.relay_worker.exec = exec;
+        self
+    }
+
+    pub fn network_scan(mut self, scan: ScanOptions) -> Self {
+        self.options.relay_worker.scan = scan;
+        self
+    }
+
+    pub fn poller_interval(mut self, interval: Duration) -> Self {
+        self.options.poller.interval = interval;
+        self
+    }
+
+    pub fn log_retention(mut self, retention: LogRetention) -> Self {
+        self.options.log_retention = retention;
+        self
+    }
+
+    pub fn max_concurrent_executions(mut self, limit: usize) -> Self {
+        self.options.max_concurrent_executions = limit;
+        self
+    }
+
+    pub fn max_concurrent_nodes(mut self, limit: usize) -> Self {
+        self.options.max_concurrent_nodes = limit;
+        self
+    }
+
+    pub fn metrics_interval(mut self, interval: Duration) -> Self {
+        self.options.metrics_interval = interval;
+        self
+    }
+
+    pub fn watchdog(mut self, watchdog: WatchdogOptions) -> Self {
+        self.options.watchdog = watchdog;
+        self
+    }
+
+    pub fn clock(mut self, clock: ClockOptions) -> Self {
+        self.options.clock = clock;
+        self
+    }
+
+    pub fn heartbeat_interval(mut self, interval: Duration) -> Self {
+        self.options.heartbeat.interval = interval;
+        self
+    }
+
+    pub fn deployer_interval(mut self, interval: Duration) -> Self {
+        self.options.deployer.interval = interval;
+        self
+    }
+
+    /// Retry failed deployments, of the deployer and of workflows, up to
+    /// `retry_count` times, `delay` apart
+    pub fn deployment_retry(mut self, retry_count: u32, delay: Duration) -> Self {
+        self.options.fsm_settings.retry_count = retry_count;
+        self.options.fsm_settings.retry_delay = delay;
+        self
+    }
+
+    /// Watch new containers for up to `timeout` before a Docker deployment
+    /// counts as successful
+    pub fn container_verify_timeout(mut self, timeout: Duration) -> Self {
+        self.options.deployer.container_verify_timeout = timeout;
+        self
+    }
+
+    /// Fail a deployment attempt that runs longer than `timeout`
+    pub fn deployment_attempt_timeout(mut self, timeout: Duration) -> Self {
+        self.options.deployer.attempt_timeout = timeout;
+        self
+    }
+
+    /// Limits of deployment processes and containers that do not set their own
+    pub fn deployment_resource_limits(mut self, limits: ResourceLimits) -> Self {
+        self.options.deployer.resource_limits = limits;
+        self
+    }
+
+    /// Spread deployment retries over up to `jitter` after the retry delay
+    pub fn retry_jitter(mut self, jitter: Duration) -> Self {
+        self.options.fsm_settings.retry_jitter = jitter;
+        self
+    }
+
+    pub fn token_reactivation(mut self, after_failures: u32) -> Self {
+        self.options.token_refresh_worker.reactivate_after_failures = after_failures;
+        self
+    }
+
+    pub fn fsm_settings(mut self, settings: FsmSettings) -> Self {
+        self.options.fsm_settings = settings;
+        self
+    }
+
+    pub fn http_client(mut self, http_client: HttpClientOptions) -> Self {
+        self.options.http_client = http_client;
+        self
+    }
+
+    pub fn sync_cooldown(mut self, cooldown: CooldownOptions) -> Self {
+        self.options.sync_cooldown = cooldown;
+        self
+    }
+
+    pub fn workflow_limits(mut self, limits: WorkflowLimits) -> Self {
+        self.options.workflow_limits = limits;
+        self
+    }
+
+    pub fn hardware(mut self, hardware: HardwareOptions) -> Self {
+        self.options.hardware = hardware;
+        self
+    }
+
+    /// Validate and return the options
+    pub fn build(self) -> Result<AppOptions, AgentError> {
+        self.options.validate()?;
+        Ok(self.options)
     }
 }
 
@@ -94,6 +730,10 @@ pub struct LifecycleOptions {
 
     /// Maximum delay for graceful shutdown
     pub max_shutdown_delay: Duration,
+
+    /// Order in which components are stopped. Unlisted components are
+    /// stopped after the listed ones; the app state is always stopped last.
+    pub shutdown_order: Vec<ShutdownStage>,
 }
 
 impl Default for LifecycleOptions {
@@ -104,7 +744,124 @@ impl Default for LifecycleOptions {
             idle_timeout_poll_interval: Duration::from_secs(10),
             max_runtime: Duration::from_secs(3600),           // 1 hour
             max_shutdown_delay: Duration::from_secs(30),
+            shutdown_order: ShutdownStage::DEFAULT_ORDER.to_vec(),
+        }
+    }
+}
+
+/// A component stopped during shutdown
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShutdownStage {
+    TokenRefresh,
+    Poller,
+    Mqtt,
+    Deployer,
+    Relay,
+    Heartbeat,
+    Clock,
+    Storage,
+    Metrics,
+    LogRetention,
+    Watchdog,
+    SocketServer,
+}
+
+impl ShutdownStage {
+    /// Default shutdown order
+    pub const DEFAULT_ORDER: [ShutdownStage; 12] = [
+        ShutdownStage::TokenRefresh,
+        ShutdownStage::Poller,
+        ShutdownStage::Mqtt,
+        ShutdownStage::Relay,
+        ShutdownStage::Deployer,
+        ShutdownStage::Heartbeat,
+        ShutdownStage::Clock,
+        ShutdownStage::Storage,
+        ShutdownStage::Metrics,
+        ShutdownStage::LogRetention,
+        ShutdownStage::Watchdog,
+        ShutdownStage::SocketServer,
+    ];
+
+    /// Stage name
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ShutdownStage::TokenRefresh => "token_refresh",
+            ShutdownStage::Poller => "poller",
+            ShutdownStage::Mqtt => "mqtt",
+            ShutdownStage::Deployer => "deployer",
+            ShutdownStage::Relay => "relay",
+            ShutdownStage::Heartbeat => "heartbeat",
+            ShutdownStage::Clock => "clock",
+            ShutdownStage::Storage => "storage",
+            ShutdownStage::Metrics => "metrics",
+            ShutdownStage::LogRetention => "log_retention",
+            ShutdownStage::Watchdog => "watchdog",
+            ShutdownStage::SocketServer => "socket_server",
+        }
+    }
+
+    /// Stages that must be stopped before this one. The deployer only stops
+    /// once nothing can hand it new deployments.
+    pub fn stop_after(&self) -> &'static [ShutdownStage] {
+        match self {
+            ShutdownStage::Deployer => {
+                &[ShutdownStage::Poller, ShutdownStage::Mqtt, ShutdownStage::Relay]
+            }
+            _ => &[],
+        }
+    }
+
+    /// Resolve a configured shutdown order into a complete one.
+    ///
+    /// Unlisted stages are appended in default order, and a stage is deferred
+    /// until everything in its `stop_after` list has stopped. A configured
+    /// order that itself violates a dependency is rejected.
+    pub fn resolve_order(configured: &[ShutdownStage]) -> Result<Vec<ShutdownStage>, AgentError> {
+        for (i, stage) in configured.iter().enumerate() {
+            if configured[..i].contains(stage) {
+                return Err(AgentError::ConfigError(format!(
+                    "Shutdown stage '{}' is listed more than once",
+                    stage.as_str()
+                )));
+            }
+            if let Some(dep) = stage
+                .stop_after()
+                .iter()
+                .find(|dep| configured[i + 1..].contains(dep))
+            {
+                return Err(AgentError::ConfigError(format!(
+                    "Shutdown stage '{}' must come after '{}'",
+                    stage.as_str(),
+                    dep.as_str()
+                )));
+            }
+        }
+
+        let mut pending: Vec<ShutdownStage> = configured.to_vec();
+        pending.extend(
+            Self::DEFAULT_ORDER
+                .iter()
+                .filter(|stage| !configured.contains(stage)),
+        );
+
+        // Stable topological sort: repeatedly take the first stage whose
+        // dependencies have all been stopped
+        let mut order = Vec::with_capacity(pending.len());
+        while !pending.is_empty() {
+            let next = pending
+                .iter()
+                .position(|stage| {
+                    stage
+                        .stop_after()
+                        .iter()
+                        .all(|dep| !pending.contains(dep))
+                })
+                .expect("shutdown stage dependencies are acyclic");
+            order.push(pending.remove(next));
         }
+        Ok(order)
     }
 }
 
@@ -116,6 +873,12 @@ pub struct StorageOptions {
 
     /// Cache capacities
     pub cache_capacities: CacheCapacities,
+
+    /// Low disk space handling
+    pub space: SpaceOptions,
+
+    /// Gzip workflow cache entries persisted to disk
+    pub compress_workflow_cache: bool,
 }
 
 impl Default for StorageOptions {
@@ -123,6 +886,8 @@ impl Default for StorageOptions {
         Self {
             layout: StorageLayout::default(),
             cache_capacities: CacheCapacities::default(),
+            space: SpaceOptions::default(),
+            compress_workflow_cache: true,
         }
     }
 }
@@ -137,6 +902,26 @@ pub struct CacheCapacities {
     pub configs: u64,
 }
 
+impl CacheCapacities {
+    /// Upper bound on any cache, to keep memory use predictable
+    pub const MAX: u64 = 10_000;
+
+    /// Reject capacities that would make a cache useless or unbounded
+    pub fn validate(&self) -> Result<(), AgentError> {
+        for (name, capacity) in [("workflow", self.workflows), ("config", self.configs)] {
+            if capacity == 0 || capacity > Self::MAX {
+                return Err(AgentError::ConfigError(format!(
+                    "The {} cache capacity must be between 1 and {}, got {}",
+                    name,
+                    Self::MAX,
+                    capacity
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
 impl Default for CacheCapacities {
     fn default() -> Self {
         Self {
@@ -146,6 +931,29 @@ impl Default for CacheCapacities {
     }
 }
 
+/// Hardware feature options
+#[derive(Debug, Clone)]
+pub struct HardwareOptions {
+    /// Enable camera support
+    pub enable_camera: bool,
+
+    /// Enable GPIO support
+    pub enable_gpio: bool,
+
+    /// Camera device path
+    pub camera_device: String,
+}
+
+impl Default for HardwareOptions {
+    fn default() -> Self {
+        Self {
+            enable_camera: false,
+            enable_gpio: false,
+            camera_device: "/dev/video0".to_string(),
+        }
+    }
+}
+
 /// Local HTTP server options
 #[derive(Debug, Clone)]
 pub struct ServerOptions {
@@ -164,3 +972,133 @@ impl Default for ServerOptions {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mqtt_address(host: &str) -> MqttAddress {
+        MqttAddress {
+            host: host.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_builder_sets_fields() {
+        let options = AppOptions::builder()
+            .backend_base_url("https://api.example.com/v1")
+            .persistent(false)
+            .enable_deployer(false)
+            .mqtt_broker(mqtt_address("mqtt.example.com"))
+            .poller_interval(Duration::from_secs(45))
+            .build()
+            .unwrap();
+
+        assert_eq!(options.backend_base_url, "https://api.example.com/v1");
+        assert!(!options.lifecycle.is_persistent);
+        assert!(!options.enable_deployer);
+        assert!(options.enable_poller);
+        assert_eq!(options.mqtt_worker.broker_address.host, "mqtt.example.com");
+        assert_eq!(options.poller.interval, Duration::from_secs(45));
+    }
+
+    #[test]
+    fn test_builder_rejects_contradictions() {
+        let base = AppOptions::builder().mqtt_broker(mqtt_address("mqtt.example.com"));
+        assert!(base.clone().build().is_ok());
+
+        // MQTT enabled without a broker host
+        assert!(AppOptions::builder().build().is_err());
+        assert!(AppOptions::builder().enable_mqtt_worker(false).build().is_ok());
+
+        assert!(base.clone().backend_base_url("api.example.com").build().is_err());
+        assert!(base.clone().poller_interval(Duration::ZERO).build().is_err());
+        assert!(base.clone().relay_ws_retry_interval(Duration::ZERO).build().is_err());
+        assert!(base
+            .clone()
+            .sync_cooldown(CooldownOptions {
+                multiplier: 0.5,
+                ..Default::default()
+            })
+            .build()
+            .is_err());
+        assert!(base
+            .clone()
+            .enable_poller(false)
+            .poller_interval(Duration::ZERO)
+            .build()
+            .is_ok());
+        assert!(base
+            .clone()
+            .mqtt_broker(MqttAddress {
+                use_tls: false,
+                ca_cert_path: Some("/etc/ajime/ca.pem".to_string()),
+                ..mqtt_address("mqtt.example.com")
+            })
+            .build()
+            .is_err());
+        assert!(base
+            .clone()
+            .storage(StorageOptions {
+                cache_capacities: CacheCapacities {
+                    workflows: 0,
+                    configs: 100,
+                },
+                ..Default::default()
+            })
+            .build()
+            .is_err());
+        assert!(base
+            .shutdown_order(vec![ShutdownStage::Deployer, ShutdownStage::Poller])
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn test_deployment_retries_come_from_the_deployer_settings() {
+        let mut settings = Settings::default();
+        settings.mqtt_broker.host = "mqtt.example.com".to_string();
+        settings.deployer.max_attempts = 4;
+        settings.deployer.retry_jitter_secs = 7;
+        let layout = StorageLayout::default();
+        let options = AppOptions::from_settings(settings.clone(), &layout).unwrap();
+        assert_eq!(options.fsm_settings.retry_count, 3);
+        assert_eq!(options.fsm_settings.retry_jitter, Duration::from_secs(7));
+
+        settings.deployer.max_attempts = 0;
+        assert!(AppOptions::from_settings(settings, &layout).is_err());
+    }
+
+    #[test]
+    fn test_warnings_name_the_fix() {
+        let options = AppOptions::builder()
+            .backend_base_url("https://api.example.com/v1")
+            .mqtt_broker(mqtt_address("mqtt.example.com"))
+            .build()
+            .unwrap();
+        assert!(options.warnings().is_empty());
+
+        let options = AppOptions::builder()
+            .backend_base_url("http://api.example.com/v1")
+            .mqtt_broker(MqttAddress {
+                ca_cert_path: Some("/nonexistent/ca.pem".to_string()),
+                ..mqtt_address("mqtt.example.com")
+            })
+            .build()
+            .unwrap();
+        let warnings = options.warnings();
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].contains("mqtt_broker.ca_cert_path"));
+        assert!(warnings[1].contains("backend.base_url"));
+
+        let options = AppOptions::builder()
+            .backend_base_url("https://api.example.com/v1")
+            .enable_mqtt_worker(false)
+            .enable_relay_worker(false)
+            .enable_poller(false)
+            .build()
+            .unwrap();
+        assert!(options.warnings()[0].contains("deployer"));
+    }
+}
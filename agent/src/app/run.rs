@@ -1,5 +1,6 @@
 //! Main application run loop
 
+use std::collections::HashMap;
 use std::future::Future;
 use std::pin::Pin;
 use std::sync::Arc;
@@ -7,16 +8,27 @@ use std::time::{Duration, SystemTime};
 
 use tokio::sync::broadcast;
 use tokio::task::JoinHandle;
-use tracing::{error, info, warn};
+use tracing::{debug, error, info, warn};
 
-use crate::app::options::{AppOptions, LifecycleOptions};
+use crate::app::drain::DrainState;
+use crate::app::options::{AppOptions, LifecycleOptions, ShutdownStage};
 use crate::app::state::{ActivityTracker, AppState};
+use crate::app::watchdog::run_watchdog;
+use crate::audit::AuditLog;
+use crate::authn::command_signing::CommandVerifier;
+use crate::authn::local_token::LocalApiToken;
 use crate::authn::token_mngr::{TokenManager, TokenManagerExt};
+use crate::clock::run_clock_monitor;
+use crate::deploy::capabilities::CapabilityManifest;
+use crate::deploy::fsm::FsmSettings;
 use crate::errors::AgentError;
 use crate::http::client::HttpClient;
+use crate::logs::{run_retention, LogRetention};
 use crate::server::serve::serve;
 use crate::server::state::ServerState;
-use crate::workers::{mqtt, poller, token_refresh, deployer, relay};
+use crate::storage::space::run_monitor;
+use crate::telemetry::run_collector;
+use crate::workers::{mqtt, poller, token_refresh, deployer, relay, heartbeat};
 
 /// Run the Ajime agent
 pub async fn run(
@@ -25,20 +37,14 @@ pub async fn run(
     shutdown_signal: impl Future<Output = ()> + Send + 'static,
 ) -> Result<(), AgentError> {
     info!("Initializing Ajime Agent...");
+    for warning in options.warnings() {
+        warn!("Configuration: {}", warning);
+    }
 
-    // Create shutdown channel
-    let (shutdown_tx, _shutdown_rx): (broadcast::Sender<()>, _) = broadcast::channel(1);
-    let mut shutdown_manager = ShutdownManager::new(shutdown_tx.clone(), options.lifecycle.clone());
+    let mut shutdown_manager = ShutdownManager::new(options.lifecycle.clone())?;
 
     // Initialize the app state
-    let app_state = match init(
-        agent_version,
-        &options,
-        shutdown_tx.clone(),
-        &mut shutdown_manager,
-    )
-    .await
-    {
+    let app_state = match init(agent_version, &options, &mut shutdown_manager).await {
         Ok(state) => state,
         Err(e) => {
             error!("Failed to start agent: {}", e);
@@ -47,12 +53,21 @@ pub async fn run(
         }
     };
 
+    #[cfg(unix)]
+    spawn_drain_signal_listener(app_state.drain.clone());
+
     // Handle lifecycle based on persistence mode
     if !options.lifecycle.is_persistent {
         tokio::select! {
             _ = shutdown_signal => {
                 info!("Shutdown signal received, shutting down...");
             }
+            reason = await_deactivation(&app_state) => {
+                error!("Device deactivated ({}), shutting down...", reason);
+            }
+            _ = app_state.drain.drained() => {
+                info!("Drain complete, shutting down...");
+            }
             _ = await_idle_timeout(
                 app_state.activity_tracker.clone(),
                 options.lifecycle.idle_timeout,
@@ -65,18 +80,57 @@ pub async fn run(
             }
         }
     } else {
+        // Deactivation is terminal but does not end the process, or the
+        // service manager would restart it straight into another rejected
+        // refresh. Every worker stops except the local server, which keeps
+        // reporting the reason on /health and /token/status until the agent
+        // is stopped and re-installed.
+        let deactivation = async {
+            let reason = await_deactivation(&app_state).await;
+            error!(
+                "Device deactivated ({}), stopping all workers but the local server",
+                reason
+            );
+            if let Err(e) = shutdown_manager.stop_workers().await {
+                error!("Failed to stop workers after deactivation: {}", e);
+            }
+            std::future::pending::<()>().await
+        };
         tokio::select! {
             _ = shutdown_signal => {
                 info!("Shutdown signal received, shutting down...");
             }
+            _ = app_state.drain.drained() => {
+                info!("Drain complete, shutting down...");
+            }
+            _ = deactivation => {}
         }
     }
 
     // Shutdown
-    drop(shutdown_tx);
     shutdown_manager.shutdown().await
 }
 
+/// Enter drain mode on SIGUSR1
+#[cfg(unix)]
+fn spawn_drain_signal_listener(drain: Arc<DrainState>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut sigusr1 = match signal(SignalKind::user_defined1()) {
+        Ok(s) => s,
+        Err(e) => {
+            warn!("Unable to listen for SIGUSR1, drain via signal disabled: {}", e);
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        if sigusr1.recv().await.is_some() {
+            info!("SIGUSR1 received, draining...");
+            drain.start();
+        }
+    });
+}
+
 async fn await_idle_timeout(
     activity_tracker: Arc<ActivityTracker>,
     idle_timeout: Duration,
@@ -99,6 +153,17 @@ async fn await_idle_timeout(
     }
 }
 
+/// Resolve with the reason once the device is deactivated
+async fn await_deactivation(app_state: &AppState) -> String {
+    let mut deactivated = app_state.deactivated.subscribe();
+    let reason = match deactivated.wait_for(Option::is_some).await {
+        Ok(reason) => reason.clone().unwrap_or_default(),
+        // The sender lives as long as the app state
+        Err(_) => return std::future::pending().await,
+    };
+    reason
+}
+
 async fn await_max_runtime(max_runtime: Duration) -> Result<(), AgentError> {
     tokio::time::sleep(max_runtime).await;
     Ok(())
@@ -109,70 +174,107 @@ async fn await_max_runtime(max_runtime: Duration) -> Result<(), AgentError> {
 async fn init(
     agent_version: String,
     options: &AppOptions,
-    shutdown_tx: broadcast::Sender<()>,
     shutdown_manager: &mut ShutdownManager,
 ) -> Result<Arc<AppState>, AgentError> {
     let app_state = init_app_state(agent_version, options, shutdown_manager).await?;
 
+    let shutdown_rx = shutdown_manager.subscribe(ShutdownStage::TokenRefresh);
     init_token_refresh_worker(
-        app_state.token_mngr.clone(),
+        app_state.clone(),
         options.token_refresh_worker.clone(),
         shutdown_manager,
-        shutdown_tx.subscribe(),
+        shutdown_rx,
     )
     .await?;
 
     if options.enable_socket_server {
+        let shutdown_rx = shutdown_manager.subscribe(ShutdownStage::SocketServer);
         init_socket_server(
             options,
             app_state.clone(),
             shutdown_manager,
-            shutdown_tx.subscribe(),
+            shutdown_rx,
         )
         .await?;
     }
 
     if options.enable_poller {
+        let shutdown_rx = shutdown_manager.subscribe(ShutdownStage::Poller);
         init_poller_worker(
             options.poller.clone(),
             app_state.clone(),
             shutdown_manager,
-            shutdown_tx.subscribe(),
+            shutdown_rx,
         )
         .await?;
     }
 
     if options.enable_mqtt_worker {
+        let shutdown_rx = shutdown_manager.subscribe(ShutdownStage::Mqtt);
         init_mqtt_worker(
             options.mqtt_worker.clone(),
             app_state.clone(),
             shutdown_manager,
-            shutdown_tx.subscribe(),
+            shutdown_rx,
         )
         .await?;
     }
 
     if options.enable_deployer {
+        let shutdown_rx = shutdown_manager.subscribe(ShutdownStage::Deployer);
         init_deployer_worker(
             options.deployer.clone(),
+            options.fsm_settings.clone(),
             app_state.clone(),
             shutdown_manager,
-            shutdown_tx.subscribe(),
+            shutdown_rx,
         )
         .await?;
     }
 
     if options.enable_relay_worker {
+        let shutdown_rx = shutdown_manager.subscribe(ShutdownStage::Relay);
         init_relay_worker(
             options.relay_worker.clone(),
             app_state.clone(),
             options.backend_base_url.clone(),
             shutdown_manager,
-            shutdown_tx.subscribe(),
+            shutdown_rx,
+        )
+        .await?;
+    }
+
+    if options.enable_heartbeat {
+        let shutdown_rx = shutdown_manager.subscribe(ShutdownStage::Heartbeat);
+        init_heartbeat_worker(
+            options.heartbeat.clone(),
+            app_state.clone(),
+            shutdown_manager,
+            shutdown_rx,
         )
         .await?;
     }
 
+    let shutdown_rx = shutdown_manager.subscribe(ShutdownStage::Clock);
+    init_clock_monitor(app_state.clone(), shutdown_manager, shutdown_rx)?;
+
+    let shutdown_rx = shutdown_manager.subscribe(ShutdownStage::Storage);
+    init_storage_monitor(app_state.clone(), shutdown_manager, shutdown_rx)?;
+
+    let shutdown_rx = shutdown_manager.subscribe(ShutdownStage::Metrics);
+    init_metrics_collector(app_state.clone(), shutdown_manager, shutdown_rx)?;
+
+    let shutdown_rx = shutdown_manager.subscribe(ShutdownStage::LogRetention);
+    init_log_retention(
+        options.log_retention.clone(),
+        app_state.clone(),
+        shutdown_manager,
+        shutdown_rx,
+    )?;
+
+    let shutdown_rx = shutdown_manager.subscribe(ShutdownStage::Watchdog);
+    init_watchdog(app_state.clone(), shutdown_manager, shutdown_rx)?;
+
     Ok(app_state)
 }
 
@@ -181,6 +283,9 @@ async fn init_app_state(
     options: &AppOptions,
     shutdown_manager: &mut ShutdownManager,
 ) -> Result<Arc<AppState>, AgentError> {
+    // Bring the storage layout up to date before anything reads from it
+    options.storage.layout.setup().await?;
+
     // Load device to get device_id for HttpClient
     use crate::storage::device::load_device;
     let device_file = Arc::new(options.storage.layout.device_file());
@@ -188,45 +293,52 @@ async fn init_app_state(
     
     // Create HttpClient with device_id for authentication
     let http_client = Arc::new(
-        HttpClient::with_device_id(&options.backend_base_url, device.id.clone()).await?
+        HttpClient::with_device_id(
+            &options.backend_base_url,
+            device.id.clone(),
+            options.http_client.clone(),
+        )
+        .await?,
     );
 
-    let (app_state, app_state_handle) = AppState::init(
+    let capabilities = Arc::new(CapabilityManifest::probe(&agent_version, options).await);
+
+    let app_state = AppState::init(
         agent_version,
-        &options.storage.layout,
-        options.storage.cache_capacities,
+        options,
         http_client,
-        options.fsm_settings.clone(),
+        capabilities,
+        // Deployment retries of synced workflows stop with the deployer
+        shutdown_manager.subscribe(ShutdownStage::Deployer),
     )
     .await?;
 
-    let app_state = Arc::new(app_state);
-    let handle_future = async move {
-        let _ = app_state_handle.await;
-    };
-    shutdown_manager.with_app_state(app_state.clone(), Box::pin(handle_future))?;
-
-    Ok(app_state)
+    Ok(Arc::new(app_state))
 }
 
 async fn init_token_refresh_worker(
-    token_mngr: Arc<TokenManager>,
+    app_state: Arc<AppState>,
     options: token_refresh::Options,
     shutdown_manager: &mut ShutdownManager,
     mut shutdown_rx: broadcast::Receiver<()>,
 ) -> Result<(), AgentError> {
     info!("Initializing token refresh worker...");
+    let token_mngr = app_state.token_mngr.clone();
 
     // Refresh token if expired
     if let Err(e) = refresh_if_expired(&token_mngr).await {
         error!("Failed to refresh expired token: {}", e);
     }
 
+    let heartbeat = app_state.watchdog.register("token_refresh");
     let token_refresh_handle = tokio::spawn(async move {
         token_refresh::run(
             &options,
             token_mngr.as_ref(),
-            |wait| tokio::time::sleep(wait),
+            &app_state.health,
+            &app_state.token_refresh,
+            &app_state.deactivated,
+            |wait| heartbeat.sleep(wait),
             Box::pin(async move {
                 let _ = shutdown_rx.recv().await;
             }),
@@ -257,12 +369,13 @@ async fn init_poller_worker(
     let syncer = app_state.syncer.clone();
     let device_file = app_state.device_file.clone();
 
+    let heartbeat = app_state.watchdog.register("poller");
     let poller_handle = tokio::spawn(async move {
         poller::run(
             &options,
             syncer.as_ref(),
             device_file.as_ref(),
-            tokio::time::sleep,
+            |wait| heartbeat.sleep(wait),
             Box::pin(async move {
                 let _ = shutdown_rx.recv().await;
             }),
@@ -283,19 +396,22 @@ async fn init_mqtt_worker(
     info!("Initializing MQTT worker...");
 
     let token_mngr_clone = app_state.token_mngr.clone();
-    let syncer_clone = app_state.syncer.clone();
-    let device_file_clone = app_state.device_file.clone();
+    let context = mqtt::MqttContext {
+        syncer: app_state.syncer.clone(),
+        health: app_state.health.clone(),
+        capabilities: app_state.capabilities.clone(),
+    };
 
     // MQTT worker runs without storing handle due to EventLoop Send+Sync constraints
     // The task will run until the application shuts down via the shutdown signal
+    let heartbeat = app_state.watchdog.register("mqtt");
     let _mqtt_handle = tokio::task::spawn_blocking(move || {
         tokio::runtime::Handle::current().block_on(async move {
             mqtt::run(
                 &options,
                 token_mngr_clone.as_ref(),
-                syncer_clone.as_ref(),
-                device_file_clone.as_ref(),
-                tokio::time::sleep,
+                &context,
+                |wait| heartbeat.sleep(wait),
                 Box::pin(async move {
                     let _ = shutdown_rx.recv().await;
                 }),
@@ -309,21 +425,30 @@ async fn init_mqtt_worker(
 
 async fn init_deployer_worker(
     options: deployer::Options,
+    fsm_settings: FsmSettings,
     app_state: Arc<AppState>,
     shutdown_manager: &mut ShutdownManager,
     mut shutdown_rx: broadcast::Receiver<()>,
 ) -> Result<(), AgentError> {
     info!("Initializing deployer worker...");
 
-    let http_client = app_state.http_client.clone();
-    let token_mngr = app_state.token_mngr.clone();
+    let context = deployer::DeployerContext {
+        http_client: app_state.http_client.clone(),
+        token_mngr: app_state.token_mngr.clone(),
+        ledger: app_state.ledger.clone(),
+        drain: app_state.drain.clone(),
+        trigger: app_state.deploy_trigger.clone(),
+        storage: app_state.storage.clone(),
+    };
+    app_state.capabilities.deploy.log();
 
+    let heartbeat = app_state.watchdog.register("deployer");
     let deployer_handle = tokio::spawn(async move {
         deployer::run(
             &options,
-            http_client,
-            token_mngr,
-            tokio::time::sleep,
+            &fsm_settings,
+            &context,
+            |wait| heartbeat.sleep(wait),
             Box::pin(async move {
                 let _ = shutdown_rx.recv().await;
             }),
@@ -345,12 +470,43 @@ async fn init_relay_worker(
     info!("Initializing relay worker...");
 
     let token_mngr = app_state.token_mngr.clone();
+    let health = app_state.health.clone();
+    let context = relay::RelayContext {
+        drain: app_state.drain.clone(),
+        deploy_trigger: app_state.deploy_trigger.clone(),
+        capabilities: app_state.capabilities.clone(),
+        terminal: options.terminal.clone(),
+        terminal_sessions: app_state.terminal_sessions.clone(),
+        exec: options.exec.clone(),
+        scan: options.scan.clone(),
+        files: options.files.clone(),
+        device_label: app_state.device_label.clone(),
+        syncer: app_state.syncer.clone(),
+        deployment_dirs: app_state.deployment_dirs.clone(),
+        workflow_checker: app_state.workflow_checker.clone(),
+        command_verifier: Arc::new(if options.require_signed_commands {
+            let device_id = app_state.token_mngr.get_device_id().await?;
+            let keys = app_state.token_mngr.signing_keys();
+            CommandVerifier::new(
+                keys,
+                &options.signed_commands,
+                &device_id,
+                options.signature_max_age,
+            )?
+        } else {
+            CommandVerifier::disabled()
+        }),
+    };
 
+    let heartbeat = app_state.watchdog.register("relay");
     let relay_handle = tokio::spawn(async move {
         relay::run(
             &options,
             token_mngr,
             backend_url,
+            &health,
+            context,
+            |wait| heartbeat.sleep(wait),
             Box::pin(async move {
                 let _ = shutdown_rx.recv().await;
             }),
@@ -362,6 +518,176 @@ async fn init_relay_worker(
     Ok(())
 }
 
+async fn init_heartbeat_worker(
+    options: heartbeat::Options,
+    app_state: Arc<AppState>,
+    shutdown_manager: &mut ShutdownManager,
+    mut shutdown_rx: broadcast::Receiver<()>,
+) -> Result<(), AgentError> {
+    info!("Initializing heartbeat worker...");
+
+    let context = heartbeat::HeartbeatContext {
+        http_client: app_state.http_client.clone(),
+        token_mngr: app_state.token_mngr.clone(),
+        device_file: app_state.device_file.clone(),
+        device_label: app_state.device_label.clone(),
+        capabilities: app_state.capabilities.clone(),
+        health: app_state.health.clone(),
+        storage: app_state.storage.clone(),
+        clock: app_state.clock.clone(),
+    };
+
+    let liveness = app_state.watchdog.register("heartbeat");
+    let heartbeat_handle = tokio::spawn(async move {
+        heartbeat::run(
+            &options,
+            &context,
+            |wait| liveness.sleep(wait),
+            Box::pin(async move {
+                let _ = shutdown_rx.recv().await;
+            }),
+        )
+        .await;
+    });
+
+    shutdown_manager.with_heartbeat_worker_handle(heartbeat_handle)?;
+    Ok(())
+}
+
+fn init_clock_monitor(
+    app_state: Arc<AppState>,
+    shutdown_manager: &mut ShutdownManager,
+    mut shutdown_rx: broadcast::Receiver<()>,
+) -> Result<(), AgentError> {
+    info!("Initializing clock monitor...");
+
+    let clock = app_state.clock.clone();
+    let http_client = app_state.http_client.clone();
+    let health = app_state.health.clone();
+
+    let liveness = app_state.watchdog.register("clock");
+    let clock_handle = tokio::spawn(async move {
+        run_clock_monitor(
+            &clock,
+            &http_client,
+            &health,
+            |wait| liveness.sleep(wait),
+            Box::pin(async move {
+                let _ = shutdown_rx.recv().await;
+            }),
+        )
+        .await;
+    });
+
+    shutdown_manager.with_clock_monitor_handle(clock_handle)?;
+    Ok(())
+}
+
+fn init_storage_monitor(
+    app_state: Arc<AppState>,
+    shutdown_manager: &mut ShutdownManager,
+    mut shutdown_rx: broadcast::Receiver<()>,
+) -> Result<(), AgentError> {
+    info!("Initializing storage monitor...");
+
+    let storage = app_state.storage.clone();
+    let health = app_state.health.clone();
+
+    let liveness = app_state.watchdog.register("storage");
+    let storage_handle = tokio::spawn(async move {
+        run_monitor(
+            &storage,
+            &health,
+            |wait| liveness.sleep(wait),
+            Box::pin(async move {
+                let _ = shutdown_rx.recv().await;
+            }),
+        )
+        .await;
+    });
+
+    shutdown_manager.with_storage_monitor_handle(storage_handle)?;
+    Ok(())
+}
+
+fn init_metrics_collector(
+    app_state: Arc<AppState>,
+    shutdown_manager: &mut ShutdownManager,
+    mut shutdown_rx: broadcast::Receiver<()>,
+) -> Result<(), AgentError> {
+    info!("Initializing metrics collector...");
+
+    let metrics = app_state.metrics.clone();
+
+    let liveness = app_state.watchdog.register("metrics");
+    let metrics_handle = tokio::spawn(async move {
+        run_collector(
+            &metrics,
+            |wait| liveness.sleep(wait),
+            Box::pin(async move {
+                let _ = shutdown_rx.recv().await;
+            }),
+        )
+        .await;
+    });
+
+    shutdown_manager.with_metrics_collector_handle(metrics_handle)?;
+    Ok(())
+}
+
+fn init_log_retention(
+    retention: LogRetention,
+    app_state: Arc<AppState>,
+    shutdown_manager: &mut ShutdownManager,
+    mut shutdown_rx: broadcast::Receiver<()>,
+) -> Result<(), AgentError> {
+    info!("Initializing log retention...");
+
+    let logs_dir = app_state.logs_dir.clone();
+
+    let liveness = app_state.watchdog.register("log_retention");
+    let retention_handle = tokio::spawn(async move {
+        run_retention(
+            &logs_dir,
+            &retention,
+            |wait| liveness.sleep(wait),
+            Box::pin(async move {
+                let _ = shutdown_rx.recv().await;
+            }),
+        )
+        .await;
+    });
+
+    shutdown_manager.with_log_retention_handle(retention_handle)?;
+    Ok(())
+}
+
+fn init_watchdog(
+    app_state: Arc<AppState>,
+    shutdown_manager: &mut ShutdownManager,
+    mut shutdown_rx: broadcast::Receiver<()>,
+) -> Result<(), AgentError> {
+    info!("Initializing watchdog...");
+
+    let watchdog = app_state.watchdog.clone();
+    let health = app_state.health.clone();
+
+    let watchdog_handle = tokio::spawn(async move {
+        run_watchdog(
+            &watchdog,
+            &health,
+            tokio::time::sleep,
+            Box::pin(async move {
+                let _ = shutdown_rx.recv().await;
+            }),
+        )
+        .await;
+    });
+
+    shutdown_manager.with_watchdog_handle(watchdog_handle)?;
+    Ok(())
+}
+
 async fn init_socket_server(
     options: &AppOptions,
     app_state: Arc<AppState>,
@@ -370,13 +696,11 @@ async fn init_socket_server(
 ) -> Result<(), AgentError> {
     info!("Initializing local HTTP server...");
 
+    let layout = &options.storage.layout;
     let server_state = ServerState::new(
-        app_state.device_file.clone(),
-        app_state.http_client.clone(),
-        app_state.syncer.clone(),
-        app_state.caches.clone(),
-        app_state.token_mngr.clone(),
-        app_state.activity_tracker.clone(),
+        &app_state,
+        Arc::new(LocalApiToken::new(layout.local_api_token_file())),
+        Arc::new(AuditLog::new(layout.audit_log_file())),
     );
 
     match serve(&options.server, Arc::new(server_state), async move {
@@ -402,164 +726,342 @@ async fn init_socket_server(
 
 // ================================= SHUTDOWN ===================================== //
 
-struct AppStateShutdownParams {
-    state: Arc<AppState>,
-    state_handle: Pin<Box<dyn Future<Output = ()> + Send>>,
-}
+/// Completion future of a stopped component
+type StageHandle = Pin<Box<dyn Future<Output = Result<(), AgentError>> + Send>>;
 
 struct ShutdownManager {
-    shutdown_tx: broadcast::Sender<()>,
     lifecycle_options: LifecycleOptions,
-    app_state: Option<AppStateShutdownParams>,
-    socket_server_handle: Option<JoinHandle<Result<(), AgentError>>>,
-    poller_worker_handle: Option<JoinHandle<()>>,
-    mqtt_worker_handle: Option<JoinHandle<()>>,
-    deployer_worker_handle: Option<JoinHandle<()>>,
-    relay_worker_handle: Option<JoinHandle<()>>,
-    token_refresh_worker_handle: Option<JoinHandle<()>>,
+    shutdown_order: Vec<ShutdownStage>,
+    signals: HashMap<ShutdownStage, broadcast::Sender<()>>,
+    handles: HashMap<ShutdownStage, StageHandle>,
 }
 
 impl ShutdownManager {
-    pub fn new(shutdown_tx: broadcast::Sender<()>, lifecycle_options: LifecycleOptions) -> Self {
-        Self {
-            shutdown_tx,
+    pub fn new(lifecycle_options: LifecycleOptions) -> Result<Self, AgentError> {
+        let shutdown_order = ShutdownStage::resolve_order(&lifecycle_options.shutdown_order)?;
+        Ok(Self {
             lifecycle_options,
-            app_state: None,
-            socket_server_handle: None,
-            poller_worker_handle: None,
-            mqtt_worker_handle: None,
-            deployer_worker_handle: None,
-            relay_worker_handle: None,
-            token_refresh_worker_handle: None,
+            shutdown_order,
+            signals: HashMap::new(),
+            handles: HashMap::new(),
+        })
+    }
+
+    /// Shutdown signal for a single stage, fired when that stage's turn comes
+    pub fn subscribe(&mut self, stage: ShutdownStage) -> broadcast::Receiver<()> {
+        self.signals
+            .entry(stage)
+            .or_insert_with(|| broadcast::channel(1).0)
+            .subscribe()
+    }
+
+    fn with_handle(&mut self, stage: ShutdownStage, handle: StageHandle) -> Result<(), AgentError> {
+        if self.handles.contains_key(&stage) {
+            return Err(AgentError::ShutdownError(format!(
+                "{}_handle already set",
+                stage.as_str()
+            )));
         }
+        self.handles.insert(stage, handle);
+        Ok(())
     }
 
-    pub fn with_app_state(
+    fn with_worker_handle(
         &mut self,
-        state: Arc<AppState>,
-        state_handle: Pin<Box<dyn Future<Output = ()> + Send>>,
+        stage: ShutdownStage,
+        handle: JoinHandle<()>,
     ) -> Result<(), AgentError> {
-        if self.app_state.is_some() {
-            return Err(AgentError::ShutdownError("app_state already set".to_string()));
-        }
-        self.app_state = Some(AppStateShutdownParams { state, state_handle });
-        Ok(())
+        self.with_handle(
+            stage,
+            Box::pin(async move { handle.await.map_err(|e| AgentError::ShutdownError(e.to_string())) }),
+        )
     }
 
     pub fn with_token_refresh_worker_handle(
         &mut self,
         handle: JoinHandle<()>,
     ) -> Result<(), AgentError> {
-        if self.token_refresh_worker_handle.is_some() {
-            return Err(AgentError::ShutdownError("token_refresh_handle already set".to_string()));
-        }
-        self.token_refresh_worker_handle = Some(handle);
-        Ok(())
+        self.with_worker_handle(ShutdownStage::TokenRefresh, handle)
     }
 
     pub fn with_poller_worker_handle(&mut self, handle: JoinHandle<()>) -> Result<(), AgentError> {
-        if self.poller_worker_handle.is_some() {
-            return Err(AgentError::ShutdownError("poller_handle already set".to_string()));
-        }
-        self.poller_worker_handle = Some(handle);
-        Ok(())
-    }
-
-    pub fn with_mqtt_worker_handle(&mut self, handle: JoinHandle<()>) -> Result<(), AgentError> {
-        if self.mqtt_worker_handle.is_some() {
-            return Err(AgentError::ShutdownError("mqtt_handle already set".to_string()));
-        }
-        self.mqtt_worker_handle = Some(handle);
-        Ok(())
+        self.with_worker_handle(ShutdownStage::Poller, handle)
     }
 
     pub fn with_deployer_worker_handle(&mut self, handle: JoinHandle<()>) -> Result<(), AgentError> {
-        if self.deployer_worker_handle.is_some() {
-            return Err(AgentError::ShutdownError("deployer_handle already set".to_string()));
-        }
-        self.deployer_worker_handle = Some(handle);
-        Ok(())
+        self.with_worker_handle(ShutdownStage::Deployer, handle)
     }
 
     pub fn with_relay_worker_handle(&mut self, handle: JoinHandle<()>) -> Result<(), AgentError> {
-        if self.relay_worker_handle.is_some() {
-            return Err(AgentError::ShutdownError("relay_handle already set".to_string()));
-        }
-        self.relay_worker_handle = Some(handle);
-        Ok(())
+        self.with_worker_handle(ShutdownStage::Relay, handle)
+    }
+
+    pub fn with_heartbeat_worker_handle(&mut self, handle: JoinHandle<()>) -> Result<(), AgentError> {
+        self.with_worker_handle(ShutdownStage::Heartbeat, handle)
+    }
+
+    pub fn with_clock_monitor_handle(&mut self, handle: JoinHandle<()>) -> Result<(), AgentError> {
+        self.with_worker_handle(ShutdownStage::Clock, handle)
+    }
+
+    pub fn with_storage_monitor_handle(&mut self, handle: JoinHandle<()>) -> Result<(), AgentError> {
+        self.with_worker_handle(ShutdownStage::Storage, handle)
+    }
+
+    pub fn with_metrics_collector_handle(
+        &mut self,
+        handle: JoinHandle<()>,
+    ) -> Result<(), AgentError> {
+        self.with_worker_handle(ShutdownStage::Metrics, handle)
+    }
+
+    pub fn with_log_retention_handle(&mut self, handle: JoinHandle<()>) -> Result<(), AgentError> {
+        self.with_worker_handle(ShutdownStage::LogRetention, handle)
+    }
+
+    pub fn with_watchdog_handle(&mut self, handle: JoinHandle<()>) -> Result<(), AgentError> {
+        self.with_worker_handle(ShutdownStage::Watchdog, handle)
     }
 
     pub fn with_socket_server_handle(
         &mut self,
         handle: JoinHandle<Result<(), AgentError>>,
     ) -> Result<(), AgentError> {
-        if self.socket_server_handle.is_some() {
-            return Err(AgentError::ShutdownError("server_handle already set".to_string()));
+        self.with_handle(
+            ShutdownStage::SocketServer,
+            Box::pin(async move { handle.await.map_err(|e| AgentError::ShutdownError(e.to_string()))? }),
+        )
+    }
+
+    /// Stop every component, including any left running after deactivation,
+    /// within one overall `max_shutdown_delay`. Components that fail or
+    /// outlast the deadline are logged and the rest are stopped anyway.
+    pub async fn shutdown(&mut self) -> Result<(), AgentError> {
+        info!("Shutting down Ajime Agent...");
+        let deadline =
+            tokio::time::Instant::now() + self.lifecycle_options.max_shutdown_delay;
+        let failed: Vec<&str> =
+            self.stop_stages(&[], deadline).await.iter().map(|s| s.as_str()).collect();
+        if !failed.is_empty() {
+            return Err(AgentError::ShutdownError(format!(
+                "Components did not stop cleanly: {}",
+                failed.join(", ")
+            )));
         }
-        self.socket_server_handle = Some(handle);
+        info!("Shutdown complete");
         Ok(())
     }
 
-    pub async fn shutdown(&mut self) -> Result<(), AgentError> {
-        let _ = self.shutdown_tx.send(());
+    /// Stop every component but the local server, leaving the agent
+    /// deactivated until it is shut down
+    pub async fn stop_workers(&mut self) -> Result<(), AgentError> {
+        let deadline =
+            tokio::time::Instant::now() + self.lifecycle_options.max_shutdown_delay;
+        let failed = self.stop_stages(&[ShutdownStage::SocketServer], deadline).await;
+        if !failed.is_empty() {
+            let failed: Vec<&str> = failed.iter().map(|s| s.as_str()).collect();
+            return Err(AgentError::ShutdownError(format!(
+                "Workers did not stop cleanly: {}",
+                failed.join(", ")
+            )));
+        }
+        Ok(())
+    }
 
-        match tokio::time::timeout(
-            self.lifecycle_options.max_shutdown_delay,
-            self.shutdown_impl(),
-        )
-        .await
-        {
-            Ok(result) => result,
-            Err(_) => {
-                error!(
-                    "Shutdown timed out after {:?}, forcing shutdown...",
-                    self.lifecycle_options.max_shutdown_delay
-                );
-                std::process::exit(1);
+    /// Stop components one at a time in the configured order, except `keep`:
+    /// each is signalled only once the previous one has finished, or failed,
+    /// or `deadline` passed. Components already stopped are skipped. Returns
+    /// the components that failed or were still running at the deadline.
+    async fn stop_stages(
+        &mut self,
+        keep: &[ShutdownStage],
+        deadline: tokio::time::Instant,
+    ) -> Vec<ShutdownStage> {
+        let mut failed = Vec::new();
+        for stage in self.shutdown_order.clone() {
+            if keep.contains(&stage) {
+                continue;
+            }
+            if let Some(signal) = self.signals.get(&stage) {
+                let _ = signal.send(());
+            }
+            let Some(handle) = self.handles.remove(&stage) else {
+                continue;
+            };
+            debug!("Waiting for {} to stop...", stage.as_str());
+            match tokio::time::timeout_at(deadline, handle).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => {
+                    error!("Failed to stop {}: {}", stage.as_str(), e);
+                    failed.push(stage);
+                }
+                Err(_) => {
+                    error!("{} did not stop before the shutdown deadline", stage.as_str());
+                    failed.push(stage);
+                }
             }
         }
+        failed
     }
+}
 
-    async fn shutdown_impl(&mut self) -> Result<(), AgentError> {
-        info!("Shutting down Ajime Agent...");
-
-        // 1. Token refresh worker
-        if let Some(handle) = self.token_refresh_worker_handle.take() {
-            handle.await.map_err(|e| AgentError::ShutdownError(e.to_string()))?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::sync::Mutex;
+
+    #[tokio::test]
+    async fn test_workers_are_joined_in_configured_order() {
+        let order = vec![
+            ShutdownStage::SocketServer,
+            ShutdownStage::Relay,
+            ShutdownStage::Poller,
+            ShutdownStage::Mqtt,
+            ShutdownStage::Deployer,
+            ShutdownStage::Heartbeat,
+            ShutdownStage::TokenRefresh,
+            ShutdownStage::Clock,
+            ShutdownStage::Storage,
+            ShutdownStage::Metrics,
+            ShutdownStage::LogRetention,
+            ShutdownStage::Watchdog,
+        ];
+        let mut manager = ShutdownManager::new(LifecycleOptions {
+            shutdown_order: order.clone(),
+            ..Default::default()
+        })
+        .unwrap();
+
+        // Register in a different order than the shutdown order
+        let stopped = Arc::new(Mutex::new(Vec::new()));
+        for stage in ShutdownStage::DEFAULT_ORDER {
+            let mut shutdown_rx = manager.subscribe(stage);
+            let stopped = stopped.clone();
+            let handle = tokio::spawn(async move {
+                let _ = shutdown_rx.recv().await;
+                // Yield so a concurrently signalled stage would interleave
+                tokio::task::yield_now().await;
+                stopped.lock().await.push(stage);
+            });
+            match stage {
+                ShutdownStage::SocketServer => manager
+                    .with_socket_server_handle(tokio::spawn(async move {
+                        handle.await.map_err(|e| AgentError::ShutdownError(e.to_string()))
+                    }))
+                    .unwrap(),
+                _ => manager.with_worker_handle(stage, handle).unwrap(),
+            }
         }
 
-        // 2. Poller worker
-        if let Some(handle) = self.poller_worker_handle.take() {
-            handle.await.map_err(|e| AgentError::ShutdownError(e.to_string()))?;
-        }
+        manager.shutdown().await.unwrap();
+        assert_eq!(*stopped.lock().await, order);
+    }
 
-        // 3. MQTT worker
-        if let Some(handle) = self.mqtt_worker_handle.take() {
-            handle.await.map_err(|e| AgentError::ShutdownError(e.to_string()))?;
+    #[tokio::test]
+    async fn test_deactivation_stops_all_workers_but_the_local_server() {
+        let mut manager = ShutdownManager::new(LifecycleOptions::default()).unwrap();
+        let stopped = Arc::new(Mutex::new(Vec::new()));
+        let stages = [ShutdownStage::SocketServer, ShutdownStage::Poller, ShutdownStage::TokenRefresh];
+        for stage in stages {
+            let mut shutdown_rx = manager.subscribe(stage);
+            let stopped = stopped.clone();
+            let handle = tokio::spawn(async move {
+                let _ = shutdown_rx.recv().await;
+                stopped.lock().await.push(stage);
+            });
+            manager.with_worker_handle(stage, handle).unwrap();
         }
 
-        // 4. Deployer worker
-        if let Some(handle) = self.deployer_worker_handle.take() {
-            handle.await.map_err(|e| AgentError::ShutdownError(e.to_string()))?;
-        }
+        manager.stop_workers().await.unwrap();
+        assert_eq!(stopped.lock().await.len(), 2);
+        assert!(!stopped.lock().await.contains(&ShutdownStage::SocketServer));
 
-        // 4.5. Relay worker
-        if let Some(handle) = self.relay_worker_handle.take() {
-            handle.await.map_err(|e| AgentError::ShutdownError(e.to_string()))?;
-        }
+        // The local server goes with the final shutdown
+        manager.shutdown().await.unwrap();
+        assert_eq!(stopped.lock().await.last(), Some(&ShutdownStage::SocketServer));
+    }
 
-        // 5. Socket server
-        if let Some(handle) = self.socket_server_handle.take() {
-            handle.await.map_err(|e| AgentError::ShutdownError(e.to_string()))??;
+    #[tokio::test]
+    async fn test_failed_and_stuck_stages_do_not_stop_the_rest() {
+        let mut manager = ShutdownManager::new(LifecycleOptions {
+            max_shutdown_delay: Duration::from_millis(100),
+            ..Default::default()
+        })
+        .unwrap();
+        let stopped = Arc::new(Mutex::new(Vec::new()));
+        for stage in ShutdownStage::DEFAULT_ORDER {
+            let mut shutdown_rx = manager.subscribe(stage);
+            let stopped = stopped.clone();
+            let handle = tokio::spawn(async move {
+                let _ = shutdown_rx.recv().await;
+                match stage {
+                    ShutdownStage::Relay | ShutdownStage::Poller => std::future::pending().await,
+                    _ => stopped.lock().await.push(stage),
+                }
+            });
+            // A cancelled task fails like a panicking one, without a slow backtrace
+            if stage == ShutdownStage::Poller {
+                handle.abort();
+            }
+            manager.with_worker_handle(stage, handle).unwrap();
         }
 
-        // 5. App state
-        if let Some(app_state) = self.app_state.take() {
-            app_state.state.shutdown().await?;
-            app_state.state_handle.await;
-        }
+        let started = std::time::Instant::now();
+        let err = manager.shutdown().await.unwrap_err();
+        // One deadline for the whole shutdown, not one per stage
+        assert!(started.elapsed() < Duration::from_millis(250));
+        assert!(err.to_string().contains("poller, relay"), "{}", err);
+
+        // The stages after the stuck relay were still signalled
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(
+            *stopped.lock().await,
+            [
+                ShutdownStage::TokenRefresh,
+                ShutdownStage::Mqtt,
+                ShutdownStage::Deployer,
+                ShutdownStage::Heartbeat,
+                ShutdownStage::Clock,
+                ShutdownStage::Storage,
+                ShutdownStage::Metrics,
+                ShutdownStage::LogRetention,
+                ShutdownStage::Watchdog,
+                ShutdownStage::SocketServer,
+            ]
+        );
+    }
 
-        info!("Shutdown complete");
-        Ok(())
+    #[test]
+    fn test_resolve_order_respects_dependencies() {
+        // The deployer waits for the unlisted mqtt worker
+        let order = ShutdownStage::resolve_order(&[
+            ShutdownStage::Relay,
+            ShutdownStage::Poller,
+            ShutdownStage::Deployer,
+        ])
+        .unwrap();
+        assert_eq!(
+            order,
+            vec![
+                ShutdownStage::Relay,
+                ShutdownStage::Poller,
+                ShutdownStage::TokenRefresh,
+                ShutdownStage::Mqtt,
+                ShutdownStage::Deployer,
+                ShutdownStage::Heartbeat,
+                ShutdownStage::Clock,
+                ShutdownStage::Storage,
+                ShutdownStage::Metrics,
+                ShutdownStage::LogRetention,
+                ShutdownStage::Watchdog,
+                ShutdownStage::SocketServer,
+            ]
+        );
+
+        // Explicitly stopping the deployer before the poller or relay is rejected
+        assert!(ShutdownStage::resolve_order(&[ShutdownStage::Deployer, ShutdownStage::Poller]).is_err());
+        assert!(ShutdownStage::resolve_order(&[ShutdownStage::Deployer, ShutdownStage::Relay]).is_err());
+        assert!(ShutdownStage::resolve_order(&[ShutdownStage::Relay, ShutdownStage::Relay]).is_err());
     }
 }
@@ -1,20 +1,36 @@
 //! Application state management
 
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 use std::sync::atomic::{AtomicU64, Ordering};
 
-use tokio::task::JoinHandle;
+use tokio::sync::{broadcast, watch};
 use tracing::info;
 
-use crate::app::options::CacheCapacities;
+use crate::app::drain::DrainState;
+use crate::app::options::{AppOptions, CacheCapacities};
+use crate::app::watchdog::Watchdog;
 use crate::authn::token_mngr::TokenManager;
+use crate::cache::store::WorkflowStore;
 use crate::cache::workflow::WorkflowCache;
-use crate::deploy::fsm::FsmSettings;
+use crate::clock::ClockMonitor;
+use crate::deploy::capabilities::CapabilityManifest;
+use crate::deploy::check::WorkflowChecker;
+use crate::deploy::dirs::DeploymentDirs;
+use crate::deploy::ledger::DeploymentLedger;
+use crate::deploy::node_runner::NodeContext;
+use crate::deploy::registry::ExecutorRegistry;
 use crate::errors::AgentError;
+use crate::filesys::dir::Dir;
 use crate::filesys::file::File;
+use crate::health::HealthRegistry;
 use crate::http::client::HttpClient;
-use crate::storage::layout::StorageLayout;
-use crate::sync::syncer::Syncer;
+use crate::storage::label::DeviceLabel;
+use crate::storage::space::StorageMonitor;
+use crate::sync::syncer::{SyncStorage, Syncer};
+use crate::telemetry::MetricsCollector;
+use crate::terminal::sessions::SessionRegistry;
+use crate::workers::deployer::DeployTrigger;
+use crate::workers::token_refresh::TokenRefreshState;
 
 /// Activity tracker for idle timeout detection
 pub struct ActivityTracker {
@@ -84,24 +100,78 @@ pub struct AppState {
 
     /// Activity tracker
     pub activity_tracker: Arc<ActivityTracker>,
+
+    /// Component health registry
+    pub health: Arc<HealthRegistry>,
+
+    /// Token refresh worker state
+    pub token_refresh: Arc<RwLock<TokenRefreshState>>,
+
+    /// Drain mode state
+    pub drain: Arc<DrainState>,
+
+    /// Wakes the deployer when a deployment is pushed
+    pub deploy_trigger: Arc<DeployTrigger>,
+
+    /// Live workflow executors
+    pub executors: Arc<ExecutorRegistry>,
+
+    /// Capabilities advertised to the backend
+    pub capabilities: Arc<CapabilityManifest>,
+
+    /// Checks whether workflows can be deployed on this device
+    pub workflow_checker: Arc<WorkflowChecker>,
+
+    /// Operator-assigned device label
+    pub device_label: Arc<DeviceLabel>,
+
+    /// Log file directory
+    pub logs_dir: Dir,
+
+    /// Deployments the deployer has processed
+    pub ledger: Arc<DeploymentLedger>,
+
+    /// Deployment directories, for manual cleanup
+    pub deployment_dirs: Arc<DeploymentDirs>,
+
+    /// Free disk space; deployments pause while it is low
+    pub storage: Arc<StorageMonitor>,
+
+    /// System metrics sampler
+    pub metrics: Arc<MetricsCollector>,
+
+    /// Supervises the workers' heartbeats
+    pub watchdog: Arc<Watchdog>,
+
+    /// Whether the device clock is synchronized
+    pub clock: Arc<ClockMonitor>,
+
+    /// Terminal sessions open over the relay
+    pub terminal_sessions: Arc<SessionRegistry>,
+
+    /// Why the device was deactivated, once its token is rejected for good
+    pub deactivated: watch::Sender<Option<String>>,
 }
 
 impl AppState {
-    /// Initialize application state
+    /// Initialize application state. Deployment retries of synced
+    /// workflows stop once `deploy_shutdown_rx` is signalled.
     pub async fn init(
         agent_version: String,
-        layout: &StorageLayout,
-        cache_capacities: CacheCapacities,
+        options: &AppOptions,
         http_client: Arc<HttpClient>,
-        fsm_settings: FsmSettings,
-    ) -> Result<(Self, JoinHandle<()>), AgentError> {
+        capabilities: Arc<CapabilityManifest>,
+        deploy_shutdown_rx: broadcast::Receiver<()>,
+    ) -> Result<Self, AgentError> {
         info!("Initializing application state...");
+        let storage_options = &options.storage;
+        let layout = &storage_options.layout;
 
         // Load device file
         let device_file = Arc::new(layout.device_file());
 
         // Create caches
-        let caches = Arc::new(Caches::new(cache_capacities));
+        let caches = Arc::new(Caches::new(storage_options.cache_capacities));
 
         // Create token manager
         let token_mngr = Arc::new(
@@ -111,36 +181,95 @@ impl AppState {
         // Create activity tracker
         let activity_tracker = Arc::new(ActivityTracker::new());
 
-        // Create syncer
+        // Create health registry
+        let health = Arc::new(HealthRegistry::new());
+
+        // Create executor registry, supervising running workflows
+        let watchdog = Arc::new(Watchdog::new(options.watchdog.clone()));
+        let metrics = Arc::new(MetricsCollector::new(options.metrics_interval));
+        let node_context = NodeContext {
+            hardware: options.hardware.clone(),
+            metrics: Some(metrics.clone()),
+            ..Default::default()
+        };
+        let executors = Arc::new(
+            ExecutorRegistry::with_execution_limit(options.max_concurrent_executions)
+                .with_node_concurrency(options.max_concurrent_nodes)
+                .with_node_context(node_context.clone())
+                .with_layout(layout.clone())
+                .with_watchdog(watchdog.clone()),
+        );
+
+        // Create syncer and reload the workflows cached by the last run,
+        // resuming their deployments
+        let workflow_store = WorkflowStore::new(
+            layout.workflows_cache_dir(),
+            storage_options.compress_workflow_cache,
+            options.workflow_limits,
+        );
+        let sync_storage = SyncStorage {
+            workflow_cache: caches.workflows.clone(),
+            workflow_store,
+            workflow_limits: options.workflow_limits,
+            deployment_dir: layout.deployment_dir(),
+        };
         let syncer = Arc::new(Syncer::new(
-            device_file.clone(),
             http_client.clone(),
             token_mngr.clone(),
-            caches.workflows.clone(),
-            layout.deployment_dir(),
-            fsm_settings,
+            sync_storage,
+            executors.clone(),
+            options.fsm_settings.clone(),
+            options.sync_cooldown.clone(),
             agent_version,
+        )
+        .with_shutdown_signal(deploy_shutdown_rx));
+        syncer.restore_cache().await;
+        syncer.restore_executors().await;
+
+        // Load the deployment ledger
+        let ledger = Arc::new(DeploymentLedger::load(layout.deployment_ledger_file()).await);
+        let deployment_dirs = Arc::new(DeploymentDirs::new(
+            layout.deployment_dir(),
+            executors.clone(),
+            ledger.clone(),
         ));
 
-        // Create background task handle (placeholder for now)
-        let handle = tokio::spawn(async {});
+        let workflow_checker = Arc::new(WorkflowChecker::new(capabilities.clone(), node_context));
+
+        // Load the device label
+        let device_label = Arc::new(DeviceLabel::load(layout.settings_file()).await);
+
+        // Check free space once up front so a full disk is reported before
+        // any worker starts writing
+        let storage = Arc::new(StorageMonitor::new(layout.base_dir.clone(), storage_options.space.clone()));
+        storage.check(&health);
+
+        let clock = Arc::new(ClockMonitor::new(options.clock.clone()));
 
-        let state = Self {
+        Ok(Self {
             device_file,
             http_client,
             token_mngr,
             syncer,
             caches,
             activity_tracker,
-        };
-
-        Ok((state, handle))
-    }
-
-    /// Shutdown application state
-    pub async fn shutdown(&self) -> Result<(), AgentError> {
-        info!("Shutting down application state...");
-        // Cleanup resources
-        Ok(())
+            health,
+            token_refresh: Arc::new(RwLock::new(TokenRefreshState::default())),
+            drain: Arc::new(DrainState::new()),
+            deploy_trigger: Arc::new(DeployTrigger::new()),
+            executors,
+            capabilities,
+            workflow_checker,
+            device_label,
+            logs_dir: layout.logs_dir(),
+            ledger,
+            deployment_dirs,
+            storage,
+            metrics,
+            watchdog,
+            clock,
+            terminal_sessions: Arc::new(SessionRegistry::new()),
+            deactivated: watch::channel(None).0,
+        })
     }
 }
@@ -0,0 +1,242 @@
+//! Watchdog for wedged workers
+//!
+//! A worker that deadlocks (a poisoned lock, a request that never returns)
+//! stops looping without crashing, so nothing notices. Each supervised
+//! worker, and each running workflow, holds a `Heartbeat` and, before every
+//! wait or long operation, announces how long it expects to be quiet. The
+//! watchdog marks the agent unhealthy when one overruns that by the stall
+//! timeout, and can exit the process so the service manager restarts it.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tracing::{error, info};
+
+use crate::health::HealthRegistry;
+
+/// Name of the watchdog in the health registry
+const HEALTH_COMPONENT: &str = "watchdog";
+
+/// Exit code when restarting because of a stalled worker
+const STALL_EXIT_CODE: i32 = 70;
+
+/// Watchdog options
+#[derive(Debug, Clone)]
+pub struct WatchdogOptions {
+    /// How long a worker may overrun its announced wait before it counts
+    /// as stalled
+    pub stall_timeout: Duration,
+
+    /// How often heartbeats are checked
+    pub check_interval: Duration,
+
+    /// Exit when a worker stalls, so the service manager restarts the agent
+    pub restart_on_stall: bool,
+}
+
+impl Default for WatchdogOptions {
+    fn default() -> Self {
+        Self {
+            stall_timeout: Duration::from_secs(300),
+            check_interval: Duration::from_secs(30),
+            restart_on_stall: false,
+        }
+    }
+}
+
+/// Source of the current instant; `Instant::now` unless replaced
+pub type InstantFn = Arc<dyn Fn() -> Instant + Send + Sync>;
+
+/// Liveness of one supervised worker
+struct Beat {
+    name: String,
+    /// When the worker is next expected to beat, at the latest; `None`
+    /// while it is idle
+    deadline: Mutex<Option<Instant>>,
+    /// The worker has finished and is no longer supervised
+    retired: AtomicBool,
+}
+
+/// Held by a supervised worker; dropping it ends supervision
+pub struct Heartbeat {
+    beat: Arc<Beat>,
+    stall_timeout: Duration,
+    now: InstantFn,
+}
+
+impl Heartbeat {
+    /// Announce that the worker is alive and will beat again within `wait`
+    pub fn expect_within(&self, wait: Duration) {
+        *self.beat.deadline.lock().unwrap_or_else(|e| e.into_inner()) =
+            Some((self.now)() + wait + self.stall_timeout);
+    }
+
+    /// Announce that the worker waits for an event that may never come,
+    /// such as a resume, and is not expected to beat until it does
+    pub fn idle(&self) {
+        *self.beat.deadline.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    }
+
+    /// `tokio::time::sleep` that beats first, for use as a worker's sleep
+    /// function
+    pub fn sleep(&self, wait: Duration) -> tokio::time::Sleep {
+        self.expect_within(wait);
+        tokio::time::sleep(wait)
+    }
+}
+
+impl Drop for Heartbeat {
+    fn drop(&mut self) {
+        self.beat.retired.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Supervises worker heartbeats
+pub struct Watchdog {
+    options: WatchdogOptions,
+    beats: Mutex<Vec<Arc<Beat>>>,
+    now: InstantFn,
+}
+
+impl Watchdog {
+    pub fn new(options: WatchdogOptions) -> Self {
+        Self {
+            options,
+            beats: Mutex::new(Vec::new()),
+            now: Arc::new(Instant::now),
+        }
+    }
+
+    /// Tell the time with `now`
+    pub fn with_instant_fn(mut self, now: InstantFn) -> Self {
+        self.now = now;
+        self
+    }
+
+    /// Start supervising the worker `name`
+    pub fn register(&self, name: &str) -> Heartbeat {
+        let beat = Arc::new(Beat {
+            name: name.to_string(),
+            deadline: Mutex::new(Some((self.now)() + self.options.stall_timeout)),
+            retired: AtomicBool::new(false),
+        });
+        let mut beats = self.beats.lock().unwrap_or_else(|e| e.into_inner());
+        beats.retain(|beat| !beat.retired.load(Ordering::SeqCst));
+        beats.push(beat.clone());
+        Heartbeat {
+            beat,
+            stall_timeout: self.options.stall_timeout,
+            now: self.now.clone(),
+        }
+    }
+
+    /// Workers past their deadline and by how much, sorted by name
+    pub fn stalled(&self) -> Vec<(String, Duration)> {
+        let now = (self.now)();
+        let beats = self.beats.lock().unwrap_or_else(|e| e.into_inner());
+        let mut stalled: Vec<(String, Duration)> = beats
+            .iter()
+            .filter(|beat| !beat.retired.load(Ordering::SeqCst))
+            .filter_map(|beat| {
+                let deadline = (*beat.deadline.lock().unwrap_or_else(|e| e.into_inner()))?;
+                (now > deadline).then(|| (beat.name.clone(), now - deadline))
+            })
+            .collect();
+        stalled.sort();
+        stalled
+    }
+
+    /// Check heartbeats once and report the result. Returns whether any
+    /// worker is stalled.
+    pub fn check(&self, health: &HealthRegistry) -> bool {
+        let stalled = self.stalled();
+        if stalled.is_empty() {
+            if health.get(HEALTH_COMPONENT).is_some_and(|c| c.message.is_some()) {
+                info!("All supervised workers are responsive again");
+            }
+            health.set_healthy(HEALTH_COMPONENT);
+            return false;
+        }
+
+        let summary = stalled
+            .iter()
+            .map(|(name, overdue)| format!("{} ({}s overdue)", name, overdue.as_secs()))
+            .collect::<Vec<_>>()
+            .join(", ");
+        error!("Stalled workers: {}", summary);
+        health.set_unhealthy(HEALTH_COMPONENT, format!("Stalled workers: {}", summary));
+        true
+    }
+}
+
+/// Check heartbeats every `check_interval` until shut down, exiting the
+/// process on a stall if `restart_on_stall` is set
+pub async fn run_watchdog<S, F>(
+    watchdog: &Watchdog,
+    health: &HealthRegistry,
+    sleep_fn: S,
+    mut shutdown_signal: Pin<Box<dyn Future<Output = ()> + Send>>,
+) where
+    S: Fn(Duration) -> F,
+    F: Future<Output = ()>,
+{
+    loop {
+        tokio::select! {
+            _ = &mut shutdown_signal => {
+                info!("Watchdog shutting down...");
+                return;
+            }
+            _ = sleep_fn(watchdog.options.check_interval) => {}
+        }
+        if watchdog.check(health) && watchdog.options.restart_on_stall {
+            error!("Exiting so the service manager restarts the agent");
+            std::process::exit(STALL_EXIT_CODE);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health::HealthStatus;
+
+    #[test]
+    fn test_worker_overrunning_its_wait_is_stalled() {
+        let health = HealthRegistry::new();
+        let now = Arc::new(Mutex::new(Instant::now()));
+        let advance = |by: Duration| *now.lock().unwrap() += by;
+        let watchdog = Watchdog::new(WatchdogOptions {
+            stall_timeout: Duration::from_millis(20),
+            ..Default::default()
+        })
+        .with_instant_fn({
+            let now = now.clone();
+            Arc::new(move || *now.lock().unwrap())
+        });
+        let poller = watchdog.register("poller");
+        let deployer = watchdog.register("deployer");
+        poller.expect_within(Duration::ZERO);
+        deployer.expect_within(Duration::from_secs(60));
+        advance(Duration::from_millis(50));
+
+        let stalled = watchdog.stalled();
+        assert_eq!(stalled.len(), 1);
+        assert_eq!(stalled[0], ("poller".to_string(), Duration::from_millis(30)));
+        assert!(watchdog.check(&health));
+        assert_eq!(health.get("watchdog").unwrap().status, HealthStatus::Unhealthy);
+
+        // Beating again recovers, and idle and finished workers are not
+        // supervised
+        poller.expect_within(Duration::from_secs(60));
+        assert!(!watchdog.check(&health));
+        poller.idle();
+        deployer.expect_within(Duration::ZERO);
+        drop(deployer);
+        advance(Duration::from_millis(50));
+        assert!(!watchdog.check(&health));
+        assert_eq!(health.get("watchdog").unwrap().status, HealthStatus::Healthy);
+    }
+}
@@ -0,0 +1,68 @@
+//! Audit log of security-relevant operator actions
+//!
+//! Events are appended as JSON lines to `audit.log` in the storage
+//! directory. It sits outside the logs directory so log retention never
+//! removes it.
+
+use chrono::Utc;
+use serde_json::Value;
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+use tracing::info;
+
+use crate::errors::AgentError;
+use crate::filesys::file::File;
+
+/// Append-only audit log
+pub struct AuditLog {
+    file: File,
+}
+
+impl AuditLog {
+    pub fn new(file: File) -> Self {
+        Self { file }
+    }
+
+    /// Record `event` with its details, e.g. who triggered it
+    pub async fn record(&self, event: &str, details: Value) -> Result<(), AgentError> {
+        info!(target: "audit", "{}: {}", event, details);
+
+        let mut line = serde_json::to_vec(&serde_json::json!({
+            "at": Utc::now(),
+            "event": event,
+            "details": details,
+        }))?;
+        line.push(b'\n');
+
+        let mut options = OpenOptions::new();
+        options.create(true).append(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        let mut file = options.open(self.file.path()).await?;
+        file.write_all(&line).await?;
+        file.sync_data().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesys::test_utils::TempFs;
+
+    #[tokio::test]
+    async fn test_events_are_appended_as_json_lines() {
+        let fs = TempFs::new();
+        let audit = AuditLog::new(File::new(fs.path("audit.log")));
+        audit.record("first", serde_json::json!({ "source": "cli" })).await.unwrap();
+        audit.record("second", Value::Null).await.unwrap();
+
+        let contents = std::fs::read_to_string(fs.path("audit.log")).unwrap();
+        let events: Vec<Value> =
+            contents.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["event"], "first");
+        assert_eq!(events[0]["details"]["source"], "cli");
+        assert!(events[1]["at"].is_string());
+    }
+}
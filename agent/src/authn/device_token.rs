@@ -3,6 +3,7 @@
 use chrono::{DateTime, Utc};
 use jsonwebtoken::{decode, DecodingKey, Validation, Algorithm};
 use serde::{Deserialize, Serialize};
+use tracing::warn;
 
 use crate::errors::AgentError;
 
@@ -40,12 +41,62 @@ pub struct DeviceToken {
     pub claims: DeviceTokenClaims,
 }
 
+/// Algorithms the backend signs device tokens with
+const TOKEN_ALGORITHMS: [Algorithm; 2] = [Algorithm::RS256, Algorithm::HS256];
+
 impl DeviceToken {
+    /// Create a device token from a JWT, checking its signature against the
+    /// backend's key and that it has not expired
+    pub fn from_raw_verified(raw: String, key: &DecodingKey) -> Result<Self, AgentError> {
+        Self::decode_verified(raw, key, true)
+    }
+
+    /// Like `from_raw_verified`, but accepts an expired token: the stored
+    /// token is what a refresh authenticates with, expired or not
+    pub fn from_stored_verified(raw: String, key: &DecodingKey) -> Result<Self, AgentError> {
+        Self::decode_verified(raw, key, false)
+    }
+
+    fn decode_verified(
+        raw: String,
+        key: &DecodingKey,
+        validate_exp: bool,
+    ) -> Result<Self, AgentError> {
+        let invalid = |e| AgentError::TokenError(format!("Invalid device token: {}", e));
+        // The key must also be of the algorithm's kind, which `decode` checks
+        let algorithm = jsonwebtoken::decode_header(&raw).map_err(invalid)?.alg;
+        if !TOKEN_ALGORITHMS.contains(&algorithm) {
+            return Err(AgentError::TokenError(format!(
+                "Device token is signed with unsupported algorithm {:?}",
+                algorithm
+            )));
+        }
+        let mut validation = Validation::new(algorithm);
+        validation.validate_exp = validate_exp;
+        let token_data = decode::<DeviceTokenClaims>(&raw, key, &validation).map_err(invalid)?;
+
+        Ok(Self {
+            raw,
+            claims: token_data.claims,
+        })
+    }
+
+    /// The ID of the key a JWT was signed with, if it names one
+    pub fn key_id(raw: &str) -> Option<String> {
+        jsonwebtoken::decode_header(raw).ok()?.kid
+    }
+
     /// Create a new device token from raw string (JWT)
-    /// Note: This does NOT validate the signature, only decodes the claims
+    /// Note: This does NOT validate the signature or expiry, only decodes
+    /// the claims; use `from_raw_verified` when the backend's key is known
     pub fn from_raw(raw: String) -> Result<Self, AgentError> {
-        // Decode without validation to extract claims
-        // In production, you'd validate against the backend's public key
+        let token = Self::decode_unverified(raw)?;
+        warn!("Device token signature not verified: no backend signing key is available");
+        Ok(token)
+    }
+
+    /// Decode the claims of a JWT without checking it
+    pub(crate) fn decode_unverified(raw: String) -> Result<Self, AgentError> {
         let mut validation = Validation::new(Algorithm::HS256);
         validation.insecure_disable_signature_validation();
         validation.validate_exp = false;
@@ -108,7 +159,7 @@ impl DeviceToken {
 
     /// Get expiration time
     pub fn expires_at(&self) -> DateTime<Utc> {
-        DateTime::from_timestamp(self.claims.exp, 0).unwrap_or_else(|| Utc::now())
+        DateTime::from_timestamp(self.claims.exp, 0).unwrap_or_else(Utc::now)
     }
 
     /// Get time until expiration in seconds
@@ -121,10 +172,47 @@ impl DeviceToken {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+
+    fn jwt(secret: &[u8], exp: i64) -> String {
+        let claims = DeviceTokenClaims {
+            sub: "device-1".to_string(),
+            owner_id: "owner".to_string(),
+            capabilities: vec![],
+            iat: Utc::now().timestamp(),
+            exp,
+            iss: None,
+        };
+        encode(&Header::new(Algorithm::HS256), &claims, &EncodingKey::from_secret(secret)).unwrap()
+    }
 
     #[test]
     fn test_token_expiry_check() {
         // This would require a valid JWT to test properly
         // For now, just test the logic
     }
+
+    #[test]
+    fn test_verified_tokens_check_signature_and_expiry() {
+        let key = DecodingKey::from_secret(b"backend");
+        let live = Utc::now().timestamp() + 3600;
+        let token = DeviceToken::from_raw_verified(jwt(b"backend", live), &key).unwrap();
+        assert_eq!(token.device_id(), "device-1");
+
+        let forged = DeviceToken::from_raw_verified(jwt(b"attacker", live), &key);
+        assert!(matches!(forged, Err(AgentError::TokenError(_))));
+        let mut tampered = jwt(b"backend", live);
+        tampered.insert(tampered.find('.').unwrap() + 2, 'x');
+        assert!(DeviceToken::from_raw_verified(tampered, &key).is_err());
+
+        // Expired tokens are only accepted from storage, to be refreshed
+        let expired = jwt(b"backend", Utc::now().timestamp() - 3600);
+        assert!(DeviceToken::from_raw_verified(expired.clone(), &key).is_err());
+        assert!(DeviceToken::from_stored_verified(expired, &key).unwrap().is_expired());
+        let forged = DeviceToken::from_stored_verified(jwt(b"attacker", live), &key);
+        assert!(forged.is_err());
+
+        // Without the backend's key nothing is checked
+        assert!(DeviceToken::from_raw(jwt(b"attacker", live)).is_ok());
+    }
 }
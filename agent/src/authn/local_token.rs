@@ -0,0 +1,198 @@
+//! Local API token
+//!
+//! Once `tokens/local_api_token` exists, the local HTTP API only serves
+//! requests carrying it as a bearer token, apart from the health and version
+//! endpoints. The token is created and rotated with
+//! `ajigent --rotate-local-token` or `POST /token/local/rotate`; the old one
+//! is rejected as soon as the new one is written.
+
+use std::time::SystemTime;
+
+use serde_json::json;
+use tokio::sync::RwLock;
+use tracing::warn;
+
+use crate::audit::AuditLog;
+use crate::errors::AgentError;
+use crate::filesys::file::File;
+use crate::storage::layout::StorageLayout;
+
+/// Audit log event recorded on rotation
+const ROTATED_EVENT: &str = "local_api_token_rotated";
+
+/// Identifies a version of the token file: rotation replaces it by rename,
+/// so a new inode means a new token even within the same mtime tick
+type Stamp = Option<(SystemTime, u64)>;
+
+/// Token as last read from disk
+#[derive(Default)]
+struct Loaded {
+    stamp: Stamp,
+    token: Option<String>,
+}
+
+/// The local API token, kept in sync with its file
+pub struct LocalApiToken {
+    file: File,
+    loaded: RwLock<Loaded>,
+}
+
+impl LocalApiToken {
+    pub fn new(file: File) -> Self {
+        Self {
+            file,
+            loaded: RwLock::new(Loaded::default()),
+        }
+    }
+
+    /// The current token; `None` when none has been created and the local
+    /// API is open. The file is re-read whenever it changes, so a rotation
+    /// by the CLI applies to the running agent too.
+    pub async fn current(&self) -> Option<String> {
+        let stamp = self.stamp().await;
+        {
+            let loaded = self.loaded.read().await;
+            if loaded.stamp.is_some() && loaded.stamp == stamp {
+                return loaded.token.clone();
+            }
+        }
+
+        let token = match stamp {
+            Some(_) => match self.file.read_string().await {
+                Ok(token) => Some(token.trim().to_string()).filter(|t| !t.is_empty()),
+                Err(e) => {
+                    // Fail closed: an unreadable token must not open the API
+                    warn!("Failed to read local API token {:?}: {}", self.file.path(), e);
+                    Some(String::new())
+                }
+            },
+            None => None,
+        };
+        let mut loaded = self.loaded.write().await;
+        *loaded = Loaded {
+            stamp,
+            token: token.clone(),
+        };
+        token
+    }
+
+    async fn stamp(&self) -> Stamp {
+        let metadata = tokio::fs::metadata(self.file.path()).await.ok()?;
+        #[cfg(unix)]
+        let inode = std::os::unix::fs::MetadataExt::ino(&metadata);
+        #[cfg(not(unix))]
+        let inode = 0;
+        Some((metadata.modified().ok()?, inode))
+    }
+
+    /// Whether `presented` is the current token; any request passes while
+    /// there is no token
+    pub async fn verify(&self, presented: Option<&str>) -> bool {
+        match self.current().await {
+            None => true,
+            Some(token) => {
+                presented.is_some_and(|p| !token.is_empty() && constant_time_eq(p, &token))
+            }
+        }
+    }
+
+    /// Replace the token with a new random one and return it
+    pub async fn rotate(&self) -> Result<String, AgentError> {
+        let token = generate();
+        self.file.write_atomic_private(token.as_bytes()).await?;
+        // Forget the cached token; the next check reloads the new file
+        *self.loaded.write().await = Loaded::default();
+        Ok(token)
+    }
+}
+
+/// Rotate the token and record who did it
+pub async fn rotate_and_audit(
+    token: &LocalApiToken,
+    audit: &AuditLog,
+    source: &str,
+) -> Result<String, AgentError> {
+    let new_token = token.rotate().await?;
+    if let Err(e) = audit.record(ROTATED_EVENT, json!({ "source": source })).await {
+        warn!("Failed to write the audit log: {}", e);
+    }
+    Ok(new_token)
+}
+
+/// `ajigent --rotate-local-token`: print a new token for the local API
+pub async fn rotate_cli(layout: &StorageLayout) {
+    let token = LocalApiToken::new(layout.local_api_token_file());
+    let audit = AuditLog::new(layout.audit_log_file());
+    match rotate_and_audit(&token, &audit, "cli").await {
+        Ok(new_token) => {
+            println!("New local API token (the previous one no longer works):");
+            println!("{}", new_token);
+        }
+        Err(e) => {
+            eprintln!("Failed to rotate the local API token: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// 64 hex characters from two random UUIDs (244 random bits)
+fn generate() -> String {
+    format!(
+        "{}{}",
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    )
+}
+
+/// Compare without short-circuiting on the first differing byte
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a
+            .bytes()
+            .zip(b.bytes())
+            .fold(0u8, |diff, (x, y)| diff | (x ^ y))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesys::test_utils::TempFs;
+
+    #[tokio::test]
+    async fn test_rotation_rejects_the_old_token() {
+        let fs = TempFs::new();
+        let token = LocalApiToken::new(File::new(fs.path("tokens/local_api_token")));
+        let audit = AuditLog::new(File::new(fs.path("audit.log")));
+
+        // No token yet: the API is open
+        assert!(token.verify(None).await);
+
+        let first = rotate_and_audit(&token, &audit, "cli").await.unwrap();
+        assert_eq!(first.len(), 64);
+        assert!(!token.verify(None).await);
+        assert!(token.verify(Some(&first)).await);
+
+        let second = rotate_and_audit(&token, &audit, "api").await.unwrap();
+        assert_ne!(first, second);
+        assert!(!token.verify(Some(&first)).await);
+        assert!(token.verify(Some(&second)).await);
+
+        let audit_log = std::fs::read_to_string(fs.path("audit.log")).unwrap();
+        assert_eq!(audit_log.matches(ROTATED_EVENT).count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_token_changed_on_disk_is_picked_up() {
+        let fs = TempFs::new();
+        let token = LocalApiToken::new(File::new(fs.path("local_api_token")));
+        let other_process = LocalApiToken::new(File::new(fs.path("local_api_token")));
+
+        let first = token.rotate().await.unwrap();
+        assert!(token.verify(Some(&first)).await);
+
+        let second = other_process.rotate().await.unwrap();
+        assert!(!token.verify(Some(&first)).await);
+        assert!(token.verify(Some(&second)).await);
+    }
+}
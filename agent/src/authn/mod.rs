@@ -1,4 +1,10 @@
 //! Authentication module
 
+pub mod command_signing;
 pub mod device_token;
+pub mod local_token;
+pub mod signing_keys;
 pub mod token_mngr;
+
+#[cfg(test)]
+pub(crate) mod test_utils;
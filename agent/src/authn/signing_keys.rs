@@ -0,0 +1,104 @@
+//! The backend's signing keys
+//!
+//! The backend publishes the public keys it signs device tokens and relay
+//! command signatures with as a JWKS. The token manager fetches it; whatever
+//! verifies a backend JWT looks the key up here by the JWT's `kid`, so a key
+//! rotation reaches every verifier at once.
+
+use std::sync::RwLock;
+
+use jsonwebtoken::jwk::{AlgorithmParameters, Jwk, JwkSet};
+use jsonwebtoken::{Algorithm, DecodingKey};
+use tracing::warn;
+
+/// The backend's public signing keys, empty until fetched
+#[derive(Default)]
+pub struct SigningKeys {
+    jwks: RwLock<Option<JwkSet>>,
+}
+
+impl SigningKeys {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace the keys with the asymmetric keys of `jwks`, and return how
+    /// many there are. A symmetric ("oct") key published there is no
+    /// secret, so JWTs signed with it prove nothing.
+    pub fn set(&self, mut jwks: JwkSet) -> usize {
+        jwks.keys.retain(|jwk| {
+            let public = !matches!(jwk.algorithm, AlgorithmParameters::OctetKey(_));
+            if !public {
+                warn!(
+                    "Ignoring symmetric signing key {} published in the JWKS",
+                    jwk.common.key_id.as_deref().unwrap_or("without ID")
+                );
+            }
+            public
+        });
+        let count = jwks.keys.len();
+        *self.jwks.write().unwrap_or_else(|e| e.into_inner()) = Some(jwks);
+        count
+    }
+
+    /// Whether the keys have been fetched
+    pub fn is_loaded(&self) -> bool {
+        self.jwks.read().unwrap_or_else(|e| e.into_inner()).is_some()
+    }
+
+    /// The key named `kid`; a JWT naming no key can only mean the backend's
+    /// only one. `None` when there is no such key or none were fetched.
+    pub fn find(&self, kid: Option<&str>) -> Option<Jwk> {
+        let jwks = self.jwks.read().unwrap_or_else(|e| e.into_inner());
+        let jwks = jwks.as_ref()?;
+        match kid {
+            Some(kid) => jwks.find(kid).cloned(),
+            None if jwks.keys.len() == 1 => jwks.keys.first().cloned(),
+            None => None,
+        }
+    }
+}
+
+/// The decoding key of `jwk` and the algorithms a JWT signed with it may
+/// use, or `None` for a key type that cannot verify JWTs
+pub fn decoding_key(jwk: &Jwk) -> Option<(DecodingKey, Vec<Algorithm>)> {
+    let algorithms = match jwk.algorithm {
+        AlgorithmParameters::RSA(_) => vec![
+            Algorithm::RS256,
+            Algorithm::RS384,
+            Algorithm::RS512,
+            Algorithm::PS256,
+            Algorithm::PS384,
+            Algorithm::PS512,
+        ],
+        AlgorithmParameters::EllipticCurve(_) => vec![Algorithm::ES256, Algorithm::ES384],
+        AlgorithmParameters::OctetKeyPair(_) => vec![Algorithm::EdDSA],
+        AlgorithmParameters::OctetKey(_) => return None,
+    };
+    let key = DecodingKey::from_jwk(jwk).ok()?;
+    Some((key, algorithms))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_symmetric_keys_are_not_trusted() {
+        let keys = SigningKeys::new();
+        assert!(!keys.is_loaded());
+        let jwks: JwkSet = serde_json::from_value(serde_json::json!({
+            "keys": [
+                { "kty": "oct", "kid": "shared", "alg": "HS256", "k": "c2VjcmV0" },
+                { "kty": "RSA", "kid": "rsa", "n": "AQAB", "e": "AQAB" },
+            ],
+        }))
+        .unwrap();
+        assert_eq!(keys.set(jwks), 1);
+        assert!(keys.is_loaded());
+        assert!(keys.find(Some("shared")).is_none());
+        assert!(keys.find(Some("rsa")).is_some());
+        // The only key left is the one a JWT without `kid` means
+        assert!(keys.find(None).is_some());
+    }
+}
@@ -0,0 +1,25 @@
+//! Token manager factory shared by the tests
+
+use std::sync::Arc;
+
+use crate::authn::token_mngr::TokenManager;
+use crate::filesys::file::File;
+use crate::filesys::test_utils::TempFs;
+use crate::http::client::{HttpClient, HttpClientOptions};
+use crate::storage::device::{save_device, Device};
+
+/// A token manager for `device-1` holding `token`, saved to `device.json`
+/// in `fs`, that refreshes it against `backend` without retries
+pub async fn token_manager(fs: &TempFs, backend: &str, token: &str) -> Arc<TokenManager> {
+    let device_file = Arc::new(File::new(fs.path("device.json")));
+    let device = Device::new(
+        "device-1".to_string(),
+        "test".to_string(),
+        "owner".to_string(),
+        token.to_string(),
+    );
+    save_device(&device_file, &device).await.unwrap();
+    let options = HttpClientOptions::without_retries();
+    let http_client = Arc::new(HttpClient::new(backend, options).await.unwrap());
+    Arc::new(TokenManager::new(device_file, http_client).await.unwrap())
+}
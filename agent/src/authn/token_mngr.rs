@@ -1,15 +1,18 @@
 //! Token manager for device authentication
 
 use std::sync::Arc;
+use std::time::Duration;
 
 use async_trait::async_trait;
-use tokio::sync::RwLock;
-use tracing::{info};
+use jsonwebtoken::DecodingKey;
+use tokio::sync::{Mutex, RwLock};
+use tracing::{info, warn};
 
 use crate::authn::device_token::DeviceToken;
+use crate::authn::signing_keys::SigningKeys;
 use crate::errors::AgentError;
 use crate::filesys::file::File;
-use crate::http::client::HttpClient;
+use crate::http::client::{DeviceRegistration, HttpClient};
 use crate::storage::device::{load_device, save_device};
 
 /// Token manager trait for testability
@@ -21,8 +24,47 @@ pub trait TokenManagerExt: Send + Sync {
     /// Refresh the token
     async fn refresh_token(&self) -> Result<DeviceToken, AgentError>;
 
+    /// Get a token valid for at least `MIN_TOKEN_VALIDITY`, refreshing it
+    /// first if it expires sooner. Use this for connections that hold on to
+    /// the token. If the refresh fails, a token that has not expired yet is
+    /// still returned.
+    async fn get_valid_token(&self) -> Result<DeviceToken, AgentError> {
+        let token = self.get_token().await?;
+        if !token.expires_within(MIN_TOKEN_VALIDITY.as_secs() as i64) {
+            return Ok(token);
+        }
+        refreshed_or_current(self.refresh_token().await, token)
+    }
+
     /// Get the device ID
     async fn get_device_id(&self) -> Result<String, AgentError>;
+
+    /// Activate the device again with an activation token, replacing its
+    /// credentials
+    async fn reactivate(&self, activation_token: &str) -> Result<DeviceToken, AgentError>;
+}
+
+/// How long fetching the backend's signing keys may hold up startup
+const JWKS_FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Runway `get_valid_token` leaves a token, so a connection is not dropped
+/// by the token expiring right after it authenticates
+pub const MIN_TOKEN_VALIDITY: Duration = Duration::from_secs(600);
+
+/// The refreshed token, or `current` with a warning if the refresh failed
+/// but `current` has not expired yet
+fn refreshed_or_current(
+    refreshed: Result<DeviceToken, AgentError>,
+    current: DeviceToken,
+) -> Result<DeviceToken, AgentError> {
+    match refreshed {
+        Ok(token) => Ok(token),
+        Err(e) if !current.is_expired() => {
+            warn!("Token refresh failed, using the current token until it expires: {}", e);
+            Ok(current)
+        }
+        Err(e) => Err(e),
+    }
 }
 
 /// Token manager implementation
@@ -30,6 +72,13 @@ pub struct TokenManager {
     device_file: Arc<File>,
     http_client: Arc<HttpClient>,
     cached_token: RwLock<Option<DeviceToken>>,
+
+    /// The backend's signing keys; tokens are not verified until they have
+    /// been fetched
+    signing_keys: Arc<SigningKeys>,
+
+    /// Held while refreshing, so concurrent callers refresh only once
+    refresh_lock: Mutex<()>,
 }
 
 impl TokenManager {
@@ -42,32 +91,99 @@ impl TokenManager {
             device_file,
             http_client,
             cached_token: RwLock::new(None),
+            signing_keys: Arc::new(SigningKeys::new()),
+            refresh_lock: Mutex::new(()),
         };
 
-        // Load initial token
-        manager.load_token().await?;
+        // Load initial token, verified if the backend's keys are reachable
+        manager.fetch_jwks().await;
+        let (_, verified) = manager.load_stored_token().await?;
+
+        // The backend may have rotated its keys since the token was issued;
+        // a refresh replaces it with one signed by a current key
+        if !verified {
+            if let Err(e) = manager.refresh_token().await {
+                warn!("Failed to replace the unverified device token: {}", e);
+            }
+        }
 
         Ok(manager)
     }
 
+    /// The backend's signing keys, replaced whenever they are fetched again
+    pub fn signing_keys(&self) -> Arc<SigningKeys> {
+        self.signing_keys.clone()
+    }
+
     /// Load token from device file
     async fn load_token(&self) -> Result<DeviceToken, AgentError> {
+        Ok(self.load_stored_token().await?.0)
+    }
+
+    /// Load token from device file, and whether it passed verification. A
+    /// stored JWT failing it, e.g. after a key rotation, is still loaded so
+    /// the device can authenticate a refresh with it.
+    async fn load_stored_token(&self) -> Result<(DeviceToken, bool), AgentError> {
         let device = load_device(&self.device_file).await?;
         
         // Try to decode as JWT first
-        let token = match DeviceToken::from_raw(device.token.clone()) {
-            Ok(token) => token,
+        let (token, verified) = match DeviceToken::decode_unverified(device.token.clone()) {
+            Ok(unverified) => match self.decode_jwt(device.token, true).await {
+                Ok(token) => (token, true),
+                Err(e) => {
+                    warn!("Stored device token failed verification, using it unverified: {}", e);
+                    (unverified, false)
+                }
+            },
             Err(_) => {
                 // If JWT decode fails, treat it as a raw device secret
                 info!("Token is not a JWT, treating as device secret");
-                DeviceToken::from_secret(device.id.clone(), device.token)
+                (DeviceToken::from_secret(device.id.clone(), device.token), true)
             }
         };
 
         let mut cached = self.cached_token.write().await;
         *cached = Some(token.clone());
 
-        Ok(token)
+        Ok((token, verified))
+    }
+
+    /// Fetch the backend's token signing keys. On failure the keys fetched
+    /// before, if any, stay in use.
+    async fn fetch_jwks(&self) {
+        match tokio::time::timeout(JWKS_FETCH_TIMEOUT, self.http_client.get_jwks()).await {
+            Ok(Ok(jwks)) => {
+                let count = self.signing_keys.set(jwks);
+                info!("Loaded {} backend signing key(s)", count);
+            }
+            Ok(Err(e)) => warn!("Failed to fetch token signing keys: {}", e),
+            Err(_) => warn!("Timed out fetching token signing keys"),
+        }
+    }
+
+    /// The key a JWT was signed with, or `None` when the backend's keys are
+    /// not known
+    async fn signing_key(&self, raw: &str) -> Result<Option<DecodingKey>, AgentError> {
+        if !self.signing_keys.is_loaded() {
+            return Ok(None);
+        }
+        let kid = DeviceToken::key_id(raw);
+        let jwk = self.signing_keys.find(kid.as_deref()).ok_or_else(|| {
+            AgentError::TokenError("Device token is not signed with a known key".to_string())
+        })?;
+        DecodingKey::from_jwk(&jwk)
+            .map(Some)
+            .map_err(|e| AgentError::TokenError(format!("Unusable token signing key: {}", e)))
+    }
+
+    /// Decode a JWT, verifying it when the backend's keys are known. Stored
+    /// tokens may have expired.
+    async fn decode_jwt(&self, raw: String, stored: bool) -> Result<DeviceToken, AgentError> {
+        match self.signing_key(&raw).await? {
+            Some(key) if stored => DeviceToken::from_stored_verified(raw, &key),
+            Some(key) => DeviceToken::from_raw_verified(raw, &key),
+            None => DeviceToken::from_raw(raw),
+        }
     }
 
     /// Save token to device file
@@ -81,6 +197,37 @@ impl TokenManager {
 
         Ok(())
     }
+
+    /// Refresh the token; callers hold `refresh_lock`
+    async fn refresh_locked(&self) -> Result<DeviceToken, AgentError> {
+        info!("Refreshing device token...");
+
+        let current_token = self.get_token().await?;
+        let device_id = current_token.device_id().to_string();
+
+        // Call backend to refresh token
+        let new_token_raw = self
+            .http_client
+            .refresh_device_token(&device_id, &current_token.raw)
+            .await?;
+
+        // The keys may have rotated since they were fetched, or have been
+        // unreachable at startup
+        let new_token = match self.decode_jwt(new_token_raw.clone(), false).await {
+            Ok(token) if self.signing_keys.is_loaded() => token,
+            _ => {
+                self.fetch_jwks().await;
+                self.decode_jwt(new_token_raw, false).await?
+            }
+        };
+
+        // Save the new token
+        self.save_token(&new_token).await?;
+
+        info!("Token refreshed successfully, expires at: {}", new_token.expires_at());
+
+        Ok(new_token)
+    }
 }
 
 #[async_trait]
@@ -99,29 +246,140 @@ impl TokenManagerExt for TokenManager {
     }
 
     async fn refresh_token(&self) -> Result<DeviceToken, AgentError> {
-        info!("Refreshing device token...");
+        let _refreshing = self.refresh_lock.lock().await;
+        self.refresh_locked().await
+    }
 
-        let current_token = self.get_token().await?;
-        let device_id = current_token.device_id().to_string();
+    async fn get_valid_token(&self) -> Result<DeviceToken, AgentError> {
+        let min_validity = MIN_TOKEN_VALIDITY.as_secs() as i64;
+        let token = self.get_token().await?;
+        if !token.expires_within(min_validity) {
+            return Ok(token);
+        }
 
-        // Call backend to refresh token
-        let new_token_raw = self
+        let _refreshing = self.refresh_lock.lock().await;
+        // Whoever held the lock before may have refreshed it already
+        let token = self.get_token().await?;
+        if !token.expires_within(min_validity) {
+            return Ok(token);
+        }
+        refreshed_or_current(self.refresh_locked().await, token)
+    }
+
+    async fn get_device_id(&self) -> Result<String, AgentError> {
+        let token = self.get_token().await?;
+        Ok(token.device_id().to_string())
+    }
+
+    async fn reactivate(&self, activation_token: &str) -> Result<DeviceToken, AgentError> {
+        info!("Re-activating device...");
+
+        let mut device = load_device(&self.device_file).await?;
+        // Register again with what was recorded at install, if anything
+        let registration = (!device.metadata.is_null()).then(|| DeviceRegistration {
+            capabilities: device.capabilities.clone(),
+            metadata: device.metadata.clone(),
+        });
+        let response = self
             .http_client
-            .refresh_device_token(&device_id, &current_token.raw)
+            .activate_device(
+                activation_token,
+                &device.name,
+                device.device_type.as_deref(),
+                registration.as_ref(),
+            )
             .await?;
 
-        let new_token = DeviceToken::from_raw(new_token_raw)?;
+        if response.device_id != device.id {
+            warn!(
+                "Re-activation assigned a new device ID ({} -> {}); restart the agent to use it",
+                device.id, response.device_id
+            );
+        }
+        device.id = response.device_id;
+        device.owner_id = response.owner_id;
+        device.token = response.token;
+        save_device(&self.device_file, &device).await?;
 
-        // Save the new token
-        self.save_token(&new_token).await?;
+        let token = self.load_token().await?;
+        info!("Device re-activated, token expires at: {}", token.expires_at());
+        Ok(token)
+    }
+}
 
-        info!("Token refreshed successfully, expires at: {}", new_token.expires_at());
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
 
-        Ok(new_token)
+    use crate::authn::device_token::DeviceTokenClaims;
+    use crate::authn::test_utils::token_manager;
+    use crate::filesys::test_utils::TempFs;
+
+    fn jwt(expires_in: i64) -> String {
+        let claims = DeviceTokenClaims {
+            sub: "device-1".to_string(),
+            owner_id: "owner".to_string(),
+            capabilities: vec![],
+            iat: Utc::now().timestamp(),
+            exp: Utc::now().timestamp() + expires_in,
+            iss: None,
+        };
+        encode(&Header::default(), &claims, &EncodingKey::from_secret(b"backend")).unwrap()
     }
 
-    async fn get_device_id(&self) -> Result<String, AgentError> {
-        let token = self.get_token().await?;
-        Ok(token.device_id().to_string())
+    #[tokio::test]
+    async fn test_concurrent_callers_refresh_an_expiring_token_once() {
+        let server = MockServer::start().await;
+        let fresh = jwt(3600);
+        Mock::given(method("POST"))
+            .and(path("/agent/devices/device-1/token/refresh"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({ "token": fresh }))
+                    .set_delay(Duration::from_millis(100)),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let fs = TempFs::new();
+        let token_mngr = token_manager(&fs, &server.uri(), &jwt(10)).await;
+
+        let (a, b) = tokio::join!(token_mngr.get_valid_token(), token_mngr.get_valid_token());
+        assert_eq!(a.unwrap().raw, fresh);
+        assert_eq!(b.unwrap().raw, fresh);
+
+        // A token with enough runway is returned as is
+        assert_eq!(token_mngr.get_valid_token().await.unwrap().raw, fresh);
+    }
+
+    #[tokio::test]
+    async fn test_token_of_a_rotated_key_is_refreshed_at_startup() {
+        // The backend now signs with a key the stored token does not name
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/.well-known/jwks.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "keys": [{ "kty": "RSA", "kid": "new", "n": "AQAB", "e": "AQAB" }],
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/agent/devices/device-1/token/refresh"))
+            .respond_with(ResponseTemplate::new(503))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let fs = TempFs::new();
+        let stored = jwt(3600);
+
+        // The agent still starts, with the stored token until a refresh works
+        let token_mngr = token_manager(&fs, &server.uri(), &stored).await;
+        assert_eq!(token_mngr.get_token().await.unwrap().raw, stored);
     }
 }
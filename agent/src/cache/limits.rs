@@ -0,0 +1,90 @@
+//! Size limits of cached workflows
+//!
+//! Workflows come from the backend and from cache files on disk, and a
+//! malformed or malicious one with hundreds of thousands of nodes could
+//! exhaust the device's memory while it is parsed and later executed. The
+//! JSON length of a workflow is checked before it is parsed, and its node
+//! and edge counts before it is cached.
+
+use crate::errors::AgentError;
+use crate::models::workflow::Workflow;
+
+/// Largest workflows the agent accepts; 0 disables a limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WorkflowLimits {
+    /// Nodes in the graph
+    pub max_nodes: usize,
+
+    /// Edges in the graph
+    pub max_edges: usize,
+
+    /// Length of the workflow's JSON in bytes
+    pub max_json_bytes: usize,
+}
+
+impl Default for WorkflowLimits {
+    fn default() -> Self {
+        Self {
+            max_nodes: 1000,
+            max_edges: 5000,
+            max_json_bytes: 4 * 1024 * 1024,
+        }
+    }
+}
+
+impl WorkflowLimits {
+    /// Reject workflow JSON of `len` bytes, before parsing it
+    pub fn check_json_len(&self, len: usize) -> Result<(), AgentError> {
+        exceeds("JSON bytes", len, self.max_json_bytes)
+    }
+
+    /// Reject a parsed workflow with too many nodes or edges
+    pub fn check(&self, workflow: &Workflow) -> Result<(), AgentError> {
+        exceeds("nodes", workflow.graph_data.nodes.len(), self.max_nodes)?;
+        exceeds("edges", workflow.graph_data.edges.len(), self.max_edges)
+    }
+}
+
+fn exceeds(what: &str, count: usize, max: usize) -> Result<(), AgentError> {
+    if max > 0 && count > max {
+        return Err(AgentError::ValidationError(format!(
+            "Workflow has {} {}, more than the limit of {}",
+            count, what, max
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::test_utils::{edge, workflow_with_graph};
+
+    fn workflow(edges: usize) -> Workflow {
+        let edges = (0..edges).map(|_| edge("a", "b")).collect();
+        workflow_with_graph("wf", vec![], edges)
+    }
+
+    #[test]
+    fn test_limits_reject_oversized_workflows() {
+        let limits = WorkflowLimits {
+            max_nodes: 10,
+            max_edges: 2,
+            max_json_bytes: 100,
+        };
+        assert!(limits.check(&workflow(2)).is_ok());
+        let err = limits.check(&workflow(3)).unwrap_err();
+        assert!(err.to_string().contains("3 edges, more than the limit of 2"), "{}", err);
+
+        assert!(limits.check_json_len(100).is_ok());
+        assert!(matches!(limits.check_json_len(101), Err(AgentError::ValidationError(_))));
+
+        let unlimited = WorkflowLimits {
+            max_nodes: 0,
+            max_edges: 0,
+            max_json_bytes: 0,
+        };
+        assert!(unlimited.check(&workflow(3)).is_ok());
+        assert!(unlimited.check_json_len(usize::MAX).is_ok());
+    }
+}
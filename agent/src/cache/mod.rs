@@ -1,3 +1,5 @@
 //! Caching module
 
+pub mod limits;
+pub mod store;
 pub mod workflow;
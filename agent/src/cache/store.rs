@@ -0,0 +1,297 @@
+//! Persisted workflow cache entries
+//!
+//! Each cached workflow is written to `<id>.wfc` in the workflow cache
+//! directory so the agent comes back up with its workflows after a restart.
+//! Files start with a header naming the format version and codec. Graph JSON
+//! compresses well, so entries are gzipped unless disabled, saving SD-card
+//! space and writes. Headerless `<id>.json` files are still loaded and
+//! migrated to the current format. Files whose workflow exceeds the
+//! workflow limits are skipped without being decoded in full.
+
+use std::io::{Read, Write};
+use std::path::Path;
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use tracing::{info, warn};
+
+use crate::cache::limits::WorkflowLimits;
+use crate::cache::workflow::WorkflowCacheEntry;
+use crate::errors::AgentError;
+use crate::filesys::dir::Dir;
+use crate::filesys::file::File;
+
+/// First bytes of a cache file with a header
+const MAGIC: &[u8; 4] = b"AJWC";
+
+/// Current format version
+const VERSION: u8 = 1;
+
+/// Extension of cache files in the current format
+const EXTENSION: &str = "wfc";
+
+/// Extension of headerless JSON cache files
+const LEGACY_EXTENSION: &str = "json";
+
+/// Encoding of the entry following the header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Codec {
+    Json = 0,
+    Gzip = 1,
+}
+
+impl Codec {
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Codec::Json),
+            1 => Some(Codec::Gzip),
+            _ => None,
+        }
+    }
+}
+
+/// Workflow cache entries on disk
+pub struct WorkflowStore {
+    dir: Dir,
+    compress: bool,
+    limits: WorkflowLimits,
+}
+
+impl WorkflowStore {
+    pub fn new(dir: Dir, compress: bool, limits: WorkflowLimits) -> Self {
+        Self {
+            dir,
+            compress,
+            limits,
+        }
+    }
+
+    /// Write an entry, replacing any previous version
+    pub async fn save(&self, entry: &WorkflowCacheEntry) -> Result<(), AgentError> {
+        let codec = if self.compress { Codec::Gzip } else { Codec::Json };
+        let bytes = encode(entry, codec)?;
+        self.file(&entry.workflow.id, EXTENSION).write_atomic(&bytes).await?;
+
+        // A legacy file would shadow nothing but waste space
+        self.file(&entry.workflow.id, LEGACY_EXTENSION).delete().await
+    }
+
+    /// Delete the files of a workflow
+    pub async fn remove(&self, workflow_id: &str) -> Result<(), AgentError> {
+        self.file(workflow_id, EXTENSION).delete().await?;
+        self.file(workflow_id, LEGACY_EXTENSION).delete().await
+    }
+
+    /// Load every readable entry, migrating legacy files. Unreadable files
+    /// are skipped; the next sync fetches those workflows again.
+    pub async fn load_all(&self) -> Vec<WorkflowCacheEntry> {
+        if !self.dir.exists().await {
+            return Vec::new();
+        }
+        let paths = match self.dir.list_files().await {
+            Ok(paths) => paths,
+            Err(e) => {
+                warn!("Failed to list workflow cache {:?}: {}", self.dir.path(), e);
+                return Vec::new();
+            }
+        };
+
+        let mut entries = Vec::new();
+        let mut migrated = 0;
+        for path in paths {
+            let legacy = match path.extension().and_then(|ext| ext.to_str()) {
+                Some(EXTENSION) => false,
+                Some(LEGACY_EXTENSION) => true,
+                _ => continue,
+            };
+            // A current file supersedes a legacy one left behind
+            if legacy && path.with_extension(EXTENSION).exists() {
+                continue;
+            }
+
+            let entry = match load(&path, &self.limits).await {
+                Ok(entry) => entry,
+                Err(e) => {
+                    warn!("Skipping unreadable workflow cache file {:?}: {}", path, e);
+                    continue;
+                }
+            };
+            if legacy {
+                match self.save(&entry).await {
+                    Ok(()) => migrated += 1,
+                    Err(e) => warn!("Failed to migrate workflow cache file {:?}: {}", path, e),
+                }
+            }
+            entries.push(entry);
+        }
+
+        if migrated > 0 {
+            info!("Migrated {} workflow cache file(s) to format version {}", migrated, VERSION);
+        }
+        entries
+    }
+
+    fn file(&self, workflow_id: &str, extension: &str) -> File {
+        self.dir.file(&format!("{}.{}", workflow_id, extension))
+    }
+}
+
+async fn load(path: &Path, limits: &WorkflowLimits) -> Result<WorkflowCacheEntry, AgentError> {
+    decode(&File::new(path).read_bytes().await?, limits)
+}
+
+/// Serialize an entry behind the header
+fn encode(entry: &WorkflowCacheEntry, codec: Codec) -> Result<Vec<u8>, AgentError> {
+    let json = serde_json::to_vec(entry)?;
+    let mut bytes = Vec::with_capacity(MAGIC.len() + 2 + json.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&[VERSION, codec as u8]);
+    match codec {
+        Codec::Json => bytes.extend_from_slice(&json),
+        Codec::Gzip => {
+            let mut encoder = GzEncoder::new(bytes, Compression::default());
+            encoder.write_all(&json)?;
+            bytes = encoder.finish()?;
+        }
+    }
+    Ok(bytes)
+}
+
+/// Parse a cache file in any supported format
+fn decode(bytes: &[u8], limits: &WorkflowLimits) -> Result<WorkflowCacheEntry, AgentError> {
+    let entry = decode_entry(bytes, limits)?;
+    limits.check(&entry.workflow)?;
+    Ok(entry)
+}
+
+fn decode_entry(bytes: &[u8], limits: &WorkflowLimits) -> Result<WorkflowCacheEntry, AgentError> {
+    let Some(rest) = bytes.strip_prefix(MAGIC.as_slice()) else {
+        // Headerless JSON
+        limits.check_json_len(bytes.len())?;
+        return Ok(serde_json::from_slice(bytes)?);
+    };
+
+    let [version, codec, body @ ..] = rest else {
+        return Err(AgentError::StorageError("Truncated workflow cache header".to_string()));
+    };
+    if *version > VERSION {
+        return Err(AgentError::StorageError(format!(
+            "Unsupported workflow cache format version {}",
+            version
+        )));
+    }
+    match Codec::from_byte(*codec) {
+        Some(Codec::Json) => {
+            limits.check_json_len(body.len())?;
+            Ok(serde_json::from_slice(body)?)
+        }
+        Some(Codec::Gzip) => {
+            // Stop inflating one byte past the limit rather than at the end
+            let mut json = Vec::new();
+            let decoder = GzDecoder::new(body);
+            match limits.max_json_bytes {
+                0 => decoder.take(u64::MAX),
+                max => decoder.take(max as u64 + 1),
+            }
+            .read_to_end(&mut json)?;
+            limits.check_json_len(json.len())?;
+            Ok(serde_json::from_slice(&json)?)
+        }
+        None => Err(AgentError::StorageError(format!(
+            "Unknown workflow cache codec {}",
+            codec
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesys::test_utils::TempFs;
+    use crate::models::test_utils::workflow;
+    use crate::models::workflow::Workflow;
+
+    fn entry(id: &str) -> WorkflowCacheEntry {
+        WorkflowCacheEntry {
+            workflow: Workflow {
+                name: "Camera Capture ".repeat(50),
+                ..workflow(id)
+            },
+            digest: format!("digest-{}", id),
+            cached_at: 42,
+        }
+    }
+
+    #[test]
+    fn test_encode_decode_round_trip() {
+        let original = entry("wf-1");
+        let plain = encode(&original, Codec::Json).unwrap();
+        let gzipped = encode(&original, Codec::Gzip).unwrap();
+        assert!(gzipped.len() < plain.len());
+
+        for bytes in [plain, gzipped, serde_json::to_vec(&original).unwrap()] {
+            let decoded = decode(&bytes, &WorkflowLimits::default()).unwrap();
+            assert_eq!(decoded.workflow.id, "wf-1");
+            assert_eq!(decoded.workflow.name, original.workflow.name);
+            assert_eq!((decoded.digest.as_str(), decoded.cached_at), ("digest-wf-1", 42));
+        }
+
+        let mut future = encode(&original, Codec::Json).unwrap();
+        future[MAGIC.len()] = VERSION + 1;
+        let limits = WorkflowLimits::default();
+        assert!(matches!(decode(&future, &limits), Err(AgentError::StorageError(_))));
+        assert!(decode(b"AJWC\x01", &limits).is_err());
+    }
+
+    #[test]
+    fn test_oversized_entries_are_rejected() {
+        let original = entry("wf-1");
+        let json_len = serde_json::to_vec(&original).unwrap().len();
+        let limits = WorkflowLimits {
+            max_json_bytes: json_len - 1,
+            ..Default::default()
+        };
+        for codec in [Codec::Json, Codec::Gzip] {
+            let bytes = encode(&original, codec).unwrap();
+            assert!(matches!(decode(&bytes, &limits), Err(AgentError::ValidationError(_))));
+        }
+
+        let mut wide = entry("wf-2");
+        wide.workflow.graph_data.edges = serde_json::from_value(serde_json::json!([
+            { "id": "e1", "source": "a", "target": "b" },
+            { "id": "e2", "source": "b", "target": "c" },
+        ]))
+        .unwrap();
+        let limits = WorkflowLimits {
+            max_edges: 1,
+            ..Default::default()
+        };
+        let bytes = encode(&wide, Codec::Gzip).unwrap();
+        assert!(matches!(decode(&bytes, &limits), Err(AgentError::ValidationError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_store_migrates_legacy_files() {
+        let fs = TempFs::new();
+        fs.write("workflows/legacy.json", serde_json::to_vec(&entry("legacy")).unwrap());
+        fs.write("workflows/broken.wfc", "AJWC\x01\x01not gzip");
+        let limits = WorkflowLimits::default();
+        let store = WorkflowStore::new(Dir::new(fs.path("workflows")), true, limits);
+        store.save(&entry("current")).await.unwrap();
+
+        let mut ids: Vec<String> =
+            store.load_all().await.into_iter().map(|e| e.workflow.id).collect();
+        ids.sort();
+        assert_eq!(ids, ["current", "legacy"]);
+        assert!(!fs.path("workflows/legacy.json").exists());
+        assert!(fs.path("workflows/legacy.wfc").exists());
+
+        // Uncompressed stores read compressed files and vice versa
+        let plain = WorkflowStore::new(Dir::new(fs.path("workflows")), false, limits);
+        assert_eq!(plain.load_all().await.len(), 2);
+
+        store.remove("legacy").await.unwrap();
+        assert!(!fs.path("workflows/legacy.wfc").exists());
+    }
+}
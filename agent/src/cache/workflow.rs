@@ -3,10 +3,12 @@
 use std::collections::HashMap;
 use std::sync::RwLock;
 
+use serde::{Deserialize, Serialize};
+
 use crate::models::workflow::Workflow;
 
 /// Workflow cache entry
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WorkflowCacheEntry {
     pub workflow: Workflow,
     pub digest: String,
@@ -20,7 +22,8 @@ pub struct WorkflowCache {
 }
 
 impl WorkflowCache {
-    /// Create a new workflow cache
+    /// Create a new workflow cache holding at most `capacity` workflows.
+    /// A capacity of 0 means unbounded.
     pub fn new(capacity: u64) -> Self {
         Self {
             entries: RwLock::new(HashMap::new()),
@@ -42,10 +45,25 @@ impl WorkflowCache {
 
     /// Insert a workflow into cache
     pub fn insert(&self, workflow: Workflow, digest: String) {
+        self.restore(WorkflowCacheEntry {
+            workflow,
+            digest,
+            cached_at: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        });
+    }
+
+    /// Insert a previously cached entry, keeping its `cached_at`
+    pub fn restore(&self, entry: WorkflowCacheEntry) {
         let mut entries = self.entries.write().unwrap_or_else(|e| e.into_inner());
 
-        // Evict oldest if at capacity
-        if entries.len() as u64 >= self.capacity {
+        // Evict oldest if at capacity, unless this replaces an existing entry
+        if self.capacity > 0
+            && !entries.contains_key(&entry.workflow.id)
+            && entries.len() as u64 >= self.capacity
+        {
             if let Some(oldest_id) = entries
                 .iter()
                 .min_by_key(|(_, e)| e.cached_at)
@@ -55,16 +73,7 @@ impl WorkflowCache {
             }
         }
 
-        let entry = WorkflowCacheEntry {
-            workflow: workflow.clone(),
-            digest,
-            cached_at: std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap_or_default()
-                .as_secs(),
-        };
-
-        entries.insert(workflow.id.clone(), entry);
+        entries.insert(entry.workflow.id.clone(), entry);
     }
 
     /// Remove a workflow from cache
@@ -85,6 +94,14 @@ impl WorkflowCache {
         entries.keys().cloned().collect()
     }
 
+    /// Get all cached entries, sorted by workflow ID
+    pub fn entries(&self) -> Vec<WorkflowCacheEntry> {
+        let entries = self.entries.read().unwrap_or_else(|e| e.into_inner());
+        let mut entries: Vec<WorkflowCacheEntry> = entries.values().cloned().collect();
+        entries.sort_by(|a, b| a.workflow.id.cmp(&b.workflow.id));
+        entries
+    }
+
     /// Get all cached digests
     pub fn digests(&self) -> Vec<(String, String)> {
         let entries = self.entries.read().unwrap_or_else(|e| e.into_inner());
@@ -105,3 +122,39 @@ impl WorkflowCache {
         self.len() == 0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::test_utils::workflow;
+
+    #[test]
+    fn test_capacity_boundaries() {
+        let cache = WorkflowCache::new(0);
+        for i in 0..5 {
+            cache.insert(workflow(&format!("wf{}", i)), "digest".to_string());
+        }
+        assert_eq!(cache.len(), 5);
+
+        let cache = WorkflowCache::new(1);
+        cache.insert(workflow("a"), "d1".to_string());
+        cache.insert(workflow("a"), "d2".to_string());
+        assert_eq!(cache.get("a").unwrap().digest, "d2");
+
+        cache.insert(workflow("b"), "d3".to_string());
+        assert_eq!(cache.keys(), vec!["b".to_string()]);
+    }
+
+    #[test]
+    fn test_entries_are_sorted() {
+        let cache = WorkflowCache::new(10);
+        for id in ["c", "a", "b"] {
+            cache.insert(workflow(id), format!("digest-{}", id));
+        }
+
+        let entries = cache.entries();
+        let ids: Vec<&str> = entries.iter().map(|e| e.workflow.id.as_str()).collect();
+        assert_eq!(ids, vec!["a", "b", "c"]);
+        assert_eq!(entries[0].digest, "digest-a");
+    }
+}
@@ -0,0 +1,265 @@
+//! Clock synchronization monitoring
+//!
+//! Token validation, TLS and deployment timestamps all depend on a correct
+//! clock, and devices without a battery-backed clock boot with a stale one
+//! until NTP catches up. Many "random" auth failures in the field trace back
+//! to this, so the monitor checks whether the system reports its clock as
+//! NTP-synchronized and how far it is off from the backend's `Date` header,
+//! and marks the `clock` health component degraded when either is wrong.
+
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::process::Command;
+use tracing::{debug, error, info};
+
+use crate::health::HealthRegistry;
+use crate::http::client::HttpClient;
+
+/// Name of the clock monitor in the health registry
+const HEALTH_COMPONENT: &str = "clock";
+
+/// Created by systemd-timesyncd once it has synchronized the clock
+const TIMESYNC_FLAG: &str = "/run/systemd/timesync/synchronized";
+
+/// Clock check options
+#[derive(Debug, Clone)]
+pub struct ClockOptions {
+    /// Largest tolerated difference from the backend's clock
+    pub max_skew: Duration,
+
+    /// How often the clock is checked
+    pub check_interval: Duration,
+}
+
+impl Default for ClockOptions {
+    fn default() -> Self {
+        Self {
+            max_skew: Duration::from_secs(60),
+            check_interval: Duration::from_secs(900),
+        }
+    }
+}
+
+/// Outcome of the last clock check
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ClockStatus {
+    /// The system reports the clock as NTP-synchronized; unknown without
+    /// systemd
+    pub ntp_synced: Option<bool>,
+
+    /// Seconds the local clock is ahead of the backend's (negative when
+    /// behind); unknown until the backend has answered
+    pub skew_secs: Option<i64>,
+
+    /// When the clock was last checked
+    pub checked_at: Option<DateTime<Utc>>,
+}
+
+/// Whether the system clock is NTP-synchronized, as systemd sees it.
+/// `None` when that cannot be told.
+pub async fn ntp_synced() -> Option<bool> {
+    if Path::new(TIMESYNC_FLAG).exists() {
+        return Some(true);
+    }
+    // Also covers chrony and ntpd, which set the kernel's sync status
+    let output = Command::new("timedatectl")
+        .args(["show", "--property=NTPSynchronized", "--value"])
+        .output()
+        .await
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    match String::from_utf8_lossy(&output.stdout).trim() {
+        "yes" => Some(true),
+        "no" => Some(false),
+        _ => None,
+    }
+}
+
+/// Seconds the local clock is ahead of a server whose response to a request
+/// sent at `sent` and answered at `received` carried the HTTP `date`. The
+/// server's time is taken to be halfway through the round trip.
+pub fn skew_from_date(date: &str, sent: DateTime<Utc>, received: DateTime<Utc>) -> Option<i64> {
+    let server = DateTime::parse_from_rfc2822(date).ok()?;
+    let local = sent + (received - sent) / 2;
+    Some((local - server.with_timezone(&Utc)).num_seconds())
+}
+
+/// Tracks whether the device clock can be trusted
+pub struct ClockMonitor {
+    options: ClockOptions,
+    status: RwLock<ClockStatus>,
+}
+
+impl ClockMonitor {
+    pub fn new(options: ClockOptions) -> Self {
+        Self {
+            options,
+            status: RwLock::new(ClockStatus::default()),
+        }
+    }
+
+    /// Status as of the last check
+    pub fn status(&self) -> ClockStatus {
+        self.status.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Check the clock and update the status and health. If the backend
+    /// cannot be reached, the last known skew is kept.
+    pub async fn check(&self, http_client: &HttpClient, health: &HealthRegistry) {
+        let skew_secs = match http_client.clock_skew().await {
+            Ok(skew) => skew,
+            Err(e) => {
+                debug!("Could not compare the clock with the backend: {}", e);
+                self.status().skew_secs
+            }
+        };
+        let status = ClockStatus {
+            ntp_synced: ntp_synced().await,
+            skew_secs,
+            checked_at: Some(Utc::now()),
+        };
+        self.record(status, health);
+    }
+
+    fn record(&self, status: ClockStatus, health: &HealthRegistry) {
+        let problem = self.problem(&status);
+        let previous = std::mem::replace(
+            &mut *self.status.write().unwrap_or_else(|e| e.into_inner()),
+            status,
+        );
+        let had_problem = self.problem(&previous).is_some();
+
+        match problem {
+            Some(message) => {
+                if !had_problem {
+                    error!("CLOCK UNRELIABLE: {}; expect token and TLS failures", message);
+                }
+                health.set_degraded(HEALTH_COMPONENT, message);
+            }
+            None => {
+                if had_problem {
+                    info!("Device clock is synchronized again");
+                }
+                health.set_healthy(HEALTH_COMPONENT);
+            }
+        }
+    }
+
+    /// What is wrong with the clock, if anything
+    fn problem(&self, status: &ClockStatus) -> Option<String> {
+        let max_skew = self.options.max_skew.as_secs() as i64;
+        match status.skew_secs {
+            Some(skew) if skew.abs() > max_skew => {
+                let direction = if skew > 0 { "ahead of" } else { "behind" };
+                Some(format!(
+                    "Device clock is {}s {} the backend's (tolerance {}s)",
+                    skew.abs(),
+                    direction,
+                    max_skew
+                ))
+            }
+            _ if status.ntp_synced == Some(false) => {
+                Some("Device clock is not NTP-synchronized".to_string())
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Check the clock every `check_interval` until shut down
+pub async fn run_clock_monitor<S, F>(
+    monitor: &ClockMonitor,
+    http_client: &HttpClient,
+    health: &HealthRegistry,
+    sleep_fn: S,
+    mut shutdown_signal: Pin<Box<dyn Future<Output = ()> + Send>>,
+) where
+    S: Fn(Duration) -> F,
+    F: Future<Output = ()>,
+{
+    loop {
+        monitor.check(http_client, health).await;
+        tokio::select! {
+            _ = &mut shutdown_signal => {
+                info!("Clock monitor shutting down...");
+                return;
+            }
+            _ = sleep_fn(monitor.options.check_interval) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health::HealthStatus;
+
+    #[test]
+    fn test_skew_is_measured_from_the_round_trip_midpoint() {
+        let sent = DateTime::parse_from_rfc3339("2025-02-07T10:00:00Z").unwrap().to_utc();
+        let received = sent + chrono::Duration::seconds(4);
+        let date = "Fri, 07 Feb 2025 09:58:00 GMT";
+        assert_eq!(skew_from_date(date, sent, received), Some(122));
+        assert_eq!(skew_from_date("Fri, 07 Feb 2025 10:05:02 GMT", sent, received), Some(-300));
+        assert_eq!(skew_from_date("yesterday", sent, received), None);
+    }
+
+    #[test]
+    fn test_monitor_degrades_health_when_unsynced_or_skewed() {
+        let health = HealthRegistry::new();
+        let monitor = ClockMonitor::new(ClockOptions::default());
+        let status = |ntp_synced, skew_secs| ClockStatus {
+            ntp_synced,
+            skew_secs,
+            checked_at: Some(Utc::now()),
+        };
+        let component = || health.get(HEALTH_COMPONENT).unwrap();
+
+        monitor.record(status(Some(true), Some(2)), &health);
+        assert_eq!(component().status, HealthStatus::Healthy);
+
+        monitor.record(status(Some(true), Some(-3600)), &health);
+        assert_eq!(component().status, HealthStatus::Degraded);
+        assert!(component().message.unwrap().contains("3600s behind"));
+
+        monitor.record(status(Some(false), Some(0)), &health);
+        assert!(component().message.unwrap().contains("not NTP-synchronized"));
+        assert_eq!(monitor.status().skew_secs, Some(0));
+
+        // Nothing known is nothing wrong
+        monitor.record(status(None, None), &health);
+        assert_eq!(component().status, HealthStatus::Healthy);
+    }
+
+    #[tokio::test]
+    async fn test_monitor_checks_then_waits_until_shut_down() {
+        let health = HealthRegistry::new();
+        let monitor = ClockMonitor::new(ClockOptions::default());
+        let options = crate::http::client::HttpClientOptions::without_retries();
+        let http_client = HttpClient::new("http://127.0.0.1:1", options).await.unwrap();
+        let waits = std::sync::Mutex::new(Vec::new());
+
+        run_clock_monitor(
+            &monitor,
+            &http_client,
+            &health,
+            |wait| {
+                waits.lock().unwrap().push(wait);
+                std::future::pending()
+            },
+            Box::pin(async {}),
+        )
+        .await;
+
+        assert!(monitor.status().checked_at.is_some());
+        assert_eq!(*waits.lock().unwrap(), [ClockOptions::default().check_interval]);
+    }
+}
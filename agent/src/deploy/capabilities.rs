@@ -0,0 +1,180 @@
+//! Deployment capability probes
+//!
+//! Detects which deployment types the device can run, so missing tooling shows
+//! up at startup and in diagnostics rather than as a failed deployment. The
+//! same probes feed the capability manifest advertised to the backend.
+
+use std::path::Path;
+
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::app::options::AppOptions;
+use crate::deploy::compose::{detect_compose, ComposeCommand};
+use crate::deploy::docker::{probe_docker, DockerStatus, MIN_DOCKER_VERSION};
+use crate::scanner::arp::read_arp_table;
+
+/// Sysfs GPIO interface
+const GPIO_SYSFS_PATH: &str = "/sys/class/gpio";
+
+/// Deployment tooling available on this device
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DeployCapabilities {
+    /// Docker CLI and daemon status
+    pub docker: DockerStatus,
+
+    /// Installed compose flavour, if any
+    pub compose: Option<ComposeCommand>,
+}
+
+impl DeployCapabilities {
+    /// Probe the system for deployment tooling
+    pub async fn probe() -> Self {
+        Self {
+            docker: probe_docker().await,
+            compose: detect_compose().await,
+        }
+    }
+
+    /// Whether `docker`/`docker_build` deployments can run
+    pub fn supports_docker(&self) -> bool {
+        self.docker.daemon_reachable()
+    }
+
+    /// Whether `docker_compose`/`git_compose` deployments can run
+    pub fn supports_compose(&self) -> bool {
+        self.compose.is_some()
+    }
+
+    /// Log the probe results, warning about unsupported deployment types
+    pub fn log(&self) {
+        info!(
+            "Deployment capabilities: docker={}, compose={}",
+            self.docker.server_version.as_deref().unwrap_or("unavailable"),
+            self.compose.map(|c| c.as_str()).unwrap_or("none")
+        );
+        if !self.docker.installed {
+            warn!("docker is not installed: docker deployments will fail on this device");
+        } else if self.docker.permission_denied {
+            warn!("Permission denied on the Docker socket: add the agent user to the docker group");
+        } else if !self.docker.daemon_reachable() {
+            warn!("Docker daemon is not reachable: docker deployments will fail on this device");
+        } else if self.docker.meets_minimum_version() == Some(false) {
+            warn!(
+                "Docker {} is older than the minimum supported {}.{}",
+                self.docker.server_version.as_deref().unwrap_or_default(),
+                MIN_DOCKER_VERSION.0,
+                MIN_DOCKER_VERSION.1
+            );
+        }
+        if !self.supports_compose() {
+            warn!("Docker Compose is not installed: compose deployments will fail on this device");
+        }
+    }
+}
+
+/// What this device can do, advertised to the backend whenever the relay or
+/// MQTT connects so it can hide unsupported commands
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CapabilityManifest {
+    pub agent_version: String,
+
+    /// Camera enabled in settings and its device present
+    pub camera: bool,
+
+    /// GPIO enabled in settings and the GPIO interface present
+    pub gpio: bool,
+
+    /// Docker deployments can run
+    pub docker: bool,
+
+    /// Compose deployments can run
+    pub compose: bool,
+
+    /// Remote terminal sessions are supported
+    pub terminal: bool,
+
+    /// One-shot `command_exec` is enabled
+    pub command_exec: bool,
+
+    /// Network scans are supported
+    pub scanner: bool,
+
+    /// Scans can use the ARP table for discovery
+    pub arp_discovery: bool,
+
+    /// Probe details behind `docker` and `compose`
+    pub deploy: DeployCapabilities,
+}
+
+impl CapabilityManifest {
+    /// Build the manifest from settings and runtime probes
+    pub async fn probe(agent_version: &str, options: &AppOptions) -> Self {
+        Self::from_probes(agent_version, options, DeployCapabilities::probe().await).await
+    }
+
+    /// Names of the supported capabilities, as stored on the device record
+    pub fn names(&self) -> Vec<String> {
+        [
+            ("camera", self.camera),
+            ("gpio", self.gpio),
+            ("docker", self.docker),
+            ("compose", self.compose),
+            ("terminal", self.terminal),
+            ("command_exec", self.command_exec),
+            ("scanner", self.scanner),
+            ("arp_discovery", self.arp_discovery),
+        ]
+        .into_iter()
+        .filter(|(_, supported)| *supported)
+        .map(|(name, _)| name.to_string())
+        .collect()
+    }
+
+    async fn from_probes(
+        agent_version: &str,
+        options: &AppOptions,
+        deploy: DeployCapabilities,
+    ) -> Self {
+        let hardware = &options.hardware;
+        Self {
+            agent_version: agent_version.to_string(),
+            camera: hardware.enable_camera && Path::new(&hardware.camera_device).exists(),
+            gpio: hardware.enable_gpio && Path::new(GPIO_SYSFS_PATH).exists(),
+            docker: deploy.supports_docker(),
+            compose: deploy.supports_compose(),
+            terminal: cfg!(unix),
+            command_exec: options.relay_worker.exec.is_enabled(),
+            scanner: true,
+            arp_discovery: read_arp_table().await.is_ok(),
+            deploy,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesys::test_utils::TempFs;
+
+    #[tokio::test]
+    async fn test_hardware_needs_setting_and_device() {
+        let fs = TempFs::new();
+        let deploy = DeployCapabilities::default();
+        let mut options = AppOptions::default();
+        options.hardware.camera_device = fs.write("video0", "").to_string_lossy().to_string();
+
+        let manifest = CapabilityManifest::from_probes("1.0.0", &options, deploy.clone()).await;
+        assert!(!manifest.camera);
+        assert!(!manifest.docker);
+        assert!(!manifest.command_exec);
+
+        options.hardware.enable_camera = true;
+        let manifest = CapabilityManifest::from_probes("1.0.0", &options, deploy.clone()).await;
+        assert!(manifest.camera);
+
+        options.hardware.camera_device = fs.path_str("video1");
+        let manifest = CapabilityManifest::from_probes("1.0.0", &options, deploy).await;
+        assert!(!manifest.camera);
+    }
+}
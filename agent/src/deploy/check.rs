@@ -0,0 +1,246 @@
+//! Workflow deployability precheck
+//!
+//! Lets the backend ask whether this device can run a workflow before
+//! assigning it. The graph must be well formed, every node needs a runner
+//! that accepts its config, and the hardware and tooling its nodes use must
+//! be present. Every problem found is reported, so the backend sees all the
+//! blockers at once.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use serde::Serialize;
+
+use crate::deploy::capabilities::CapabilityManifest;
+use crate::deploy::node_runner::{NodeContext, NodeRunnerFactory};
+use crate::models::workflow::{GraphData, Workflow};
+
+/// What keeps a workflow from deploying
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockerKind {
+    /// The graph is malformed
+    Graph,
+    /// No runner on this agent handles the node type
+    UnsupportedNodeType,
+    /// The runner rejects the node's config
+    InvalidConfig,
+    /// The device lacks a capability the node needs
+    MissingCapability,
+    /// Hardware the node is configured for is absent
+    MissingHardware,
+}
+
+/// One reason the workflow cannot deploy
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Blocker {
+    pub kind: BlockerKind,
+    /// Node the blocker concerns, if any
+    pub node_id: Option<String>,
+    pub message: String,
+}
+
+/// Result of a precheck
+#[derive(Debug, Clone, Serialize)]
+pub struct WorkflowCheckReport {
+    pub workflow_id: String,
+    pub deployable: bool,
+    pub blockers: Vec<Blocker>,
+}
+
+/// Checks workflows against this device
+pub struct WorkflowChecker {
+    capabilities: Arc<CapabilityManifest>,
+    node_context: NodeContext,
+}
+
+impl WorkflowChecker {
+    pub fn new(capabilities: Arc<CapabilityManifest>, node_context: NodeContext) -> Self {
+        Self {
+            capabilities,
+            node_context,
+        }
+    }
+
+    /// Check whether `workflow` could be deployed on this device
+    pub async fn check(&self, workflow: &Workflow) -> WorkflowCheckReport {
+        let mut blockers: Vec<Blocker> = graph_problems(&workflow.graph_data)
+            .into_iter()
+            .map(|message| Blocker {
+                kind: BlockerKind::Graph,
+                node_id: None,
+                message,
+            })
+            .collect();
+
+        for node in &workflow.graph_data.nodes {
+            let blocker = |kind, message: String| Blocker {
+                kind,
+                node_id: Some(node.id.clone()),
+                message,
+            };
+            if !NodeRunnerFactory::supports(&node.node_type) {
+                blockers.push(blocker(
+                    BlockerKind::UnsupportedNodeType,
+                    format!("Node type '{}' is not supported by this agent", node.node_type),
+                ));
+                continue;
+            }
+            if let Some(capability) = self.missing_capability(&node.node_type) {
+                blockers.push(blocker(
+                    BlockerKind::MissingCapability,
+                    format!("Node type '{}' needs {}", node.node_type, capability),
+                ));
+                continue;
+            }
+            let runner = match NodeRunnerFactory::create(node, &self.node_context) {
+                Ok(runner) => runner,
+                Err(e) => {
+                    blockers.push(blocker(BlockerKind::InvalidConfig, e.to_string()));
+                    continue;
+                }
+            };
+            if let Err(e) = runner.check_resources().await {
+                blockers.push(blocker(BlockerKind::MissingHardware, e.to_string()));
+            }
+        }
+
+        WorkflowCheckReport {
+            workflow_id: workflow.id.clone(),
+            deployable: blockers.is_empty(),
+            blockers,
+        }
+    }
+
+    /// Capability a node type needs that this device lacks
+    fn missing_capability(&self, node_type: &str) -> Option<&'static str> {
+        let capabilities = &self.capabilities;
+        match node_type {
+            "camera" | "camera_capture" if !capabilities.camera => Some("a camera"),
+            "gpio_read" | "gpio_input" | "gpio_write" | "gpio_output" if !capabilities.gpio => {
+                Some("GPIO")
+            }
+            "docker" | "container" if !capabilities.docker => Some("a reachable Docker daemon"),
+            _ => None,
+        }
+    }
+}
+
+/// Structural problems of a graph: duplicate node IDs, edges to unknown
+/// nodes and cycles
+pub fn graph_problems(graph: &GraphData) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut known = HashSet::new();
+    for node in &graph.nodes {
+        if !known.insert(node.id.as_str()) {
+            problems.push(format!("Duplicate node ID: {}", node.id));
+        }
+    }
+
+    for edge in &graph.edges {
+        for end in [&edge.source, &edge.target] {
+            if !known.contains(end.as_str()) {
+                problems.push(format!("Edge {} references unknown node {}", edge.id, end));
+            }
+        }
+    }
+
+    let cycle = cycle_nodes(graph);
+    if !cycle.is_empty() {
+        problems.push(format!("Graph has a cycle through nodes: {}", cycle.join(", ")));
+    }
+    problems
+}
+
+/// IDs of the nodes on a cycle, sorted; nodes merely downstream of a cycle
+/// are not included. Edges to unknown nodes are ignored.
+pub fn cycle_nodes(graph: &GraphData) -> Vec<&str> {
+    let known: HashSet<&str> = graph.nodes.iter().map(|n| n.id.as_str()).collect();
+    let mut downstream: HashMap<&str, HashSet<&str>> = HashMap::new();
+    for edge in &graph.edges {
+        let (source, target) = (edge.source.as_str(), edge.target.as_str());
+        if known.contains(source) && known.contains(target) {
+            downstream.entry(source).or_default().insert(target);
+        }
+    }
+
+    // A node is on a cycle if it can reach itself
+    let mut cycle: Vec<&str> = known
+        .iter()
+        .copied()
+        .filter(|&start| {
+            let mut stack: Vec<&str> =
+                downstream.get(start).into_iter().flatten().copied().collect();
+            let mut visited = HashSet::new();
+            while let Some(id) = stack.pop() {
+                if id == start {
+                    return true;
+                }
+                if visited.insert(id) {
+                    stack.extend(downstream.get(id).into_iter().flatten());
+                }
+            }
+            false
+        })
+        .collect();
+    cycle.sort();
+    cycle
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::test_utils::{edge, node, workflow_with_graph};
+
+    #[tokio::test]
+    async fn test_blockers_are_reported() {
+        let checker =
+            WorkflowChecker::new(Arc::new(CapabilityManifest::default()), NodeContext::default());
+
+        let fine = workflow_with_graph(
+            "wf",
+            vec![
+                node("wait", "delay", serde_json::json!({ "delay_ms": 1 })),
+                node("log", "log", serde_json::json!({})),
+            ],
+            vec![edge("wait", "log")],
+        );
+        let report = checker.check(&fine).await;
+        assert!(report.deployable, "{:?}", report.blockers);
+
+        let broken = workflow_with_graph(
+            "wf",
+            vec![
+                node("a", "delay", serde_json::json!({ "delay_ms": 1 })),
+                node("b", "delay", serde_json::json!({ "dleay_ms": 1 })),
+                node("cam", "camera", serde_json::json!({})),
+                node("magic", "teleport", serde_json::json!({})),
+            ],
+            vec![
+                edge("a", "magic"),
+                edge("magic", "a"),
+                edge("a", "ghost"),
+                edge("magic", "b"),
+            ],
+        );
+        let report = checker.check(&broken).await;
+        assert!(!report.deployable);
+        let kinds: Vec<(BlockerKind, Option<&str>)> = report
+            .blockers
+            .iter()
+            .map(|b| (b.kind, b.node_id.as_deref()))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                (BlockerKind::Graph, None),
+                (BlockerKind::Graph, None),
+                (BlockerKind::InvalidConfig, Some("b")),
+                (BlockerKind::MissingCapability, Some("cam")),
+                (BlockerKind::UnsupportedNodeType, Some("magic")),
+            ]
+        );
+        assert!(report.blockers[0].message.contains("ghost"));
+        assert!(report.blockers[1].message.ends_with("a, magic"));
+    }
+}
@@ -1,11 +1,65 @@
 //! Docker Compose deployment executor
 
 use std::path::Path;
+use std::process::Stdio;
+use serde::Serialize;
 use tokio::process::Command;
 use tracing::{info, debug};
+use crate::deploy::output::{run_logged, LogSink};
 use crate::errors::AgentError;
 
-pub async fn deploy_compose(target_dir: &str) -> Result<(), AgentError> {
+/// The Docker Compose flavour available on this device
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ComposeCommand {
+    /// Standalone `docker-compose` binary (v1)
+    Standalone,
+
+    /// `docker compose` CLI plugin (v2)
+    Plugin,
+}
+
+impl ComposeCommand {
+    /// Program and leading arguments used to invoke compose
+    fn invocation(&self) -> (&'static str, &'static [&'static str]) {
+        match self {
+            ComposeCommand::Standalone => ("docker-compose", &[]),
+            ComposeCommand::Plugin => ("docker", &["compose"]),
+        }
+    }
+
+    /// Human-readable command name
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ComposeCommand::Standalone => "docker-compose",
+            ComposeCommand::Plugin => "docker compose",
+        }
+    }
+}
+
+/// Detect which Docker Compose flavour is installed, preferring the standalone
+/// binary to match the order deployments have always used.
+pub async fn detect_compose() -> Option<ComposeCommand> {
+    for candidate in [ComposeCommand::Standalone, ComposeCommand::Plugin] {
+        let (program, prefix) = candidate.invocation();
+        let status = Command::new(program)
+            .kill_on_drop(true)
+            .args(prefix)
+            .arg("version")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .await;
+
+        if matches!(status, Ok(s) if s.success()) {
+            return Some(candidate);
+        }
+    }
+    None
+}
+
+/// Run `compose up` in `target_dir`, passing its output to `log`
+pub async fn deploy_compose(target_dir: &str, log: &LogSink) -> Result<(), AgentError> {
     info!("Deploying with Docker Compose in: {}", target_dir);
 
     let path = Path::new(target_dir);
@@ -13,28 +67,22 @@ pub async fn deploy_compose(target_dir: &str) -> Result<(), AgentError> {
         return Err(AgentError::DeployError(format!("Target directory does not exist: {}", target_dir)));
     }
 
-    // Run docker-compose up -d
-    debug!("Running docker-compose up -d...");
-    let status = Command::new("docker-compose")
-        .current_dir(path)
-        .args(["up", "-d", "--build"])
-        .status()
+    let compose = detect_compose()
         .await
-        .map_err(|e| AgentError::DeployError(format!("Failed to run docker-compose: {}", e)))?;
+        .ok_or_else(|| AgentError::DeployError("Docker Compose is not installed".to_string()))?;
+
+    // Run compose up -d
+    debug!("Running {} up -d...", compose.as_str());
+    let (program, prefix) = compose.invocation();
+    let status = run_logged(
+        Command::new(program).current_dir(path).args(prefix).args(["up", "-d", "--build"]),
+        log,
+    )
+    .await
+        .map_err(|e| AgentError::DeployError(format!("Failed to run {}: {}", compose.as_str(), e)))?;
 
     if !status.success() {
-        // Try 'docker compose' (newer version)
-        debug!("docker-compose failed, trying 'docker compose'...");
-        let status = Command::new("docker")
-            .current_dir(path)
-            .args(["compose", "up", "-d", "--build"])
-            .status()
-            .await
-            .map_err(|e| AgentError::DeployError(format!("Failed to run docker compose: {}", e)))?;
-        
-        if !status.success() {
-            return Err(AgentError::DeployError("Docker Compose failed".to_string()));
-        }
+        return Err(AgentError::DeployError("Docker Compose failed".to_string()));
     }
 
     info!("Successfully deployed Docker Compose application");
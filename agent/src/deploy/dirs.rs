@@ -0,0 +1,235 @@
+//! Deployment directories
+//!
+//! Workflows and deployer deployments each get a subdirectory of the
+//! deployment directory named after their ID. The syncer removes directories
+//! of unassigned workflows, but operators sometimes need to inspect and clean
+//! them up by hand.
+
+use std::path::{Component, Path};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use serde::Serialize;
+use tracing::info;
+
+use crate::deploy::ledger::DeploymentLedger;
+use crate::deploy::limits;
+use crate::deploy::registry::ExecutorRegistry;
+use crate::errors::AgentError;
+use crate::filesys::dir::Dir;
+use crate::filesys::relay::validate_path;
+
+/// A deployment subdirectory
+#[derive(Debug, Clone, Serialize)]
+pub struct DeploymentDir {
+    /// Directory name, i.e. the workflow or deployment ID
+    pub name: String,
+    pub path: String,
+    /// Total size of its files in bytes
+    pub size: u64,
+    /// Last-modified time as a Unix timestamp in seconds (None if unavailable)
+    pub modified: Option<u64>,
+    /// Workflow deployment state, or deployer status, if known
+    pub state: Option<&'static str>,
+    /// The deployment is active and the directory cannot be removed
+    pub in_use: bool,
+}
+
+/// The deployment directory and what knows which deployments are active
+pub struct DeploymentDirs {
+    root: Dir,
+    executors: Arc<ExecutorRegistry>,
+    ledger: Arc<DeploymentLedger>,
+}
+
+impl DeploymentDirs {
+    pub fn new(
+        root: Dir,
+        executors: Arc<ExecutorRegistry>,
+        ledger: Arc<DeploymentLedger>,
+    ) -> Self {
+        Self {
+            root,
+            executors,
+            ledger,
+        }
+    }
+
+    /// List the deployment subdirectories, sorted by name
+    pub async fn list(&self) -> Result<Vec<DeploymentDir>, AgentError> {
+        if !self.root.exists().await {
+            return Ok(Vec::new());
+        }
+
+        let states = self.executors.states().await;
+        let mut dirs = Vec::new();
+        for path in self.root.list_dirs().await? {
+            let Some(name) = path.file_name().map(|n| n.to_string_lossy().into_owned()) else {
+                continue;
+            };
+            let dir = Dir::new(&path);
+            let (state, in_use) = match states.get(&name) {
+                Some(state) => (Some(state.as_str()), state.is_active()),
+                None => self.deployment_state(&name).await,
+            };
+            dirs.push(DeploymentDir {
+                size: dir.size().await?,
+                modified: dir
+                    .modified()
+                    .await
+                    .ok()
+                    .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                    .map(|d| d.as_secs()),
+                state,
+                in_use,
+                path: path.to_string_lossy().into_owned(),
+                name,
+            });
+        }
+        dirs.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(dirs)
+    }
+
+    /// Delete the deployment subdirectory `name`, refusing while its
+    /// deployment is active
+    pub async fn remove(&self, name: &str) -> Result<(), AgentError> {
+        validate_path(name)?;
+        // A plain directory name, so nothing outside the deployment directory
+        // can be targeted
+        let mut components = Path::new(name).components();
+        if !matches!(
+            (components.next(), components.next()),
+            (Some(Component::Normal(_)), None)
+        ) {
+            return Err(AgentError::ValidationError(format!(
+                "Invalid deployment directory name: {:?}",
+                name
+            )));
+        }
+
+        let (state, in_use) = match self.executors.get(name) {
+            Some(executor) => {
+                let state = executor.state().await;
+                (Some(state.as_str()), state.is_active())
+            }
+            None => self.deployment_state(name).await,
+        };
+        if in_use {
+            return Err(AgentError::Conflict(format!(
+                "Deployment {} is {}; stop it before removing its directory",
+                name,
+                state.unwrap_or("active")
+            )));
+        }
+
+        let dir = self.root.subdir(name);
+        if !dir.exists().await {
+            return Err(AgentError::NotFound(format!(
+                "Deployment directory not found: {}",
+                name
+            )));
+        }
+        // Whatever the deployment left running goes with its directory
+        limits::remove_workload(name).await;
+        dir.delete().await?;
+        info!("Removed deployment directory {:?}", dir.path());
+        Ok(())
+    }
+
+    /// Status of the deployer's deployment `id`; in use while it executes
+    /// and, once it succeeded, for as long as the ledger remembers it
+    async fn deployment_state(&self, id: &str) -> (Option<&'static str>, bool) {
+        match self.ledger.get(id).await {
+            Some(entry) => (Some(entry.outcome.as_status()), entry.outcome.is_active()),
+            None => (None, false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deploy::executor::WorkflowExecutor;
+    use crate::deploy::ledger::DeploymentOutcome;
+    use crate::filesys::file::File;
+    use crate::filesys::test_utils::TempFs;
+    use crate::models::test_utils::workflow;
+
+    fn executor(id: &str) -> Arc<WorkflowExecutor> {
+        Arc::new(WorkflowExecutor::new(workflow(id)))
+    }
+
+    /// Deployment dirs where workflow `wf-a` is deployed, `wf-b` is pending,
+    /// deployment `dep-1` is still executing, `dep-2` succeeded and `dep-3`
+    /// failed
+    async fn dirs(fs: &TempFs) -> DeploymentDirs {
+        let executors = Arc::new(ExecutorRegistry::new());
+        let deployed = executor("wf-a");
+        deployed.deploy().await.unwrap();
+        executors.insert(deployed);
+        executors.insert(executor("wf-b"));
+
+        let ledger = Arc::new(DeploymentLedger::load(File::new(fs.path("ledger.json"))).await);
+        ledger.record("dep-1", DeploymentOutcome::InProgress, None).await.unwrap();
+        ledger.record("dep-2", DeploymentOutcome::Success, None).await.unwrap();
+        ledger.record("dep-3", DeploymentOutcome::Failed, None).await.unwrap();
+
+        DeploymentDirs::new(Dir::new(fs.path("deployments")), executors, ledger)
+    }
+
+    #[tokio::test]
+    async fn test_list_reports_sizes_and_states() {
+        let fs = TempFs::new();
+        fs.write("deployments/wf-b/data.bin", "12345");
+        fs.write("deployments/wf-a/nested/config.json", "{}");
+        fs.write("deployments/dep-1/run.sh", "");
+        fs.write("deployments/dep-2/compose.yml", "");
+        fs.write("deployments/dep-3/compose.yml", "");
+        fs.write("deployments/stray.txt", "not a deployment");
+
+        let dirs = dirs(&fs).await.list().await.unwrap();
+        let names: Vec<&str> = dirs.iter().map(|d| d.name.as_str()).collect();
+        assert_eq!(names, ["dep-1", "dep-2", "dep-3", "wf-a", "wf-b"]);
+        assert_eq!((dirs[0].state, dirs[0].in_use), (Some("in_progress"), true));
+        assert_eq!((dirs[1].state, dirs[1].in_use), (Some("success"), true));
+        assert_eq!((dirs[2].state, dirs[2].in_use), (Some("failed"), false));
+        assert_eq!((dirs[3].size, dirs[3].state, dirs[3].in_use), (2, Some("deployed"), true));
+        assert_eq!((dirs[4].size, dirs[4].state, dirs[4].in_use), (5, Some("pending"), false));
+        assert!(dirs[4].modified.is_some());
+
+        let empty = TempFs::new();
+        assert!(self::dirs(&empty).await.list().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_remove_refuses_active_deployments_and_escapes() {
+        let fs = TempFs::new();
+        for name in ["wf-a", "wf-b", "dep-1", "dep-2", "dep-3"] {
+            fs.write(&format!("deployments/{}/data.bin", name), name);
+        }
+        fs.write("outside/keep.txt", "keep");
+        let dirs = dirs(&fs).await;
+
+        // A deployment that succeeded leaves its containers running
+        for name in ["wf-a", "dep-1", "dep-2"] {
+            assert!(matches!(dirs.remove(name).await, Err(AgentError::Conflict(_))));
+            assert!(fs.path("deployments").join(name).exists());
+        }
+
+        for name in ["../outside", "wf-b/..", "/tmp", "", "."] {
+            assert!(
+                matches!(dirs.remove(name).await, Err(AgentError::ValidationError(_))),
+                "{:?} was accepted",
+                name
+            );
+        }
+        assert!(fs.path("outside/keep.txt").exists());
+
+        // Pending workflows and failed deployments hold nothing live
+        for name in ["wf-b", "dep-3"] {
+            dirs.remove(name).await.unwrap();
+            assert!(!fs.path("deployments").join(name).exists());
+        }
+        assert!(matches!(dirs.remove("wf-b").await, Err(AgentError::NotFound(_))));
+    }
+}
@@ -1,24 +1,364 @@
 //! Docker deployment executor
 
 use std::process::Stdio;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
 use tokio::io::AsyncWriteExt;
 use tokio::process::Command;
 use tracing::{info, debug};
+use crate::deploy::limits::ResourceLimits;
+use crate::deploy::output::{run_logged, LogSink};
 use crate::errors::AgentError;
 
-pub async fn deploy_docker(
-    image: &str,
-    tag: &str,
-    registry_token: Option<String>,
-    registry_username: Option<String>,
-) -> Result<(), AgentError> {
+/// How often a new container's state is polled while verifying it
+const VERIFY_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Lines of container logs included when a container fails verification
+const FAILURE_LOG_LINES: &str = "20";
+
+/// Oldest Docker Engine version deployments are tested against (major, minor)
+pub const MIN_DOCKER_VERSION: (u32, u32) = (20, 10);
+
+/// Result of probing the local Docker installation
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DockerStatus {
+    /// The docker CLI could be executed
+    pub installed: bool,
+
+    /// Docker CLI version
+    pub client_version: Option<String>,
+
+    /// Docker Engine (daemon) version; `None` when the daemon is unreachable
+    pub server_version: Option<String>,
+
+    /// The agent user lacks permission to talk to the Docker socket
+    pub permission_denied: bool,
+
+    /// Error reported by docker when the daemon could not be reached
+    pub error: Option<String>,
+}
+
+impl DockerStatus {
+    /// Whether the daemon answered
+    pub fn daemon_reachable(&self) -> bool {
+        self.server_version.is_some()
+    }
+
+    /// Whether the daemon version meets `MIN_DOCKER_VERSION`.
+    /// `None` when the version is unknown.
+    pub fn meets_minimum_version(&self) -> Option<bool> {
+        self.server_version
+            .as_deref()
+            .and_then(parse_version)
+            .map(|v| v >= MIN_DOCKER_VERSION)
+    }
+}
+
+/// Parse the major and minor components of a docker version string
+/// (e.g. "24.0.7" or "20.10.21+dfsg1")
+fn parse_version(version: &str) -> Option<(u32, u32)> {
+    let mut parts = version.trim().split(|c: char| !c.is_ascii_digit());
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some((major, minor))
+}
+
+/// Probe the docker CLI and daemon
+pub async fn probe_docker() -> DockerStatus {
+    let output = Command::new("docker")
+        .kill_on_drop(true)
+        .args(["version", "--format", "{{.Client.Version}}|{{.Server.Version}}"])
+        .stdin(Stdio::null())
+        .output()
+        .await;
+
+    let output = match output {
+        Ok(output) => output,
+        Err(e) => {
+            return DockerStatus {
+                error: Some(e.to_string()),
+                ..Default::default()
+            }
+        }
+    };
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    let mut versions = stdout.trim().splitn(2, '|');
+    let non_empty = |v: Option<&str>| v.map(str::trim).filter(|v| !v.is_empty()).map(String::from);
+    let client_version = non_empty(versions.next());
+    let server_version = non_empty(versions.next());
+
+    DockerStatus {
+        installed: true,
+        client_version,
+        server_version,
+        permission_denied: stderr.to_lowercase().contains("permission denied"),
+        error: (!stderr.is_empty()).then_some(stderr),
+    }
+}
+
+/// Check a digest has the form `sha256:<64 hex chars>`
+fn validate_digest(digest: &str) -> Result<(), AgentError> {
+    let valid = digest
+        .strip_prefix("sha256:")
+        .is_some_and(|hex| hex.len() == 64 && hex.chars().all(|c| c.is_ascii_hexdigit()));
+    if valid {
+        Ok(())
+    } else {
+        Err(AgentError::ConfigError(format!(
+            "Invalid image digest '{}', expected sha256:<64 hex characters>",
+            digest
+        )))
+    }
+}
+
+/// Repository part of an image reference, without tag or digest. A colon
+/// before the last '/' belongs to a registry port, not a tag.
+fn repository(image: &str) -> &str {
+    let image = image.split('@').next().unwrap_or(image);
+    let name_start = image.rfind('/').map_or(0, |i| i + 1);
+    match image[name_start..].find(':') {
+        Some(i) => &image[..name_start + i],
+        None => image,
+    }
+}
+
+/// Reference to pull: `repo@digest` when pinned, otherwise `image:tag`
+fn image_reference(image: &str, tag: &str, digest: Option<&str>) -> String {
+    if let Some(digest) = digest {
+        return format!("{}@{}", repository(image), digest);
+    }
+
     // Handle case where image already includes tag (e.g., from Ajime builder)
-    let full_image = if image.contains(':') || tag.is_empty() {
+    if repository(image) != image || tag.is_empty() {
         image.to_string()
     } else {
         format!("{}:{}", image, tag)
+    }
+}
+
+/// Container name of a deployment. Each deployment gets its own container,
+/// so deployments of the same image do not replace each other.
+pub fn container_name(deployment_id: &str) -> String {
+    let id: String = deployment_id
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || "-_.".contains(c) { c } else { '_' })
+        .collect();
+    format!("ajime-{}", id)
+}
+
+/// Name older agents gave the container of `image`: the image basename
+fn legacy_container_name(image: &str) -> &str {
+    let repo = repository(image);
+    repo.rsplit('/').next().filter(|n| !n.is_empty()).unwrap_or("container")
+}
+
+/// Containers stopped and removed before `container` runs `image`: its own
+/// previous run and the container an older agent would have used
+fn replaced_containers(container: &str, image: &str) -> Vec<String> {
+    let mut replaced = vec![container.to_string()];
+    let legacy = legacy_container_name(image);
+    if legacy != container {
+        replaced.push(legacy.to_string());
+    }
+    replaced
+}
+
+/// Whether any of an image's `RepoDigests` (`repo@sha256:...`) is `digest`
+fn has_repo_digest(repo_digests: &[String], digest: &str) -> bool {
+    repo_digests
+        .iter()
+        .any(|d| d.rsplit_once('@').is_some_and(|(_, d)| d == digest))
+}
+
+/// Confirm the pulled image carries the pinned digest
+async fn verify_digest(reference: &str, digest: &str) -> Result<(), AgentError> {
+    let output = Command::new("docker")
+        .kill_on_drop(true)
+        .args(["image", "inspect", "--format", "{{json .RepoDigests}}", reference])
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .map_err(|e| AgentError::DeployError(format!("Failed to run docker inspect: {}", e)))?;
+
+    if !output.status.success() {
+        return Err(AgentError::DeployError(format!(
+            "Docker inspect failed for {}: {}",
+            reference,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    let repo_digests: Vec<String> = serde_json::from_slice(&output.stdout).map_err(|e| {
+        AgentError::DeployError(format!("Unexpected docker inspect output: {}", e))
+    })?;
+    if !has_repo_digest(&repo_digests, digest) {
+        return Err(AgentError::DeployError(format!(
+            "Digest mismatch for {}: expected {}, image has {:?}",
+            reference, digest, repo_digests
+        )));
+    }
+
+    info!("Verified image digest {}", digest);
+    Ok(())
+}
+
+/// Container state as reported by `docker inspect`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ContainerState {
+    #[serde(default)]
+    status: String,
+    #[serde(default)]
+    running: bool,
+    #[serde(default)]
+    restarting: bool,
+    #[serde(default)]
+    exit_code: i64,
+    /// Present only when the image or run defines a healthcheck
+    health: Option<ContainerHealth>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ContainerHealth {
+    #[serde(default)]
+    status: String,
+}
+
+/// What one look at a new container tells
+#[derive(Debug, PartialEq, Eq)]
+enum Verdict {
+    /// Its healthcheck passed
+    Healthy,
+    /// Running, without a verdict from a healthcheck yet
+    Up,
+    /// Exited, crash looping or unhealthy
+    Failed(String),
+}
+
+fn assess(state: &ContainerState, restart_count: u64) -> Verdict {
+    if state.restarting || restart_count > 0 {
+        return Verdict::Failed(format!(
+            "container is restarting (restarted {} times, last exit code {})",
+            restart_count, state.exit_code
+        ));
+    }
+    if !state.running {
+        return Verdict::Failed(format!(
+            "container is {} (exit code {})",
+            state.status, state.exit_code
+        ));
+    }
+    match state.health.as_ref().map(|h| h.status.as_str()) {
+        Some("healthy") => Verdict::Healthy,
+        Some("unhealthy") => Verdict::Failed("container is unhealthy".to_string()),
+        _ => Verdict::Up,
+    }
+}
+
+async fn inspect_container(name: &str) -> Result<(ContainerState, u64), AgentError> {
+    let output = Command::new("docker")
+        .kill_on_drop(true)
+        .args(["inspect", "--format", "{{json .State}}|{{.RestartCount}}", name])
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .map_err(|e| AgentError::DeployError(format!("Failed to run docker inspect: {}", e)))?;
+
+    if !output.status.success() {
+        return Err(AgentError::DeployError(format!(
+            "Docker inspect failed for container {}: {}",
+            name,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let (state, restart_count) = stdout.trim().rsplit_once('|').unwrap_or((stdout.trim(), "0"));
+    let state = serde_json::from_str(state).map_err(|e| {
+        AgentError::DeployError(format!("Unexpected docker inspect output: {}", e))
+    })?;
+    Ok((state, restart_count.trim().parse().unwrap_or(0)))
+}
+
+/// Last lines of a container's output, stdout and stderr combined
+async fn container_logs(name: &str) -> String {
+    let output = Command::new("docker")
+        .kill_on_drop(true)
+        .args(["logs", "--tail", FAILURE_LOG_LINES, name])
+        .stdin(Stdio::null())
+        .output()
+        .await;
+
+    match output {
+        Ok(output) => {
+            let mut logs = String::from_utf8_lossy(&output.stdout).into_owned();
+            logs.push_str(&String::from_utf8_lossy(&output.stderr));
+            logs.trim_end().to_string()
+        }
+        Err(e) => format!("(failed to read logs: {})", e),
+    }
+}
+
+/// Watch a new container for up to `timeout`. A container with a
+/// healthcheck passes once healthy; one without must keep running for the
+/// whole timeout, so a crash soon after start is caught.
+async fn verify_container(name: &str, timeout: Duration) -> Result<(), AgentError> {
+    debug!("Verifying container {} for up to {:?}", name, timeout);
+    let deadline = tokio::time::Instant::now() + timeout;
+    let failure = loop {
+        let (state, restart_count) = inspect_container(name).await?;
+        match assess(&state, restart_count) {
+            Verdict::Healthy => return Ok(()),
+            Verdict::Failed(reason) => break reason,
+            Verdict::Up if tokio::time::Instant::now() >= deadline => {
+                if state.health.is_some() {
+                    break format!("container did not become healthy within {:?}", timeout);
+                }
+                return Ok(());
+            }
+            Verdict::Up => tokio::time::sleep(VERIFY_POLL_INTERVAL).await,
+        }
     };
 
+    let logs = container_logs(name).await;
+    Err(AgentError::DeployError(format!(
+        "Container {} failed verification: {}\nLast {} lines of logs:\n{}",
+        name, failure, FAILURE_LOG_LINES, logs
+    )))
+}
+
+/// Image to deploy and how to pull it
+pub struct DockerImage<'a> {
+    pub image: &'a str,
+    pub tag: &'a str,
+    /// Pin of the image (`sha256:...`), overriding the tag
+    pub digest: Option<&'a str>,
+    pub registry_token: Option<String>,
+    pub registry_username: Option<String>,
+}
+
+/// Deploy a container image as `container`, replacing an earlier run of
+/// it. With a digest the image is pulled by digest and verified after the
+/// pull, so a moved tag cannot change what runs. The new container is then
+/// watched for up to `verify_timeout` (zero skips this) and the deployment
+/// fails if it exits or turns unhealthy. The container runs with the memory
+/// and CPU `limits`. Output of the pull and run goes to `log`.
+pub async fn deploy_docker(
+    container: &str,
+    image: DockerImage<'_>,
+    verify_timeout: Duration,
+    limits: &ResourceLimits,
+    log: &LogSink,
+) -> Result<(), AgentError> {
+    let DockerImage { image, tag, digest, registry_token, registry_username } = image;
+    if let Some(digest) = digest {
+        validate_digest(digest)?;
+    }
+    let full_image = image_reference(image, tag, digest);
+
     info!("Deploying Docker image: {}", full_image);
 
     // 1. Authenticate with GHCR if this is a ghcr.io image
@@ -37,11 +377,12 @@ pub async fn deploy_docker(
             let username = registry_username
                 .as_deref()
                 .filter(|u| !u.is_empty())
-                .or_else(|| if !env_username.is_empty() { Some(env_username.as_str()) } else { None })
+                .or((!env_username.is_empty()).then_some(env_username.as_str()))
                 .unwrap_or("x-access-token");
 
             let login_result: Result<bool, std::io::Error> = async {
                 let mut child = Command::new("docker")
+                    .kill_on_drop(true)
                     .args(["login", "ghcr.io", "-u", username, "--password-stdin"])
                     .stdin(Stdio::piped())
                     .stdout(Stdio::null())
@@ -66,9 +407,7 @@ pub async fn deploy_docker(
 
     // 2. Pull image
     debug!("Pulling image: {}", full_image);
-    let pull_status = Command::new("docker")
-        .args(["pull", &full_image])
-        .status()
+    let pull_status = run_logged(Command::new("docker").args(["pull", &full_image]), log)
         .await
         .map_err(|e| AgentError::DeployError(format!("Failed to run docker pull: {}", e)))?;
 
@@ -76,31 +415,132 @@ pub async fn deploy_docker(
         return Err(AgentError::DeployError(format!("Docker pull failed for {}", full_image)));
     }
 
-    // 3. Stop existing container if any (using image name as container name)
-    let container_name = full_image
-        .split('/')
-        .last()
-        .unwrap_or(&full_image)
-        .split(':')
-        .next()
-        .unwrap_or("container");
+    if let Some(digest) = digest {
+        verify_digest(&full_image, digest).await?;
+    }
 
-    debug!("Stopping existing container: {}", container_name);
-    let _ = Command::new("docker").args(["stop", container_name]).status().await;
-    let _ = Command::new("docker").args(["rm", container_name]).status().await;
+    // 3. Stop the previous run, and a container named after the image by
+    // older agents
+    for old in replaced_containers(container, &full_image) {
+        debug!("Stopping existing container: {}", old);
+        let _ = Command::new("docker").kill_on_drop(true).args(["stop", &old]).status().await;
+        let _ = Command::new("docker").kill_on_drop(true).args(["rm", &old]).status().await;
+    }
 
     // 4. Run new container
-    debug!("Running new container: {}", container_name);
-    let run_status = Command::new("docker")
-        .args(["run", "-d", "--name", container_name, "--restart", "unless-stopped", &full_image])
-        .status()
-        .await
+    debug!("Running new container: {}", container);
+    let run_status = run_logged(
+        Command::new("docker")
+            .args(["run", "-d", "--name", container, "--restart", "unless-stopped"])
+            .args(limits.docker_args())
+            .arg(&full_image),
+        log,
+    )
+    .await
         .map_err(|e| AgentError::DeployError(format!("Failed to run docker run: {}", e)))?;
 
     if !run_status.success() {
         return Err(AgentError::DeployError(format!("Docker run failed for {}", full_image)));
     }
 
+    // 5. Make sure it stays up
+    if !verify_timeout.is_zero() {
+        verify_container(container, verify_timeout).await?;
+    }
+
     info!("Successfully deployed Docker image: {}", full_image);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(parse_version("24.0.7"), Some((24, 0)));
+        assert_eq!(parse_version("20.10.21+dfsg1"), Some((20, 10)));
+        assert_eq!(parse_version("garbage"), None);
+    }
+
+    #[test]
+    fn test_image_reference() {
+        let digest = format!("sha256:{}", "a".repeat(64));
+        assert_eq!(image_reference("nginx", "1.25", None), "nginx:1.25");
+        assert_eq!(image_reference("ghcr.io/o/app:v2", "latest", None), "ghcr.io/o/app:v2");
+        assert_eq!(image_reference("localhost:5000/app", "v1", None), "localhost:5000/app:v1");
+        assert_eq!(
+            image_reference("localhost:5000/app:v1", "", Some(&digest)),
+            format!("localhost:5000/app@{}", digest)
+        );
+        assert_eq!(repository(&format!("ghcr.io/o/app@{}", digest)), "ghcr.io/o/app");
+    }
+
+    #[test]
+    fn test_deployments_of_one_image_coexist() {
+        let (first, second) = (container_name("dep-1"), container_name("dep-2"));
+        assert_eq!(first, "ajime-dep-1");
+        assert_ne!(first, second);
+
+        // Deploying nginx:1.25 replaces its own container and the legacy
+        // `nginx`, but not the container running nginx:1.24
+        assert_eq!(replaced_containers(&first, "nginx:1.24"), ["ajime-dep-1", "nginx"]);
+        let replaced = replaced_containers(&second, "nginx:1.25");
+        assert_eq!(replaced, ["ajime-dep-2", "nginx"]);
+        assert!(!replaced.contains(&first));
+
+        assert_eq!(container_name("../x y"), "ajime-.._x_y");
+        assert_eq!(legacy_container_name("ghcr.io/o/app:v2"), "app");
+    }
+
+    #[test]
+    fn test_digest_validation_and_match() {
+        let digest = format!("sha256:{}", "0123456789abcdef".repeat(4));
+        assert!(validate_digest(&digest).is_ok());
+        assert!(validate_digest("sha256:abc").is_err());
+        assert!(validate_digest(&digest.replace("sha256", "md5")).is_err());
+
+        let other = format!("sha256:{}", "f".repeat(64));
+        let repo_digests = vec![format!("ghcr.io/o/app@{}", digest)];
+        assert!(has_repo_digest(&repo_digests, &digest));
+        assert!(!has_repo_digest(&repo_digests, &other));
+        assert!(!has_repo_digest(&[], &digest));
+    }
+
+    #[test]
+    fn test_assess_container_state() {
+        let state = |json: &str| serde_json::from_str::<ContainerState>(json).unwrap();
+        let running = state(r#"{"Status":"running","Running":true,"ExitCode":0}"#);
+        assert_eq!(assess(&running, 0), Verdict::Up);
+        assert!(matches!(assess(&running, 2), Verdict::Failed(_)));
+
+        let exited = state(r#"{"Status":"exited","Running":false,"ExitCode":137}"#);
+        assert_eq!(
+            assess(&exited, 0),
+            Verdict::Failed("container is exited (exit code 137)".to_string())
+        );
+
+        let health = |status: &str| {
+            state(&format!(
+                r#"{{"Status":"running","Running":true,"Health":{{"Status":"{}"}}}}"#,
+                status
+            ))
+        };
+        assert_eq!(assess(&health("starting"), 0), Verdict::Up);
+        assert_eq!(assess(&health("healthy"), 0), Verdict::Healthy);
+        assert!(matches!(assess(&health("unhealthy"), 0), Verdict::Failed(_)));
+    }
+
+    #[test]
+    fn test_meets_minimum_version() {
+        let status = |v: &str| DockerStatus {
+            installed: true,
+            server_version: Some(v.to_string()),
+            ..Default::default()
+        };
+        assert_eq!(status("24.0.7").meets_minimum_version(), Some(true));
+        assert_eq!(status("20.10.0").meets_minimum_version(), Some(true));
+        assert_eq!(status("19.03.12").meets_minimum_version(), Some(false));
+        assert_eq!(DockerStatus::default().meets_minimum_version(), None);
+    }
+}
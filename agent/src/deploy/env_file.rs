@@ -0,0 +1,142 @@
+//! Per-deployment environment files
+//!
+//! Git and compose deployments often need secrets that should not be
+//! committed to the repository. The backend sends them as an `env_file`
+//! config field holding a base64 `.env` file, which the deployer writes into
+//! the deployment directory (owner-only) before install, run or compose
+//! steps, so `docker-compose` and the app pick it up. Its contents never
+//! appear in logs or error messages. The file is deleted when the deployment
+//! fails, when a redeploy comes without one, and with the deployment
+//! directory.
+
+use std::fmt;
+use std::path::Path;
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use serde_json::Value;
+
+use crate::errors::AgentError;
+use crate::filesys::file::File;
+
+/// Name of the file written into the deployment directory
+pub const ENV_FILE_NAME: &str = ".env";
+
+/// Config field carrying the base64 env file
+pub const ENV_FILE_FIELD: &str = "env_file";
+
+/// A validated environment file
+pub struct EnvFile {
+    contents: Vec<u8>,
+    variables: usize,
+}
+
+impl fmt::Debug for EnvFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EnvFile")
+            .field("variables", &self.variables)
+            .finish_non_exhaustive()
+    }
+}
+
+impl EnvFile {
+    /// The env file of a deployment config, or `None` when it has none
+    pub fn from_config(config: &Value) -> Result<Option<Self>, AgentError> {
+        let Some(encoded) = config.get(ENV_FILE_FIELD).and_then(Value::as_str) else {
+            return Ok(None);
+        };
+        let contents = BASE64
+            .decode(encoded.trim())
+            .map_err(|_| AgentError::ConfigError("env_file is not valid base64".to_string()))?;
+        let variables = validate(&contents)?;
+        Ok(Some(Self {
+            contents,
+            variables,
+        }))
+    }
+
+    /// Number of variables the file sets
+    pub fn variables(&self) -> usize {
+        self.variables
+    }
+
+    /// Write the file into `dir`, readable by its owner only
+    pub async fn write(&self, dir: &Path) -> Result<(), AgentError> {
+        File::new(dir.join(ENV_FILE_NAME)).write_atomic_private(&self.contents).await
+    }
+
+    /// Delete the env file from `dir`, if there is one
+    pub async fn remove(dir: &Path) -> Result<(), AgentError> {
+        File::new(dir.join(ENV_FILE_NAME)).delete().await
+    }
+}
+
+/// Check every line is blank, a `#` comment or `KEY=VALUE` (optionally
+/// prefixed with `export`), and count the variables. Errors name the line,
+/// never its contents.
+fn validate(contents: &[u8]) -> Result<usize, AgentError> {
+    let text = std::str::from_utf8(contents)
+        .map_err(|_| AgentError::ConfigError("env_file is not valid UTF-8".to_string()))?;
+
+    let mut variables = 0;
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").map_or(line, str::trim_start);
+        let valid = line.split_once('=').is_some_and(|(key, _)| is_valid_key(key.trim_end()));
+        if !valid {
+            return Err(AgentError::ConfigError(format!(
+                "env_file line {} is not KEY=VALUE",
+                i + 1
+            )));
+        }
+        variables += 1;
+    }
+    Ok(variables)
+}
+
+/// Shell-style variable name: a letter or `_`, then letters, digits or `_`
+fn is_valid_key(key: &str) -> bool {
+    let mut chars = key.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesys::test_utils::TempFs;
+
+    fn config(contents: &str) -> Value {
+        serde_json::json!({ "env_file": BASE64.encode(contents) })
+    }
+
+    #[tokio::test]
+    async fn test_env_file_is_validated_and_written_private() {
+        let contents = "# db\nDB_URL=postgres://u:p@db/app\n\nexport API_KEY = s3cret\nEMPTY=\n";
+        let env_file = EnvFile::from_config(&config(contents)).unwrap().unwrap();
+        assert_eq!(env_file.variables(), 3);
+        assert!(!format!("{:?}", env_file).contains("s3cret"));
+        assert!(EnvFile::from_config(&serde_json::json!({})).unwrap().is_none());
+
+        let fs = TempFs::new();
+        env_file.write(&fs.path("")).await.unwrap();
+        let path = fs.path(ENV_FILE_NAME);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), contents);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        EnvFile::remove(&fs.path("")).await.unwrap();
+        assert!(!path.exists());
+
+        // Errors point at the line without echoing secrets
+        let err = EnvFile::from_config(&config("OK=1\nsecret-value\n")).unwrap_err();
+        assert_eq!(err.to_string(), "Configuration error: env_file line 2 is not KEY=VALUE");
+        assert!(EnvFile::from_config(&config("1KEY=x")).is_err());
+        assert!(EnvFile::from_config(&serde_json::json!({ "env_file": "%%%" })).is_err());
+    }
+}
@@ -1,35 +1,145 @@
 //! Workflow executor
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::Arc;
+use std::time::Duration;
 
-use tokio::sync::RwLock;
-use tracing::{debug, error, info};
+use futures::stream::{FuturesUnordered, StreamExt};
+use tokio::sync::{watch, RwLock};
+use tracing::{debug, error, info, warn};
 
+use crate::app::watchdog::Heartbeat;
+use crate::deploy::check::cycle_nodes;
 use crate::deploy::fsm::{DeploymentEvent, DeploymentFsm, DeploymentState};
-use crate::deploy::node_runner::{NodeRunner, NodeRunnerFactory};
+use crate::deploy::memo::{is_cacheable, MemoStats, NodeOutputs, NodeResultCache};
+use crate::deploy::node_runner::{NodeContext, NodeRunner, NodeRunnerFactory};
+use crate::deploy::resources::ResourceLocks;
 use crate::errors::AgentError;
-use crate::models::workflow::{ExecutionState, Workflow, WorkflowExecution};
+use crate::filesys::file::File;
+use crate::models::execution::ExecutionResult;
+use crate::models::workflow::{
+    Edge, ExecutionState, Node, NodeExecutionState, Workflow, WorkflowExecution,
+};
+
+/// Tell the watchdog, if the run is supervised, that it will make progress
+/// within `wait`
+fn beat(heartbeat: Option<&Heartbeat>, wait: Duration) {
+    if let Some(heartbeat) = heartbeat {
+        heartbeat.expect_within(wait);
+    }
+}
 
 /// Workflow executor
 pub struct WorkflowExecutor {
     workflow: Workflow,
     fsm: RwLock<DeploymentFsm>,
+    /// The state of `fsm`, published on every transition
+    state_tx: watch::Sender<DeploymentState>,
     node_runners: RwLock<HashMap<String, Arc<dyn NodeRunner>>>,
     execution: RwLock<Option<WorkflowExecution>>,
+    result_cache: Arc<NodeResultCache>,
+    resource_locks: Arc<ResourceLocks>,
+    node_context: NodeContext,
+    max_concurrent_nodes: usize,
+    /// Where the FSM is persisted so a restart resumes it
+    state_file: Option<File>,
 }
 
 impl WorkflowExecutor {
     /// Create a new workflow executor
     pub fn new(workflow: Workflow) -> Self {
+        Self::with_result_cache(workflow, Arc::new(NodeResultCache::default()))
+    }
+
+    /// Create a workflow executor memoizing node results in a shared cache
+    pub fn with_result_cache(workflow: Workflow, result_cache: Arc<NodeResultCache>) -> Self {
+        let fsm = DeploymentFsm::new();
         Self {
             workflow,
-            fsm: RwLock::new(DeploymentFsm::new()),
+            state_tx: watch::channel(fsm.state().clone()).0,
+            fsm: RwLock::new(fsm),
             node_runners: RwLock::new(HashMap::new()),
             execution: RwLock::new(None),
+            result_cache,
+            resource_locks: Arc::new(ResourceLocks::new()),
+            node_context: NodeContext::default(),
+            max_concurrent_nodes: 1,
+            state_file: None,
         }
     }
 
+    /// Run up to `limit` independent nodes at once (default 1, sequential)
+    pub fn with_concurrency(mut self, limit: usize) -> Self {
+        self.max_concurrent_nodes = limit.max(1);
+        self
+    }
+
+    /// Share hardware locks with other executors so their nodes do not clash
+    pub fn with_resource_locks(mut self, resource_locks: Arc<ResourceLocks>) -> Self {
+        self.resource_locks = resource_locks;
+        self
+    }
+
+    /// Give node runners the agent's hardware settings and services
+    pub fn with_node_context(mut self, node_context: NodeContext) -> Self {
+        self.node_context = node_context;
+        self
+    }
+
+    /// Persist the deployment state to `file` on every transition (see
+    /// `restore`)
+    pub fn with_state_file(mut self, file: File) -> Self {
+        self.state_file = Some(file);
+        self
+    }
+
+    /// Restore the deployment state persisted by a previous run and return
+    /// it. A workflow that was deployed, running or paused has lost its node
+    /// runners, so it is deployed again; the registry then starts it again
+    /// if it was running, or starts it paused if it was paused.
+    pub async fn restore(&self) -> Result<Option<DeploymentState>, AgentError> {
+        let Some(file) = &self.state_file else {
+            return Ok(None);
+        };
+        if !file.exists().await {
+            return Ok(None);
+        }
+        let persisted: DeploymentFsm = file.read_json().await?;
+        let state = persisted.state().clone();
+        info!("Restoring workflow {} as {}", self.workflow.name, state.as_str());
+
+        if persisted.needs_runners() {
+            self.deploy().await?;
+        } else {
+            *self.fsm.write().await = persisted;
+            self.state_tx.send_replace(state.clone());
+        }
+        Ok(Some(state))
+    }
+
+    /// Apply `event` and persist the new state. Failing to persist is
+    /// logged; the transition stands.
+    async fn transition(
+        &self,
+        fsm: &mut DeploymentFsm,
+        event: DeploymentEvent,
+    ) -> Result<(), AgentError> {
+        fsm.process(event).map_err(AgentError::DeployError)?;
+        self.state_tx.send_replace(fsm.state().clone());
+        if let Some(file) = &self.state_file {
+            let persisted = serde_json::to_vec(&*fsm)?;
+            if let Err(e) = file.write_atomic(&persisted).await {
+                warn!("Failed to persist the state of workflow {}: {}", self.workflow.name, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Hit/miss counters of the node result cache
+    pub fn result_cache_stats(&self) -> MemoStats {
+        self.result_cache.stats()
+    }
+
     /// Get the workflow
     pub fn workflow(&self) -> &Workflow {
         &self.workflow
@@ -47,23 +157,21 @@ impl WorkflowExecutor {
         // Transition to deploying
         {
             let mut fsm = self.fsm.write().await;
-            fsm.process(DeploymentEvent::Deploy)
-                .map_err(|e| AgentError::DeployError(e))?;
+            self.transition(&mut fsm, DeploymentEvent::Deploy).await?;
         }
 
         // Create node runners
         match self.create_node_runners().await {
             Ok(_) => {
                 let mut fsm = self.fsm.write().await;
-                fsm.process(DeploymentEvent::DeploySuccess)
-                    .map_err(|e| AgentError::DeployError(e))?;
+                self.transition(&mut fsm, DeploymentEvent::DeploySuccess).await?;
                 info!("Workflow deployed successfully: {}", self.workflow.name);
                 Ok(())
             }
             Err(e) => {
                 let mut fsm = self.fsm.write().await;
-                fsm.process(DeploymentEvent::DeployFailed(e.to_string()))
-                    .map_err(|e| AgentError::DeployError(e))?;
+                self.transition(&mut fsm, DeploymentEvent::DeployFailed(e.to_string()))
+                    .await?;
                 Err(e)
             }
         }
@@ -73,8 +181,29 @@ impl WorkflowExecutor {
         let mut runners = self.node_runners.write().await;
         runners.clear();
 
+        // A cycle would leave its nodes waiting forever, so nothing runs
+        let cycle = cycle_nodes(&self.workflow.graph_data);
+        if !cycle.is_empty() {
+            return Err(AgentError::DeployError(format!(
+                "Workflow graph has a cycle through nodes: {}",
+                cycle.join(", ")
+            )));
+        }
+
         for node in &self.workflow.graph_data.nodes {
-            let runner = NodeRunnerFactory::create(node)?;
+            let runner = NodeRunnerFactory::create(node, &self.node_context)?;
+            runner.check_resources().await.map_err(|e| {
+                AgentError::DeployError(format!(
+                    "Node {} ({}) cannot be deployed: {}",
+                    node.id, node.node_type, e
+                ))
+            })?;
+            if is_cacheable(node) && !runner.is_deterministic() {
+                warn!(
+                    "Node {} ({}) has side effects; ignoring its cacheable flag",
+                    node.id, node.node_type
+                );
+            }
             runners.insert(node.id.clone(), runner);
             debug!("Created runner for node: {} ({})", node.id, node.node_type);
         }
@@ -82,15 +211,32 @@ impl WorkflowExecutor {
         Ok(())
     }
 
-    /// Start workflow execution
-    pub async fn start(&self) -> Result<(), AgentError> {
+    /// Start workflow execution. Only the registry starts workflows, so
+    /// that its limit on concurrent executions holds. The run beats
+    /// `heartbeat`, if given, as it makes progress.
+    pub(super) async fn start(&self, heartbeat: Option<&Heartbeat>) -> Result<(), AgentError> {
+        self.begin(false, heartbeat).await
+    }
+
+    /// Start workflow execution paused, as it was before a restart; nothing
+    /// runs until it is resumed
+    pub(super) async fn start_paused(
+        &self,
+        heartbeat: Option<&Heartbeat>,
+    ) -> Result<(), AgentError> {
+        self.begin(true, heartbeat).await
+    }
+
+    async fn begin(&self, paused: bool, heartbeat: Option<&Heartbeat>) -> Result<(), AgentError> {
         info!("Starting workflow: {}", self.workflow.name);
 
         // Transition to running
         {
             let mut fsm = self.fsm.write().await;
-            fsm.process(DeploymentEvent::Start)
-                .map_err(|e| AgentError::DeployError(e))?;
+            self.transition(&mut fsm, DeploymentEvent::Start).await?;
+            if paused {
+                self.transition(&mut fsm, DeploymentEvent::Pause).await?;
+            }
         }
 
         // Create execution context
@@ -98,7 +244,7 @@ impl WorkflowExecutor {
             let mut execution = self.execution.write().await;
             *execution = Some(WorkflowExecution {
                 workflow: self.workflow.clone(),
-                state: ExecutionState::Running,
+                state: if paused { ExecutionState::Paused } else { ExecutionState::Running },
                 started_at: Some(chrono::Utc::now()),
                 finished_at: None,
                 error: None,
@@ -106,26 +252,165 @@ impl WorkflowExecutor {
             });
         }
 
+        if paused && !self.wait_while_paused(heartbeat).await {
+            return Ok(());
+        }
+
         // Start execution loop
-        self.run_execution_loop().await
+        self.run_execution_loop(heartbeat).await
+    }
+
+    /// Wait until the workflow is no longer paused; false if it was stopped
+    /// meanwhile
+    async fn wait_while_paused(&self, heartbeat: Option<&Heartbeat>) -> bool {
+        let mut state = self.state_tx.subscribe();
+        loop {
+            let current = state.borrow_and_update().clone();
+            match current {
+                DeploymentState::Running => return true,
+                DeploymentState::Paused => {}
+                _ => return false,
+            }
+            // A pause may last indefinitely; the run beats again once resumed
+            if let Some(heartbeat) = heartbeat {
+                heartbeat.idle();
+            }
+            if state.changed().await.is_err() {
+                return false;
+            }
+        }
     }
 
-    async fn run_execution_loop(&self) -> Result<(), AgentError> {
-        // This is a simplified execution loop
-        // In production, this would handle message passing between nodes
-        
+    /// Run every node once its upstream nodes have completed, with up to
+    /// `max_concurrent_nodes` running at a time. Each node receives the
+    /// outputs of its upstream nodes as mapped by the edges (see
+    /// `map_inputs`).
+    ///
+    /// A workflow with a streaming node runs again for each item it produces
+    /// until stopped, waiting while paused.
+    async fn run_execution_loop(&self, heartbeat: Option<&Heartbeat>) -> Result<(), AgentError> {
+        let streaming = self.node_runners.read().await.values().any(|r| r.is_streaming());
+        let result = loop {
+            let result = self.run_graph(heartbeat).await;
+            if !streaming {
+                break result;
+            }
+            if !self.wait_while_paused(heartbeat).await {
+                // Stopped meanwhile; a failure then is the stream closing
+                // and `stop` has recorded the outcome
+                return Ok(());
+            }
+            if result.is_err() {
+                break result;
+            }
+        };
+
+        let mut execution = self.execution.write().await;
+        if let Some(ref mut exec) = *execution {
+            match &result {
+                Ok(()) => exec.state = ExecutionState::Completed,
+                Err(e) => {
+                    exec.state = ExecutionState::Error;
+                    exec.error = Some(e.to_string());
+                }
+            }
+            exec.finished_at = Some(chrono::Utc::now());
+        }
+        drop(execution);
+
+        // A run stopped or paused meanwhile keeps the state it was put in
+        let mut fsm = self.fsm.write().await;
+        if *fsm.state() == DeploymentState::Running {
+            let event = match &result {
+                Ok(()) => DeploymentEvent::Complete,
+                Err(e) => DeploymentEvent::Error(e.to_string()),
+            };
+            self.transition(&mut fsm, event).await?;
+        }
+        result
+    }
+
+    async fn run_graph(&self, heartbeat: Option<&Heartbeat>) -> Result<(), AgentError> {
         let runners = self.node_runners.read().await;
-        
-        for (node_id, runner) in runners.iter() {
-            debug!("Executing node: {}", node_id);
-            
-            // Execute node with empty inputs (simplified)
-            match runner.execute(HashMap::new()).await {
+        let nodes = &self.workflow.graph_data.nodes;
+        let known: HashSet<&str> = nodes.iter().map(|n| n.id.as_str()).collect();
+
+        // Count each node's distinct upstream nodes
+        let mut upstream: HashMap<&str, usize> = HashMap::new();
+        let mut downstream: HashMap<&str, Vec<&str>> = HashMap::new();
+        let mut incoming: HashMap<&str, Vec<&Edge>> = HashMap::new();
+        let mut seen = HashSet::new();
+        for edge in &self.workflow.graph_data.edges {
+            let (source, target) = (edge.source.as_str(), edge.target.as_str());
+            if !known.contains(source) || !known.contains(target) {
+                continue;
+            }
+            incoming.entry(target).or_default().push(edge);
+            if !seen.insert((source, target)) {
+                continue;
+            }
+            *upstream.entry(target).or_default() += 1;
+            downstream.entry(source).or_default().push(target);
+        }
+
+        let by_id: HashMap<&str, &Node> = nodes.iter().map(|n| (n.id.as_str(), n)).collect();
+        let mut ready: VecDeque<&Node> = nodes
+            .iter()
+            .filter(|n| !upstream.contains_key(n.id.as_str()))
+            .collect();
+        let mut outputs_by_node: HashMap<&str, NodeOutputs> = HashMap::new();
+        let mut running = FuturesUnordered::new();
+        // How long each running node may take
+        let mut budgets: HashMap<&str, Duration> = HashMap::new();
+
+        loop {
+            while running.len() < self.max_concurrent_nodes {
+                let Some(node) = ready.pop_front() else {
+                    break;
+                };
+                let inputs = map_inputs(
+                    incoming.get(node.id.as_str()).map_or(&[][..], Vec::as_slice),
+                    &outputs_by_node,
+                );
+                self.record_node_state(node, ExecutionState::Running, None, None).await;
+                let runner = runners.get(&node.id).cloned();
+                let budget = runner.as_ref().map_or(Duration::ZERO, |r| r.max_duration());
+                budgets.insert(node.id.as_str(), budget);
+                running.push(async move {
+                    let result = match runner {
+                        Some(runner) => self.run_node(node, runner.as_ref(), inputs).await,
+                        None => Ok(inputs),
+                    };
+                    (node, result)
+                });
+            }
+
+            // Progress is due by the time the slowest running node may take
+            beat(heartbeat, budgets.values().max().copied().unwrap_or_default());
+            let Some((node, result)) = running.next().await else {
+                break;
+            };
+            budgets.remove(node.id.as_str());
+            match result {
                 Ok(outputs) => {
-                    debug!("Node {} completed with {} outputs", node_id, outputs.len());
+                    debug!("Node {} completed with {} outputs", node.id, outputs.len());
+                    let recorded = serde_json::Value::Object(outputs.clone().into_iter().collect());
+                    self.record_node_state(node, ExecutionState::Completed, Some(recorded), None)
+                        .await;
+                    outputs_by_node.insert(node.id.as_str(), outputs);
+                    for target in downstream.get(node.id.as_str()).into_iter().flatten() {
+                        let remaining = upstream.entry(target).or_default();
+                        *remaining -= 1;
+                        if *remaining == 0 {
+                            ready.push_back(by_id[target]);
+                        }
+                    }
                 }
                 Err(e) => {
-                    error!("Node {} failed: {}", node_id, e);
+                    // Dropping `running` cancels the sibling nodes
+                    error!("Node {} failed: {}", node.id, e);
+                    self.record_node_state(node, ExecutionState::Error, None, Some(e.to_string()))
+                        .await;
                     return Err(e);
                 }
             }
@@ -134,15 +419,81 @@ impl WorkflowExecutor {
         Ok(())
     }
 
+    async fn record_node_state(
+        &self,
+        node: &Node,
+        state: ExecutionState,
+        outputs: Option<serde_json::Value>,
+        error: Option<String>,
+    ) {
+        let now = chrono::Utc::now();
+        let mut execution = self.execution.write().await;
+        if let Some(ref mut exec) = *execution {
+            let started_at = match state {
+                ExecutionState::Running => Some(now),
+                _ => exec.node_states.get(&node.id).and_then(|s| s.started_at),
+            };
+            let finished_at = (state != ExecutionState::Running).then_some(now);
+            exec.node_states.insert(
+                node.id.clone(),
+                NodeExecutionState {
+                    node_id: node.id.clone(),
+                    state,
+                    outputs,
+                    error,
+                    started_at,
+                    finished_at,
+                },
+            );
+        }
+    }
+
+    /// Execute a node while holding the locks of the hardware it uses
+    async fn run_node(
+        &self,
+        node: &Node,
+        runner: &dyn NodeRunner,
+        inputs: NodeOutputs,
+    ) -> Result<NodeOutputs, AgentError> {
+        debug!("Executing node: {}", node.id);
+        let _guards = self.resource_locks.acquire(&runner.resources()).await;
+        self.execute_node(node, runner, inputs).await
+    }
+
+    /// Execute a node, serving deterministic cacheable nodes from the result cache
+    async fn execute_node(
+        &self,
+        node: &Node,
+        runner: &dyn NodeRunner,
+        inputs: NodeOutputs,
+    ) -> Result<NodeOutputs, AgentError> {
+        if !(is_cacheable(node) && runner.is_deterministic()) {
+            return runner.execute(inputs).await;
+        }
+
+        let key = NodeResultCache::key(node, &inputs);
+        if let Some(outputs) = self.result_cache.get(&key) {
+            debug!("Node {} served from the result cache", node.id);
+            return Ok(outputs);
+        }
+
+        let outputs = runner.execute(inputs).await?;
+        self.result_cache.insert(key, outputs.clone());
+        Ok(outputs)
+    }
+
     /// Stop workflow execution
     pub async fn stop(&self) -> Result<(), AgentError> {
         info!("Stopping workflow: {}", self.workflow.name);
 
+        // An invalid transition is rejected before touching the runners.
+        // The runners stop after the lock is released, so reading the state
+        // meanwhile does not wait on a slow runner.
         {
             let mut fsm = self.fsm.write().await;
-            fsm.process(DeploymentEvent::Stop)
-                .map_err(|e| AgentError::DeployError(e))?;
+            self.transition(&mut fsm, DeploymentEvent::Stop).await?;
         }
+        self.stop_node_runners().await;
 
         // Update execution state
         {
@@ -156,22 +507,33 @@ impl WorkflowExecutor {
         Ok(())
     }
 
+    /// Stop every node runner concurrently so they release what they hold.
+    /// A runner that fails to stop is logged and does not keep the others
+    /// from stopping.
+    async fn stop_node_runners(&self) {
+        let runners = self.node_runners.read().await;
+        let results = futures::future::join_all(
+            runners
+                .iter()
+                .map(|(id, runner)| async move { (id, runner.stop().await) }),
+        )
+        .await;
+        for (id, result) in results {
+            if let Err(e) = result {
+                error!("Failed to stop node {}: {}", id, e);
+            }
+        }
+    }
+
     /// Pause workflow execution
     pub async fn pause(&self) -> Result<(), AgentError> {
         info!("Pausing workflow: {}", self.workflow.name);
 
-        {
-            let mut fsm = self.fsm.write().await;
-            fsm.process(DeploymentEvent::Pause)
-                .map_err(|e| AgentError::DeployError(e))?;
-        }
-
-        // Update execution state
-        {
-            let mut execution = self.execution.write().await;
-            if let Some(ref mut exec) = *execution {
-                exec.state = ExecutionState::Paused;
-            }
+        let mut fsm = self.fsm.write().await;
+        let mut execution = self.execution.write().await;
+        self.transition(&mut fsm, DeploymentEvent::Pause).await?;
+        if let Some(ref mut exec) = *execution {
+            exec.state = ExecutionState::Paused;
         }
 
         Ok(())
@@ -181,18 +543,13 @@ impl WorkflowExecutor {
     pub async fn resume(&self) -> Result<(), AgentError> {
         info!("Resuming workflow: {}", self.workflow.name);
 
-        {
-            let mut fsm = self.fsm.write().await;
-            fsm.process(DeploymentEvent::Resume)
-                .map_err(|e| AgentError::DeployError(e))?;
-        }
-
-        // Update execution state
-        {
-            let mut execution = self.execution.write().await;
-            if let Some(ref mut exec) = *execution {
-                exec.state = ExecutionState::Running;
-            }
+        // Both locks are held so the run cannot record its outcome between
+        // the transition and the execution state
+        let mut fsm = self.fsm.write().await;
+        let mut execution = self.execution.write().await;
+        self.transition(&mut fsm, DeploymentEvent::Resume).await?;
+        if let Some(ref mut exec) = *execution {
+            exec.state = ExecutionState::Running;
         }
 
         Ok(())
@@ -202,4 +559,411 @@ impl WorkflowExecutor {
     pub async fn get_execution(&self) -> Option<WorkflowExecution> {
         self.execution.read().await.clone()
     }
+
+    /// Execution status in the form reported to the backend
+    pub async fn execution_result(&self) -> Option<ExecutionResult> {
+        self.execution.read().await.as_ref().map(ExecutionResult::from_execution)
+    }
+}
+
+/// Inputs of a node from the outputs of its upstream nodes, edge by edge:
+/// an edge with a `sourceHandle` carries that output only, one without
+/// carries every output. A `targetHandle` names the input it lands on; without
+/// one the outputs keep their names. Later edges win on conflicts.
+fn map_inputs(incoming: &[&Edge], outputs_by_node: &HashMap<&str, NodeOutputs>) -> NodeOutputs {
+    let mut inputs = NodeOutputs::new();
+    for edge in incoming {
+        let Some(outputs) = outputs_by_node.get(edge.source.as_str()) else {
+            continue;
+        };
+        match (&edge.source_handle, &edge.target_handle) {
+            (Some(source), target) => {
+                if let Some(value) = outputs.get(source) {
+                    inputs.insert(target.as_ref().unwrap_or(source).clone(), value.clone());
+                }
+            }
+            (None, Some(target)) => {
+                let all = serde_json::Value::Object(outputs.clone().into_iter().collect());
+                inputs.insert(target.clone(), all);
+            }
+            (None, None) => inputs.extend(outputs.clone()),
+        }
+    }
+    inputs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::test_utils::{edge, node, workflow_with_graph};
+    use std::time::{Duration, Instant};
+
+    fn delays(count: usize, delay_ms: u64) -> Vec<Node> {
+        (0..count)
+            .map(|i| node(&format!("d{}", i), "delay", serde_json::json!({ "delay_ms": delay_ms })))
+            .collect()
+    }
+
+    fn workflow(nodes: Vec<Node>) -> Workflow {
+        workflow_with_graph("wf", nodes, vec![])
+    }
+
+    async fn run(executor: &WorkflowExecutor) -> Result<Duration, AgentError> {
+        executor.deploy().await?;
+        let started = Instant::now();
+        executor.start(None).await?;
+        Ok(started.elapsed())
+    }
+
+    #[tokio::test]
+    async fn test_only_deterministic_cacheable_nodes_are_memoized() {
+        let nodes = vec![
+            node("transform", "transform", serde_json::json!({ "cacheable": true })),
+            node("uncached", "transform", serde_json::json!({})),
+            node("log", "log", serde_json::json!({ "cacheable": true })),
+        ];
+        let executor = WorkflowExecutor::new(workflow(nodes.clone()));
+        let inputs = HashMap::from([("x".to_string(), serde_json::Value::from(1))]);
+
+        for _ in 0..2 {
+            for node in &nodes {
+                let runner = NodeRunnerFactory::create(node, &NodeContext::default()).unwrap();
+                executor
+                    .execute_node(node, runner.as_ref(), inputs.clone())
+                    .await
+                    .unwrap();
+            }
+        }
+
+        assert_eq!(
+            executor.result_cache_stats(),
+            MemoStats {
+                hits: 1,
+                misses: 1,
+                entries: 1
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_independent_nodes_run_concurrently_up_to_limit() {
+        let sequential = WorkflowExecutor::new(workflow(delays(3, 100)));
+        assert!(run(&sequential).await.unwrap() >= Duration::from_millis(300));
+
+        let concurrent = WorkflowExecutor::new(workflow(delays(3, 100))).with_concurrency(3);
+        assert!(run(&concurrent).await.unwrap() < Duration::from_millis(250));
+
+        // A chain cannot be parallelized
+        let chain = WorkflowExecutor::new(workflow_with_graph(
+            "wf",
+            delays(3, 100),
+            vec![edge("d0", "d1"), edge("d1", "d2")],
+        ))
+        .with_concurrency(3);
+        assert!(run(&chain).await.unwrap() >= Duration::from_millis(300));
+    }
+
+    #[tokio::test]
+    async fn test_cycle_is_rejected_at_deploy() {
+        let executor = WorkflowExecutor::new(workflow_with_graph(
+            "wf",
+            delays(4, 0),
+            vec![edge("d0", "d1"), edge("d1", "d2"), edge("d2", "d1"), edge("d2", "d3")],
+        ));
+        let err = executor.deploy().await.unwrap_err();
+        // Only the nodes on the cycle are named, not those up- or downstream
+        assert!(matches!(err, AgentError::DeployError(msg) if msg.ends_with("nodes: d1, d2")));
+        assert_eq!(executor.state().await, DeploymentState::Failed);
+        assert!(executor.start(None).await.is_err());
+        assert!(executor.get_execution().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_paused_workflow_waits_for_resume_or_stop() {
+        let paused = |executor: &WorkflowExecutor| {
+            let mut state = executor.state_tx.subscribe();
+            async move {
+                state.wait_for(|s| *s == DeploymentState::Paused).await.unwrap();
+            }
+        };
+
+        let resumed = WorkflowExecutor::new(workflow(delays(1, 0)));
+        resumed.deploy().await.unwrap();
+        let (started, _) = tokio::join!(resumed.start_paused(None), async {
+            paused(&resumed).await;
+            assert!(resumed.get_execution().await.unwrap().node_states.is_empty());
+            resumed.resume().await.unwrap();
+        });
+        started.unwrap();
+        assert_eq!(resumed.get_execution().await.unwrap().state, ExecutionState::Completed);
+
+        let stopped = WorkflowExecutor::new(workflow(delays(1, 0)));
+        stopped.deploy().await.unwrap();
+        let (started, _) = tokio::join!(stopped.start_paused(None), async {
+            paused(&stopped).await;
+            stopped.stop().await.unwrap();
+        });
+        started.unwrap();
+        let execution = stopped.get_execution().await.unwrap();
+        assert_eq!(execution.state, ExecutionState::Cancelled);
+        assert!(execution.node_states.is_empty());
+    }
+
+    #[test]
+    fn test_edges_map_upstream_outputs_to_inputs() {
+        let mut outputs_by_node = HashMap::new();
+        outputs_by_node.insert(
+            "camera",
+            NodeOutputs::from([
+                ("frame".to_string(), serde_json::json!("jpeg")),
+                ("width".to_string(), serde_json::json!(640)),
+            ]),
+        );
+        outputs_by_node.insert(
+            "sensor",
+            NodeOutputs::from([("value".to_string(), serde_json::json!(true))]),
+        );
+        let handle = |source: &str, source_handle: Option<&str>, target_handle: Option<&str>| {
+            Edge {
+                source_handle: source_handle.map(str::to_string),
+                target_handle: target_handle.map(str::to_string),
+                ..edge(source, "target")
+            }
+        };
+        let edges = [
+            handle("camera", Some("frame"), Some("image")),
+            handle("camera", Some("missing"), Some("ignored")),
+            handle("camera", None, Some("camera")),
+            handle("sensor", None, None),
+            handle("pending", None, None),
+        ];
+
+        let inputs = map_inputs(&edges.iter().collect::<Vec<_>>(), &outputs_by_node);
+        assert_eq!(inputs.len(), 3);
+        assert_eq!(inputs["image"], "jpeg");
+        assert_eq!(inputs["camera"]["width"], 640);
+        assert_eq!(inputs["value"], true);
+    }
+
+    #[tokio::test]
+    async fn test_node_states_are_recorded() {
+        let executor = WorkflowExecutor::new(workflow_with_graph(
+            "wf",
+            delays(2, 0),
+            vec![edge("d0", "d1")],
+        ));
+        run(&executor).await.unwrap();
+
+        let execution = executor.get_execution().await.unwrap();
+        assert_eq!(execution.state, ExecutionState::Completed);
+        assert_eq!(executor.state().await, DeploymentState::Deployed);
+        assert_eq!(execution.node_states.len(), 2);
+        for state in execution.node_states.values() {
+            assert_eq!(state.state, ExecutionState::Completed);
+            assert_eq!(state.outputs, Some(serde_json::json!({})));
+            assert!(state.finished_at >= state.started_at);
+        }
+        let result = executor.execution_result().await.unwrap();
+        assert_eq!(result.status, ExecutionState::Completed);
+        assert_eq!(
+            result.nodes.iter().map(|n| n.node_id.as_str()).collect::<Vec<_>>(),
+            ["d0", "d1"]
+        );
+
+        let failing = WorkflowExecutor::new(workflow(vec![node(
+            "http",
+            "http_request",
+            serde_json::json!({ "url": "http://127.0.0.1:1/", "timeout_ms": 500 }),
+        )]));
+        assert!(run(&failing).await.is_err());
+        let execution = failing.get_execution().await.unwrap();
+        assert_eq!(execution.state, ExecutionState::Error);
+        assert!(execution.node_states["http"].error.is_some());
+        assert_eq!(failing.state().await, DeploymentState::Failed);
+    }
+
+    struct SlowStop {
+        stopped: Arc<std::sync::atomic::AtomicUsize>,
+        fail: bool,
+    }
+
+    #[async_trait::async_trait]
+    impl NodeRunner for SlowStop {
+        async fn execute(&self, inputs: NodeOutputs) -> Result<NodeOutputs, AgentError> {
+            Ok(inputs)
+        }
+
+        fn node_type(&self) -> &str {
+            "slow_stop"
+        }
+
+        async fn stop(&self) -> Result<(), AgentError> {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            self.stopped.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            if self.fail {
+                return Err(AgentError::HardwareError("stuck".to_string()));
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stop_stops_every_runner_concurrently() {
+        let executor = Arc::new(WorkflowExecutor::new(workflow(delays(3, 0))));
+        // Stopping before running is rejected without touching the runners
+        assert!(executor.stop().await.is_err());
+
+        run(&executor).await.unwrap();
+        let stopped = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        {
+            let mut runners = executor.node_runners.write().await;
+            for (i, runner) in runners.values_mut().enumerate() {
+                *runner = Arc::new(SlowStop {
+                    stopped: stopped.clone(),
+                    fail: i == 0,
+                });
+            }
+        }
+
+        let started = Instant::now();
+        let stopping = tokio::spawn({
+            let executor = executor.clone();
+            async move { executor.stop().await }
+        });
+        // The state can be read while the runners are still stopping
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let state = tokio::time::timeout(Duration::from_millis(50), executor.state()).await;
+        assert_eq!(state.unwrap(), DeploymentState::Stopped);
+        stopping.await.unwrap().unwrap();
+        assert!(started.elapsed() < Duration::from_millis(250));
+        assert_eq!(stopped.load(std::sync::atomic::Ordering::SeqCst), 3);
+        assert_eq!(executor.state().await, DeploymentState::Stopped);
+    }
+
+    /// Emits an increasing counter every 10ms
+    struct Counter(std::sync::atomic::AtomicUsize);
+
+    #[async_trait::async_trait]
+    impl NodeRunner for Counter {
+        async fn execute(&self, _inputs: NodeOutputs) -> Result<NodeOutputs, AgentError> {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            let n = self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(NodeOutputs::from([("n".to_string(), serde_json::Value::from(n))]))
+        }
+
+        fn node_type(&self) -> &str {
+            "counter"
+        }
+
+        fn is_streaming(&self) -> bool {
+            true
+        }
+    }
+
+    #[tokio::test]
+    async fn test_streaming_workflow_runs_per_item_until_stopped() {
+        let executor = Arc::new(WorkflowExecutor::new(workflow_with_graph(
+            "wf",
+            vec![node("source", "counter", serde_json::json!({})), delays(1, 0).remove(0)],
+            vec![edge("source", "d0")],
+        )));
+        executor.deploy().await.unwrap();
+        executor
+            .node_runners
+            .write()
+            .await
+            .insert("source".to_string(), Arc::new(Counter(Default::default())));
+
+        let running = tokio::spawn({
+            let executor = executor.clone();
+            async move { executor.start(None).await }
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        executor.stop().await.unwrap();
+        running.await.unwrap().unwrap();
+
+        let execution = executor.get_execution().await.unwrap();
+        assert_eq!(execution.state, ExecutionState::Cancelled);
+        let last = execution.node_states["d0"].outputs.clone().unwrap();
+        assert!(last["n"].as_u64().unwrap() >= 2);
+    }
+
+    /// Takes 150ms, whatever it claims
+    struct Sluggish(Duration);
+
+    #[async_trait::async_trait]
+    impl NodeRunner for Sluggish {
+        async fn execute(&self, inputs: NodeOutputs) -> Result<NodeOutputs, AgentError> {
+            tokio::time::sleep(Duration::from_millis(150)).await;
+            Ok(inputs)
+        }
+
+        fn node_type(&self) -> &str {
+            "sluggish"
+        }
+
+        fn max_duration(&self) -> Duration {
+            self.0
+        }
+    }
+
+    #[tokio::test]
+    async fn test_nodes_overrunning_their_budget_stall_the_watchdog() {
+        use crate::app::watchdog::{Watchdog, WatchdogOptions};
+
+        let watchdog = Watchdog::new(WatchdogOptions {
+            stall_timeout: Duration::from_millis(30),
+            ..Default::default()
+        });
+        for (budget, stalls) in [(Duration::from_secs(1), false), (Duration::ZERO, true)] {
+            let executor = WorkflowExecutor::new(workflow(delays(1, 0)));
+            executor.deploy().await.unwrap();
+            executor
+                .node_runners
+                .write()
+                .await
+                .insert("d0".to_string(), Arc::new(Sluggish(budget)));
+
+            let heartbeat = watchdog.register("workflow wf");
+            let running = executor.start(Some(&heartbeat));
+            let check = async {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                watchdog.stalled()
+            };
+            let (result, stalled) = tokio::join!(running, check);
+            result.unwrap();
+            assert_eq!(!stalled.is_empty(), stalls, "budget {:?}", budget);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_state_is_persisted_and_restored() {
+        let fs = crate::filesys::test_utils::TempFs::new();
+        let layout = crate::storage::layout::StorageLayout::new(fs.path(""));
+        let executor = |nodes| {
+            WorkflowExecutor::new(workflow(nodes)).with_state_file(layout.workflow_state_file("wf"))
+        };
+
+        // Nothing persisted yet
+        assert_eq!(executor(delays(1, 0)).restore().await.unwrap(), None);
+
+        let first = executor(delays(1, 0));
+        run(&first).await.unwrap();
+        let persisted = fs.path("deployments/wf/state.json");
+        assert!(std::fs::read_to_string(&persisted).unwrap().contains("deployed"));
+
+        // A deployed workflow is deployed again on restore
+        let restarted = executor(delays(1, 0));
+        assert_eq!(restarted.restore().await.unwrap(), Some(DeploymentState::Deployed));
+        assert_eq!(restarted.state().await, DeploymentState::Deployed);
+        assert_eq!(restarted.node_runners.read().await.len(), 1);
+
+        // A failed deployment is restored as it was
+        let failing = executor(vec![node("cam", "camera", serde_json::json!({ "bogus": 1 }))]);
+        assert!(failing.deploy().await.is_err());
+        let restarted = executor(delays(1, 0));
+        assert_eq!(restarted.restore().await.unwrap(), Some(DeploymentState::Failed));
+        assert_eq!(restarted.state().await, DeploymentState::Failed);
+        assert_eq!(restarted.fsm.read().await.retry_count(), 1);
+    }
 }
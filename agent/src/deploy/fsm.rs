@@ -9,23 +9,35 @@ pub struct FsmSettings {
     /// Timeout for deployment operations
     pub deployment_timeout: Duration,
 
-    /// Retry count for failed deployments
+    /// Retries of a failed deployment after its first attempt
     pub retry_count: u32,
 
     /// Delay between retries
     pub retry_delay: Duration,
+
+    /// Upper bound of the random delay added to `retry_delay`, so devices
+    /// that failed together do not all retry at the same moment
+    pub retry_jitter: Duration,
 }
 
 impl Default for FsmSettings {
     fn default() -> Self {
         Self {
             deployment_timeout: Duration::from_secs(60),
-            retry_count: 3,
+            retry_count: 2,
             retry_delay: Duration::from_secs(5),
+            retry_jitter: Duration::from_secs(5),
         }
     }
 }
 
+impl FsmSettings {
+    /// Delay before the next retry, in [retry_delay, retry_delay + retry_jitter)
+    pub fn next_retry_delay(&self) -> Duration {
+        self.retry_delay + crate::utils::jitter(self.retry_jitter)
+    }
+}
+
 /// Deployment state
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
@@ -52,6 +64,32 @@ pub enum DeploymentState {
     Stopped,
 }
 
+impl DeploymentState {
+    /// State name as reported by the local API
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeploymentState::Pending => "pending",
+            DeploymentState::Deploying => "deploying",
+            DeploymentState::Deployed => "deployed",
+            DeploymentState::Running => "running",
+            DeploymentState::Paused => "paused",
+            DeploymentState::Failed => "failed",
+            DeploymentState::Stopped => "stopped",
+        }
+    }
+
+    /// Deployment still uses its files: being deployed, deployed or running
+    pub fn is_active(&self) -> bool {
+        matches!(
+            self,
+            DeploymentState::Deploying
+                | DeploymentState::Deployed
+                | DeploymentState::Running
+                | DeploymentState::Paused
+        )
+    }
+}
+
 /// Deployment event
 #[derive(Debug, Clone)]
 pub enum DeploymentEvent {
@@ -87,7 +125,7 @@ pub enum DeploymentEvent {
 }
 
 /// Deployment FSM
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DeploymentFsm {
     state: DeploymentState,
     error: Option<String>,
@@ -142,6 +180,7 @@ impl DeploymentFsm {
             // From Deployed
             (DeploymentState::Deployed, DeploymentEvent::Start) => DeploymentState::Running,
             (DeploymentState::Deployed, DeploymentEvent::Deploy) => DeploymentState::Deploying,
+            (DeploymentState::Deployed, DeploymentEvent::Stop) => DeploymentState::Stopped,
 
             // From Running
             (DeploymentState::Running, DeploymentEvent::Pause) => DeploymentState::Paused,
@@ -188,6 +227,18 @@ impl DeploymentFsm {
         Ok(())
     }
 
+    /// Whether the state only holds while node runners exist, so it cannot
+    /// be resumed after a restart without deploying again
+    pub fn needs_runners(&self) -> bool {
+        matches!(
+            self.state,
+            DeploymentState::Deploying
+                | DeploymentState::Deployed
+                | DeploymentState::Running
+                | DeploymentState::Paused
+        )
+    }
+
     /// Check if deployment can be retried
     pub fn can_retry(&self, max_retries: u32) -> bool {
         self.state == DeploymentState::Failed && self.retry_count < max_retries
@@ -240,4 +291,24 @@ mod tests {
         assert_eq!(fsm.error(), Some("test error"));
         assert_eq!(fsm.retry_count(), 1);
     }
+
+    #[test]
+    fn test_retry_delay_jitter_stays_within_bounds() {
+        let settings = FsmSettings {
+            retry_delay: Duration::from_secs(5),
+            retry_jitter: Duration::from_millis(50),
+            ..Default::default()
+        };
+        for _ in 0..100 {
+            let delay = settings.next_retry_delay();
+            assert!(delay >= settings.retry_delay);
+            assert!(delay < settings.retry_delay + settings.retry_jitter);
+        }
+
+        let no_jitter = FsmSettings {
+            retry_jitter: Duration::ZERO,
+            ..settings
+        };
+        assert_eq!(no_jitter.next_retry_delay(), no_jitter.retry_delay);
+    }
 }
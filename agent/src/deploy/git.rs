@@ -3,6 +3,9 @@
 use std::path::Path;
 use tokio::process::Command;
 use tracing::{info, debug};
+use crate::deploy::env_file::EnvFile;
+use crate::deploy::limits::ResourceLimits;
+use crate::deploy::output::{run_logged, LogSink};
 use crate::errors::AgentError;
 
 /// Validate that a shell command string does not contain metacharacters that could
@@ -19,11 +22,12 @@ fn validate_shell_command(cmd: &str, field: &str) -> Result<(), AgentError> {
     Ok(())
 }
 
-/// Sync a git repository (clone or pull)
+/// Sync a git repository (clone or pull), passing git's output to `log`
 pub async fn sync_repository(
     repo_url: &str,
     branch: &str,
-    target_dir: &str
+    target_dir: &str,
+    log: &LogSink,
 ) -> Result<(), AgentError> {
     info!("Syncing Git repository: {} (branch: {}) to {}", repo_url, branch, target_dir);
 
@@ -32,11 +36,11 @@ pub async fn sync_repository(
     // Clone or Pull
     if path.exists() {
         debug!("Target directory exists, pulling updates...");
-        let status = Command::new("git")
-            .current_dir(path)
-            .args(["pull", "origin", branch])
-            .status()
-            .await
+        let status = run_logged(
+            Command::new("git").current_dir(path).args(["pull", "origin", branch]),
+            log,
+        )
+        .await
             .map_err(|e| AgentError::DeployError(format!("Failed to run git pull: {}", e)))?;
         
         if !status.success() {
@@ -44,10 +48,11 @@ pub async fn sync_repository(
         }
     } else {
         debug!("Cloning repository to {}...", target_dir);
-        let status = Command::new("git")
-            .args(["clone", "-b", branch, repo_url, target_dir])
-            .status()
-            .await
+        let status = run_logged(
+            Command::new("git").args(["clone", "-b", branch, repo_url, target_dir]),
+            log,
+        )
+        .await
             .map_err(|e| AgentError::DeployError(format!("Failed to run git clone: {}", e)))?;
         
         if !status.success() {
@@ -59,13 +64,27 @@ pub async fn sync_repository(
     Ok(())
 }
 
+/// Repository to deploy and how to run it
+pub struct GitDeployment<'a> {
+    pub repo_url: &'a str,
+    pub branch: &'a str,
+    pub install_cmd: &'a str,
+    pub run_cmd: &'a str,
+    pub target_dir: &'a str,
+    pub env_file: Option<&'a EnvFile>,
+}
+
+/// Clone or update a repository, write its env file if there is one, then
+/// run the install and run commands within `limits`, as workload `name`.
+/// Output of git and the install command goes to `log`.
 pub async fn deploy_git(
-    repo_url: &str, 
-    branch: &str, 
-    install_cmd: &str, 
-    run_cmd: &str,
-    target_dir: &str
+    name: &str,
+    deployment: &GitDeployment<'_>,
+    limits: &ResourceLimits,
+    log: &LogSink,
 ) -> Result<(), AgentError> {
+    let GitDeployment { repo_url, branch, install_cmd, run_cmd, target_dir, env_file } =
+        *deployment;
     info!("Deploying Git repository: {} (branch: {})", repo_url, branch);
 
     let path = Path::new(target_dir);
@@ -73,11 +92,11 @@ pub async fn deploy_git(
     // 1. Clone or Pull
     if path.exists() {
         debug!("Target directory exists, pulling updates...");
-        let status = Command::new("git")
-            .current_dir(path)
-            .args(["pull", "origin", branch])
-            .status()
-            .await
+        let status = run_logged(
+            Command::new("git").current_dir(path).args(["pull", "origin", branch]),
+            log,
+        )
+        .await
             .map_err(|e| AgentError::DeployError(format!("Failed to run git pull: {}", e)))?;
         
         if !status.success() {
@@ -85,10 +104,11 @@ pub async fn deploy_git(
         }
     } else {
         debug!("Cloning repository to {}...", target_dir);
-        let status = Command::new("git")
-            .args(["clone", "-b", branch, repo_url, target_dir])
-            .status()
-            .await
+        let status = run_logged(
+            Command::new("git").args(["clone", "-b", branch, repo_url, target_dir]),
+            log,
+        )
+        .await
             .map_err(|e| AgentError::DeployError(format!("Failed to run git clone: {}", e)))?;
         
         if !status.success() {
@@ -96,15 +116,23 @@ pub async fn deploy_git(
         }
     }
 
+    // A redeploy without an env file must not leave the previous one behind
+    match env_file {
+        Some(env_file) => {
+            debug!("Writing environment file ({} variables)", env_file.variables());
+            env_file.write(path).await?;
+        }
+        None => EnvFile::remove(path).await?,
+    }
+
     // 2. Install dependencies
     if !install_cmd.is_empty() {
         validate_shell_command(install_cmd, "install_cmd")?;
         info!("Running install command");
-        let status = Command::new("bash")
-            .current_dir(path)
-            .args(["-c", install_cmd])
-            .status()
-            .await
+        let mut command = limits.command("bash", name);
+        command.current_dir(path).args(["-c", install_cmd]);
+        let status = run_logged(&mut command, log)
+        .await
             .map_err(|e| AgentError::DeployError(format!("Failed to run install command: {}", e)))?;
 
         if !status.success() {
@@ -118,11 +146,9 @@ pub async fn deploy_git(
         info!("Starting application");
         // Note: In production, this should be managed by a process supervisor
         let cmd = format!("nohup {} > app.log 2>&1 &", run_cmd);
-        let _ = Command::new("bash")
-            .current_dir(path)
-            .args(["-c", &cmd])
-            .status()
-            .await;
+        let mut command = limits.command("bash", name);
+        command.current_dir(path).args(["-c", &cmd]);
+        let _ = command.status().await;
     }
 
     info!("Successfully deployed Git repository");
@@ -0,0 +1,237 @@
+//! Persistent record of processed deployments
+//!
+//! The deployer records every deployment it starts and how it ended, so a
+//! deployment that already ran is not executed again after a crash or restart
+//! (e.g. when the agent died before reporting the final status).
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::warn;
+
+use crate::errors::AgentError;
+use crate::filesys::file::File;
+use crate::models::deployment::Deployment;
+
+/// Maximum number of deployments remembered
+const MAX_ENTRIES: usize = 500;
+
+/// Outcome of a processed deployment
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeploymentOutcome {
+    /// Execution started but no final status was recorded
+    InProgress,
+    Success,
+    Failed,
+}
+
+impl DeploymentOutcome {
+    /// Status string reported to the backend
+    pub fn as_status(&self) -> &'static str {
+        match self {
+            DeploymentOutcome::InProgress => "in_progress",
+            DeploymentOutcome::Success => "success",
+            DeploymentOutcome::Failed => "failed",
+        }
+    }
+
+    pub fn is_terminal(&self) -> bool {
+        !matches!(self, DeploymentOutcome::InProgress)
+    }
+
+    /// Whether the deployment may have something running: it is executing,
+    /// or it succeeded and its containers are left up
+    pub fn is_active(&self) -> bool {
+        !matches!(self, DeploymentOutcome::Failed)
+    }
+}
+
+/// A recorded deployment
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LedgerEntry {
+    pub outcome: DeploymentOutcome,
+
+    /// Error message of a failed deployment
+    #[serde(default)]
+    pub error_message: Option<String>,
+
+    /// Last update (Unix epoch seconds)
+    pub updated_at: u64,
+
+    /// Container the deployment runs in, for Docker deployments
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub container: Option<String>,
+}
+
+/// Deployment ledger backed by a JSON file
+pub struct DeploymentLedger {
+    file: File,
+    entries: Mutex<HashMap<String, LedgerEntry>>,
+}
+
+impl DeploymentLedger {
+    /// Load the ledger, starting empty if the file is missing or unreadable
+    pub async fn load(file: File) -> Self {
+        let entries = if file.exists().await {
+            match file.read_json::<HashMap<String, LedgerEntry>>().await {
+                Ok(entries) => entries,
+                Err(e) => {
+                    warn!("Ignoring unreadable deployment ledger {:?}: {}", file.path(), e);
+                    HashMap::new()
+                }
+            }
+        } else {
+            HashMap::new()
+        };
+
+        Self {
+            file,
+            entries: Mutex::new(entries),
+        }
+    }
+
+    /// Get the recorded entry of a deployment
+    pub async fn get(&self, deployment_id: &str) -> Option<LedgerEntry> {
+        self.entries.lock().await.get(deployment_id).cloned()
+    }
+
+    /// The terminal entry of a deployment that must not run again, or `None`
+    /// if it should be executed. Deployments with `"force": true` in their
+    /// config are always executed.
+    pub async fn completed(&self, deployment: &Deployment) -> Option<LedgerEntry> {
+        let force = deployment
+            .config
+            .get("force")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+        if force {
+            return None;
+        }
+        self.get(&deployment.id)
+            .await
+            .filter(|entry| entry.outcome.is_terminal())
+    }
+
+    /// Container recorded for a deployment
+    pub async fn container(&self, deployment_id: &str) -> Option<String> {
+        self.get(deployment_id).await.and_then(|entry| entry.container)
+    }
+
+    /// Record the container a deployment runs in and persist the ledger
+    pub async fn set_container(
+        &self,
+        deployment_id: &str,
+        container: &str,
+    ) -> Result<(), AgentError> {
+        let mut entries = self.entries.lock().await;
+        let entry = entries.entry(deployment_id.to_string()).or_insert_with(|| LedgerEntry {
+            outcome: DeploymentOutcome::InProgress,
+            error_message: None,
+            updated_at: now_secs(),
+            container: None,
+        });
+        entry.container = Some(container.to_string());
+        self.persist(&entries).await
+    }
+
+    /// Record a deployment's outcome and persist the ledger
+    pub async fn record(
+        &self,
+        deployment_id: &str,
+        outcome: DeploymentOutcome,
+        error_message: Option<String>,
+    ) -> Result<(), AgentError> {
+        let mut entries = self.entries.lock().await;
+        let container = entries.get(deployment_id).and_then(|e| e.container.clone());
+        entries.insert(
+            deployment_id.to_string(),
+            LedgerEntry {
+                outcome,
+                error_message,
+                updated_at: now_secs(),
+                container,
+            },
+        );
+
+        // Forget the oldest deployments beyond the cap
+        if entries.len() > MAX_ENTRIES {
+            let mut by_age: Vec<(String, u64)> = entries
+                .iter()
+                .map(|(id, entry)| (id.clone(), entry.updated_at))
+                .collect();
+            by_age.sort_by_key(|(_, updated_at)| *updated_at);
+            let excess = entries.len() - MAX_ENTRIES;
+            for (id, _) in by_age.into_iter().take(excess) {
+                entries.remove(&id);
+            }
+        }
+
+        self.persist(&entries).await
+    }
+
+    async fn persist(&self, entries: &HashMap<String, LedgerEntry>) -> Result<(), AgentError> {
+        if let Some(parent) = self.file.path().parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let contents = serde_json::to_vec_pretty(entries)?;
+        self.file.write_atomic(&contents).await
+    }
+}
+
+fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesys::test_utils::TempFs;
+
+    fn deployment(id: &str, config: serde_json::Value) -> Deployment {
+        Deployment {
+            id: id.to_string(),
+            device_id: "device".to_string(),
+            deployment_type: "docker".to_string(),
+            config,
+            status: "pending".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_completed_deployments_survive_reload() {
+        let fs = TempFs::new();
+        let path = fs.path("state/deployment_ledger.json");
+        let ledger = DeploymentLedger::load(File::new(&path)).await;
+
+        let d1 = deployment("d1", serde_json::json!({}));
+        ledger.record("d1", DeploymentOutcome::InProgress, None).await.unwrap();
+        assert!(ledger.completed(&d1).await.is_none());
+
+        ledger
+            .record("d1", DeploymentOutcome::Failed, Some("pull failed".to_string()))
+            .await
+            .unwrap();
+
+        let reloaded = DeploymentLedger::load(File::new(&path)).await;
+        let entry = reloaded.completed(&d1).await.unwrap();
+        assert_eq!(entry.outcome, DeploymentOutcome::Failed);
+        assert_eq!(entry.error_message.as_deref(), Some("pull failed"));
+
+        // An explicit force flag or a new ID redeploys
+        let forced = deployment("d1", serde_json::json!({ "force": true }));
+        assert!(reloaded.completed(&forced).await.is_none());
+        assert!(reloaded.completed(&deployment("d2", serde_json::json!({}))).await.is_none());
+
+        // The container survives outcome updates and reloads
+        reloaded.set_container("d1", "ajime-d1").await.unwrap();
+        reloaded.record("d1", DeploymentOutcome::Success, None).await.unwrap();
+        let reloaded = DeploymentLedger::load(File::new(&path)).await;
+        assert_eq!(reloaded.container("d1").await.as_deref(), Some("ajime-d1"));
+        assert_eq!(reloaded.container("d2").await, None);
+    }
+}
@@ -0,0 +1,318 @@
+//! Resource limits for deployment workloads
+//!
+//! Install and run commands of git deployments run directly on the device,
+//! where a runaway app can take all CPU and memory and leave the agent
+//! unable to even report it. Where systemd manages the device, each command
+//! runs in a transient scope created with `systemd-run --scope`, with
+//! `MemoryMax` and `CPUQuota` set from the limits. The scopes of a
+//! deployment share the slice `ajime-<deployment>.slice`, outside the
+//! agent's service, so systemd owns the cgroups and removes each scope once
+//! its processes exit. Without systemd, memory is capped per process with
+//! `RLIMIT_DATA` and CPU use is only deprioritised. Workloads always run
+//! reniced. Docker deployments get the same limits as `--memory` and
+//! `--cpus`.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::process::Command;
+use tracing::{debug, warn};
+
+use crate::errors::AgentError;
+
+/// Deployment config field overriding the default limits
+pub const LIMITS_FIELD: &str = "resources";
+
+/// Parent slice of the deployment slices
+const WORKLOAD_SLICE: &str = "ajime";
+
+/// CPU and memory limits of a deployment's processes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResourceLimits {
+    /// Memory cap in MiB; unlimited when unset
+    #[serde(default)]
+    pub memory_mb: Option<u64>,
+
+    /// CPU cap in cores, e.g. 0.5; unlimited when unset
+    #[serde(default)]
+    pub cpus: Option<f64>,
+
+    /// Niceness the processes run at, from -20 (highest priority) to 19
+    #[serde(default = "default_nice")]
+    pub nice: i32,
+}
+
+fn default_nice() -> i32 {
+    10
+}
+
+impl Default for ResourceLimits {
+    fn default() -> Self {
+        Self {
+            memory_mb: None,
+            cpus: None,
+            nice: default_nice(),
+        }
+    }
+}
+
+/// Per-deployment overrides; unset fields keep the defaults
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LimitOverrides {
+    memory_mb: Option<u64>,
+    cpus: Option<f64>,
+    nice: Option<i32>,
+}
+
+impl ResourceLimits {
+    /// The limits of a deployment: its `resources` field over `defaults`
+    pub fn from_config(config: &Value, defaults: &Self) -> Result<Self, AgentError> {
+        let Some(value) = config.get(LIMITS_FIELD) else {
+            return Ok(defaults.clone());
+        };
+        let overrides: LimitOverrides = serde_json::from_value(value.clone()).map_err(|e| {
+            AgentError::ConfigError(format!("Invalid {}: {}", LIMITS_FIELD, e))
+        })?;
+        let limits = Self {
+            memory_mb: overrides.memory_mb.or(defaults.memory_mb),
+            cpus: overrides.cpus.or(defaults.cpus),
+            nice: overrides.nice.unwrap_or(defaults.nice),
+        };
+        limits.validate()?;
+        Ok(limits)
+    }
+
+    pub fn validate(&self) -> Result<(), AgentError> {
+        if self.memory_mb == Some(0) {
+            return Err(AgentError::ConfigError(
+                "The memory limit must be at least 1 MiB".to_string(),
+            ));
+        }
+        if let Some(cpus) = self.cpus {
+            if !cpus.is_finite() || cpus <= 0.0 {
+                return Err(AgentError::ConfigError(format!(
+                    "The CPU limit must be a positive number of cores, got {}",
+                    cpus
+                )));
+            }
+        }
+        if !(-20..=19).contains(&self.nice) {
+            return Err(AgentError::ConfigError(format!(
+                "Niceness must be between -20 and 19, got {}",
+                self.nice
+            )));
+        }
+        Ok(())
+    }
+
+    /// `docker run` arguments applying the memory and CPU limits
+    pub fn docker_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(memory_mb) = self.memory_mb {
+            args.extend(["--memory".to_string(), format!("{}m", memory_mb)]);
+        }
+        if let Some(cpus) = self.cpus {
+            args.extend(["--cpus".to_string(), cpus.to_string()]);
+        }
+        args
+    }
+
+    /// A command running `program` as workload `name`, limited and
+    /// reniced. Processes it starts inherit the limits.
+    pub fn command(&self, program: &str, name: &str) -> Command {
+        self.command_in(program, name, systemd_available())
+    }
+
+    fn command_in(&self, program: &str, name: &str, systemd: bool) -> Command {
+        let mut command = if systemd {
+            let mut command = Command::new("systemd-run");
+            command.args(self.scope_args(name)).arg("--").arg(program);
+            command
+        } else {
+            if self.memory_mb.is_some() || self.cpus.is_some() {
+                debug!("No systemd to limit {} with; using rlimits", name);
+            }
+            Command::new(program)
+        };
+        self.limit_process(&mut command, !systemd);
+        command.kill_on_drop(true);
+        command
+    }
+
+    /// `systemd-run` arguments starting a scope with these limits in
+    /// workload `name`'s slice
+    fn scope_args(&self, name: &str) -> Vec<String> {
+        let mut args = vec![
+            "--scope".to_string(),
+            "--quiet".to_string(),
+            "--collect".to_string(),
+            format!("--slice={}", workload_slice(name)),
+        ];
+        if let Some(memory_mb) = self.memory_mb {
+            args.push(format!("--property=MemoryMax={}M", memory_mb));
+        }
+        if let Some(cpus) = self.cpus {
+            let percent = ((cpus * 100.0).round() as u64).max(1);
+            args.push(format!("--property=CPUQuota={}%", percent));
+        }
+        args
+    }
+
+    /// Renice the process `command` starts and, with `rlimits`, cap its memory
+    #[cfg(unix)]
+    fn limit_process(&self, command: &mut Command, rlimits: bool) {
+        let memory_bytes = self
+            .memory_mb
+            .filter(|_| rlimits)
+            .map(|mb| mb.saturating_mul(1024 * 1024));
+        let nice = self.nice;
+        // Only async-signal-safe calls between fork and exec
+        let limit = move || {
+            // RLIMIT_DATA rather than RLIMIT_AS: runtimes reserve far more
+            // address space than they use, and would fail to start
+            if let Some(bytes) = memory_bytes {
+                let limit = libc::rlimit {
+                    rlim_cur: bytes as libc::rlim_t,
+                    rlim_max: bytes as libc::rlim_t,
+                };
+                // SAFETY: setrlimit reads a valid rlimit
+                if unsafe { libc::setrlimit(libc::RLIMIT_DATA, &limit) } != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            // Best effort: raising the priority needs privileges
+            // SAFETY: setpriority has no memory safety requirements
+            unsafe {
+                libc::setpriority(libc::PRIO_PROCESS, 0, nice);
+            }
+            Ok(())
+        };
+        // SAFETY: the closure only makes async-signal-safe system calls
+        unsafe {
+            command.pre_exec(limit);
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn limit_process(&self, _command: &mut Command, _rlimits: bool) {
+        debug!("Resource limits are not supported here; workloads run unlimited");
+    }
+}
+
+/// Stop whatever still runs as workload `name`, removing its slice
+pub async fn remove_workload(name: &str) {
+    if !systemd_available() {
+        return;
+    }
+    let slice = workload_slice(name);
+    let result = Command::new("systemctl")
+        .args(["stop", "--quiet", &slice])
+        .output()
+        .await;
+    match result {
+        Ok(output) if output.status.success() => debug!("Stopped {}", slice),
+        // A slice without running scopes is gone already
+        Ok(_) => debug!("No {} to stop", slice),
+        Err(e) => warn!("Failed to stop {}: {}", slice, e),
+    }
+}
+
+/// Slice of workload `name`; `-` nests slices, so only the separator
+/// after the parent may be one
+fn workload_slice(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' })
+        .collect();
+    format!("{}-{}.slice", WORKLOAD_SLICE, name)
+}
+
+/// Whether workloads can be put in scopes: systemd manages the device and
+/// the agent may ask it to
+fn systemd_available() -> bool {
+    #[cfg(unix)]
+    {
+        // SAFETY: geteuid has no memory safety requirements
+        let root = unsafe { libc::geteuid() } == 0;
+        root && std::path::Path::new("/run/systemd/system").is_dir()
+    }
+    #[cfg(not(unix))]
+    {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deployment_limits_override_defaults() {
+        let defaults = ResourceLimits {
+            memory_mb: Some(512),
+            ..Default::default()
+        };
+        let config = serde_json::json!({ "resources": { "cpus": 1.5, "nice": 5 } });
+        let limits = ResourceLimits::from_config(&config, &defaults).unwrap();
+        assert_eq!(limits.memory_mb, Some(512));
+        assert_eq!(limits.cpus, Some(1.5));
+        assert_eq!(limits.nice, 5);
+        assert_eq!(limits.docker_args(), ["--memory", "512m", "--cpus", "1.5"]);
+
+        let plain = ResourceLimits::from_config(&serde_json::json!({}), &defaults).unwrap();
+        assert_eq!(plain, defaults);
+
+        for resources in [
+            serde_json::json!({ "memory_mb": 0 }),
+            serde_json::json!({ "cpus": -1.0 }),
+            serde_json::json!({ "nice": 40 }),
+            serde_json::json!({ "memroy_mb": 64 }),
+        ] {
+            let config = serde_json::json!({ "resources": resources });
+            assert!(ResourceLimits::from_config(&config, &defaults).is_err());
+        }
+    }
+
+    #[test]
+    fn test_workloads_run_in_limited_scopes() {
+        let limits = ResourceLimits {
+            memory_mb: Some(64),
+            cpus: Some(0.5),
+            ..Default::default()
+        };
+        let command = limits.command_in("bash", "dep-1/x", true);
+        let command = command.as_std();
+        assert_eq!(command.get_program(), "systemd-run");
+        let args: Vec<_> = command.get_args().map(|a| a.to_str().unwrap()).collect();
+        assert_eq!(
+            args,
+            [
+                "--scope",
+                "--quiet",
+                "--collect",
+                "--slice=ajime-dep_1_x.slice",
+                "--property=MemoryMax=64M",
+                "--property=CPUQuota=50%",
+                "--",
+                "bash",
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_rlimits_apply_without_systemd() {
+        let limits = ResourceLimits {
+            memory_mb: Some(512),
+            nice: 15,
+            ..Default::default()
+        };
+        let mut command = limits.command_in("sh", "dep", false);
+        command.args(["-c", "ulimit -d; nice"]);
+
+        let output = command.output().await.unwrap();
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let lines: Vec<&str> = stdout.lines().collect();
+        assert_eq!(lines, ["524288", "15"]);
+    }
+}
@@ -0,0 +1,190 @@
+//! Memoization of deterministic node results
+//!
+//! Nodes whose runner is deterministic (outputs depend only on inputs and
+//! config) and whose config sets `"cacheable": true` have their results
+//! remembered across executions, keyed by a hash of the node type, config and
+//! inputs.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::models::workflow::Node;
+use crate::utils::sha256_hash;
+
+/// Default number of memoized results
+pub const DEFAULT_CAPACITY: usize = 256;
+
+/// Node outputs
+pub type NodeOutputs = HashMap<String, Value>;
+
+/// Whether a node opted into memoization (off by default)
+pub fn is_cacheable(node: &Node) -> bool {
+    node.data
+        .config
+        .get("cacheable")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false)
+}
+
+/// Cache hit/miss counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct MemoStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+}
+
+#[derive(Default)]
+struct Entries {
+    results: HashMap<String, NodeOutputs>,
+    /// Keys from least to most recently used
+    recency: VecDeque<String>,
+}
+
+impl Entries {
+    fn touch(&mut self, key: &str) {
+        if let Some(pos) = self.recency.iter().position(|k| k == key) {
+            if let Some(key) = self.recency.remove(pos) {
+                self.recency.push_back(key);
+            }
+        }
+    }
+}
+
+/// Bounded, least-recently-used cache of node results
+pub struct NodeResultCache {
+    entries: Mutex<Entries>,
+    capacity: usize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl NodeResultCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: Mutex::new(Entries::default()),
+            capacity,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Cache key of a node run on the given inputs
+    pub fn key(node: &Node, inputs: &NodeOutputs) -> String {
+        // serde_json maps are sorted, so sorting the inputs makes the key
+        // independent of insertion order
+        let inputs: BTreeMap<&String, &Value> = inputs.iter().collect();
+        let material = serde_json::json!({
+            "type": node.node_type,
+            "config": node.data.config,
+            "inputs": inputs,
+        });
+        sha256_hash(material.to_string().as_bytes())
+    }
+
+    /// Look up a result, counting the hit or miss
+    pub fn get(&self, key: &str) -> Option<NodeOutputs> {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        match entries.results.get(key).cloned() {
+            Some(outputs) => {
+                entries.touch(key);
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(outputs)
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// Remember a result, evicting the least recently used one when full
+    pub fn insert(&self, key: String, outputs: NodeOutputs) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if entries.results.insert(key.clone(), outputs).is_some() {
+            entries.touch(&key);
+            return;
+        }
+
+        entries.recency.push_back(key);
+        while entries.results.len() > self.capacity {
+            match entries.recency.pop_front() {
+                Some(oldest) => {
+                    entries.results.remove(&oldest);
+                }
+                None => break,
+            }
+        }
+    }
+
+    pub fn stats(&self) -> MemoStats {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        MemoStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: entries.results.len(),
+        }
+    }
+}
+
+impl Default for NodeResultCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::test_utils::node;
+
+    fn outputs(value: i64) -> NodeOutputs {
+        HashMap::from([("value".to_string(), Value::from(value))])
+    }
+
+    #[test]
+    fn test_key_covers_config_and_inputs() {
+        let a = node("n1", "transform", serde_json::json!({ "scale": 2 }));
+        let b = node("n1", "transform", serde_json::json!({ "scale": 3 }));
+
+        let mut inputs = outputs(1);
+        inputs.insert("other".to_string(), Value::Bool(true));
+        let mut pairs: Vec<_> = inputs.clone().into_iter().collect();
+        pairs.reverse();
+        let reordered: NodeOutputs = pairs.into_iter().collect();
+
+        assert_eq!(NodeResultCache::key(&a, &inputs), NodeResultCache::key(&a, &reordered));
+        assert_ne!(NodeResultCache::key(&a, &inputs), NodeResultCache::key(&b, &inputs));
+        assert_ne!(NodeResultCache::key(&a, &inputs), NodeResultCache::key(&a, &outputs(2)));
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let cache = NodeResultCache::new(2);
+        cache.insert("a".to_string(), outputs(1));
+        cache.insert("b".to_string(), outputs(2));
+        assert_eq!(cache.get("a"), Some(outputs(1)));
+
+        cache.insert("c".to_string(), outputs(3));
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("c").is_some());
+
+        assert_eq!(
+            cache.stats(),
+            MemoStats {
+                hits: 3,
+                misses: 1,
+                entries: 2
+            }
+        );
+    }
+}
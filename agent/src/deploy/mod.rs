@@ -1,8 +1,19 @@
 //! Deployment module
 
+pub mod capabilities;
+pub mod check;
+pub mod dirs;
+pub mod env_file;
 pub mod executor;
 pub mod fsm;
+pub mod ledger;
+pub mod limits;
+pub mod memo;
+pub mod node_config;
 pub mod node_runner;
+pub mod output;
+pub mod registry;
+pub mod resources;
 pub mod docker;
 pub mod git;
 pub mod compose;
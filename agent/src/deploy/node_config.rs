@@ -0,0 +1,313 @@
+//! Typed node configurations
+//!
+//! Each runner deserializes `node.data.config` into its own config type. Keys
+//! the runner does not know are rejected at deploy time, so a typo such as
+//! `widht` fails loudly instead of silently falling back to a default.
+
+use std::collections::HashMap;
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use crate::errors::AgentError;
+use crate::models::workflow::Node;
+
+/// Keys any node may carry besides its runner's own config
+pub const COMMON_KEYS: &[&str] = &["cacheable", "label", "description"];
+
+/// A runner's config type
+pub trait NodeConfig: DeserializeOwned {
+    /// Config keys the runner understands
+    const KEYS: &'static [&'static str];
+}
+
+/// Parse a node's config, rejecting unknown keys and invalid values
+pub fn parse<T: NodeConfig>(node: &Node) -> Result<T, AgentError> {
+    if let Some(config) = node.data.config.as_object() {
+        let mut unknown: Vec<&String> = config
+            .keys()
+            .filter(|key| {
+                !T::KEYS.contains(&key.as_str()) && !COMMON_KEYS.contains(&key.as_str())
+            })
+            .collect();
+        unknown.sort();
+
+        if let Some(key) = unknown.first() {
+            let hint = suggest(key, T::KEYS)
+                .map(|known| format!("; did you mean '{}'?", known))
+                .unwrap_or_default();
+            return Err(AgentError::ConfigError(format!(
+                "Unknown config key '{}' for node {} ({}){}",
+                key, node.id, node.node_type, hint
+            )));
+        }
+    }
+
+    T::deserialize(&node.data.config).map_err(|e| {
+        AgentError::ConfigError(format!(
+            "Invalid config for node {} ({}): {}",
+            node.id, node.node_type, e
+        ))
+    })
+}
+
+/// The known key closest to a misspelled one, if any is close enough
+fn suggest(key: &str, known: &[&'static str]) -> Option<&'static str> {
+    known
+        .iter()
+        .map(|candidate| (edit_distance(key, candidate), *candidate))
+        .filter(|(distance, _)| *distance <= 2)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Levenshtein distance, counting an adjacent swap as one edit
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+/// Camera capture config
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CameraConfig {
+    pub device: String,
+    pub width: u32,
+    pub height: u32,
+    /// Give up on a capture after this long
+    pub timeout_ms: u64,
+    /// One frame per execution, or a continuous stream
+    pub mode: CaptureMode,
+    /// Frames per second in stream mode
+    pub fps: u32,
+}
+
+/// How a camera node captures
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum CaptureMode {
+    /// Capture a frame each time the node runs
+    #[default]
+    Single,
+    /// Capture continuously; the workflow runs once per frame
+    Stream,
+}
+
+impl Default for CameraConfig {
+    fn default() -> Self {
+        Self {
+            device: "/dev/video0".to_string(),
+            width: 640,
+            height: 480,
+            timeout_ms: 5000,
+            mode: CaptureMode::Single,
+            fps: 5,
+        }
+    }
+}
+
+impl NodeConfig for CameraConfig {
+    const KEYS: &'static [&'static str] =
+        &["device", "width", "height", "timeout_ms", "mode", "fps"];
+}
+
+/// GPIO read/write config
+#[derive(Debug, Clone, Deserialize)]
+pub struct GpioConfig {
+    pub pin: u8,
+}
+
+impl NodeConfig for GpioConfig {
+    const KEYS: &'static [&'static str] = &["pin"];
+}
+
+/// Delay config
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DelayConfig {
+    pub delay_ms: u64,
+}
+
+impl Default for DelayConfig {
+    fn default() -> Self {
+        Self { delay_ms: 1000 }
+    }
+}
+
+impl NodeConfig for DelayConfig {
+    const KEYS: &'static [&'static str] = &["delay_ms"];
+}
+
+/// HTTP request config
+#[derive(Debug, Clone, Deserialize)]
+pub struct HttpRequestConfig {
+    pub url: String,
+
+    #[serde(default = "default_http_method")]
+    pub method: String,
+
+    /// Give up on the request after this long
+    #[serde(default = "default_http_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+fn default_http_method() -> String {
+    "GET".to_string()
+}
+
+fn default_http_timeout_ms() -> u64 {
+    30_000
+}
+
+impl NodeConfig for HttpRequestConfig {
+    const KEYS: &'static [&'static str] = &["url", "method", "timeout_ms"];
+}
+
+/// Container node config
+#[derive(Debug, Clone, Deserialize)]
+pub struct DockerConfig {
+    pub image: String,
+
+    /// Command and arguments, replacing the image's default command
+    #[serde(default)]
+    pub command: Vec<String>,
+
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+
+    /// Kill the container after this long
+    #[serde(default = "default_docker_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+fn default_docker_timeout_ms() -> u64 {
+    60_000
+}
+
+impl NodeConfig for DockerConfig {
+    const KEYS: &'static [&'static str] = &["image", "command", "env", "timeout_ms"];
+}
+
+/// Log config
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LogConfig {
+    pub prefix: String,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            prefix: "[LOG]".to_string(),
+        }
+    }
+}
+
+impl NodeConfig for LogConfig {
+    const KEYS: &'static [&'static str] = &["prefix"];
+}
+
+/// Accumulator config
+#[derive(Debug, Clone, Deserialize)]
+pub struct AccumulatorConfig {
+    pub operation: AccumulatorOperation,
+
+    /// Input holding the value to append or average
+    #[serde(default = "default_accumulator_input")]
+    pub input: String,
+
+    /// Amount each execution adds when incrementing
+    #[serde(default = "default_accumulator_step")]
+    pub step: i64,
+
+    /// Values kept when appending or averaging; older ones are dropped
+    #[serde(default = "default_accumulator_window")]
+    pub window: usize,
+
+    /// Keep the state on disk so it survives redeploys and restarts
+    #[serde(default)]
+    pub persist: bool,
+}
+
+/// What an accumulator does with each execution
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccumulatorOperation {
+    /// Count executions, adding `step` each time
+    Increment,
+    /// Collect the last `window` input values
+    Append,
+    /// Average the last `window` numeric input values
+    MovingAverage,
+}
+
+fn default_accumulator_input() -> String {
+    "value".to_string()
+}
+
+fn default_accumulator_step() -> i64 {
+    1
+}
+
+fn default_accumulator_window() -> usize {
+    10
+}
+
+impl NodeConfig for AccumulatorConfig {
+    const KEYS: &'static [&'static str] = &["operation", "input", "step", "window", "persist"];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::test_utils::node;
+
+    #[test]
+    fn test_defaults_and_common_keys() {
+        let config: CameraConfig =
+            parse(&node("n1", "camera", serde_json::json!({ "width": 1280, "cacheable": false }))).unwrap();
+        assert_eq!(config.width, 1280);
+        assert_eq!(config.height, 480);
+        assert_eq!(config.device, "/dev/video0");
+    }
+
+    #[test]
+    fn test_misspelled_key_is_reported() {
+        let err = parse::<CameraConfig>(&node("n1", "camera", serde_json::json!({ "widht": 1280 })))
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            AgentError::ConfigError(msg) if msg.contains("'widht'") && msg.ends_with("did you mean 'width'?")
+        ));
+
+        let err = parse::<DelayConfig>(&node("n1", "delay", serde_json::json!({ "color": "red" })))
+            .unwrap_err();
+        assert!(matches!(err, AgentError::ConfigError(msg) if !msg.contains("did you mean")));
+    }
+
+    #[test]
+    fn test_invalid_values_are_reported() {
+        assert!(parse::<GpioConfig>(&node("n1", "gpio_read", serde_json::json!({}))).is_err());
+        assert!(parse::<GpioConfig>(&node("n1", "gpio_read", serde_json::json!({ "pin": 300 }))).is_err());
+        assert!(parse::<DelayConfig>(&node("n1", "delay", serde_json::json!({ "delay_ms": "1s" }))).is_err());
+    }
+}
@@ -1,14 +1,32 @@
 //! Node runner implementations
 
-use std::collections::HashMap;
-use std::sync::Arc;
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use tracing::{debug, info};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
 
+use crate::deploy::node_config::{
+    self, AccumulatorConfig, AccumulatorOperation, CameraConfig, CaptureMode, DelayConfig,
+    DockerConfig, GpioConfig, HttpRequestConfig, LogConfig,
+};
+use crate::app::options::HardwareOptions;
 use crate::errors::AgentError;
+use crate::filesys::file::File;
+use crate::hardware::camera::CameraDevice;
+use crate::hardware::gpio::{check_pin, GpioController, GpioPin, PinMode, PinState};
 use crate::models::workflow::Node;
+use crate::telemetry::MetricsCollector;
+use crate::utils::sha256_hash;
 
 /// Node runner trait
 #[async_trait]
@@ -19,25 +37,100 @@ pub trait NodeRunner: Send + Sync {
     /// Get the node type
     fn node_type(&self) -> &str;
 
+    /// Whether outputs depend only on inputs and config, so results may be
+    /// memoized. Runners with side effects must keep the default.
+    fn is_deterministic(&self) -> bool {
+        false
+    }
+
+    /// Shared hardware this node needs exclusive access to while executing
+    fn resources(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Check at deploy time that the hardware the node is configured for is
+    /// present, so a workflow for absent hardware fails to deploy
+    async fn check_resources(&self) -> Result<(), AgentError> {
+        Ok(())
+    }
+
+    /// Whether the node produces a stream rather than one result. The
+    /// workflow then runs once per item, `execute` waiting for the next one.
+    fn is_streaming(&self) -> bool {
+        false
+    }
+
+    /// How long `execute` may legitimately take, on top of which the
+    /// watchdog allows its stall timeout
+    fn max_duration(&self) -> Duration {
+        Duration::ZERO
+    }
+
     /// Stop the node
     async fn stop(&self) -> Result<(), AgentError> {
         Ok(())
     }
 }
 
+/// What node runners may need from the agent
+#[derive(Debug, Clone, Default)]
+pub struct NodeContext {
+    pub hardware: HardwareOptions,
+
+    /// Shared by HTTP request nodes so they reuse connections
+    pub http: reqwest::Client,
+
+    /// The agent's metrics collector, read by metrics nodes; without one
+    /// each metrics node keeps its own
+    pub metrics: Option<Arc<MetricsCollector>>,
+
+    /// Directory the workflow's stateful nodes persist their state in;
+    /// without one their state is kept in memory only
+    pub state_dir: Option<PathBuf>,
+}
+
 /// Factory for creating node runners
 pub struct NodeRunnerFactory;
 
+/// Node types with a dedicated runner; any other type passes its inputs
+/// through unchanged
+const NODE_TYPES: &[&str] = &[
+    "camera",
+    "camera_capture",
+    "gpio_read",
+    "gpio_input",
+    "gpio_write",
+    "gpio_output",
+    "delay",
+    "timer",
+    "http_request",
+    "docker",
+    "container",
+    "log",
+    "debug",
+    "metrics_read",
+    "accumulator",
+    "state",
+];
+
 impl NodeRunnerFactory {
+    /// Whether `node_type` has a dedicated runner
+    pub fn supports(node_type: &str) -> bool {
+        NODE_TYPES.contains(&node_type)
+    }
+
     /// Create a node runner for the given node
-    pub fn create(node: &Node) -> Result<Arc<dyn NodeRunner>, AgentError> {
+    pub fn create(node: &Node, context: &NodeContext) -> Result<Arc<dyn NodeRunner>, AgentError> {
         let runner: Arc<dyn NodeRunner> = match node.node_type.as_str() {
-            "camera" | "camera_capture" => Arc::new(CameraNodeRunner::new(node)?),
+            "camera" | "camera_capture" => Arc::new(CameraNodeRunner::new(node, context)?),
             "gpio_read" | "gpio_input" => Arc::new(GpioReadNodeRunner::new(node)?),
             "gpio_write" | "gpio_output" => Arc::new(GpioWriteNodeRunner::new(node)?),
             "delay" | "timer" => Arc::new(DelayNodeRunner::new(node)?),
-            "http_request" => Arc::new(HttpRequestNodeRunner::new(node)?),
+            "http_request" => Arc::new(HttpRequestNodeRunner::new(node, context)?),
+            "docker" | "container" => Arc::new(DockerNodeRunner::new(node)?),
             "log" | "debug" => Arc::new(LogNodeRunner::new(node)?),
+            "metrics_read" => Arc::new(MetricsReadNodeRunner::new(node, context)),
+            "accumulator" | "state" => Arc::new(AccumulatorNodeRunner::new(node, context)?),
             _ => Arc::new(PassthroughNodeRunner::new(node)?),
         };
 
@@ -45,56 +138,104 @@ impl NodeRunnerFactory {
     }
 }
 
+/// Frames a streaming camera node buffers for the workflow; newer frames are
+/// dropped while it is full
+const CAMERA_FRAME_QUEUE: usize = 2;
+
 /// Camera capture node runner
 pub struct CameraNodeRunner {
     node_id: String,
-    device: String,
-    width: u32,
-    height: u32,
+    config: CameraConfig,
+    enabled: bool,
+    /// Capture stream in stream mode, started on first use
+    stream: tokio::sync::Mutex<Option<CameraStream>>,
+    cancel: CancellationToken,
+}
+
+/// A running camera stream
+struct CameraStream {
+    frames: mpsc::Receiver<Vec<u8>>,
+    task: tokio::task::JoinHandle<Result<(), AgentError>>,
 }
 
 impl CameraNodeRunner {
-    pub fn new(node: &Node) -> Result<Self, AgentError> {
-        let device = node
-            .data
-            .config
-            .get("device")
-            .and_then(|v| v.as_str())
-            .unwrap_or("/dev/video0")
-            .to_string();
-
-        let width = node
-            .data
-            .config
-            .get("width")
-            .and_then(|v| v.as_u64())
-            .unwrap_or(640) as u32;
-
-        let height = node
-            .data
-            .config
-            .get("height")
-            .and_then(|v| v.as_u64())
-            .unwrap_or(480) as u32;
+    pub fn new(node: &Node, context: &NodeContext) -> Result<Self, AgentError> {
+        let config: CameraConfig = node_config::parse(node)?;
+        if config.mode == CaptureMode::Stream && config.fps == 0 {
+            return Err(AgentError::ConfigError(format!(
+                "Camera node {} needs fps above 0 in stream mode",
+                node.id
+            )));
+        }
 
         Ok(Self {
             node_id: node.id.clone(),
-            device,
-            width,
-            height,
+            config,
+            enabled: context.hardware.enable_camera,
+            stream: tokio::sync::Mutex::new(None),
+            cancel: CancellationToken::new(),
         })
     }
+
+    async fn capture(&self) -> Result<Vec<u8>, AgentError> {
+        let mut camera =
+            CameraDevice::new(&self.config.device, self.config.width, self.config.height);
+        camera.open().await?;
+        camera
+            .capture_frame(Duration::from_millis(self.config.timeout_ms))
+            .await
+    }
+
+    /// The next frame of the stream, starting it if needed
+    async fn next_streamed(&self) -> Result<Vec<u8>, AgentError> {
+        let mut stream = self.stream.lock().await;
+        if stream.is_none() {
+            let mut camera =
+                CameraDevice::new(&self.config.device, self.config.width, self.config.height);
+            camera.open().await?;
+            let (tx, frames) = mpsc::channel(CAMERA_FRAME_QUEUE);
+            let (fps, cancel) = (self.config.fps, self.cancel.child_token());
+            let task =
+                tokio::spawn(async move { camera.stream_frames(fps, tx, cancel).await });
+            *stream = Some(CameraStream { frames, task });
+        }
+
+        let running = stream.as_mut().expect("stream was just started");
+        if let Some(frame) = running.frames.recv().await {
+            return Ok(frame);
+        }
+        // The stream ended; report why and start over on the next execution
+        let ended = stream.take().expect("stream is running");
+        match ended.task.await {
+            Ok(Err(e)) => Err(e),
+            _ => Err(AgentError::HardwareError(format!(
+                "Camera stream from {} stopped",
+                self.config.device
+            ))),
+        }
+    }
 }
 
 #[async_trait]
 impl NodeRunner for CameraNodeRunner {
     async fn execute(&self, _inputs: HashMap<String, Value>) -> Result<HashMap<String, Value>, AgentError> {
-        debug!("Camera capture: {} ({}x{})", self.device, self.width, self.height);
-        
-        // In production, this would capture from the camera
-        // For now, return a placeholder
+        debug!(
+            "Camera capture [{}]: {} ({}x{})",
+            self.node_id, self.config.device, self.config.width, self.config.height
+        );
+        if !self.enabled {
+            return Err(AgentError::HardwareError(
+                "Camera capture is disabled (hardware.enable_camera)".to_string(),
+            ));
+        }
+
+        let frame = match self.config.mode {
+            CaptureMode::Single => self.capture().await?,
+            CaptureMode::Stream => self.next_streamed().await?,
+        };
+
         let mut outputs = HashMap::new();
-        outputs.insert("frame".to_string(), Value::String("base64_frame_data".to_string()));
+        outputs.insert("frame".to_string(), Value::String(BASE64.encode(frame)));
         outputs.insert("timestamp".to_string(), Value::Number(chrono::Utc::now().timestamp().into()));
         
         Ok(outputs)
@@ -103,26 +244,50 @@ impl NodeRunner for CameraNodeRunner {
     fn node_type(&self) -> &str {
         "camera"
     }
+
+    fn resources(&self) -> Vec<String> {
+        vec![format!("camera:{}", self.config.device)]
+    }
+
+    async fn check_resources(&self) -> Result<(), AgentError> {
+        // A disabled camera is reported when the node runs
+        if !self.enabled {
+            return Ok(());
+        }
+        CameraDevice::new(&self.config.device, self.config.width, self.config.height)
+            .check()
+            .await
+    }
+
+    fn is_streaming(&self) -> bool {
+        self.config.mode == CaptureMode::Stream
+    }
+
+    fn max_duration(&self) -> Duration {
+        Duration::from_millis(self.config.timeout_ms)
+    }
+
+    async fn stop(&self) -> Result<(), AgentError> {
+        self.cancel.cancel();
+        Ok(())
+    }
 }
 
 /// GPIO read node runner
 pub struct GpioReadNodeRunner {
     node_id: String,
     pin: u8,
+    gpio: GpioLine,
 }
 
 impl GpioReadNodeRunner {
     pub fn new(node: &Node) -> Result<Self, AgentError> {
-        let pin = node
-            .data
-            .config
-            .get("pin")
-            .and_then(|v| v.as_u64())
-            .ok_or_else(|| AgentError::ConfigError("GPIO pin not specified".to_string()))? as u8;
+        let config: GpioConfig = node_config::parse(node)?;
 
         Ok(Self {
             node_id: node.id.clone(),
-            pin,
+            pin: config.pin,
+            gpio: GpioLine::new(config.pin, PinMode::Input),
         })
     }
 }
@@ -130,11 +295,11 @@ impl GpioReadNodeRunner {
 #[async_trait]
 impl NodeRunner for GpioReadNodeRunner {
     async fn execute(&self, _inputs: HashMap<String, Value>) -> Result<HashMap<String, Value>, AgentError> {
-        debug!("GPIO read: pin {}", self.pin);
-        
-        // In production, this would read from GPIO
+        debug!("GPIO read [{}]: pin {}", self.node_id, self.pin);
+
+        let state = self.gpio.with_pin(|pin| pin.read()).await?;
         let mut outputs = HashMap::new();
-        outputs.insert("value".to_string(), Value::Bool(false));
+        outputs.insert("value".to_string(), Value::Bool(state.into()));
         
         Ok(outputs)
     }
@@ -142,26 +307,36 @@ impl NodeRunner for GpioReadNodeRunner {
     fn node_type(&self) -> &str {
         "gpio_read"
     }
+
+    fn resources(&self) -> Vec<String> {
+        vec!["gpio".to_string()]
+    }
+
+    async fn check_resources(&self) -> Result<(), AgentError> {
+        check_pin(self.pin)
+    }
+
+    async fn stop(&self) -> Result<(), AgentError> {
+        self.gpio.release();
+        Ok(())
+    }
 }
 
 /// GPIO write node runner
 pub struct GpioWriteNodeRunner {
     node_id: String,
     pin: u8,
+    gpio: GpioLine,
 }
 
 impl GpioWriteNodeRunner {
     pub fn new(node: &Node) -> Result<Self, AgentError> {
-        let pin = node
-            .data
-            .config
-            .get("pin")
-            .and_then(|v| v.as_u64())
-            .ok_or_else(|| AgentError::ConfigError("GPIO pin not specified".to_string()))? as u8;
+        let config: GpioConfig = node_config::parse(node)?;
 
         Ok(Self {
             node_id: node.id.clone(),
-            pin,
+            pin: config.pin,
+            gpio: GpioLine::new(config.pin, PinMode::Output),
         })
     }
 }
@@ -174,9 +349,9 @@ impl NodeRunner for GpioWriteNodeRunner {
             .and_then(|v| v.as_bool())
             .unwrap_or(false);
 
-        debug!("GPIO write: pin {} = {}", self.pin, value);
-        
-        // In production, this would write to GPIO
+        debug!("GPIO write [{}]: pin {} = {}", self.node_id, self.pin, value);
+
+        self.gpio.with_pin(move |pin| pin.write(PinState::from(value))).await?;
         let mut outputs = HashMap::new();
         outputs.insert("success".to_string(), Value::Bool(true));
         
@@ -186,6 +361,68 @@ impl NodeRunner for GpioWriteNodeRunner {
     fn node_type(&self) -> &str {
         "gpio_write"
     }
+
+    fn resources(&self) -> Vec<String> {
+        vec!["gpio".to_string()]
+    }
+
+    async fn check_resources(&self) -> Result<(), AgentError> {
+        check_pin(self.pin)
+    }
+
+    async fn stop(&self) -> Result<(), AgentError> {
+        self.gpio.release();
+        Ok(())
+    }
+}
+
+/// A GPIO node's pin, set up on first use and released when the node stops
+struct GpioLine {
+    pin: u8,
+    mode: PinMode,
+    controller: Arc<Mutex<GpioController>>,
+}
+
+impl GpioLine {
+    fn new(pin: u8, mode: PinMode) -> Self {
+        Self {
+            pin,
+            mode,
+            controller: Arc::new(Mutex::new(GpioController::new())),
+        }
+    }
+
+    /// Run `f` on the pin off the async runtime: sysfs access blocks, and
+    /// setting up a freshly exported line waits for udev
+    async fn with_pin<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut GpioPin) -> Result<T, AgentError> + Send + 'static,
+    ) -> Result<T, AgentError> {
+        let (pin, mode) = (self.pin, self.mode);
+        let controller = self.controller.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut controller = controller.lock().unwrap_or_else(|e| e.into_inner());
+            if controller.get_pin(pin).is_none() {
+                controller.setup_pin(pin, mode)?;
+            }
+            match controller.get_pin_mut(pin) {
+                Some(gpio) => f(gpio),
+                None => Err(AgentError::HardwareError(format!(
+                    "GPIO pin {} is not set up",
+                    pin
+                ))),
+            }
+        })
+        .await
+        .map_err(|e| AgentError::Internal(format!("GPIO access failed: {}", e)))?
+    }
+
+    fn release(&self) {
+        self.controller
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .release_all();
+    }
 }
 
 /// Delay node runner
@@ -196,16 +433,11 @@ pub struct DelayNodeRunner {
 
 impl DelayNodeRunner {
     pub fn new(node: &Node) -> Result<Self, AgentError> {
-        let delay_ms = node
-            .data
-            .config
-            .get("delay_ms")
-            .and_then(|v| v.as_u64())
-            .unwrap_or(1000);
+        let config: DelayConfig = node_config::parse(node)?;
 
         Ok(Self {
             node_id: node.id.clone(),
-            delay_ms,
+            delay_ms: config.delay_ms,
         })
     }
 }
@@ -213,7 +445,7 @@ impl DelayNodeRunner {
 #[async_trait]
 impl NodeRunner for DelayNodeRunner {
     async fn execute(&self, inputs: HashMap<String, Value>) -> Result<HashMap<String, Value>, AgentError> {
-        debug!("Delay: {}ms", self.delay_ms);
+        debug!("Delay [{}]: {}ms", self.node_id, self.delay_ms);
         tokio::time::sleep(std::time::Duration::from_millis(self.delay_ms)).await;
         Ok(inputs)
     }
@@ -221,57 +453,210 @@ impl NodeRunner for DelayNodeRunner {
     fn node_type(&self) -> &str {
         "delay"
     }
+
+    fn max_duration(&self) -> Duration {
+        Duration::from_millis(self.delay_ms)
+    }
 }
 
 /// HTTP request node runner
 pub struct HttpRequestNodeRunner {
     node_id: String,
     url: String,
-    method: String,
+    method: reqwest::Method,
+    timeout: Duration,
+    client: reqwest::Client,
 }
 
 impl HttpRequestNodeRunner {
-    pub fn new(node: &Node) -> Result<Self, AgentError> {
-        let url = node
-            .data
-            .config
-            .get("url")
-            .and_then(|v| v.as_str())
-            .ok_or_else(|| AgentError::ConfigError("HTTP URL not specified".to_string()))?
-            .to_string();
-
-        let method = node
-            .data
-            .config
-            .get("method")
-            .and_then(|v| v.as_str())
-            .unwrap_or("GET")
-            .to_string();
+    pub fn new(node: &Node, context: &NodeContext) -> Result<Self, AgentError> {
+        let config: HttpRequestConfig = node_config::parse(node)?;
+        let method = match config.method.to_ascii_uppercase().as_str() {
+            "GET" => reqwest::Method::GET,
+            "POST" => reqwest::Method::POST,
+            "PUT" => reqwest::Method::PUT,
+            "DELETE" => reqwest::Method::DELETE,
+            other => {
+                return Err(AgentError::ConfigError(format!(
+                    "Node {}: unsupported HTTP method '{}' (expected GET, POST, PUT or DELETE)",
+                    node.id, other
+                )))
+            }
+        };
 
         Ok(Self {
             node_id: node.id.clone(),
-            url,
+            url: config.url,
             method,
+            timeout: Duration::from_millis(config.timeout_ms),
+            client: context.http.clone(),
         })
     }
 }
 
 #[async_trait]
 impl NodeRunner for HttpRequestNodeRunner {
-    async fn execute(&self, _inputs: HashMap<String, Value>) -> Result<HashMap<String, Value>, AgentError> {
-        debug!("HTTP {}: {}", self.method, self.url);
-        
-        // In production, this would make the HTTP request
+    /// Send the request with the optional `headers` (an object) and `body`
+    /// inputs; a string body is sent as is, anything else as JSON. Any
+    /// response, including an error status, is a successful execution.
+    async fn execute(&self, inputs: HashMap<String, Value>) -> Result<HashMap<String, Value>, AgentError> {
+        debug!("HTTP [{}] {}: {}", self.node_id, self.method, self.url);
+
+        let mut request = self
+            .client
+            .request(self.method.clone(), &self.url)
+            .timeout(self.timeout);
+        if let Some(headers) = inputs.get("headers").and_then(Value::as_object) {
+            for (name, value) in headers {
+                let value = match value {
+                    Value::String(value) => value.clone(),
+                    other => other.to_string(),
+                };
+                request = request.header(name.as_str(), value);
+            }
+        }
+        request = match inputs.get("body") {
+            None | Some(Value::Null) => request,
+            Some(Value::String(body)) => request.body(body.clone()),
+            Some(body) => request.json(body),
+        };
+
+        let response = request.send().await?;
+        let status = response.status().as_u16();
+        let body = response.text().await?;
+
         let mut outputs = HashMap::new();
-        outputs.insert("status".to_string(), Value::Number(200.into()));
-        outputs.insert("body".to_string(), Value::String("{}".to_string()));
-        
+        outputs.insert("status".to_string(), Value::Number(status.into()));
+        outputs.insert("body".to_string(), Value::String(body));
+
         Ok(outputs)
     }
 
     fn node_type(&self) -> &str {
         "http_request"
     }
+
+    fn max_duration(&self) -> Duration {
+        self.timeout
+    }
+}
+
+/// Container node runner: runs the image once per execution with the inputs
+/// as JSON on stdin, and takes the JSON object it prints as the outputs
+pub struct DockerNodeRunner {
+    node_id: String,
+    config: DockerConfig,
+}
+
+impl DockerNodeRunner {
+    pub fn new(node: &Node) -> Result<Self, AgentError> {
+        let config: DockerConfig = node_config::parse(node)?;
+        if config.image.trim().is_empty() {
+            return Err(AgentError::ConfigError(format!(
+                "Container node {} needs an image",
+                node.id
+            )));
+        }
+
+        Ok(Self {
+            node_id: node.id.clone(),
+            config,
+        })
+    }
+}
+
+#[async_trait]
+impl NodeRunner for DockerNodeRunner {
+    async fn execute(&self, inputs: HashMap<String, Value>) -> Result<HashMap<String, Value>, AgentError> {
+        debug!("Container node [{}]: {}", self.node_id, self.config.image);
+        let container = format!("ajime-node-{}", uuid::Uuid::new_v4().simple());
+        let mut command = tokio::process::Command::new("docker");
+        command.args(["run", "--rm", "-i", "--name", &container]);
+        // Values reach docker through its environment so they do not show
+        // up in the process list
+        let mut env: Vec<_> = self.config.env.iter().collect();
+        env.sort();
+        for (key, value) in env {
+            command.args(["-e", key]).env(key, value);
+        }
+        let mut child = command
+            .arg(&self.config.image)
+            .args(&self.config.command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| AgentError::DeployError(format!("Failed to run docker: {}", e)))?;
+
+        let input = serde_json::to_vec(&inputs)?;
+        let mut stdin = child.stdin.take();
+        let write_input = async move {
+            if let Some(stdin) = stdin.as_mut() {
+                // A container that ignores stdin may close it early
+                if let Err(e) = stdin.write_all(&input).await {
+                    debug!("Container did not read its input: {}", e);
+                }
+            }
+            drop(stdin);
+        };
+        let timeout = Duration::from_millis(self.config.timeout_ms);
+        let output = tokio::time::timeout(timeout, async {
+            let (_, output) = tokio::join!(write_input, child.wait_with_output());
+            output
+        })
+        .await;
+
+        let output = match output {
+            Ok(output) => output?,
+            Err(_) => {
+                // Killing the CLI leaves the container running
+                let _ = tokio::process::Command::new("docker")
+                    .args(["rm", "-f", &container])
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .status()
+                    .await;
+                return Err(AgentError::Timeout(format!(
+                    "Container {} for node {} timed out after {:?}",
+                    self.config.image, self.node_id, timeout
+                )));
+            }
+        };
+        if !output.status.success() {
+            return Err(AgentError::DeployError(format!(
+                "Container {} for node {} failed ({}): {}",
+                self.config.image,
+                self.node_id,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        parse_container_output(&output.stdout)
+    }
+
+    fn node_type(&self) -> &str {
+        "docker"
+    }
+
+    fn max_duration(&self) -> Duration {
+        Duration::from_millis(self.config.timeout_ms)
+    }
+}
+
+/// Outputs from what a container printed: a JSON object, or nothing
+fn parse_container_output(stdout: &[u8]) -> Result<HashMap<String, Value>, AgentError> {
+    if stdout.iter().all(u8::is_ascii_whitespace) {
+        return Ok(HashMap::new());
+    }
+    match serde_json::from_slice(stdout) {
+        Ok(Value::Object(outputs)) => Ok(outputs.into_iter().collect()),
+        _ => Err(AgentError::DeployError(format!(
+            "Container output is not a JSON object: {}",
+            String::from_utf8_lossy(stdout).chars().take(200).collect::<String>()
+        ))),
+    }
 }
 
 /// Log node runner
@@ -282,17 +667,11 @@ pub struct LogNodeRunner {
 
 impl LogNodeRunner {
     pub fn new(node: &Node) -> Result<Self, AgentError> {
-        let prefix = node
-            .data
-            .config
-            .get("prefix")
-            .and_then(|v| v.as_str())
-            .unwrap_or("[LOG]")
-            .to_string();
+        let config: LogConfig = node_config::parse(node)?;
 
         Ok(Self {
             node_id: node.id.clone(),
-            prefix,
+            prefix: config.prefix,
         })
     }
 }
@@ -300,7 +679,7 @@ impl LogNodeRunner {
 #[async_trait]
 impl NodeRunner for LogNodeRunner {
     async fn execute(&self, inputs: HashMap<String, Value>) -> Result<HashMap<String, Value>, AgentError> {
-        info!("{} {:?}", self.prefix, inputs);
+        info!("{} [{}] {:?}", self.prefix, self.node_id, inputs);
         Ok(inputs)
     }
 
@@ -309,6 +688,204 @@ impl NodeRunner for LogNodeRunner {
     }
 }
 
+/// Metrics node runner: outputs the current system metrics, one output per
+/// `SystemMetrics` field
+pub struct MetricsReadNodeRunner {
+    node_id: String,
+    metrics: Arc<MetricsCollector>,
+}
+
+impl MetricsReadNodeRunner {
+    pub fn new(node: &Node, context: &NodeContext) -> Self {
+        let metrics = context
+            .metrics
+            .clone()
+            .unwrap_or_else(|| Arc::new(MetricsCollector::new(Duration::ZERO)));
+        Self {
+            node_id: node.id.clone(),
+            metrics,
+        }
+    }
+}
+
+#[async_trait]
+impl NodeRunner for MetricsReadNodeRunner {
+    async fn execute(&self, _inputs: HashMap<String, Value>) -> Result<HashMap<String, Value>, AgentError> {
+        let metrics = self.metrics.clone();
+        let metrics = tokio::task::spawn_blocking(move || metrics.latest())
+            .await
+            .map_err(|e| AgentError::Internal(format!("Metrics collection failed: {}", e)))?;
+        debug!("Metrics [{}]: cpu {:.1}%", self.node_id, metrics.cpu_usage);
+
+        let Value::Object(fields) = serde_json::to_value(metrics)? else {
+            return Err(AgentError::Internal("Metrics are not an object".to_string()));
+        };
+        Ok(fields.into_iter().collect())
+    }
+
+    fn node_type(&self) -> &str {
+        "metrics_read"
+    }
+}
+
+/// What an accumulator has gathered so far
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct AccumulatorState {
+    count: i64,
+    values: VecDeque<Value>,
+}
+
+/// Accumulator node runner: keeps a counter, the latest values or their
+/// moving average across executions, and outputs it as `value`. A `reset`
+/// input set to true starts over.
+///
+/// The state lives as long as the runner: it is kept while the workflow is
+/// paused, resumed, stopped and started again, and starts over when the
+/// workflow is redeployed, which creates new runners. With `persist` it is
+/// saved after every execution and loaded again by the new runner, so it
+/// also survives redeploys and agent restarts.
+pub struct AccumulatorNodeRunner {
+    node_id: String,
+    /// `accumulator` or its alias `state`
+    node_type: String,
+    config: AccumulatorConfig,
+    state_file: Option<File>,
+    /// Loaded on first use; held across the update and save so concurrent
+    /// executions apply in order
+    state: tokio::sync::Mutex<Option<AccumulatorState>>,
+}
+
+impl AccumulatorNodeRunner {
+    pub fn new(node: &Node, context: &NodeContext) -> Result<Self, AgentError> {
+        let config: AccumulatorConfig = node_config::parse(node)?;
+        if config.window == 0 {
+            return Err(AgentError::ConfigError(format!(
+                "Accumulator node {} needs a window of at least 1",
+                node.id
+            )));
+        }
+
+        let state_file = match (&context.state_dir, config.persist) {
+            (Some(dir), true) => {
+                // Node IDs come from the workflow and may hold any character;
+                // their hash is a safe file name that no other node shares
+                let name = sha256_hash(node.id.as_bytes());
+                Some(File::new(dir.join(format!("{}.json", name))))
+            }
+            (None, true) => {
+                warn!("Accumulator node {} cannot persist here; keeping it in memory", node.id);
+                None
+            }
+            (_, false) => None,
+        };
+
+        Ok(Self {
+            node_id: node.id.clone(),
+            node_type: node.node_type.clone(),
+            config,
+            state_file,
+            state: tokio::sync::Mutex::new(None),
+        })
+    }
+
+    /// The persisted state, or an empty one
+    async fn load(&self) -> AccumulatorState {
+        let Some(file) = &self.state_file else {
+            return AccumulatorState::default();
+        };
+        if !file.exists().await {
+            return AccumulatorState::default();
+        }
+        file.read_json().await.unwrap_or_else(|e| {
+            warn!("Accumulator {} state unreadable, starting over: {}", self.node_id, e);
+            AccumulatorState::default()
+        })
+    }
+
+    /// Apply one execution's inputs and return the outputs
+    fn apply(
+        &self,
+        state: &mut AccumulatorState,
+        inputs: &HashMap<String, Value>,
+    ) -> Result<HashMap<String, Value>, AgentError> {
+        if inputs.get("reset") == Some(&Value::Bool(true)) {
+            *state = AccumulatorState::default();
+        }
+
+        let input = || {
+            inputs.get(&self.config.input).cloned().ok_or_else(|| {
+                AgentError::ValidationError(format!(
+                    "Accumulator node {} has no '{}' input",
+                    self.node_id, self.config.input
+                ))
+            })
+        };
+        let push = |state: &mut AccumulatorState, value: Value| {
+            state.values.push_back(value);
+            while state.values.len() > self.config.window {
+                state.values.pop_front();
+            }
+        };
+
+        let value = match self.config.operation {
+            AccumulatorOperation::Increment => {
+                state.count = state.count.saturating_add(self.config.step);
+                Value::from(state.count)
+            }
+            AccumulatorOperation::Append => {
+                push(state, input()?);
+                Value::Array(state.values.iter().cloned().collect())
+            }
+            AccumulatorOperation::MovingAverage => {
+                let sample = input()?;
+                if !sample.is_number() {
+                    return Err(AgentError::ValidationError(format!(
+                        "Accumulator node {} can only average numbers, got {}",
+                        self.node_id, sample
+                    )));
+                }
+                push(state, sample);
+                let sum: f64 = state.values.iter().filter_map(Value::as_f64).sum();
+                Value::from(sum / state.values.len() as f64)
+            }
+        };
+
+        Ok(HashMap::from([
+            ("value".to_string(), value),
+            ("samples".to_string(), Value::from(state.values.len())),
+        ]))
+    }
+}
+
+#[async_trait]
+impl NodeRunner for AccumulatorNodeRunner {
+    async fn execute(&self, inputs: HashMap<String, Value>) -> Result<HashMap<String, Value>, AgentError> {
+        let mut guard = self.state.lock().await;
+        let state = match guard.as_mut() {
+            Some(state) => state,
+            None => guard.insert(self.load().await),
+        };
+        let outputs = self.apply(state, &inputs)?;
+        debug!("Accumulator [{}]: {:?}", self.node_id, outputs["value"]);
+
+        if let Some(file) = &self.state_file {
+            // Failing to save is not worth failing the workflow over
+            let saved = match serde_json::to_vec(&*state) {
+                Ok(bytes) => file.write_atomic(&bytes).await,
+                Err(e) => Err(e.into()),
+            };
+            if let Err(e) = saved {
+                warn!("Failed to save accumulator {} state: {}", self.node_id, e);
+            }
+        }
+        Ok(outputs)
+    }
+
+    fn node_type(&self) -> &str {
+        &self.node_type
+    }
+}
+
 /// Passthrough node runner (for unknown node types)
 pub struct PassthroughNodeRunner {
     node_id: String,
@@ -327,11 +904,203 @@ impl PassthroughNodeRunner {
 #[async_trait]
 impl NodeRunner for PassthroughNodeRunner {
     async fn execute(&self, inputs: HashMap<String, Value>) -> Result<HashMap<String, Value>, AgentError> {
-        debug!("Passthrough node ({}): {:?}", self.node_type, inputs);
+        debug!("Passthrough node {} ({}): {:?}", self.node_id, self.node_type, inputs);
         Ok(inputs)
     }
 
     fn node_type(&self) -> &str {
         &self.node_type
     }
+
+    fn is_deterministic(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::test_utils::node;
+
+    #[tokio::test]
+    async fn test_camera_capture_respects_hardware_settings() {
+        let node = node("camera", "camera", serde_json::json!({ "device": "/nonexistent/video0" }));
+
+        let mut context = NodeContext::default();
+        let disabled = NodeRunnerFactory::create(&node, &context).unwrap();
+        let err = disabled.execute(HashMap::new()).await.unwrap_err();
+        assert!(matches!(err, AgentError::HardwareError(msg) if msg.contains("disabled")));
+
+        context.hardware.enable_camera = true;
+        let missing = NodeRunnerFactory::create(&node, &context).unwrap();
+        let err = missing.execute(HashMap::new()).await.unwrap_err();
+        assert!(matches!(err, AgentError::HardwareError(msg) if msg.contains("Failed to open")));
+    }
+
+    #[test]
+    fn test_container_node_config_and_output() {
+        let config = serde_json::json!({
+            "image": "ghcr.io/acme/detector:1",
+            "command": ["detect", "--json"],
+            "env": { "THRESHOLD": "0.5" }
+        });
+        for node_type in ["docker", "container"] {
+            let node = node(node_type, node_type, config.clone());
+            let runner = NodeRunnerFactory::create(&node, &NodeContext::default()).unwrap();
+            assert_eq!(runner.node_type(), "docker");
+        }
+        let blank_image = node("docker", "docker", serde_json::json!({ "image": " " }));
+        assert!(DockerNodeRunner::new(&blank_image).is_err());
+        assert!(DockerNodeRunner::new(&node("docker", "docker", serde_json::json!({}))).is_err());
+
+        let outputs = parse_container_output(br#"{"label":"cat","score":0.9}"#).unwrap();
+        assert_eq!(outputs["label"], "cat");
+        assert!(parse_container_output(b"\n").unwrap().is_empty());
+        assert!(matches!(
+            parse_container_output(b"done"),
+            Err(AgentError::DeployError(msg)) if msg.contains("done")
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_camera_resources_are_checked_when_enabled() {
+        let node = node("camera", "camera", serde_json::json!({ "device": "/nonexistent/video0" }));
+        let mut context = NodeContext::default();
+        let disabled = NodeRunnerFactory::create(&node, &context).unwrap();
+        disabled.check_resources().await.unwrap();
+
+        context.hardware.enable_camera = true;
+        let missing = NodeRunnerFactory::create(&node, &context).unwrap();
+        let err = missing.check_resources().await.unwrap_err();
+        assert!(matches!(err, AgentError::HardwareError(msg) if msg.contains("not available")));
+    }
+
+    #[tokio::test]
+    async fn test_http_request_sends_inputs_and_returns_response() {
+        use wiremock::matchers::{body_json, header, method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("PUT"))
+            .and(path("/items/1"))
+            .and(header("x-api-key", "secret"))
+            .and(body_json(serde_json::json!({ "on": true })))
+            .respond_with(ResponseTemplate::new(201).set_body_string("created"))
+            .mount(&server)
+            .await;
+
+        let context = NodeContext::default();
+        let url = format!("{}/items/1", server.uri());
+        let config = serde_json::json!({ "url": url, "method": "put" });
+        let runner = NodeRunnerFactory::create(&node("http_request", "http_request", config), &context).unwrap();
+        let outputs = runner
+            .execute(HashMap::from([
+                ("headers".to_string(), serde_json::json!({ "x-api-key": "secret" })),
+                ("body".to_string(), serde_json::json!({ "on": true })),
+            ]))
+            .await
+            .unwrap();
+        assert_eq!(outputs["status"], 201);
+        assert_eq!(outputs["body"], "created");
+
+        // Error statuses are results; unreachable servers are errors
+        let config = serde_json::json!({ "url": format!("{}/missing", server.uri()) });
+        let runner = NodeRunnerFactory::create(&node("http_request", "http_request", config), &context).unwrap();
+        assert_eq!(runner.execute(HashMap::new()).await.unwrap()["status"], 404);
+
+        let config = serde_json::json!({ "url": "http://127.0.0.1:1/", "timeout_ms": 1000 });
+        let runner = NodeRunnerFactory::create(&node("http_request", "http_request", config), &context).unwrap();
+        let err = runner.execute(HashMap::new()).await.unwrap_err();
+        assert!(matches!(err, AgentError::HttpError(_)));
+
+        let config = serde_json::json!({ "url": server.uri(), "method": "TRACE" });
+        let err = NodeRunnerFactory::create(&node("http_request", "http_request", config), &context).err();
+        assert!(matches!(err, Some(AgentError::ConfigError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_metrics_read_outputs_system_metrics() {
+        let context = NodeContext {
+            metrics: Some(Arc::new(MetricsCollector::new(Duration::ZERO))),
+            ..Default::default()
+        };
+        let node = node("metrics_read", "metrics_read", Value::Null);
+        let runner = NodeRunnerFactory::create(&node, &context).unwrap();
+        assert_eq!(runner.node_type(), "metrics_read");
+        assert!(!runner.is_deterministic());
+
+        let outputs = runner.execute(HashMap::new()).await.unwrap();
+        assert!(outputs["cpu_usage"].is_number());
+        assert!(outputs["memory_total"].as_u64().unwrap() > 0);
+        assert!(outputs["cpu_count"].as_u64().unwrap() > 0);
+        assert!(outputs["hostname"].is_string());
+    }
+
+    #[tokio::test]
+    async fn test_accumulator_keeps_state_across_executions() {
+        let context = NodeContext::default();
+        let config = serde_json::json!({ "operation": "increment", "step": 2 });
+        let counter = NodeRunnerFactory::create(&node("state", "state", config), &context).unwrap();
+        for expected in [2, 4, 6] {
+            assert_eq!(counter.execute(HashMap::new()).await.unwrap()["value"], expected);
+        }
+        let reset = HashMap::from([("reset".to_string(), Value::Bool(true))]);
+        assert_eq!(counter.execute(reset).await.unwrap()["value"], 2);
+
+        let config = serde_json::json!({ "operation": "moving_average", "window": 2 });
+        let average = NodeRunnerFactory::create(&node("accumulator", "accumulator", config), &context).unwrap();
+        let sample = |v: Value| HashMap::from([("value".to_string(), v)]);
+        for (value, expected) in [(1.0, 1.0), (3.0, 2.0), (7.0, 5.0)] {
+            let outputs = average.execute(sample(Value::from(value))).await.unwrap();
+            assert_eq!(outputs["value"], expected);
+        }
+        assert!(average.execute(sample(Value::from("hot"))).await.is_err());
+        assert!(average.execute(HashMap::new()).await.is_err());
+
+        let config = serde_json::json!({ "operation": "average" });
+        assert!(NodeRunnerFactory::create(&node("accumulator", "accumulator", config), &context).is_err());
+        let config = serde_json::json!({ "operation": "append", "window": 0 });
+        assert!(NodeRunnerFactory::create(&node("accumulator", "accumulator", config), &context).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_persisted_accumulator_survives_redeploy() {
+        let fs = crate::filesys::test_utils::TempFs::new();
+        let context = NodeContext {
+            state_dir: Some(fs.path("state")),
+            ..Default::default()
+        };
+        let config = serde_json::json!({ "operation": "append", "window": 2, "persist": true });
+        let node = node("accumulator", "accumulator", config);
+        let sample = |v: i64| HashMap::from([("value".to_string(), Value::from(v))]);
+
+        let first = NodeRunnerFactory::create(&node, &context).unwrap();
+        first.execute(sample(1)).await.unwrap();
+        first.execute(sample(2)).await.unwrap();
+        let file = format!("state/{}.json", sha256_hash(b"accumulator"));
+        assert!(fs.path(&file).exists());
+
+        // A new runner, as after a redeploy, picks up where the last one left
+        let second = NodeRunnerFactory::create(&node, &context).unwrap();
+        let outputs = second.execute(sample(3)).await.unwrap();
+        assert_eq!(outputs["value"], serde_json::json!([2, 3]));
+        assert_eq!(outputs["samples"], 2);
+
+        // IDs that would sanitize to the same name keep separate state
+        let config = serde_json::json!({ "operation": "increment", "persist": true });
+        let with_id = |id: &str| Node {
+            id: id.to_string(),
+            node_type: "state".to_string(),
+            ..node.clone()
+        };
+        let (mut slash, mut underscore) = (with_id("a/b"), with_id("a_b"));
+        slash.data.config = config.clone();
+        underscore.data.config = config;
+        let slash = NodeRunnerFactory::create(&slash, &context).unwrap();
+        let underscore = NodeRunnerFactory::create(&underscore, &context).unwrap();
+        assert_eq!(slash.node_type(), "state");
+        slash.execute(HashMap::new()).await.unwrap();
+        let outputs = underscore.execute(HashMap::new()).await.unwrap();
+        assert_eq!(outputs["value"], 1);
+    }
 }
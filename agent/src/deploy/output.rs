@@ -0,0 +1,137 @@
+//! Streaming deployment command output
+//!
+//! Deployment steps run `docker`, `git` and compose commands whose output
+//! (pull progress, build steps, errors) users want to follow from the
+//! backend. These commands run with their output piped, and each line is
+//! handed to a `LogSink` as soon as it is printed. A command abandoned
+//! before it exits, e.g. when the deployment attempt times out, is killed
+//! together with everything it started, so a retry does not race it.
+
+use std::process::{ExitStatus, Stdio};
+use std::sync::Arc;
+
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::{Child, Command};
+use tracing::debug;
+
+use crate::models::deployment::DeploymentLog;
+
+/// Receives deployment output lines
+pub type LogSink = Arc<dyn Fn(DeploymentLog) + Send + Sync>;
+
+/// Run `command` to completion, passing each line it prints to `log`:
+/// stdout as `info` and stderr as `warn` (git and compose report progress on
+/// stderr, so it is not necessarily an error)
+pub async fn run_logged(command: &mut Command, log: &LogSink) -> std::io::Result<ExitStatus> {
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    #[cfg(unix)]
+    command.process_group(0);
+    let mut child = GroupChild(command.spawn()?);
+    tokio::join!(
+        forward(child.0.stdout.take(), "info", log),
+        forward(child.0.stderr.take(), "warn", log)
+    );
+    child.0.wait().await
+}
+
+/// A child running in its own process group, which is killed if the child
+/// is dropped before it exited
+struct GroupChild(Child);
+
+impl Drop for GroupChild {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if matches!(self.0.try_wait(), Ok(None)) {
+            crate::terminal::exec::kill_group(&self.0);
+        }
+    }
+}
+
+/// Forward lines until `reader` closes. Invalid UTF-8 is replaced rather
+/// than ending the stream, so the child never blocks on a full pipe.
+async fn forward<R: AsyncRead + Unpin>(reader: Option<R>, level: &str, log: &LogSink) {
+    let Some(reader) = reader else {
+        return;
+    };
+    let mut reader = BufReader::new(reader);
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        match reader.read_until(b'\n', &mut buf).await {
+            Ok(0) | Err(_) => return,
+            Ok(_) => {}
+        }
+        // Progress bars redraw with carriage returns; keep the last state
+        let text = String::from_utf8_lossy(&buf);
+        let line = text.trim_end().rsplit('\r').next().unwrap_or_default().trim_end();
+        if line.is_empty() {
+            continue;
+        }
+        debug!("{}", line);
+        log(DeploymentLog {
+            level: level.to_string(),
+            message: line.to_string(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_output_lines_reach_the_sink() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let collected = lines.clone();
+        let log: LogSink = Arc::new(move |log: DeploymentLog| {
+            collected.lock().unwrap().push((log.level, log.message));
+        });
+
+        let script = "echo pulling; printf '10%%\\r50%%\\n\\n'; echo oops >&2; exit 3";
+        let status = run_logged(Command::new("sh").args(["-c", script]), &log).await.unwrap();
+        assert_eq!(status.code(), Some(3));
+
+        let mut lines = lines.lock().unwrap().clone();
+        lines.sort();
+        let expected = [("info", "50%"), ("info", "pulling"), ("warn", "oops")];
+        assert_eq!(
+            lines,
+            expected.map(|(level, message)| (level.to_string(), message.to_string()))
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_abandoned_command_is_killed_with_its_children() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let collected = lines.clone();
+        let log: LogSink = Arc::new(move |log: DeploymentLog| {
+            collected.lock().unwrap().push(log.message);
+        });
+
+        // The step's own child prints its PID, then outlives the timeout
+        let script = "sleep 60 & echo $!; wait";
+        let mut command = Command::new("sh");
+        command.args(["-c", script]);
+        let step = run_logged(&mut command, &log);
+        assert!(tokio::time::timeout(Duration::from_millis(300), step).await.is_err());
+
+        let pid = lines.lock().unwrap()[0].clone();
+        let gone = || {
+            // Reaped, or a zombie waiting for a reaper
+            std::fs::read_to_string(format!("/proc/{}/stat", pid))
+                .map_or(true, |stat| stat.rsplit(')').next().unwrap().trim().starts_with('Z'))
+        };
+        let deadline = std::time::Instant::now() + Duration::from_secs(2);
+        while !gone() && std::time::Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(gone(), "sleep {} is still running", pid);
+    }
+}
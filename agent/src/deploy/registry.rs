@@ -0,0 +1,285 @@
+//! Registry of live workflow executors
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use tokio::sync::Semaphore;
+
+use crate::app::watchdog::Watchdog;
+use crate::deploy::executor::WorkflowExecutor;
+use crate::deploy::fsm::DeploymentState;
+use crate::deploy::memo::NodeResultCache;
+use crate::deploy::node_runner::NodeContext;
+use crate::deploy::resources::ResourceLocks;
+use crate::errors::AgentError;
+use crate::models::workflow::Workflow;
+use crate::storage::layout::StorageLayout;
+
+/// Workflows allowed to run at once unless configured otherwise
+pub const DEFAULT_MAX_CONCURRENT_EXECUTIONS: usize = 16;
+
+/// Workflow executors by workflow ID
+pub struct ExecutorRegistry {
+    executors: RwLock<HashMap<String, Arc<WorkflowExecutor>>>,
+    /// One permit per workflow allowed to run at once
+    execution_slots: Arc<Semaphore>,
+    max_concurrent_executions: usize,
+    /// Independent nodes each created executor runs at once
+    max_concurrent_nodes: usize,
+    /// Shared by the executors `create` makes
+    node_context: NodeContext,
+    result_cache: Arc<NodeResultCache>,
+    resource_locks: Arc<ResourceLocks>,
+    /// Where created executors persist their state, if anywhere
+    layout: Option<StorageLayout>,
+    /// Supervises running workflows, if set
+    watchdog: Option<Arc<Watchdog>>,
+}
+
+impl Default for ExecutorRegistry {
+    fn default() -> Self {
+        Self::with_execution_limit(DEFAULT_MAX_CONCURRENT_EXECUTIONS)
+    }
+}
+
+impl ExecutorRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A registry running at most `limit` workflows at once
+    pub fn with_execution_limit(limit: usize) -> Self {
+        let limit = limit.max(1);
+        Self {
+            executors: RwLock::new(HashMap::new()),
+            execution_slots: Arc::new(Semaphore::new(limit)),
+            max_concurrent_executions: limit,
+            max_concurrent_nodes: 1,
+            node_context: NodeContext::default(),
+            result_cache: Arc::new(NodeResultCache::default()),
+            resource_locks: Arc::new(ResourceLocks::new()),
+            layout: None,
+            watchdog: None,
+        }
+    }
+
+    /// Let created executors run up to `limit` independent nodes at once
+    /// (default 1, sequential)
+    pub fn with_node_concurrency(mut self, limit: usize) -> Self {
+        self.max_concurrent_nodes = limit.max(1);
+        self
+    }
+
+    /// Give the node runners of created executors the agent's hardware
+    /// settings and services
+    pub fn with_node_context(mut self, node_context: NodeContext) -> Self {
+        self.node_context = node_context;
+        self
+    }
+
+    /// Persist the state of created executors under `layout`
+    pub fn with_layout(mut self, layout: StorageLayout) -> Self {
+        self.layout = Some(layout);
+        self
+    }
+
+    /// Report workflows whose runs stop making progress to `watchdog`
+    pub fn with_watchdog(mut self, watchdog: Arc<Watchdog>) -> Self {
+        self.watchdog = Some(watchdog);
+        self
+    }
+
+    /// An executor for `workflow` sharing the registry's node context,
+    /// result cache, hardware locks and node concurrency with every other
+    /// executor it creates.
+    /// Its stateful nodes persist their state in a directory of their own
+    /// workflow. It is not registered until `insert`ed.
+    pub fn create(&self, workflow: Workflow) -> WorkflowExecutor {
+        let mut node_context = self.node_context.clone();
+        let mut state_file = None;
+        if let Some(layout) = &self.layout {
+            let state_dir = layout.workflow_node_state_dir(&workflow.id);
+            node_context.state_dir = Some(state_dir.path().to_path_buf());
+            state_file = Some(layout.workflow_state_file(&workflow.id));
+        }
+        let executor = WorkflowExecutor::with_result_cache(workflow, self.result_cache.clone())
+            .with_resource_locks(self.resource_locks.clone())
+            .with_node_context(node_context)
+            .with_concurrency(self.max_concurrent_nodes);
+        match state_file {
+            Some(file) => executor.with_state_file(file),
+            None => executor,
+        }
+    }
+
+    /// Start a registered workflow and run it until it finishes. Fails
+    /// without starting it when `max_concurrent_executions` workflows are
+    /// already running, rather than slowing every workflow on the device.
+    pub async fn start(&self, workflow_id: &str) -> Result<(), AgentError> {
+        self.run(workflow_id, false).await
+    }
+
+    /// Start a registered workflow paused, as it was before a restart, and
+    /// run it until it finishes once resumed. It takes an execution slot
+    /// while paused, as a workflow paused mid-run does.
+    pub async fn start_paused(&self, workflow_id: &str) -> Result<(), AgentError> {
+        self.run(workflow_id, true).await
+    }
+
+    async fn run(&self, workflow_id: &str, paused: bool) -> Result<(), AgentError> {
+        let executor = self.get(workflow_id).ok_or_else(|| {
+            AgentError::NotFound(format!("Workflow {} is not deployed", workflow_id))
+        })?;
+        let _slot = self.execution_slots.clone().try_acquire_owned().map_err(|_| {
+            AgentError::WorkflowError(format!(
+                "Cannot start workflow {}: {} workflows are already running, the maximum \
+                 (max_concurrent_executions)",
+                workflow_id, self.max_concurrent_executions
+            ))
+        })?;
+        let heartbeat = self
+            .watchdog
+            .as_ref()
+            .map(|watchdog| watchdog.register(&format!("workflow {}", workflow_id)));
+        if paused {
+            executor.start_paused(heartbeat.as_ref()).await
+        } else {
+            executor.start(heartbeat.as_ref()).await
+        }
+    }
+
+    /// Workflows currently running (or paused while running) through
+    /// `start`
+    pub fn running(&self) -> usize {
+        self.max_concurrent_executions - self.execution_slots.available_permits()
+    }
+
+    pub fn max_concurrent_executions(&self) -> usize {
+        self.max_concurrent_executions
+    }
+
+    /// Register an executor, returning the one it replaces
+    pub fn insert(&self, executor: Arc<WorkflowExecutor>) -> Option<Arc<WorkflowExecutor>> {
+        let mut executors = self.executors.write().unwrap_or_else(|e| e.into_inner());
+        executors.insert(executor.workflow().id.clone(), executor)
+    }
+
+    pub fn get(&self, workflow_id: &str) -> Option<Arc<WorkflowExecutor>> {
+        let executors = self.executors.read().unwrap_or_else(|e| e.into_inner());
+        executors.get(workflow_id).cloned()
+    }
+
+    pub fn remove(&self, workflow_id: &str) -> Option<Arc<WorkflowExecutor>> {
+        let mut executors = self.executors.write().unwrap_or_else(|e| e.into_inner());
+        executors.remove(workflow_id)
+    }
+
+    /// Deployment state of every registered workflow
+    pub async fn states(&self) -> HashMap<String, DeploymentState> {
+        let executors: Vec<Arc<WorkflowExecutor>> = {
+            let executors = self.executors.read().unwrap_or_else(|e| e.into_inner());
+            executors.values().cloned().collect()
+        };
+
+        let mut states = HashMap::with_capacity(executors.len());
+        for executor in executors {
+            states.insert(executor.workflow().id.clone(), executor.state().await);
+        }
+        states
+    }
+
+    pub fn len(&self) -> usize {
+        let executors = self.executors.read().unwrap_or_else(|e| e.into_inner());
+        executors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::test_utils::workflow_with_graph;
+    use crate::models::workflow::Node;
+
+    fn executor(id: &str) -> Arc<WorkflowExecutor> {
+        executor_with_nodes(id, vec![])
+    }
+
+    fn executor_with_nodes(id: &str, nodes: Vec<Node>) -> Arc<WorkflowExecutor> {
+        Arc::new(WorkflowExecutor::new(workflow_with_graph(id, nodes, vec![])))
+    }
+
+    #[tokio::test]
+    async fn test_states_reflect_executors() {
+        let registry = ExecutorRegistry::new();
+        let deployed = executor("a");
+        deployed.deploy().await.unwrap();
+        registry.insert(deployed);
+        registry.insert(executor("b"));
+
+        let states = registry.states().await;
+        assert_eq!(states["a"], DeploymentState::Deployed);
+        assert_eq!(states["b"], DeploymentState::Pending);
+
+        registry.remove("a");
+        assert_eq!(registry.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_starts_beyond_the_limit_are_refused() {
+        let registry = Arc::new(ExecutorRegistry::with_execution_limit(1));
+        let delay: Vec<Node> = serde_json::from_value(serde_json::json!([
+            { "id": "wait", "type": "delay", "data": { "delay_ms": 200 } },
+        ]))
+        .unwrap();
+        for id in ["a", "b"] {
+            let executor = executor_with_nodes(id, delay.clone());
+            executor.deploy().await.unwrap();
+            registry.insert(executor);
+        }
+
+        // "a" takes the only slot while it runs
+        let running = tokio::spawn({
+            let registry = registry.clone();
+            async move { registry.start("a").await }
+        });
+        while registry.running() == 0 {
+            tokio::task::yield_now().await;
+        }
+        let err = registry.start("b").await.unwrap_err();
+        assert!(matches!(err, AgentError::WorkflowError(_)), "{}", err);
+        assert_eq!(registry.get("b").unwrap().state().await, DeploymentState::Deployed);
+
+        // The slot is released once the run finishes, and reused
+        running.await.unwrap().unwrap();
+        assert_eq!(registry.running(), 0);
+        registry.start("b").await.unwrap();
+        assert_eq!(registry.running(), 0);
+        assert!(matches!(registry.start("ghost").await, Err(AgentError::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn test_created_executors_run_nodes_concurrently() {
+        let delays: Vec<Node> = serde_json::from_value(serde_json::json!([
+            { "id": "a", "type": "delay", "data": { "delay_ms": 100 } },
+            { "id": "b", "type": "delay", "data": { "delay_ms": 100 } },
+            { "id": "c", "type": "delay", "data": { "delay_ms": 100 } },
+        ]))
+        .unwrap();
+        for (limit, concurrent) in [(1, false), (3, true)] {
+            let registry = ExecutorRegistry::new().with_node_concurrency(limit);
+            let executor = Arc::new(registry.create(workflow_with_graph("wf", delays.clone(), vec![])));
+            executor.deploy().await.unwrap();
+            registry.insert(executor);
+
+            let started = std::time::Instant::now();
+            registry.start("wf").await.unwrap();
+            let elapsed = started.elapsed();
+            let fast = elapsed < std::time::Duration::from_millis(250);
+            assert_eq!(fast, concurrent, "limit {}: {:?}", limit, elapsed);
+        }
+    }
+}
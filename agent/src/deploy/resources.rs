@@ -0,0 +1,64 @@
+//! Mutual exclusion for shared hardware used by workflow nodes
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::OwnedMutexGuard;
+
+/// Per-resource locks (e.g. `camera:/dev/video0`, `gpio`) shared by every
+/// node that touches the same hardware
+#[derive(Default)]
+pub struct ResourceLocks {
+    locks: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+impl ResourceLocks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Lock all given resources, waiting until each is free. Resources are
+    /// locked in sorted order so overlapping sets cannot deadlock.
+    pub async fn acquire(&self, resources: &[String]) -> Vec<OwnedMutexGuard<()>> {
+        let mut names: Vec<&String> = resources.iter().collect();
+        names.sort();
+        names.dedup();
+
+        let mut guards = Vec::with_capacity(names.len());
+        for name in names {
+            let lock = {
+                let mut locks = self.locks.lock().unwrap_or_else(|e| e.into_inner());
+                locks.entry(name.clone()).or_default().clone()
+            };
+            guards.push(lock.lock_owned().await);
+        }
+        guards
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_same_resource_is_exclusive() {
+        let locks = ResourceLocks::new();
+        let camera = vec!["camera:/dev/video0".to_string()];
+
+        let held = locks.acquire(&camera).await;
+        assert!(
+            tokio::time::timeout(Duration::from_millis(20), locks.acquire(&camera))
+                .await
+                .is_err()
+        );
+
+        // Other resources stay available
+        locks.acquire(&["gpio".to_string()]).await;
+
+        drop(held);
+        tokio::time::timeout(Duration::from_secs(1), locks.acquire(&camera))
+            .await
+            .unwrap();
+    }
+}
@@ -1,5 +1,7 @@
 //! Error types for the Ajime agent
 
+use openapi_client::models::ErrorResponse;
+use reqwest::StatusCode;
 use thiserror::Error;
 
 /// Main error type for the Ajime agent
@@ -14,6 +16,14 @@ pub enum AgentError {
     #[error("HTTP error: {0}")]
     HttpError(#[from] reqwest::Error),
 
+    /// The backend answered with a non-success status, and the
+    /// `ErrorResponse` body if it sent one
+    #[error("Backend responded {}", describe_http_status(*status, error.as_ref()))]
+    HttpStatus {
+        status: u16,
+        error: Option<ErrorResponse>,
+    },
+
     #[error("Authentication error: {0}")]
     AuthError(String),
 
@@ -56,10 +66,47 @@ pub enum AgentError {
     #[error("Not found: {0}")]
     NotFound(String),
 
+    #[error("Conflict: {0}")]
+    Conflict(String),
+
+    #[error("Timed out: {0}")]
+    Timeout(String),
+
     #[error("Internal error: {0}")]
     Internal(String),
 }
 
+impl AgentError {
+    /// An `HttpStatus` error for a `status` response with `body`
+    pub fn http_status(status: StatusCode, body: &str) -> Self {
+        AgentError::HttpStatus {
+            status: status.as_u16(),
+            error: serde_json::from_str(body).ok(),
+        }
+    }
+
+    /// Status of the backend response this error stands for, if any
+    pub fn http_status_code(&self) -> Option<u16> {
+        match self {
+            AgentError::HttpStatus { status, .. } => Some(*status),
+            AgentError::HttpError(e) => e.status().map(|status| status.as_u16()),
+            _ => None,
+        }
+    }
+}
+
+/// E.g. "404 Not Found: Workflow wf-1 does not exist (not_found)"
+fn describe_http_status(status: u16, error: Option<&ErrorResponse>) -> String {
+    let status = match StatusCode::from_u16(status) {
+        Ok(code) => code.to_string(),
+        Err(_) => status.to_string(),
+    };
+    match error {
+        Some(error) => format!("{}: {} ({})", status, error.message, error.error),
+        None => status,
+    }
+}
+
 impl From<anyhow::Error> for AgentError {
     fn from(err: anyhow::Error) -> Self {
         AgentError::Internal(err.to_string())
@@ -1,6 +1,7 @@
 //! Directory operations
 
 use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
 use tokio::fs;
 
@@ -75,6 +76,34 @@ impl Dir {
         Ok(dirs)
     }
 
+    /// Total size in bytes of the files in this directory and its
+    /// subdirectories; 0 when it does not exist
+    pub async fn size(&self) -> Result<u64, AgentError> {
+        if !self.exists().await {
+            return Ok(0);
+        }
+
+        let mut total = 0;
+        let mut pending = vec![self.path.clone()];
+        while let Some(dir) = pending.pop() {
+            let mut entries = fs::read_dir(&dir).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let metadata = entry.metadata().await?;
+                if metadata.is_dir() {
+                    pending.push(entry.path());
+                } else if metadata.is_file() {
+                    total += metadata.len();
+                }
+            }
+        }
+        Ok(total)
+    }
+
+    /// Last-modified time of the directory itself
+    pub async fn modified(&self) -> Result<SystemTime, AgentError> {
+        Ok(fs::metadata(&self.path).await?.modified()?)
+    }
+
     /// Get a file within this directory
     pub fn file(&self, name: &str) -> crate::filesys::file::File {
         crate::filesys::file::File::new(self.path.join(name))
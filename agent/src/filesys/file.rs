@@ -110,16 +110,101 @@ impl File {
 
     /// Atomic write using a temporary file
     pub async fn write_atomic(&self, contents: &[u8]) -> Result<(), AgentError> {
+        self.write_atomic_with_mode(contents, None).await
+    }
+
+    /// Atomic write of a secret; the file is owner-read/write only (0o600)
+    /// from the moment it is created. Permissions are ignored on non-Unix
+    /// platforms.
+    pub async fn write_atomic_private(&self, contents: &[u8]) -> Result<(), AgentError> {
+        self.write_atomic_with_mode(contents, Some(0o600)).await
+    }
+
+    async fn write_atomic_with_mode(
+        &self,
+        contents: &[u8],
+        mode: Option<u32>,
+    ) -> Result<(), AgentError> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
         let temp_path = self.path.with_extension("tmp");
 
-        // Write to temp file
-        let mut file = fs::File::create(&temp_path).await?;
-        file.write_all(contents).await?;
-        file.sync_all().await?;
-        drop(file);
+        // Write to temp file; a partial one (e.g. on a full disk) is removed
+        let written = async {
+            let mut options = fs::OpenOptions::new();
+            options.write(true).create(true).truncate(true);
+            #[cfg(unix)]
+            if let Some(mode) = mode {
+                // The mode only applies to new files, so clear a stale one
+                let _ = fs::remove_file(&temp_path).await;
+                options.mode(mode);
+            }
+            #[cfg(not(unix))]
+            let _ = mode;
+            let mut file = options.open(&temp_path).await?;
+            file.write_all(contents).await?;
+            file.sync_all().await
+        }
+        .await;
+        if let Err(e) = written {
+            let _ = fs::remove_file(&temp_path).await;
+            return Err(e.into());
+        }
 
         // Rename to target
         fs::rename(&temp_path, &self.path).await?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesys::test_utils::TempFs;
+
+    #[tokio::test]
+    async fn test_json_round_trip_creates_parents() {
+        let fs = TempFs::new();
+        let file = File::new(fs.path("a/b/value.json"));
+        assert!(!file.exists().await);
+
+        file.write_json(&serde_json::json!({ "k": 1 })).await.unwrap();
+        let value: serde_json::Value = file.read_json().await.unwrap();
+        assert_eq!(value["k"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_write_atomic_replaces_contents() {
+        let fs = TempFs::new();
+        let file = File::new(fs.write("data.txt", "old"));
+
+        file.write_atomic(b"new").await.unwrap();
+        assert_eq!(file.read_string().await.unwrap(), "new");
+        assert!(!fs.path("data.tmp").exists());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_write_atomic_private_is_owner_only() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let fs = TempFs::new();
+        fs.write("secret.tmp", "stale");
+        let file = File::new(fs.path("secret"));
+
+        file.write_atomic_private(b"s3cret").await.unwrap();
+        let mode = std::fs::metadata(file.path()).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(file.read_string().await.unwrap(), "s3cret");
+    }
+
+    #[tokio::test]
+    async fn test_delete_missing_file_is_ok() {
+        let fs = TempFs::new();
+        let file = File::new(fs.path("missing.txt"));
+
+        assert!(matches!(file.read_string().await, Err(AgentError::IoError(_))));
+        file.delete().await.unwrap();
+    }
+}
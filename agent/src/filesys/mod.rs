@@ -3,3 +3,6 @@
 pub mod dir;
 pub mod file;
 pub mod relay;
+
+#[cfg(test)]
+pub(crate) mod test_utils;
@@ -1,25 +1,77 @@
 //! File system operations exposed through the relay channel.
 //!
-//! All file content is Base64-encoded so it can be safely embedded in JSON
-//! messages over the WebSocket relay.
+//! File content is Base64-encoded so it can be safely embedded in JSON
+//! messages over the WebSocket relay; streamed chunks go out as binary
+//! frames instead where the backend supports them.
+//!
+//! Operations are confined to a root directory: paths are relative to it, or
+//! absolute paths inside it, and are resolved through symlinks before use so
+//! a link cannot lead outside it.
+//!
+//! Files too large for one message are streamed in chunks instead: reads as
+//! a sequence of `FileChunk`s, writes chunk by chunk into a `.part` file that
+//! is moved into place once the last one arrives.
 
-use std::path::Path;
-use std::time::UNIX_EPOCH;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
 
 use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
 use serde::{Deserialize, Serialize};
 use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
 use crate::errors::AgentError;
+use crate::storage::layout::StorageLayout;
+use crate::utils::sha256_hash;
+
+/// Bytes per streamed chunk unless the request asks otherwise.
+pub const DEFAULT_CHUNK_SIZE: usize = 256 * 1024;
+
+/// Largest streamed chunk, in bytes.
+pub const MAX_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Where relay file operations may act and how much they may move.
+#[derive(Debug, Clone)]
+pub struct FileOptions {
+    /// Directory file commands are confined to.
+    pub root: PathBuf,
+
+    /// Largest file that may be read or written, in bytes.
+    pub max_file_size: u64,
+
+    /// Streamed reads are paced to this many bytes per second (0 =
+    /// unlimited), so a slow link does not queue up the whole file.
+    pub max_transfer_rate: u64,
+}
+
+impl Default for FileOptions {
+    fn default() -> Self {
+        Self {
+            root: StorageLayout::default().files_dir().path().to_path_buf(),
+            max_file_size: 512 * 1024 * 1024,
+            max_transfer_rate: 1024 * 1024,
+        }
+    }
+}
+
+/// Reject files over the size limit.
+fn check_size(size: u64, options: &FileOptions) -> Result<(), AgentError> {
+    if size > options.max_file_size {
+        return Err(AgentError::ValidationError(format!(
+            "File size {} bytes exceeds the {} byte limit",
+            size, options.max_file_size
+        )));
+    }
+    Ok(())
+}
 
 /// Reject paths that contain directory traversal sequences.
 ///
 /// This is a defence-in-depth guard: the server already validates paths before
 /// relaying commands, but the agent enforces the same rule independently.
-fn validate_path(path: &str) -> Result<(), AgentError> {
+pub(crate) fn validate_path(path: &str) -> Result<(), AgentError> {
     let normalized = Path::new(path);
     for component in normalized.components() {
-        use std::path::Component;
         if matches!(component, Component::ParentDir) {
             return Err(AgentError::ValidationError(
                 "Path traversal is not allowed".to_string(),
@@ -29,7 +81,51 @@ fn validate_path(path: &str) -> Result<(), AgentError> {
     Ok(())
 }
 
-/// Metadata for a single file or directory entry.
+/// Resolve `path` within `root`, following symlinks, and reject it if it
+/// ends up outside. Missing trailing components (a file about to be written)
+/// are resolved through their nearest existing ancestor.
+pub(crate) async fn resolve_in_root(root: &Path, path: &str) -> Result<PathBuf, AgentError> {
+    resolve(root, path, true).await
+}
+
+/// `resolve_in_root`, optionally leaving the last component unresolved so a
+/// symlink itself can be acted on
+async fn resolve(root: &Path, path: &str, follow_last: bool) -> Result<PathBuf, AgentError> {
+    validate_path(path)?;
+    fs::create_dir_all(root).await?;
+    let root = fs::canonicalize(root).await?;
+    let requested = root.join(path);
+
+    let mut missing = Vec::new();
+    let mut existing = requested.as_path();
+    if let (false, Some(parent), Some(name)) =
+        (follow_last, requested.parent(), requested.file_name())
+    {
+        missing.push(name.to_owned());
+        existing = parent;
+    }
+    while fs::symlink_metadata(existing).await.is_err() {
+        let (Some(parent), Some(name)) = (existing.parent(), existing.file_name()) else {
+            break;
+        };
+        missing.push(name.to_owned());
+        existing = parent;
+    }
+    let mut resolved = fs::canonicalize(existing).await?;
+    resolved.extend(missing.iter().rev());
+
+    if !resolved.starts_with(&root) {
+        return Err(AgentError::ValidationError(format!(
+            "Path {} is outside the file root",
+            path
+        )));
+    }
+    Ok(resolved)
+}
+
+/// Metadata for a single file or directory entry. For a symlink, everything
+/// but `is_symlink` and `symlink_target` describes what it points to, or the
+/// link itself when that does not exist.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FileEntry {
     pub name: String,
@@ -38,18 +134,46 @@ pub struct FileEntry {
     pub size: u64,
     /// Last-modified time as a Unix timestamp in seconds (None if unavailable).
     pub modified: Option<u64>,
+    /// Permission bits in octal, e.g. "0644" (None on non-Unix platforms).
+    pub mode: Option<String>,
+    pub is_symlink: bool,
+    /// Where a symlink points, as stored in the link.
+    pub symlink_target: Option<String>,
+}
+
+/// Octal permission bits of `metadata`.
+#[cfg(unix)]
+fn mode_string(metadata: &std::fs::Metadata) -> Option<String> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(format!("{:04o}", metadata.permissions().mode() & 0o7777))
+}
+
+#[cfg(not(unix))]
+fn mode_string(_metadata: &std::fs::Metadata) -> Option<String> {
+    None
 }
 
 /// List the contents of a directory.
 ///
 /// Entries are sorted: directories first, then files, both alphabetically.
-pub async fn list_directory(path: &str) -> Result<Vec<FileEntry>, AgentError> {
-    validate_path(path)?;
+pub async fn list_directory(
+    options: &FileOptions,
+    path: &str,
+) -> Result<Vec<FileEntry>, AgentError> {
+    let path = resolve_in_root(&options.root, path).await?;
     let mut read_dir = fs::read_dir(path).await?;
     let mut entries = Vec::new();
 
     while let Some(entry) = read_dir.next_entry().await? {
-        let metadata = entry.metadata().await?;
+        let link_metadata = entry.metadata().await?;
+        let is_symlink = link_metadata.file_type().is_symlink();
+        let (metadata, symlink_target) = if is_symlink {
+            let target = fs::read_link(entry.path()).await.ok();
+            let metadata = fs::metadata(entry.path()).await.unwrap_or(link_metadata);
+            (metadata, target.map(|t| t.to_string_lossy().into_owned()))
+        } else {
+            (link_metadata, None)
+        };
         let name = entry.file_name().to_string_lossy().into_owned();
         let full_path = entry.path().to_string_lossy().into_owned();
         let modified = metadata
@@ -64,6 +188,9 @@ pub async fn list_directory(path: &str) -> Result<Vec<FileEntry>, AgentError> {
             is_dir: metadata.is_dir(),
             size: if metadata.is_dir() { 0 } else { metadata.len() },
             modified,
+            mode: mode_string(&metadata),
+            is_symlink,
+            symlink_target,
         });
     }
 
@@ -76,21 +203,45 @@ pub async fn list_directory(path: &str) -> Result<Vec<FileEntry>, AgentError> {
     Ok(entries)
 }
 
-/// Read a file and return its contents as a Base64-encoded string.
-pub async fn read_file(path: &str) -> Result<String, AgentError> {
-    validate_path(path)?;
+/// A file's content, as returned by `read_file`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileContent {
+    /// Base64-encoded content.
+    pub content: String,
+    /// Hex SHA-256 of the content, when requested.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+/// Read a file and return its contents Base64-encoded, with their SHA-256
+/// if `include_hash` is set.
+pub async fn read_file(
+    options: &FileOptions,
+    path: &str,
+    include_hash: bool,
+) -> Result<FileContent, AgentError> {
+    let path = resolve_in_root(&options.root, path).await?;
+    check_size(fs::metadata(&path).await?.len(), options)?;
     let bytes = fs::read(path).await?;
-    Ok(BASE64.encode(&bytes))
+    Ok(FileContent {
+        content: BASE64.encode(&bytes),
+        sha256: include_hash.then(|| sha256_hash(&bytes)),
+    })
 }
 
 /// Write Base64-encoded `content` to `path`, creating parent directories as needed.
-pub async fn write_file(path: &str, content_b64: &str) -> Result<(), AgentError> {
-    validate_path(path)?;
+pub async fn write_file(
+    options: &FileOptions,
+    path: &str,
+    content_b64: &str,
+) -> Result<(), AgentError> {
+    let path = resolve_in_root(&options.root, path).await?;
     let bytes = BASE64
         .decode(content_b64)
         .map_err(|e| AgentError::ValidationError(format!("Invalid base64: {e}")))?;
+    check_size(bytes.len() as u64, options)?;
 
-    if let Some(parent) = std::path::Path::new(path).parent() {
+    if let Some(parent) = path.parent() {
         fs::create_dir_all(parent).await?;
     }
 
@@ -99,9 +250,16 @@ pub async fn write_file(path: &str, content_b64: &str) -> Result<(), AgentError>
 }
 
 /// Delete a file or directory (recursive for directories).
-pub async fn delete_path(path: &str) -> Result<(), AgentError> {
-    validate_path(path)?;
-    let metadata = fs::metadata(path).await?;
+///
+/// A symlink is removed itself, not what it points to.
+pub async fn delete_path(options: &FileOptions, path: &str) -> Result<(), AgentError> {
+    let path = resolve(&options.root, path, false).await?;
+    if path == fs::canonicalize(&options.root).await? {
+        return Err(AgentError::ValidationError(
+            "The file root cannot be deleted".to_string(),
+        ));
+    }
+    let metadata = fs::symlink_metadata(&path).await?;
     if metadata.is_dir() {
         fs::remove_dir_all(path).await?;
     } else {
@@ -109,3 +267,368 @@ pub async fn delete_path(path: &str) -> Result<(), AgentError> {
     }
     Ok(())
 }
+
+/// One piece of a streamed file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileChunk {
+    pub offset: u64,
+    pub total: u64,
+    /// Content; the relay encodes it for the wire.
+    pub chunk: Vec<u8>,
+}
+
+/// Read a file in chunks of `chunk_size` bytes (at most `MAX_CHUNK_SIZE`),
+/// handing each to `send` in order. Stops early when `send` returns false.
+/// Returns the file size and the number of chunks sent.
+pub async fn stream_file(
+    options: &FileOptions,
+    path: &str,
+    chunk_size: usize,
+    mut send: impl FnMut(FileChunk) -> bool,
+) -> Result<(u64, u64), AgentError> {
+    if chunk_size == 0 {
+        return Err(AgentError::ValidationError("Chunk size must be non-zero".to_string()));
+    }
+    let path = resolve_in_root(&options.root, path).await?;
+    let file = fs::File::open(&path).await?;
+    let total = file.metadata().await?.len();
+    check_size(total, options)?;
+
+    // Read no further than the size announced in every chunk
+    let mut file = file.take(total);
+    let mut buf = vec![0u8; chunk_size.min(MAX_CHUNK_SIZE)];
+    let started = tokio::time::Instant::now();
+    let (mut offset, mut chunks) = (0u64, 0u64);
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        let chunk = FileChunk {
+            offset,
+            total,
+            chunk: buf[..n].to_vec(),
+        };
+        if !send(chunk) {
+            return Err(AgentError::Internal(format!(
+                "Stopped streaming {:?}: the relay connection closed",
+                path
+            )));
+        }
+        offset += n as u64;
+        chunks += 1;
+
+        if options.max_transfer_rate > 0 {
+            let due = offset as f64 / options.max_transfer_rate as f64;
+            tokio::time::sleep_until(started + Duration::from_secs_f64(due)).await;
+        }
+    }
+    Ok((total, chunks))
+}
+
+/// Write one chunk of a file sent in pieces. Chunks are appended to
+/// `<path>.part` in order: offset 0 starts the file over, and any other
+/// offset must match what has been written so far. With `commit` the
+/// finished file is synced and renamed into place, as `File::write_atomic`
+/// does. Returns the bytes written so far.
+pub async fn write_chunk(
+    options: &FileOptions,
+    path: &str,
+    offset: u64,
+    content_b64: &str,
+    commit: bool,
+) -> Result<u64, AgentError> {
+    let path = resolve_in_root(&options.root, path).await?;
+    let bytes = BASE64
+        .decode(content_b64)
+        .map_err(|e| AgentError::ValidationError(format!("Invalid base64: {e}")))?;
+    let end = offset.saturating_add(bytes.len() as u64);
+    check_size(end, options)?;
+
+    let Some(name) = path.file_name() else {
+        return Err(AgentError::ValidationError("Not a file path".to_string()));
+    };
+    let part = path.with_file_name(format!("{}.part", name.to_string_lossy()));
+    let existing = fs::symlink_metadata(&part).await.ok();
+    if existing.as_ref().is_some_and(|m| m.file_type().is_symlink()) {
+        return Err(AgentError::ValidationError(format!("{:?} is a symlink", part)));
+    }
+
+    let mut file = if offset == 0 {
+        if let Some(parent) = part.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::File::create(&part).await?
+    } else {
+        let written = existing.map(|m| m.len());
+        if written != Some(offset) {
+            return Err(AgentError::ValidationError(format!(
+                "Chunk at offset {} does not follow the {} bytes written to {:?}",
+                offset,
+                written.unwrap_or(0),
+                path
+            )));
+        }
+        fs::OpenOptions::new().append(true).open(&part).await?
+    };
+    file.write_all(&bytes).await?;
+
+    if commit {
+        file.sync_all().await?;
+        drop(file);
+        fs::rename(&part, &path).await?;
+    }
+    Ok(end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesys::test_utils::TempFs;
+
+    fn files(root: PathBuf) -> FileOptions {
+        FileOptions {
+            root,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_list_directory_sorts_dirs_first() {
+        let fs = TempFs::new();
+        let files = files(fs.path(""));
+        fs.write("b.txt", "b");
+        fs.write("a.txt", "aa");
+        fs.mkdir("zdir");
+        fs.mkdir("adir");
+
+        let entries = list_directory(&files, &fs.path_str("")).await.unwrap();
+        let names: Vec<&str> = entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, vec!["adir", "zdir", "a.txt", "b.txt"]);
+
+        let a = entries.iter().find(|e| e.name == "a.txt").unwrap();
+        assert!(!a.is_dir);
+        assert_eq!(a.size, 2);
+        assert_eq!(a.path, fs.path_str("a.txt"));
+        assert!(entries.iter().filter(|e| e.is_dir).all(|e| e.size == 0));
+    }
+
+    #[tokio::test]
+    async fn test_nonexistent_paths_are_io_errors() {
+        let fs = TempFs::new();
+        let files = files(fs.path(""));
+        let missing = fs.path_str("missing");
+
+        assert!(matches!(list_directory(&files, &missing).await, Err(AgentError::IoError(_))));
+        assert!(matches!(read_file(&files, &missing, false).await, Err(AgentError::IoError(_))));
+        assert!(matches!(delete_path(&files, &missing).await, Err(AgentError::IoError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_write_read_base64_round_trip() {
+        let fs = TempFs::new();
+        let files = files(fs.path(""));
+        let path = fs.path_str("nested/dir/data.bin");
+        let bytes: Vec<u8> = (0..=255).collect();
+
+        write_file(&files, &path, &BASE64.encode(&bytes)).await.unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), bytes);
+        let read = read_file(&files, &path, false).await.unwrap();
+        assert_eq!(BASE64.decode(read.content).unwrap(), bytes);
+        assert_eq!(read.sha256, None);
+    }
+
+    #[tokio::test]
+    async fn test_write_rejects_invalid_base64() {
+        let fs = TempFs::new();
+        let files = files(fs.path(""));
+        let path = fs.path_str("bad.txt");
+
+        assert!(matches!(
+            write_file(&files, &path, "not base64!").await,
+            Err(AgentError::ValidationError(_))
+        ));
+        assert!(!fs.path("bad.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn test_delete_file_and_directory() {
+        let fs = TempFs::new();
+        let files = files(fs.path(""));
+        let file = fs.write("file.txt", "x");
+        let dir = fs.mkdir("dir");
+        fs.write("dir/inner/file.txt", "y");
+
+        delete_path(&files, &fs.path_str("file.txt")).await.unwrap();
+        delete_path(&files, &fs.path_str("dir")).await.unwrap();
+        assert!(!file.exists());
+        assert!(!dir.exists());
+    }
+
+    #[tokio::test]
+    async fn test_traversal_is_rejected() {
+        let fs = TempFs::new();
+        let files = files(fs.path(""));
+        fs.write("secret.txt", "s");
+        let path = fs.path_str("sub/../secret.txt");
+
+        let read = read_file(&files, &path, false).await;
+        assert!(matches!(read, Err(AgentError::ValidationError(_))));
+        assert!(matches!(delete_path(&files, &path).await, Err(AgentError::ValidationError(_))));
+        assert!(fs.path("secret.txt").exists());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_permission_errors_are_io_errors() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let fs = TempFs::new();
+        let files = files(fs.path(""));
+        if !crate::filesys::test_utils::permissions_enforced(&fs) {
+            // Running as root: permission bits are not enforced
+            return;
+        }
+
+        let locked = fs.mkdir("locked");
+        fs.write("locked/file.txt", "x");
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o000)).unwrap();
+
+        let list = list_directory(&files, &fs.path_str("locked")).await;
+        let read = read_file(&files, &fs.path_str("locked/file.txt"), false).await;
+        let write = write_file(&files, &fs.path_str("locked/new.txt"), "eA==").await;
+
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(matches!(list, Err(AgentError::IoError(_))));
+        assert!(matches!(read, Err(AgentError::IoError(_))));
+        assert!(matches!(write, Err(AgentError::IoError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_paths_resolve_within_root() {
+        let fs = TempFs::new();
+        let files = files(fs.path("root"));
+        fs.write("root/notes/a.txt", "a");
+        fs.write("outside.txt", "secret");
+
+        // Relative paths are taken from the root and "." is normalized away
+        let content = read_file(&files, "./notes//a.txt", false).await.unwrap();
+        assert_eq!(BASE64.decode(content.content).unwrap(), b"a");
+        let entries = list_directory(&files, ".").await.unwrap();
+        assert_eq!(entries[0].path, fs.path_str("root/notes"));
+        let resolved = resolve_in_root(&files.root, "notes/./new/b.txt").await.unwrap();
+        assert_eq!(resolved, fs.path("root/notes/new/b.txt"));
+
+        for path in ["../outside.txt", "notes/../../outside.txt", "/etc/shadow"] {
+            let read = read_file(&files, path, false).await;
+            assert!(matches!(read, Err(AgentError::ValidationError(_))));
+        }
+        let outside = fs.path_str("outside.txt");
+        assert!(matches!(delete_path(&files, &outside).await, Err(AgentError::ValidationError(_))));
+        assert!(matches!(delete_path(&files, "").await, Err(AgentError::ValidationError(_))));
+        assert!(fs.path("outside.txt").exists());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_symlinks_cannot_escape_root() {
+        let fs = TempFs::new();
+        let files = files(fs.path("root"));
+        fs.mkdir("root");
+        fs.write("outside/secret.txt", "secret");
+        std::os::unix::fs::symlink(fs.path("outside"), fs.path("root/link")).unwrap();
+
+        let escapes = [
+            read_file(&files, "link/secret.txt", false).await.map(|c| c.content),
+            list_directory(&files, "link").await.map(|_| String::new()),
+            write_file(&files, "link/new.txt", "eA==").await.map(|_| String::new()),
+        ];
+        for result in escapes {
+            assert!(matches!(result, Err(AgentError::ValidationError(_))));
+        }
+        assert!(!fs.path("outside/new.txt").exists());
+
+        // Deleting the link removes the link, not its target
+        delete_path(&files, "link").await.unwrap();
+        assert!(!fs.path("root/link").exists());
+        assert!(fs.path("outside/secret.txt").exists());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_entries_report_modes_symlinks_and_hashes() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let fs = TempFs::new();
+        let files = files(fs.path(""));
+        fs.write("run.sh", "echo hi");
+        std::fs::set_permissions(fs.path("run.sh"), std::fs::Permissions::from_mode(0o750))
+            .unwrap();
+        fs.mkdir("logs");
+        std::os::unix::fs::symlink("logs", fs.path("latest")).unwrap();
+        std::os::unix::fs::symlink("gone", fs.path("dangling")).unwrap();
+
+        let entries = list_directory(&files, ".").await.unwrap();
+        let entry = |name: &str| entries.iter().find(|e| e.name == name).unwrap();
+        assert_eq!(entry("run.sh").mode.as_deref(), Some("0750"));
+        assert!(!entry("run.sh").is_symlink);
+        assert_eq!(entry("run.sh").symlink_target, None);
+        // A link to a directory is listed as one
+        assert!(entry("latest").is_symlink && entry("latest").is_dir);
+        assert_eq!(entry("latest").symlink_target.as_deref(), Some("logs"));
+        assert!(entry("dangling").is_symlink && !entry("dangling").is_dir);
+        assert_eq!(entry("dangling").symlink_target.as_deref(), Some("gone"));
+
+        let read = read_file(&files, "run.sh", true).await.unwrap();
+        assert_eq!(read.sha256, Some(sha256_hash(b"echo hi")));
+        let json = serde_json::to_value(read_file(&files, "run.sh", false).await.unwrap()).unwrap();
+        assert_eq!(json, serde_json::json!({ "content": BASE64.encode("echo hi") }));
+    }
+
+    #[tokio::test]
+    async fn test_large_files_transfer_in_chunks() {
+        let fs = TempFs::new();
+        let files = FileOptions {
+            max_file_size: 1000,
+            max_transfer_rate: 0,
+            ..files(fs.path(""))
+        };
+        let bytes: Vec<u8> = (0..250u8).cycle().take(1000).collect();
+
+        // Written in order, the file only appears on commit
+        let mut offset = 0;
+        for (i, piece) in bytes.chunks(300).enumerate() {
+            let commit = i == 3;
+            let content = BASE64.encode(piece);
+            offset = write_chunk(&files, "model.bin", offset, &content, commit).await.unwrap();
+            assert_eq!(fs.path("model.bin").exists(), commit);
+        }
+        assert_eq!(std::fs::read(fs.path("model.bin")).unwrap(), bytes);
+        assert!(!fs.path("model.bin.part").exists());
+
+        // Out-of-order and oversized chunks are refused
+        write_chunk(&files, "next.bin", 0, "AAAA", false).await.unwrap();
+        let gap = write_chunk(&files, "next.bin", 10, "AAAA", false).await;
+        assert!(matches!(gap, Err(AgentError::ValidationError(_))));
+        let big = write_chunk(&files, "next.bin", 3, &BASE64.encode([0; 998]), true).await;
+        assert!(matches!(big, Err(AgentError::ValidationError(_))));
+
+        let mut received = Vec::new();
+        let (total, chunks) = stream_file(&files, "model.bin", 400, |chunk| {
+            assert_eq!((chunk.offset, chunk.total), (received.len() as u64, 1000));
+            received.extend(chunk.chunk);
+            true
+        })
+        .await
+        .unwrap();
+        assert_eq!((total, chunks), (1000, 3));
+        assert_eq!(received, bytes);
+        assert!(stream_file(&files, "model.bin", 400, |_| false).await.is_err());
+
+        fs.write("huge.bin", [0; 1001]);
+        let huge = stream_file(&files, "huge.bin", 400, |_| true).await;
+        assert!(matches!(huge, Err(AgentError::ValidationError(_))));
+        let read = read_file(&files, "huge.bin", false).await;
+        assert!(matches!(read, Err(AgentError::ValidationError(_))));
+    }
+}
@@ -0,0 +1,58 @@
+//! Test harness scoping file system operations to a temporary directory
+
+use std::path::PathBuf;
+
+use tempfile::TempDir;
+
+/// A temporary directory removed when dropped
+pub struct TempFs {
+    dir: TempDir,
+}
+
+impl TempFs {
+    pub fn new() -> Self {
+        Self {
+            dir: tempfile::tempdir().expect("failed to create temp dir"),
+        }
+    }
+
+    /// Absolute path of `rel` inside the temporary directory
+    pub fn path(&self, rel: &str) -> PathBuf {
+        self.dir.path().join(rel)
+    }
+
+    /// Absolute path of `rel` as a string, as taken by the relay operations
+    pub fn path_str(&self, rel: &str) -> String {
+        self.path(rel).to_string_lossy().into_owned()
+    }
+
+    /// Create a file (and its parents) with `contents`
+    pub fn write(&self, rel: &str, contents: impl AsRef<[u8]>) -> PathBuf {
+        let path = self.path(rel);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).expect("failed to create parent dirs");
+        }
+        std::fs::write(&path, contents).expect("failed to write file");
+        path
+    }
+
+    /// Create a directory (and its parents)
+    pub fn mkdir(&self, rel: &str) -> PathBuf {
+        let path = self.path(rel);
+        std::fs::create_dir_all(&path).expect("failed to create dir");
+        path
+    }
+}
+
+/// Whether permission bits are enforced for this process (they are not for root)
+#[cfg(unix)]
+pub fn permissions_enforced(fs: &TempFs) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    let probe = fs.mkdir(".permission-probe");
+    std::fs::set_permissions(&probe, std::fs::Permissions::from_mode(0o000)).unwrap();
+    let enforced = std::fs::read_dir(&probe).is_err();
+    std::fs::set_permissions(&probe, std::fs::Permissions::from_mode(0o755)).unwrap();
+    std::fs::remove_dir(&probe).unwrap();
+    enforced
+}
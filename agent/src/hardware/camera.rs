@@ -1,7 +1,33 @@
 //! Camera interface
+//!
+//! Frames are captured from V4L2 devices with `v4l2-ctl` (from v4l-utils),
+//! asking the camera for Motion-JPEG so each frame is already a JPEG image.
+
+use std::process::Stdio;
+use std::time::Duration;
+
+use tokio::io::AsyncReadExt;
+use tokio::process::Command;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info};
 
 use crate::errors::AgentError;
 
+/// Frames the camera may deliver before the captured one, letting auto
+/// exposure settle
+const SKIP_FRAMES: u32 = 3;
+
+/// JPEG start-of-image marker
+const JPEG_SOI: [u8; 2] = [0xFF, 0xD8];
+
+/// JPEG end-of-image marker
+const JPEG_EOI: [u8; 2] = [0xFF, 0xD9];
+
+/// Stream data without a complete frame beyond this is discarded
+const MAX_FRAME_BYTES: usize = 16 * 1024 * 1024;
+
 /// Camera device wrapper
 pub struct CameraDevice {
     device_path: String,
@@ -21,10 +47,30 @@ impl CameraDevice {
         }
     }
 
-    /// Open the camera device
+    /// Check the device exists, without opening it
+    pub async fn check(&self) -> Result<(), AgentError> {
+        match tokio::fs::metadata(&self.device_path).await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(AgentError::HardwareError(format!(
+                "Camera {} is not available: {}",
+                self.device_path, e
+            ))),
+        }
+    }
+
+    /// Open the camera device, checking it exists and is accessible
     pub async fn open(&mut self) -> Result<(), AgentError> {
-        // In production, this would use V4L2 or similar
-        // For now, just mark as open
+        tokio::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&self.device_path)
+            .await
+            .map_err(|e| {
+                AgentError::HardwareError(format!(
+                    "Failed to open camera {}: {}",
+                    self.device_path, e
+                ))
+            })?;
         self.is_open = true;
         Ok(())
     }
@@ -40,15 +86,125 @@ impl CameraDevice {
         self.is_open
     }
 
-    /// Capture a single frame
-    pub async fn capture_frame(&self) -> Result<Vec<u8>, AgentError> {
+    /// Capture a single frame as a JPEG image, giving up after `timeout` so
+    /// a stuck camera cannot hang the caller
+    pub async fn capture_frame(&self, timeout: Duration) -> Result<Vec<u8>, AgentError> {
+        if !self.is_open {
+            return Err(AgentError::HardwareError("Camera not open".to_string()));
+        }
+
+        let child = Command::new("v4l2-ctl")
+            .arg(format!("--device={}", self.device_path))
+            .arg(format!(
+                "--set-fmt-video=width={},height={},pixelformat=MJPG",
+                self.width, self.height
+            ))
+            .arg("--stream-mmap")
+            .arg(format!("--stream-skip={}", SKIP_FRAMES))
+            .arg("--stream-count=1")
+            .arg("--stream-to=-")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| AgentError::HardwareError(format!("Failed to run v4l2-ctl: {}", e)))?;
+
+        // Dropping the child on timeout kills it
+        let output = tokio::time::timeout(timeout, child.wait_with_output())
+            .await
+            .map_err(|_| {
+                AgentError::Timeout(format!(
+                    "Camera {} did not deliver a frame within {:?}",
+                    self.device_path, timeout
+                ))
+            })??;
+        if !output.status.success() {
+            return Err(AgentError::HardwareError(format!(
+                "Capture from {} failed: {}",
+                self.device_path,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        if !output.stdout.starts_with(&JPEG_SOI) {
+            return Err(AgentError::HardwareError(format!(
+                "Camera {} did not deliver a JPEG frame; it may not support MJPG",
+                self.device_path
+            )));
+        }
+        Ok(output.stdout)
+    }
+
+    /// Stream JPEG frames into `frames` at up to `fps` until cancelled or
+    /// the receiver is dropped. A frame that finds the queue full is
+    /// dropped, so a slow consumer never backs up memory.
+    pub async fn stream_frames(
+        &self,
+        fps: u32,
+        frames: mpsc::Sender<Vec<u8>>,
+        cancel: CancellationToken,
+    ) -> Result<(), AgentError> {
         if !self.is_open {
             return Err(AgentError::HardwareError("Camera not open".to_string()));
         }
 
-        // In production, this would capture from V4L2
-        // For now, return a placeholder
-        Ok(vec![0u8; (self.width * self.height * 3) as usize])
+        let mut child = Command::new("v4l2-ctl")
+            .arg(format!("--device={}", self.device_path))
+            .arg(format!(
+                "--set-fmt-video=width={},height={},pixelformat=MJPG",
+                self.width, self.height
+            ))
+            .arg(format!("--set-parm={}", fps))
+            .arg("--stream-mmap")
+            .arg(format!("--stream-skip={}", SKIP_FRAMES))
+            .arg("--stream-to=-")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| AgentError::HardwareError(format!("Failed to run v4l2-ctl: {}", e)))?;
+        let mut stdout = child.stdout.take().ok_or_else(|| {
+            AgentError::HardwareError("v4l2-ctl has no output stream".to_string())
+        })?;
+
+        // The camera may not honor the requested rate, so throttle here too
+        let min_interval = Duration::from_secs(1) / fps.max(1);
+        let mut last_sent: Option<Instant> = None;
+        let mut splitter = JpegFrames::default();
+        let mut chunk = vec![0u8; 64 * 1024];
+        let mut dropped = 0u64;
+        let result = loop {
+            let read = tokio::select! {
+                _ = cancel.cancelled() => break Ok(()),
+                read = stdout.read(&mut chunk) => read?,
+            };
+            if read == 0 {
+                break Err(AgentError::HardwareError(format!(
+                    "Camera stream from {} ended",
+                    self.device_path
+                )));
+            }
+
+            for frame in splitter.push(&chunk[..read]) {
+                if last_sent.is_some_and(|sent| sent.elapsed() < min_interval) {
+                    continue;
+                }
+                match frames.try_send(frame) {
+                    Ok(()) => last_sent = Some(Instant::now()),
+                    Err(mpsc::error::TrySendError::Full(_)) => {
+                        dropped += 1;
+                        debug!("Camera {} queue full; dropped a frame", self.device_path);
+                    }
+                    Err(mpsc::error::TrySendError::Closed(_)) => return Ok(()),
+                }
+            }
+        };
+
+        if dropped > 0 {
+            info!("Camera {} stream dropped {} frames", self.device_path, dropped);
+        }
+        result
     }
 
     /// Get camera resolution
@@ -62,6 +218,44 @@ impl CameraDevice {
     }
 }
 
+/// Splits a Motion-JPEG byte stream into frames. Entropy-coded JPEG data
+/// never contains an end-of-image marker, so the first one ends the frame.
+#[derive(Default)]
+pub struct JpegFrames {
+    buf: Vec<u8>,
+}
+
+impl JpegFrames {
+    /// Append `chunk` and return the frames it completes
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<Vec<u8>> {
+        self.buf.extend_from_slice(chunk);
+        let mut frames = Vec::new();
+        loop {
+            let Some(start) = find(&self.buf, &JPEG_SOI) else {
+                // Keep a trailing 0xFF, which may begin the next marker
+                let keep = usize::from(self.buf.last() == Some(&0xFF));
+                self.buf.drain(..self.buf.len() - keep);
+                break;
+            };
+            let Some(end) = find(&self.buf[start + 2..], &JPEG_EOI) else {
+                self.buf.drain(..start);
+                if self.buf.len() > MAX_FRAME_BYTES {
+                    self.buf.clear();
+                }
+                break;
+            };
+            let end = start + 2 + end + 2;
+            frames.push(self.buf[start..end].to_vec());
+            self.buf.drain(..end);
+        }
+        frames
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
 /// List available camera devices
 pub fn list_cameras() -> Vec<String> {
     let mut cameras = Vec::new();
@@ -76,3 +270,36 @@ pub fn list_cameras() -> Vec<String> {
 
     cameras
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesys::test_utils::TempFs;
+
+    #[tokio::test]
+    async fn test_missing_device_cannot_be_opened() {
+        let fs = TempFs::new();
+        let mut camera = CameraDevice::new(&fs.path_str("video9"), 640, 480);
+        assert!(matches!(camera.open().await, Err(AgentError::HardwareError(_))));
+        assert!(!camera.is_open());
+        assert!(matches!(
+            camera.capture_frame(Duration::from_secs(1)).await,
+            Err(AgentError::HardwareError(_))
+        ));
+    }
+
+    #[test]
+    fn test_stream_is_split_into_frames() {
+        let mut frames = JpegFrames::default();
+        assert!(frames.push(b"noise\xFF").is_empty());
+        assert_eq!(
+            frames.push(b"\xD8one\xFF\xD9\xFF\xD8tw"),
+            vec![b"\xFF\xD8one\xFF\xD9".to_vec()]
+        );
+        assert!(frames.push(b"o\xFF").is_empty());
+        assert_eq!(
+            frames.push(b"\xD9\xFF\xD8three\xFF\xD9"),
+            vec![b"\xFF\xD8two\xFF\xD9".to_vec(), b"\xFF\xD8three\xFF\xD9".to_vec()]
+        );
+    }
+}
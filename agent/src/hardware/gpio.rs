@@ -1,4 +1,9 @@
 //! GPIO interface
+//!
+//! With the `gpio-hardware` feature, pins are driven through the kernel's
+//! sysfs interface (`/sys/class/gpio`). Without it, pins are simulated so
+//! builds for other boards still compile and run: reads return low and
+//! writes are dropped.
 
 use crate::errors::AgentError;
 
@@ -36,14 +41,20 @@ impl From<PinState> for bool {
 pub struct GpioPin {
     pin: u8,
     mode: PinMode,
+    #[cfg(feature = "gpio-hardware")]
+    line: sysfs::Line,
 }
 
 impl GpioPin {
-    /// Create a new GPIO pin
+    /// Create a new GPIO pin, claiming the line from the kernel. The line is
+    /// released when the pin is dropped.
     pub fn new(pin: u8, mode: PinMode) -> Result<Self, AgentError> {
-        // In production, this would initialize the GPIO pin
-        // using rppal or sysfs
-        Ok(Self { pin, mode })
+        Ok(Self {
+            pin,
+            mode,
+            #[cfg(feature = "gpio-hardware")]
+            line: sysfs::Line::open(std::path::Path::new(sysfs::ROOT), pin, mode)?,
+        })
     }
 
     /// Get pin number
@@ -64,20 +75,27 @@ impl GpioPin {
             ));
         }
 
-        // In production, this would read from the actual GPIO
+        #[cfg(feature = "gpio-hardware")]
+        return self.line.read();
+        #[cfg(not(feature = "gpio-hardware"))]
         Ok(PinState::Low)
     }
 
     /// Write pin state (for output pins)
-    pub fn write(&mut self, _state: PinState) -> Result<(), AgentError> {
+    pub fn write(&mut self, state: PinState) -> Result<(), AgentError> {
         if self.mode != PinMode::Output {
             return Err(AgentError::HardwareError(
                 "Cannot write to input pin".to_string(),
             ));
         }
 
-        // In production, this would write to the actual GPIO
-        Ok(())
+        #[cfg(feature = "gpio-hardware")]
+        return self.line.write(state);
+        #[cfg(not(feature = "gpio-hardware"))]
+        {
+            let _ = state;
+            Ok(())
+        }
     }
 
     /// Set pin high
@@ -100,6 +118,17 @@ impl GpioPin {
     }
 }
 
+/// Check at deploy time that `pin` exists. Simulated pins always do.
+pub fn check_pin(pin: u8) -> Result<(), AgentError> {
+    #[cfg(feature = "gpio-hardware")]
+    return sysfs::check(std::path::Path::new(sysfs::ROOT), pin);
+    #[cfg(not(feature = "gpio-hardware"))]
+    {
+        let _ = pin;
+        Ok(())
+    }
+}
+
 /// GPIO controller for managing multiple pins
 pub struct GpioController {
     pins: std::collections::HashMap<u8, GpioPin>,
@@ -146,3 +175,197 @@ impl Default for GpioController {
         Self::new()
     }
 }
+
+/// Lines exported through `/sys/class/gpio`
+#[cfg(any(feature = "gpio-hardware", test))]
+mod sysfs {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::thread;
+    use std::time::Duration;
+
+    use tracing::{debug, warn};
+
+    use super::{PinMode, PinState};
+    use crate::errors::AgentError;
+
+    /// Root of the sysfs GPIO interface
+    #[cfg(feature = "gpio-hardware")]
+    pub const ROOT: &str = "/sys/class/gpio";
+
+    /// How long to wait for udev to make a freshly exported line writable
+    const EXPORT_WAIT: Duration = Duration::from_millis(500);
+
+    /// An exported GPIO line
+    pub struct Line {
+        root: PathBuf,
+        number: u32,
+        dir: PathBuf,
+        /// Whether we exported the line, and so must unexport it
+        exported: bool,
+    }
+
+    impl Line {
+        /// Export the line for header pin `pin` and set its direction
+        pub fn open(root: &Path, pin: u8, mode: PinMode) -> Result<Self, AgentError> {
+            let number = chip_base(root) + u32::from(pin);
+            let dir = root.join(format!("gpio{}", number));
+            let exported = !dir.exists();
+            if exported {
+                fs::write(root.join("export"), number.to_string())
+                    .map_err(|e| sysfs_error("export", number, e))?;
+            }
+            let line = Self {
+                root: root.to_path_buf(),
+                number,
+                dir,
+                exported,
+            };
+
+            let direction = match mode {
+                PinMode::Input => "in",
+                PinMode::Output => "out",
+            };
+            // The attribute files only become writable once udev has fixed
+            // their permissions, shortly after the export
+            let mut waited = Duration::ZERO;
+            loop {
+                match fs::write(line.dir.join("direction"), direction) {
+                    Ok(()) => break,
+                    Err(e) if waited >= EXPORT_WAIT => {
+                        // Dropping `line` unexports it again
+                        return Err(sysfs_error("set the direction of", number, e));
+                    }
+                    Err(_) => {
+                        thread::sleep(Duration::from_millis(20));
+                        waited += Duration::from_millis(20);
+                    }
+                }
+            }
+            debug!("Opened GPIO line {} as {:?}", number, mode);
+            Ok(line)
+        }
+
+        /// Read the live line level
+        pub fn read(&self) -> Result<PinState, AgentError> {
+            let value = fs::read_to_string(self.dir.join("value"))
+                .map_err(|e| sysfs_error("read", self.number, e))?;
+            Ok(PinState::from(value.trim() == "1"))
+        }
+
+        /// Drive the line
+        pub fn write(&self, state: PinState) -> Result<(), AgentError> {
+            let value = if bool::from(state) { "1" } else { "0" };
+            fs::write(self.dir.join("value"), value)
+                .map_err(|e| sysfs_error("write", self.number, e))
+        }
+    }
+
+    impl Drop for Line {
+        fn drop(&mut self) {
+            if !self.exported {
+                return;
+            }
+            if let Err(e) = fs::write(self.root.join("unexport"), self.number.to_string()) {
+                warn!("Failed to unexport GPIO line {}: {}", self.number, e);
+            }
+        }
+    }
+
+    /// First line number of the SoC's pin controller. Header pins are
+    /// numbered from 0 on older kernels but from the chip base (e.g. 512)
+    /// on newer ones.
+    fn chip_base(root: &Path) -> u32 {
+        pin_controller(root).map_or(0, |(base, _)| base)
+    }
+
+    /// Base and directory of the SoC's pin controller chip
+    fn pin_controller(root: &Path) -> Option<(u32, PathBuf)> {
+        fs::read_dir(root)
+            .ok()?
+            .flatten()
+            .filter(|entry| entry.file_name().to_string_lossy().starts_with("gpiochip"))
+            .filter(|entry| {
+                fs::read_to_string(entry.path().join("label"))
+                    .is_ok_and(|label| label.trim().starts_with("pinctrl-"))
+            })
+            .filter_map(|entry| {
+                let base = fs::read_to_string(entry.path().join("base")).ok()?;
+                Some((base.trim().parse().ok()?, entry.path()))
+            })
+            .min()
+    }
+
+    /// Check that header pin `pin` can be opened: the interface is present
+    /// and the pin controller has that many lines
+    pub fn check(root: &Path, pin: u8) -> Result<(), AgentError> {
+        if !root.join("export").exists() {
+            return Err(AgentError::HardwareError(format!(
+                "GPIO interface {} is not available",
+                root.display()
+            )));
+        }
+        let lines = pin_controller(root)
+            .and_then(|(_, chip)| fs::read_to_string(chip.join("ngpio")).ok())
+            .and_then(|ngpio| ngpio.trim().parse::<u32>().ok());
+        if let Some(lines) = lines.filter(|lines| u32::from(pin) >= *lines) {
+            return Err(AgentError::HardwareError(format!(
+                "GPIO pin {} does not exist; the pin controller has {} lines",
+                pin, lines
+            )));
+        }
+        Ok(())
+    }
+
+    fn sysfs_error(action: &str, number: u32, e: std::io::Error) -> AgentError {
+        AgentError::HardwareError(format!("Failed to {} GPIO line {}: {}", action, number, e))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::filesys::test_utils::TempFs;
+
+        #[test]
+        fn test_line_is_exported_driven_and_released() {
+            let fs = TempFs::new();
+            fs.write("gpiochip512/label", "pinctrl-bcm2711\n");
+            fs.write("gpiochip512/base", "512\n");
+            fs.write("gpiochip570/label", "raspberrypi-exp-gpio\n");
+            fs.write("gpiochip570/base", "570\n");
+            fs.write("export", "");
+            fs.write("unexport", "");
+            let root = fs.path("");
+
+            // An already exported line is left exported
+            fs.write("gpio529/value", "0");
+            let line = Line::open(&root, 17, PinMode::Output).unwrap();
+            assert_eq!(std::fs::read_to_string(fs.path("gpio529/direction")).unwrap(), "out");
+            line.write(PinState::High).unwrap();
+            assert_eq!(line.read().unwrap(), PinState::High);
+            drop(line);
+            assert_eq!(std::fs::read_to_string(fs.path("unexport")).unwrap(), "");
+
+            // A line we export is unexported on drop, even if opening fails
+            assert!(Line::open(&root, 4, PinMode::Input).is_err());
+            assert_eq!(std::fs::read_to_string(fs.path("export")).unwrap(), "516");
+            assert_eq!(std::fs::read_to_string(fs.path("unexport")).unwrap(), "516");
+        }
+
+        #[test]
+        fn test_check_requires_interface_and_line() {
+            let fs = TempFs::new();
+            assert!(check(&fs.path(""), 17).is_err());
+
+            fs.write("export", "");
+            fs.write("gpiochip512/label", "pinctrl-bcm2711\n");
+            fs.write("gpiochip512/base", "512\n");
+            fs.write("gpiochip512/ngpio", "58\n");
+            check(&fs.path(""), 17).unwrap();
+            assert!(matches!(
+                check(&fs.path(""), 58),
+                Err(AgentError::HardwareError(msg)) if msg.contains("58 lines")
+            ));
+        }
+    }
+}
@@ -0,0 +1,118 @@
+//! Component health tracking
+
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use serde::{Deserialize, Serialize};
+
+/// Health status of a component
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    Healthy,
+    Degraded,
+    Unhealthy,
+}
+
+/// Health of a single component
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ComponentHealth {
+    /// Component name (e.g. "mqtt", "relay")
+    pub name: String,
+
+    /// Current status
+    pub status: HealthStatus,
+
+    /// Detail, or the reason for a non-healthy status
+    pub message: Option<String>,
+
+    /// Last update (Unix epoch seconds)
+    pub updated_at: u64,
+}
+
+/// Registry of component health reported by workers
+#[derive(Default)]
+pub struct HealthRegistry {
+    components: RwLock<HashMap<String, ComponentHealth>>,
+}
+
+impl HealthRegistry {
+    /// Create an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set a component's status
+    pub fn set(&self, name: &str, status: HealthStatus, message: Option<String>) {
+        let mut components = self.components.write().unwrap_or_else(|e| e.into_inner());
+        components.insert(
+            name.to_string(),
+            ComponentHealth {
+                name: name.to_string(),
+                status,
+                message,
+                updated_at: std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs(),
+            },
+        );
+    }
+
+    /// Mark a component healthy
+    pub fn set_healthy(&self, name: &str) {
+        self.set(name, HealthStatus::Healthy, None);
+    }
+
+    /// Mark a component degraded
+    pub fn set_degraded(&self, name: &str, message: impl Into<String>) {
+        self.set(name, HealthStatus::Degraded, Some(message.into()));
+    }
+
+    /// Mark a component unhealthy
+    pub fn set_unhealthy(&self, name: &str, message: impl Into<String>) {
+        self.set(name, HealthStatus::Unhealthy, Some(message.into()));
+    }
+
+    /// Get a component's health
+    pub fn get(&self, name: &str) -> Option<ComponentHealth> {
+        let components = self.components.read().unwrap_or_else(|e| e.into_inner());
+        components.get(name).cloned()
+    }
+
+    /// Get all components, sorted by name
+    pub fn components(&self) -> Vec<ComponentHealth> {
+        let components = self.components.read().unwrap_or_else(|e| e.into_inner());
+        let mut all: Vec<ComponentHealth> = components.values().cloned().collect();
+        all.sort_by(|a, b| a.name.cmp(&b.name));
+        all
+    }
+
+    /// Overall status: the worst status of any component
+    pub fn overall(&self) -> HealthStatus {
+        let components = self.components.read().unwrap_or_else(|e| e.into_inner());
+        components
+            .values()
+            .map(|c| c.status)
+            .max()
+            .unwrap_or(HealthStatus::Healthy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overall_is_worst_status() {
+        let registry = HealthRegistry::new();
+        assert_eq!(registry.overall(), HealthStatus::Healthy);
+
+        registry.set_healthy("relay");
+        registry.set_degraded("mqtt", "auth failed");
+        assert_eq!(registry.overall(), HealthStatus::Degraded);
+
+        registry.set_healthy("mqtt");
+        assert_eq!(registry.overall(), HealthStatus::Healthy);
+    }
+}
@@ -1,42 +1,109 @@
 //! HTTP client implementation
 
-use reqwest::{Client, header};
+use std::time::Duration;
+
+use jsonwebtoken::jwk::JwkSet;
+use reqwest::{Client, RequestBuilder, Response, StatusCode, header};
 use serde::{de::DeserializeOwned, Serialize};
-use tracing::{debug, error};
+use tracing::{debug, error, warn};
 
 use crate::errors::AgentError;
+use crate::http::retry::{is_retryable_error, is_retryable_status, retry_after, RetryPolicy};
+
+/// Timeouts, connection pooling and retries of an `HttpClient`
+#[derive(Debug, Clone)]
+pub struct HttpClientOptions {
+    /// Longest a request may take, from connecting to the end of the body
+    pub request_timeout: Duration,
+
+    /// Longest connecting may take; only bounded by `request_timeout` when
+    /// unset
+    pub connect_timeout: Option<Duration>,
+
+    /// How long an idle connection is kept for reuse
+    pub pool_idle_timeout: Duration,
+
+    /// Idle connections kept per host; unlimited when unset
+    pub pool_max_idle_per_host: Option<usize>,
+
+    /// Retries of failed requests
+    pub retry: RetryPolicy,
+}
+
+impl Default for HttpClientOptions {
+    fn default() -> Self {
+        Self {
+            request_timeout: Duration::from_secs(30),
+            connect_timeout: None,
+            pool_idle_timeout: Duration::from_secs(90),
+            pool_max_idle_per_host: None,
+            retry: RetryPolicy::default(),
+        }
+    }
+}
+
+impl HttpClientOptions {
+    /// Default options, sending every request once
+    pub fn without_retries() -> Self {
+        Self {
+            retry: RetryPolicy::disabled(),
+            ..Default::default()
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), AgentError> {
+        if self.request_timeout.is_zero() {
+            return Err(AgentError::ConfigError(
+                "HTTP request timeout must be greater than zero".to_string(),
+            ));
+        }
+        if self.connect_timeout.is_some_and(|timeout| timeout.is_zero()) {
+            return Err(AgentError::ConfigError(
+                "HTTP connect timeout must be greater than zero".to_string(),
+            ));
+        }
+        self.retry.backoff.validate()
+    }
+}
 
 /// HTTP client for backend communication
 pub struct HttpClient {
     client: Client,
     base_url: String,
     device_id: Option<String>,
+    retry: RetryPolicy,
 }
 
 impl HttpClient {
     /// Create a new HTTP client
-    pub async fn new(base_url: &str) -> Result<Self, AgentError> {
-        let client = Client::builder()
-            .timeout(std::time::Duration::from_secs(30))
-            .build()?;
+    pub async fn new(base_url: &str, options: HttpClientOptions) -> Result<Self, AgentError> {
+        let mut builder = Client::builder()
+            .timeout(options.request_timeout)
+            .pool_idle_timeout(options.pool_idle_timeout);
+        if let Some(timeout) = options.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        if let Some(max_idle) = options.pool_max_idle_per_host {
+            builder = builder.pool_max_idle_per_host(max_idle);
+        }
 
         Ok(Self {
-            client,
+            client: builder.build()?,
             base_url: base_url.trim_end_matches('/').to_string(),
             device_id: None,
+            retry: options.retry,
         })
     }
 
     /// Create a new HTTP client with device ID for authentication
-    pub async fn with_device_id(base_url: &str, device_id: String) -> Result<Self, AgentError> {
-        let client = Client::builder()
-            .timeout(std::time::Duration::from_secs(30))
-            .build()?;
-
+    pub async fn with_device_id(
+        base_url: &str,
+        device_id: String,
+        options: HttpClientOptions,
+    ) -> Result<Self, AgentError> {
         Ok(Self {
-            client,
-            base_url: base_url.trim_end_matches('/').to_string(),
             device_id: Some(device_id),
+            ..Self::new(base_url, options).await?
         })
     }
 
@@ -45,6 +112,37 @@ impl HttpClient {
         &self.base_url
     }
 
+    /// Send `request`, retrying it as far as the retry policy allows
+    async fn send(&self, request: RequestBuilder) -> Result<Response, AgentError> {
+        let request = request.build()?;
+        let retries = self.retry.retries_for(request.method());
+        let mut attempt = 0;
+        loop {
+            // The last attempt sends the request itself
+            let copy = if attempt < retries { request.try_clone() } else { None };
+            let Some(copy) = copy else {
+                return Ok(self.client.execute(request).await?);
+            };
+            let (reason, wait) = match self.client.execute(copy).await {
+                Ok(response) if is_retryable_status(response.status()) => {
+                    (response.status().to_string(), retry_after(response.headers()))
+                }
+                Err(e) if is_retryable_error(&e) => (e.to_string(), None),
+                result => return Ok(result?),
+            };
+            let delay = self.retry.delay(attempt, wait);
+            warn!(
+                "{} {} failed ({}), retrying in {:?}",
+                request.method(),
+                request.url(),
+                reason,
+                delay
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
     /// Make a GET request
     pub async fn get<T: DeserializeOwned>(&self, path: &str, token: &str) -> Result<T, AgentError> {
         let url = format!("{}{}", self.base_url, path);
@@ -60,13 +158,13 @@ impl HttpClient {
             request = request.header("X-Device-ID", device_id);
         }
 
-        let response = request.send().await?;
+        let response = self.send(request).await?;
 
         if !response.status().is_success() {
             let status = response.status();
             let body = response.text().await.unwrap_or_default();
             error!("HTTP GET failed: {} - {}", status, body);
-            return Err(AgentError::ConfigError(format!("{}: {}", status, body)));
+            return Err(AgentError::http_status(status, &body));
         }
 
         let body = response.json().await?;
@@ -94,13 +192,13 @@ impl HttpClient {
             request = request.header("X-Device-ID", device_id);
         }
 
-        let response = request.send().await?;
+        let response = self.send(request).await?;
 
         if !response.status().is_success() {
             let status = response.status();
             let body = response.text().await.unwrap_or_default();
             error!("HTTP POST failed: {} - {}", status, body);
-            return Err(AgentError::ConfigError(format!("{}: {}", status, body)));
+            return Err(AgentError::http_status(status, &body));
         }
 
         let body = response.json().await?;
@@ -128,13 +226,13 @@ impl HttpClient {
             request = request.header("X-Device-ID", device_id);
         }
 
-        let response = request.send().await?;
+        let response = self.send(request).await?;
 
         if !response.status().is_success() {
             let status = response.status();
             let body = response.text().await.unwrap_or_default();
             error!("HTTP PUT failed: {} - {}", status, body);
-            return Err(AgentError::ConfigError(format!("{}: {}", status, body)));
+            return Err(AgentError::http_status(status, &body));
         }
 
         let body = response.json().await?;
@@ -162,13 +260,13 @@ impl HttpClient {
             request = request.header("X-Device-ID", device_id);
         }
 
-        let response = request.send().await?;
+        let response = self.send(request).await?;
 
         if !response.status().is_success() {
             let status = response.status();
             let body = response.text().await.unwrap_or_default();
             error!("HTTP PATCH failed: {} - {}", status, body);
-            return Err(AgentError::ConfigError(format!("{}: {}", status, body)));
+            return Err(AgentError::http_status(status, &body));
         }
 
         let body = response.json().await?;
@@ -181,15 +279,21 @@ impl HttpClient {
         activation_token: &str,
         device_name: &str,
         device_type: Option<&str>,
+        registration: Option<&DeviceRegistration>,
     ) -> Result<DeviceActivationResponse, AgentError> {
         let url = format!("{}/agent/devices/activate", self.base_url);
         debug!("POST {} (activation)", url);
 
-        let body = serde_json::json!({
+        let mut body = serde_json::json!({
             "activation_token": activation_token,
             "device_name": device_name,
             "device_type": device_type,
         });
+        // Older backends only know the fields above, so send these only when set
+        if let Some(registration) = registration {
+            body["capabilities"] = serde_json::json!(registration.capabilities);
+            body["metadata"] = registration.metadata.clone();
+        }
 
         let response = self.client.post(&url).json(&body).send().await?;
 
@@ -227,10 +331,12 @@ impl HttpClient {
             let status = response.status();
             let body = response.text().await.unwrap_or_default();
             error!("Token refresh failed: {} - {}", status, body);
-            return Err(AgentError::TokenError(format!(
-                "Token refresh failed: {} - {}",
-                status, body
-            )));
+            let message = format!("Token refresh failed: {} - {}", status, body);
+            // A rejected token will not become valid by retrying
+            return Err(match status {
+                StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => AgentError::AuthError(message),
+                _ => AgentError::TokenError(message),
+            });
         }
 
         #[derive(serde::Deserialize)]
@@ -241,6 +347,50 @@ impl HttpClient {
         let body: TokenResponse = response.json().await?;
         Ok(body.token)
     }
+
+    /// Get the public keys the backend signs device tokens with
+    pub async fn get_jwks(&self) -> Result<JwkSet, AgentError> {
+        let url = format!("{}/.well-known/jwks.json", self.base_url);
+        debug!("GET {}", url);
+
+        let response = self.send(self.client.get(&url)).await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            return Err(AgentError::TokenError(format!(
+                "Fetching token signing keys failed: {} - {}",
+                status, body
+            )));
+        }
+
+        let body = response.json().await?;
+        Ok(body)
+    }
+
+    /// Seconds the local clock is ahead of the backend's, from the `Date`
+    /// header of any response; `None` if the backend sends none
+    pub async fn clock_skew(&self) -> Result<Option<i64>, AgentError> {
+        debug!("HEAD {}", self.base_url);
+
+        let sent = chrono::Utc::now();
+        let response = self.client.head(&self.base_url).send().await?;
+        let received = chrono::Utc::now();
+
+        let skew = response
+            .headers()
+            .get(header::DATE)
+            .and_then(|date| date.to_str().ok())
+            .and_then(|date| crate::clock::skew_from_date(date, sent, received));
+        Ok(skew)
+    }
+}
+
+/// Optional details registered with the device at activation
+#[derive(Debug, Clone, Default)]
+pub struct DeviceRegistration {
+    pub capabilities: Vec<String>,
+    pub metadata: serde_json::Value,
 }
 
 /// Device activation response
@@ -251,3 +401,365 @@ pub struct DeviceActivationResponse {
     pub token: String,
     pub device_name: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::workflows::WorkflowDigest;
+    use wiremock::matchers::{body_partial_json, header, header_exists, method, path};
+    use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_requests_carry_auth_and_device_headers() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/agent/devices/dev-1/deployments"))
+            .and(header("Authorization", "Bearer tok"))
+            .and(header("X-Device-ID", "dev-1"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "deployments": []
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = HttpClient::with_device_id(
+            &server.uri(),
+            "dev-1".to_string(),
+            HttpClientOptions::without_retries(),
+        )
+        .await
+        .unwrap();
+        let deployments = client.get_pending_deployments("dev-1", "tok").await.unwrap();
+        assert!(deployments.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_transient_failures_are_retried_for_gets_only() {
+        let server = MockServer::start().await;
+        let policy = RetryPolicy {
+            backoff: crate::utils::CooldownOptions {
+                base_delay: std::time::Duration::from_millis(1),
+                max_delay: std::time::Duration::from_millis(10),
+                multiplier: 2.0,
+            },
+            ..Default::default()
+        };
+        let options = HttpClientOptions {
+            retry: policy,
+            ..Default::default()
+        };
+        let client = HttpClient::new(&server.uri(), options).await.unwrap();
+
+        // A 503 and a rate limit, then the answer
+        for failure in [
+            ResponseTemplate::new(503),
+            ResponseTemplate::new(429).insert_header("Retry-After", "0"),
+        ] {
+            Mock::given(method("GET"))
+                .respond_with(failure)
+                .up_to_n_times(1)
+                .mount(&server)
+                .await;
+        }
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({ "ok": 1 })))
+            .mount(&server)
+            .await;
+        let body: serde_json::Value = client.get("/status", "tok").await.unwrap();
+        assert_eq!(body["ok"], 1);
+        assert_eq!(server.received_requests().await.unwrap().len(), 3);
+
+        // Client errors are final
+        server.reset().await;
+        Mock::given(method("GET")).respond_with(ResponseTemplate::new(404)).mount(&server).await;
+        assert!(client.get::<serde_json::Value>("/status", "tok").await.is_err());
+        assert_eq!(server.received_requests().await.unwrap().len(), 1);
+
+        // A POST may already have taken effect, so it is sent once
+        server.reset().await;
+        Mock::given(method("POST")).respond_with(ResponseTemplate::new(503)).mount(&server).await;
+        let err = client
+            .post::<serde_json::Value, _>("/status", "tok", &serde_json::json!({}))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AgentError::HttpStatus { status: 503, .. }));
+        assert_eq!(server.received_requests().await.unwrap().len(), 1);
+
+        // Persistent failures give up after the last retry
+        server.reset().await;
+        Mock::given(method("GET")).respond_with(ResponseTemplate::new(502)).mount(&server).await;
+        assert!(client.get::<serde_json::Value>("/status", "tok").await.is_err());
+        assert_eq!(server.received_requests().await.unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_slow_responses_time_out_as_configured() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(5)))
+            .mount(&server)
+            .await;
+        let options = HttpClientOptions {
+            request_timeout: Duration::from_millis(100),
+            connect_timeout: Some(Duration::from_millis(100)),
+            ..HttpClientOptions::without_retries()
+        };
+        let client = HttpClient::new(&server.uri(), options).await.unwrap();
+
+        let started = std::time::Instant::now();
+        let err = client.get::<serde_json::Value>("/slow", "tok").await.unwrap_err();
+        assert!(matches!(err, AgentError::HttpError(e) if e.is_timeout()));
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        let no_timeout = HttpClientOptions {
+            connect_timeout: Some(Duration::ZERO),
+            ..Default::default()
+        };
+        assert!(no_timeout.validate().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_client_without_device_id_omits_header() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/agent/workflows/wf-1"))
+            .and(header_exists("X-Device-ID"))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/agent/workflows/wf-1"))
+            .respond_with(ResponseTemplate::new(404).set_body_string("missing"))
+            .mount(&server)
+            .await;
+
+        let client =
+
+            HttpClient::new(&server.uri(), HttpClientOptions::without_retries()).await.unwrap();
+        let err = client.get_workflow("wf-1", "tok").await.unwrap_err();
+        assert!(matches!(err, AgentError::HttpStatus { status: 404, error: None }));
+    }
+
+    #[tokio::test]
+    async fn test_activate_device_path_and_errors() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/agent/devices/activate"))
+            .and(body_partial_json(serde_json::json!({
+                "activation_token": "act",
+                "device_name": "pi",
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "device_id": "dev-1",
+                "owner_id": "owner",
+                "token": "tok",
+                "device_name": "pi",
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client =
+
+            HttpClient::new(&server.uri(), HttpClientOptions::without_retries()).await.unwrap();
+        let response = client.activate_device("act", "pi", None, None).await.unwrap();
+        assert_eq!(response.device_id, "dev-1");
+        assert_eq!(response.token, "tok");
+
+        // Activation is unauthenticated, and without registration details the
+        // body is what older backends expect
+        let requests: Vec<Request> = server.received_requests().await.unwrap();
+        assert!(!requests[0].headers.contains_key("authorization"));
+        let body: serde_json::Value = requests[0].body_json().unwrap();
+        assert!(body.get("metadata").is_none() && body.get("capabilities").is_none());
+
+        server.reset().await;
+        Mock::given(method("POST"))
+            .and(path("/agent/devices/activate"))
+            .respond_with(ResponseTemplate::new(401).set_body_string("bad token"))
+            .mount(&server)
+            .await;
+        let err = client.activate_device("act", "pi", None, None).await.unwrap_err();
+        assert!(matches!(err, AgentError::AuthError(msg) if msg.contains("401")));
+    }
+
+    #[tokio::test]
+    async fn test_activate_device_sends_registration() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/agent/devices/activate"))
+            .and(body_partial_json(serde_json::json!({
+                "device_type": "raspberry_pi",
+                "capabilities": ["docker", "terminal"],
+                "metadata": { "location": "lab" },
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "device_id": "dev-1",
+                "owner_id": "owner",
+                "token": "tok",
+                "device_name": "pi",
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let registration = DeviceRegistration {
+            capabilities: vec!["docker".to_string(), "terminal".to_string()],
+            metadata: serde_json::json!({ "location": "lab", "agent_version": "1.0.0" }),
+        };
+        let client =
+            HttpClient::new(&server.uri(), HttpClientOptions::without_retries()).await.unwrap();
+        client
+            .activate_device("act", "pi", Some("raspberry_pi"), Some(&registration))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_refresh_device_token_path_and_errors() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/agent/devices/dev-1/token/refresh"))
+            .and(header("Authorization", "Bearer old"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({ "token": "new" })),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = HttpClient::with_device_id(
+            &server.uri(),
+            "dev-1".to_string(),
+            HttpClientOptions::without_retries(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(client.refresh_device_token("dev-1", "old").await.unwrap(), "new");
+
+        server.reset().await;
+        Mock::given(method("POST"))
+            .and(path("/agent/devices/dev-1/token/refresh"))
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&server)
+            .await;
+        let err = client.refresh_device_token("dev-1", "old").await.unwrap_err();
+        assert!(matches!(err, AgentError::TokenError(msg) if msg.contains("503")));
+
+        server.reset().await;
+        Mock::given(method("POST"))
+            .and(path("/agent/devices/dev-1/token/refresh"))
+            .respond_with(ResponseTemplate::new(401))
+            .mount(&server)
+            .await;
+        let err = client.refresh_device_token("dev-1", "old").await.unwrap_err();
+        assert!(matches!(err, AgentError::AuthError(msg) if msg.contains("401")));
+    }
+
+    #[tokio::test]
+    async fn test_get_jwks() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/.well-known/jwks.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "keys": [{ "kty": "oct", "kid": "k1", "alg": "HS256", "k": "c2VjcmV0" }],
+            })))
+            .mount(&server)
+            .await;
+
+        let client =
+
+            HttpClient::new(&server.uri(), HttpClientOptions::without_retries()).await.unwrap();
+        let jwks = client.get_jwks().await.unwrap();
+        assert!(jwks.find("k1").is_some());
+
+        server.reset().await;
+        let err = client.get_jwks().await.unwrap_err();
+        assert!(matches!(err, AgentError::TokenError(msg) if msg.contains("404")));
+    }
+
+    #[tokio::test]
+    async fn test_clock_skew_from_date_header() {
+        let server = MockServer::start().await;
+        let hour_ago = chrono::Utc::now() - chrono::Duration::hours(1);
+        Mock::given(method("HEAD"))
+            .respond_with(ResponseTemplate::new(404).insert_header("Date", hour_ago.to_rfc2822()))
+            .mount(&server)
+            .await;
+
+        let client =
+
+            HttpClient::new(&server.uri(), HttpClientOptions::without_retries()).await.unwrap();
+        let skew = client.clock_skew().await.unwrap().unwrap();
+        assert!((3595..=3605).contains(&skew), "{}", skew);
+    }
+
+    #[tokio::test]
+    async fn test_sync_workflows_path_and_errors() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/agent/devices/dev-1/workflows/sync"))
+            .and(header("Authorization", "Bearer tok"))
+            .and(header("X-Device-ID", "dev-1"))
+            .and(body_partial_json(serde_json::json!([{ "workflow_id": "wf-1" }])))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "workflows": [],
+                "digests": [],
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = HttpClient::with_device_id(
+            &server.uri(),
+            "dev-1".to_string(),
+            HttpClientOptions::without_retries(),
+        )
+        .await
+        .unwrap();
+        let digests = vec![WorkflowDigest {
+            workflow_id: "wf-1".to_string(),
+            digest: "abc".to_string(),
+            updated_at: "2024-01-01T00:00:00Z".to_string(),
+        }];
+        let response = client.sync_workflows("dev-1", "tok", &digests).await.unwrap();
+        assert!(response.workflows.is_empty());
+
+        server.reset().await;
+        Mock::given(method("POST"))
+            .and(path("/agent/devices/dev-1/workflows/sync"))
+            .respond_with(ResponseTemplate::new(500).set_body_json(serde_json::json!({
+                "error": "internal_error",
+                "message": "boom",
+                "details": { "trace_id": "t1" },
+            })))
+            .mount(&server)
+            .await;
+        let err = client.sync_workflows("dev-1", "tok", &digests).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Backend responded 500 Internal Server Error: boom (internal_error)"
+        );
+        let AgentError::HttpStatus { status: 500, error: Some(body) } = err else {
+            panic!("unexpected error: {:?}", err);
+        };
+        assert_eq!(body.details.unwrap()["trace_id"], "t1");
+    }
+
+    #[tokio::test]
+    async fn test_malformed_success_body_is_http_error() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/agent/devices/dev-1/token/refresh"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("not json"))
+            .mount(&server)
+            .await;
+
+        let client =
+
+            HttpClient::new(&server.uri(), HttpClientOptions::without_retries()).await.unwrap();
+        let err = client.refresh_device_token("dev-1", "old").await.unwrap_err();
+        assert!(matches!(err, AgentError::HttpError(_)));
+    }
+}
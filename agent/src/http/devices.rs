@@ -1,5 +1,6 @@
 //! Device API client
 
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
 use crate::errors::AgentError;
@@ -10,11 +11,36 @@ use crate::telemetry::SystemMetrics;
 #[derive(Debug, Clone, Serialize)]
 pub struct DeviceStatusUpdate {
     pub status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
     pub agent_version: String,
     pub last_sync_at: Option<u64>,
     pub metrics: Option<SystemMetrics>,
 }
 
+/// Periodic device record update, keeping `last_seen` fresh while idle
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceHeartbeat {
+    pub last_seen: DateTime<Utc>,
+    pub agent_version: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// Omitted when already advertised another way
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub capabilities: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "serde_json::Value::is_null")]
+    pub metadata: serde_json::Value,
+    /// Free disk space is below the agent's threshold
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub storage_full: bool,
+    /// Seconds the device clock is ahead of the backend's, once measured
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clock_skew_secs: Option<i64>,
+    /// The system reports its clock as not NTP-synchronized
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub clock_unsynced: bool,
+}
+
 /// Device sync request
 #[derive(Debug, Clone, Serialize)]
 pub struct DeviceSyncRequest {
@@ -44,6 +70,18 @@ impl HttpClient {
         Ok(())
     }
 
+    /// Send a heartbeat to the device record
+    pub async fn send_heartbeat(
+        &self,
+        device_id: &str,
+        token: &str,
+        heartbeat: &DeviceHeartbeat,
+    ) -> Result<(), AgentError> {
+        let path = format!("/agent/devices/{}", device_id);
+        let _: serde_json::Value = self.patch(&path, token, heartbeat).await?;
+        Ok(())
+    }
+
     /// Sync device with backend
     pub async fn sync_device(
         &self,
@@ -3,4 +3,5 @@
 pub mod client;
 pub mod devices;
 pub mod workflows;
-pub mod deployments;
\ No newline at end of file
+pub mod deployments;
+pub mod retry;
\ No newline at end of file
@@ -0,0 +1,137 @@
+//! Retries of failed backend requests
+//!
+//! A network blip during sync or deployment polling would otherwise surface
+//! as a hard error and put the sync into cooldown. Requests that fail to
+//! connect or time out, or that get a 5xx or 429 response, are retried with
+//! exponential backoff, waiting as long as a `Retry-After` header asks (up to
+//! the backoff's cap). Only GETs are retried by default: retrying a POST, PUT
+//! or PATCH may repeat its side effects.
+
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{Method, StatusCode};
+
+use crate::utils::{calc_exp_backoff, CooldownOptions};
+
+/// When and how often failed backend requests are retried
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt; 0 disables retrying
+    pub max_retries: u32,
+
+    /// Delay between attempts
+    pub backoff: CooldownOptions,
+
+    /// Also retry POST, PUT and PATCH requests
+    pub retry_non_idempotent: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            backoff: CooldownOptions {
+                base_delay: Duration::from_millis(500),
+                max_delay: Duration::from_secs(10),
+                multiplier: 2.0,
+            },
+            retry_non_idempotent: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Send every request once
+    pub fn disabled() -> Self {
+        Self {
+            max_retries: 0,
+            ..Default::default()
+        }
+    }
+
+    /// Retries allowed for a `method` request
+    pub fn retries_for(&self, method: &Method) -> u32 {
+        if *method == Method::GET || *method == Method::HEAD || self.retry_non_idempotent {
+            self.max_retries
+        } else {
+            0
+        }
+    }
+
+    /// Delay before retry number `attempt` (from 0), or what the server asked
+    /// for in `retry_after`, capped at the backoff's max delay
+    pub fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        retry_after
+            .unwrap_or_else(|| calc_exp_backoff(&self.backoff, attempt))
+            .min(self.backoff.max_delay)
+    }
+}
+
+/// Responses worth another attempt: server errors and rate limiting
+pub fn is_retryable_status(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+/// Errors worth another attempt: the request never got an answer
+pub fn is_retryable_error(error: &reqwest::Error) -> bool {
+    error.is_connect() || error.is_timeout() || error.is_request()
+}
+
+/// How long a response's `Retry-After` header asks to wait, given in
+/// seconds or as an HTTP date
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = DateTime::parse_from_rfc2822(value).ok()?.with_timezone(&Utc);
+    Some((at - Utc::now()).to_std().unwrap_or(Duration::ZERO))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn test_policy_retries_only_idempotent_methods_by_default() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.retries_for(&Method::GET), 3);
+        assert_eq!(policy.retries_for(&Method::POST), 0);
+        assert_eq!(policy.retries_for(&Method::PATCH), 0);
+
+        let opt_in = RetryPolicy {
+            retry_non_idempotent: true,
+            ..Default::default()
+        };
+        assert_eq!(opt_in.retries_for(&Method::POST), 3);
+        assert_eq!(RetryPolicy::disabled().retries_for(&Method::GET), 0);
+
+        assert_eq!(policy.delay(0, None), Duration::from_millis(500));
+        assert_eq!(policy.delay(2, None), Duration::from_secs(2));
+        assert_eq!(policy.delay(0, Some(Duration::from_secs(3))), Duration::from_secs(3));
+        assert_eq!(policy.delay(0, Some(Duration::from_secs(600))), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_retry_after_in_seconds_or_as_date() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("7"));
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(7)));
+
+        let later = (Utc::now() + chrono::Duration::seconds(30)).to_rfc2822();
+        headers.insert(RETRY_AFTER, HeaderValue::from_str(&later).unwrap());
+        let wait = retry_after(&headers).unwrap();
+        assert!((Duration::from_secs(28)..=Duration::from_secs(30)).contains(&wait), "{:?}", wait);
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("Thu, 01 Jan 1970 00:00:00 GMT"));
+        assert_eq!(retry_after(&headers), Some(Duration::ZERO));
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("soon"));
+        assert_eq!(retry_after(&headers), None);
+    }
+}
@@ -4,7 +4,9 @@ use serde::{Deserialize, Serialize};
 
 use crate::errors::AgentError;
 use crate::http::client::HttpClient;
-use crate::models::workflow::Workflow;
+use crate::models::execution::ExecutionResult;
+use crate::models::workflow::{ExecutionState, Workflow};
+use crate::sync::syncer::WorkflowSyncError;
 
 /// Workflow list response
 #[derive(Debug, Clone, Deserialize)]
@@ -26,10 +28,19 @@ pub struct WorkflowSummary {
 /// Workflow sync response
 #[derive(Debug, Clone, Deserialize)]
 pub struct WorkflowSyncResponse {
-    pub workflows: Vec<Workflow>,
+    /// Parsed one by one by the syncer, so one this agent cannot read does
+    /// not fail the whole response, and one too large is never parsed
+    pub workflows: Vec<Box<serde_json::value::RawValue>>,
     pub digests: Vec<WorkflowDigest>,
 }
 
+/// Workflows a sync could not apply, as reported to the backend
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncErrorReport<'a> {
+    pub agent_version: &'a str,
+    pub errors: &'a [WorkflowSyncError],
+}
+
 /// Workflow digest for change detection
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WorkflowDigest {
@@ -80,35 +91,71 @@ impl HttpClient {
         self.post(&path, token, &local_digests).await
     }
 
+    /// Report workflows the last sync could not apply
+    pub async fn report_sync_errors(
+        &self,
+        device_id: &str,
+        token: &str,
+        report: &SyncErrorReport<'_>,
+    ) -> Result<(), AgentError> {
+        let path = format!("/agent/devices/{}/workflows/sync/errors", device_id);
+        let _: serde_json::Value = self.post(&path, token, report).await?;
+        Ok(())
+    }
+
     /// Report workflow execution status
     pub async fn report_workflow_status(
         &self,
         device_id: &str,
         workflow_id: &str,
         token: &str,
-        status: &WorkflowStatusReport,
+        result: &ExecutionResult,
     ) -> Result<(), AgentError> {
         let path = format!("/agent/devices/{}/workflows/{}/status", device_id, workflow_id);
-        let _: serde_json::Value = self.post(&path, token, status).await?;
+        let report = WorkflowStatusReport::new(result);
+        let _: serde_json::Value = self.post(&path, token, &report).await?;
         Ok(())
     }
 }
 
-/// Workflow status report
-#[derive(Debug, Clone, Serialize)]
-pub struct WorkflowStatusReport {
-    pub status: String,
-    pub error: Option<String>,
-    pub started_at: Option<String>,
-    pub finished_at: Option<String>,
-    pub node_statuses: Vec<NodeStatusReport>,
+/// Workflow status report: the execution result, plus the per-node
+/// `node_statuses` that backends predating typed results read
+#[derive(Debug, Serialize)]
+struct WorkflowStatusReport<'a> {
+    #[serde(flatten)]
+    result: &'a ExecutionResult,
+    node_statuses: Vec<NodeStatusReport<'a>>,
 }
 
-/// Node status report
-#[derive(Debug, Clone, Serialize)]
-pub struct NodeStatusReport {
-    pub node_id: String,
-    pub status: String,
-    pub error: Option<String>,
-    pub outputs: Option<serde_json::Value>,
+/// Node status in the form of `node_statuses`
+#[derive(Debug, Serialize)]
+struct NodeStatusReport<'a> {
+    node_id: &'a str,
+    status: &'a ExecutionState,
+    error: Option<&'a str>,
+    outputs: Option<serde_json::Value>,
+}
+
+impl<'a> WorkflowStatusReport<'a> {
+    fn new(result: &'a ExecutionResult) -> Self {
+        let node_statuses = result
+            .nodes
+            .iter()
+            .map(|node| NodeStatusReport {
+                node_id: &node.node_id,
+                status: &node.status,
+                error: node.error.as_deref(),
+                outputs: (!node.outputs.is_empty()).then(|| {
+                    node.outputs
+                        .iter()
+                        .map(|output| (output.port.clone(), output.value.to_json()))
+                        .collect()
+                }),
+            })
+            .collect();
+        Self {
+            result,
+            node_statuses,
+        }
+    }
 }
@@ -4,7 +4,9 @@ use std::collections::HashMap;
 
 use tracing::{error, info};
 
-use crate::http::client::HttpClient;
+use crate::app::options::AppOptions;
+use crate::http::client::{HttpClient, HttpClientOptions};
+use crate::installer::metadata;
 use crate::logs::{init_logging, LogOptions};
 use crate::storage::device::Device;
 use crate::storage::layout::StorageLayout;
@@ -56,40 +58,68 @@ async fn install_impl(cli_args: &HashMap<String, String>) -> Result<(), Box<dyn
     let device_name = cli_args
         .get("name")
         .cloned()
-        .or_else(|| get_hostname())
+        .or_else(get_hostname)
         .unwrap_or_else(|| "ajime-device".to_string());
 
     // Get device type
     let device_type = cli_args
         .get("type")
         .cloned()
-        .or_else(|| detect_device_type());
+        .or_else(detect_device_type);
 
     println!("Device name: {}", device_name);
     if let Some(ref dt) = device_type {
         println!("Device type: {}", dt);
     }
-    println!();
 
-    // Setup storage layout
+    // Settings kept from an earlier install, so a re-install reports what
+    // the agent will run with
     let layout = StorageLayout::default();
-    println!("Setting up storage at: {:?}", layout.base_dir);
-    layout.setup().await?;
+    let settings_file = layout.settings_file();
+    let mut settings = if settings_file.exists().await {
+        settings_file.read_json::<Settings>().await?
+    } else {
+        Settings::default()
+    };
 
     // Get backend URL from args or use default
     let backend_url = cli_args
         .get("backend")
         .cloned()
         .unwrap_or_else(|| "http://localhost:8000/api/v1".to_string());
+    settings.backend.base_url = backend_url.clone();
+
+    // Describe the device to the backend; skipped with --no-metadata
+    let version = version_info();
+    let registration = if cli_args.contains_key("no-metadata") {
+        None
+    } else {
+        let location = cli_args.get("location").cloned();
+        if let Some(ref location) = location {
+            println!("Location: {}", location);
+        }
+        let options = AppOptions::from_settings(settings.clone(), &layout)?;
+        Some(metadata::detect(location, &version.version, &options).await)
+    };
+    println!();
+
+    // Setup storage layout
+    println!("Setting up storage at: {:?}", layout.base_dir);
+    layout.setup().await?;
 
     println!("Backend URL: {}", backend_url);
     println!();
 
     // Create HTTP client and activate device
     println!("Activating device...");
-    let http_client = HttpClient::new(&backend_url).await?;
+    let http_client = HttpClient::new(&backend_url, HttpClientOptions::default()).await?;
     let activation_response = http_client
-        .activate_device(&activation_token, &device_name, device_type.as_deref())
+        .activate_device(
+            &activation_token,
+            &device_name,
+            device_type.as_deref(),
+            registration.as_ref(),
+        )
         .await?;
 
     println!("Device activated!");
@@ -98,27 +128,40 @@ async fn install_impl(cli_args: &HashMap<String, String>) -> Result<(), Box<dyn
     println!();
 
     // Create and save device file
-    let device = Device::new(
+    let mut device = Device::new(
         activation_response.device_id.clone(),
         activation_response.device_name.clone(),
         activation_response.owner_id.clone(),
         activation_response.token.clone(),
     );
+    device.device_type = device_type;
+    if let Some(registration) = registration {
+        device.capabilities = registration.capabilities;
+        device.metadata = registration.metadata;
+    }
 
     let device_file = layout.device_file();
     device_file.write_json(&device).await?;
     println!("Device credentials saved to: {:?}", device_file.path());
 
-    // Create and save settings file
-    let mut settings = Settings::default();
-    settings.backend.base_url = backend_url;
+    // Keep the activation token for re-activation if the refresh token is
+    // ever revoked; otherwise drop one left by an earlier install
+    let activation_token_file = layout.activation_token_file();
+    if cli_args.contains_key("keep-activation-token") {
+        activation_token_file
+            .write_atomic(activation_token.as_bytes())
+            .await?;
+        activation_token_file.set_permissions_600().await?;
+        println!("Activation token kept at: {:?}", activation_token_file.path());
+    } else {
+        activation_token_file.delete().await?;
+    }
 
-    let settings_file = layout.settings_file();
+    // Save the settings with the backend URL
     settings_file.write_json(&settings).await?;
     println!("Settings saved to: {:?}", settings_file.path());
 
     // Print version info
-    let version = version_info();
     println!();
     println!("Agent version: {}", version.version);
     println!("Git hash: {}", version.git_hash);
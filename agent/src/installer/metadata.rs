@@ -0,0 +1,102 @@
+//! Registration metadata sent at activation
+//!
+//! Describes the device to the backend up front (where it is, what hardware
+//! it has, what it can run) so the dashboard does not have to wait for the
+//! first telemetry report.
+
+use serde::Serialize;
+
+use crate::app::options::AppOptions;
+use crate::deploy::capabilities::CapabilityManifest;
+use crate::http::client::DeviceRegistration;
+use crate::telemetry::{collect_metrics, SystemMetrics};
+
+/// Metadata object stored on the backend device record
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceMetadata {
+    /// Operator-supplied location, e.g. "Greenhouse 2"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+
+    pub agent_version: String,
+
+    pub hardware: HardwareSpec,
+}
+
+/// Static hardware specs
+#[derive(Debug, Clone, Serialize)]
+pub struct HardwareSpec {
+    pub hostname: String,
+    pub os: String,
+    pub arch: String,
+    pub cpu_count: usize,
+    pub memory_total: u64,
+    pub disk_total: u64,
+}
+
+impl From<SystemMetrics> for HardwareSpec {
+    fn from(metrics: SystemMetrics) -> Self {
+        Self {
+            hostname: metrics.hostname,
+            os: std::env::consts::OS.to_string(),
+            arch: std::env::consts::ARCH.to_string(),
+            cpu_count: metrics.cpu_count,
+            memory_total: metrics.memory_total,
+            disk_total: metrics.disk_total,
+        }
+    }
+}
+
+/// Detect the registration details of this device
+pub async fn detect(
+    location: Option<String>,
+    agent_version: &str,
+    options: &AppOptions,
+) -> DeviceRegistration {
+    let capabilities = CapabilityManifest::probe(agent_version, options).await;
+    let metadata = DeviceMetadata {
+        location: location.filter(|l| !l.trim().is_empty()),
+        agent_version: agent_version.to_string(),
+        hardware: collect_metrics().into(),
+    };
+    registration(metadata, &capabilities)
+}
+
+fn registration(metadata: DeviceMetadata, capabilities: &CapabilityManifest) -> DeviceRegistration {
+    DeviceRegistration {
+        capabilities: capabilities.names(),
+        metadata: serde_json::to_value(metadata).unwrap_or_default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registration_carries_metadata_and_capability_names() {
+        let metadata = DeviceMetadata {
+            location: Some("Greenhouse 2".to_string()),
+            agent_version: "1.2.3".to_string(),
+            hardware: HardwareSpec {
+                hostname: "pi".to_string(),
+                os: "linux".to_string(),
+                arch: "aarch64".to_string(),
+                cpu_count: 4,
+                memory_total: 4 << 30,
+                disk_total: 32 << 30,
+            },
+        };
+        let capabilities = CapabilityManifest {
+            terminal: true,
+            docker: true,
+            ..Default::default()
+        };
+
+        let registration = registration(metadata, &capabilities);
+        assert_eq!(registration.capabilities, ["docker", "terminal"]);
+        assert_eq!(registration.metadata["location"], "Greenhouse 2");
+        assert_eq!(registration.metadata["agent_version"], "1.2.3");
+        assert_eq!(registration.metadata["hardware"]["cpu_count"], 4);
+    }
+}
@@ -1,3 +1,4 @@
 //! Installation module
 
 pub mod install;
+pub mod metadata;
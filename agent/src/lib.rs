@@ -3,12 +3,15 @@
 //! Core modules for the Ajime edge agent.
 
 pub mod app;
+pub mod audit;
 pub mod authn;
 pub mod cache;
+pub mod clock;
 pub mod deploy;
 pub mod errors;
 pub mod filesys;
 pub mod hardware;
+pub mod health;
 pub mod http;
 pub mod installer;
 pub mod logs;
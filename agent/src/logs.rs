@@ -1,8 +1,12 @@
 //! Logging configuration
 
+use std::future::Future;
 use std::path::PathBuf;
+use std::pin::Pin;
+use std::time::{Duration, SystemTime};
 
-use tracing::Level;
+use serde::Serialize;
+use tracing::{debug, info, warn, Level};
 use tracing_subscriber::{
     fmt,
     layer::SubscriberExt,
@@ -11,6 +15,7 @@ use tracing_subscriber::{
 };
 
 use crate::errors::AgentError;
+use crate::filesys::dir::Dir;
 
 /// Log level configuration
 #[derive(Debug, Clone, Default, PartialEq, Eq)]
@@ -129,3 +134,200 @@ pub fn init_logging(options: LogOptions) -> Result<(), AgentError> {
 
     Ok(())
 }
+
+/// Limits on the files in the log directory; zero disables a limit
+#[derive(Debug, Clone)]
+pub struct LogRetention {
+    /// Total size of all log files
+    pub max_total_bytes: u64,
+
+    /// Number of log files
+    pub max_files: usize,
+
+    /// Age of a log file, by last modification
+    pub max_age: Duration,
+
+    /// How often the limits are enforced
+    pub check_interval: Duration,
+}
+
+impl Default for LogRetention {
+    fn default() -> Self {
+        Self {
+            max_total_bytes: 50 * 1024 * 1024,
+            max_files: 10,
+            max_age: Duration::from_secs(14 * 24 * 3600),
+            check_interval: Duration::from_secs(3600),
+        }
+    }
+}
+
+/// Disk usage of the log directory
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct LogUsage {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// Delete the oldest log files until the directory is within the limits.
+/// The newest file is the one being written to and is always kept. Returns
+/// the usage afterwards.
+pub async fn enforce_retention(dir: &Dir, retention: &LogRetention) -> Result<LogUsage, AgentError> {
+    if !dir.exists().await {
+        return Ok(LogUsage::default());
+    }
+
+    let mut files = Vec::new();
+    for path in dir.list_files().await? {
+        let metadata = tokio::fs::metadata(&path).await?;
+        let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+        files.push((path, metadata.len(), modified));
+    }
+    // Newest first
+    files.sort_by_key(|file| std::cmp::Reverse(file.2));
+
+    let now = SystemTime::now();
+    let mut usage = LogUsage::default();
+    let mut over_limit = false;
+    for (i, (path, len, modified)) in files.into_iter().enumerate() {
+        let age = now.duration_since(modified).unwrap_or_default();
+        over_limit = over_limit
+            || (retention.max_files > 0 && i >= retention.max_files)
+            || (!retention.max_age.is_zero() && age > retention.max_age)
+            || (retention.max_total_bytes > 0 && usage.bytes + len > retention.max_total_bytes);
+
+        if i > 0 && over_limit {
+            match tokio::fs::remove_file(&path).await {
+                Ok(()) => {
+                    debug!("Deleted old log file {:?} ({} bytes)", path, len);
+                    continue;
+                }
+                Err(e) => warn!("Failed to delete old log file {:?}: {}", path, e),
+            }
+        }
+        usage.files += 1;
+        usage.bytes += len;
+    }
+    Ok(usage)
+}
+
+/// Enforce the retention limits every `check_interval` until shut down
+pub async fn run_retention<S, F>(
+    dir: &Dir,
+    retention: &LogRetention,
+    sleep_fn: S,
+    mut shutdown_signal: Pin<Box<dyn Future<Output = ()> + Send>>,
+) where
+    S: Fn(Duration) -> F,
+    F: Future<Output = ()>,
+{
+    loop {
+        match enforce_retention(dir, retention).await {
+            Ok(usage) => debug!("Log directory uses {} bytes in {} files", usage.bytes, usage.files),
+            Err(e) => warn!("Failed to enforce log retention: {}", e),
+        }
+        tokio::select! {
+            _ = &mut shutdown_signal => {
+                info!("Log retention stopped");
+                return;
+            }
+            _ = sleep_fn(retention.check_interval) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesys::test_utils::TempFs;
+
+    /// Write a log file and backdate it by `age_secs`
+    fn log_file(fs: &TempFs, name: &str, len: usize, age_secs: u64) {
+        let path = fs.write(&format!("logs/{}", name), vec![b'x'; len]);
+        let modified = SystemTime::now() - Duration::from_secs(age_secs);
+        std::fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+    }
+
+    fn remaining(fs: &TempFs) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(fs.path("logs"))
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[tokio::test]
+    async fn test_size_cap_deletes_oldest_first() {
+        let fs = TempFs::new();
+        log_file(&fs, "agent.log", 40, 0);
+        log_file(&fs, "agent.log.1", 40, 10);
+        log_file(&fs, "agent.log.2", 40, 20);
+        log_file(&fs, "agent.log.3", 40, 30);
+        let dir = Dir::new(fs.path("logs"));
+        assert_eq!(dir.size().await.unwrap(), 160);
+
+        let retention = LogRetention {
+            max_total_bytes: 100,
+            max_files: 0,
+            max_age: Duration::ZERO,
+            ..Default::default()
+        };
+        let usage = enforce_retention(&dir, &retention).await.unwrap();
+        assert_eq!((usage.files, usage.bytes), (2, 80));
+        assert_eq!(remaining(&fs), ["agent.log", "agent.log.1"]);
+        assert_eq!(dir.size().await.unwrap(), 80);
+    }
+
+    #[tokio::test]
+    async fn test_count_and_age_limits_keep_active_file() {
+        let fs = TempFs::new();
+        log_file(&fs, "agent.log", 500, 7200);
+        log_file(&fs, "agent.log.1", 10, 8000);
+        log_file(&fs, "agent.log.2", 10, 9000);
+        let dir = Dir::new(fs.path("logs"));
+
+        // Everything is too old and the active file alone is over the cap,
+        // but it is still kept
+        let retention = LogRetention {
+            max_total_bytes: 100,
+            max_files: 2,
+            max_age: Duration::from_secs(3600),
+            ..Default::default()
+        };
+        let usage = enforce_retention(&dir, &retention).await.unwrap();
+        assert_eq!((usage.files, usage.bytes), (1, 500));
+        assert_eq!(remaining(&fs), ["agent.log"]);
+    }
+
+    #[tokio::test]
+    async fn test_retention_is_enforced_then_waits_until_shut_down() {
+        let fs = TempFs::new();
+        log_file(&fs, "agent.log", 10, 0);
+        log_file(&fs, "agent.log.1", 10, 10);
+        let retention = LogRetention {
+            max_files: 1,
+            ..Default::default()
+        };
+        let waits = std::sync::Mutex::new(Vec::new());
+
+        run_retention(
+            &Dir::new(fs.path("logs")),
+            &retention,
+            |wait| {
+                waits.lock().unwrap().push(wait);
+                std::future::pending()
+            },
+            Box::pin(async {}),
+        )
+        .await;
+
+        assert_eq!(remaining(&fs), ["agent.log"]);
+        assert_eq!(*waits.lock().unwrap(), [retention.check_interval]);
+    }
+}
@@ -6,16 +6,15 @@
 use std::collections::HashMap;
 use std::env;
 
-use ajigent::app::options::{AppOptions, LifecycleOptions};
+use ajigent::app::options::AppOptions;
 use ajigent::app::run::run;
+use ajigent::authn::local_token::rotate_cli;
 use ajigent::installer::install::install;
 use ajigent::logs::{init_logging, LogOptions};
-use ajigent::mqtt::client::MqttAddress;
 use ajigent::storage::device::assert_activated;
 use ajigent::storage::layout::StorageLayout;
 use ajigent::storage::settings::Settings;
 use ajigent::utils::{version_info, run_diagnostic};
-use ajigent::workers::mqtt;
 
 use tracing::{error, info};
 
@@ -55,6 +54,11 @@ async fn main() {
         return install(&cli_args).await;
     }
 
+    // Create or rotate the local API token
+    if cli_args.contains_key("rotate-local-token") {
+        return rotate_cli(&StorageLayout::default()).await;
+    }
+
     // Run the agent starting here
 
     // Check the agent has been activated
@@ -86,25 +90,12 @@ async fn main() {
     }
 
     // Run the server
-    let options = AppOptions {
-        lifecycle: LifecycleOptions {
-            is_persistent: settings.is_persistent,
-            ..Default::default()
-        },
-        backend_base_url: settings.backend.base_url.clone(),
-        enable_socket_server: settings.enable_socket_server,
-        enable_mqtt_worker: settings.enable_mqtt_worker,
-        enable_poller: settings.enable_poller,
-        mqtt_worker: mqtt::Options {
-            broker_address: MqttAddress {
-                host: settings.mqtt_broker.host.clone(),
-                port: settings.mqtt_broker.port,
-                use_tls: settings.mqtt_broker.tls,
-                ca_cert_path: settings.mqtt_broker.ca_cert_path.clone(),
-            },
-            ..Default::default()
-        },
-        ..Default::default()
+    let options = match AppOptions::from_settings(settings, &layout) {
+        Ok(options) => options,
+        Err(e) => {
+            error!("Invalid settings in {:?}: {}", settings_file.path(), e);
+            return;
+        }
     };
 
     info!("Running Ajime Agent with options: {:?}", options);
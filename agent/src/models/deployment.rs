@@ -1,9 +1,13 @@
 //! Deployment models
 
+use std::fmt;
+
 use serde::{Deserialize, Serialize};
 
+use crate::deploy::env_file::ENV_FILE_FIELD;
+
 /// A deployment task received from the backend
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Deployment {
     /// Unique deployment ID
     pub id: String,
@@ -21,6 +25,23 @@ pub struct Deployment {
     pub status: String,
 }
 
+impl fmt::Debug for Deployment {
+    /// The env file holds secrets, so it is left out
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut config = self.config.clone();
+        if let Some(env_file) = config.get_mut(ENV_FILE_FIELD) {
+            *env_file = "<redacted>".into();
+        }
+        f.debug_struct("Deployment")
+            .field("id", &self.id)
+            .field("device_id", &self.device_id)
+            .field("deployment_type", &self.deployment_type)
+            .field("config", &config)
+            .field("status", &self.status)
+            .finish()
+    }
+}
+
 /// Status update to send back to the backend
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DeploymentStatusUpdate {
@@ -30,6 +51,14 @@ pub struct DeploymentStatusUpdate {
     /// Optional error message
     #[serde(skip_serializing_if = "Option::is_none")]
     pub error_message: Option<String>,
+
+    /// Attempt this update belongs to, starting at 1
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attempt: Option<u32>,
+
+    /// Attempts allowed for this deployment
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_attempts: Option<u32>,
 }
 
 /// Log entry to stream to the backend
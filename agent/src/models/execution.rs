@@ -0,0 +1,205 @@
+//! Workflow execution results as reported to the backend
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::models::workflow::{ExecutionState, NodeExecutionState, Port, WorkflowExecution};
+
+/// Strings longer than this are summarized instead of inlined
+const MAX_INLINE_STRING: usize = 4096;
+
+/// Result of a workflow execution
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExecutionResult {
+    pub workflow_id: String,
+    pub status: ExecutionState,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub duration_ms: Option<u64>,
+    pub error: Option<String>,
+    /// Nodes that ran, in workflow order
+    pub nodes: Vec<NodeResult>,
+}
+
+/// Result of one node
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NodeResult {
+    pub node_id: String,
+    pub status: ExecutionState,
+    pub duration_ms: Option<u64>,
+    pub error: Option<String>,
+    /// Outputs sorted by port
+    pub outputs: Vec<NodeOutput>,
+}
+
+/// One output of a node
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NodeOutput {
+    pub port: String,
+    #[serde(flatten)]
+    pub value: OutputValue,
+}
+
+/// An output value, typed by its port where the port type is known
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "lowercase")]
+pub enum OutputValue {
+    Number(f64),
+    Boolean(bool),
+    String(String),
+    /// Binary data such as camera frames, reported by size and hash only
+    Binary { size: usize, sha256: String },
+    /// Anything else, as produced by the node
+    Json(Value),
+}
+
+impl OutputValue {
+    /// Type `value` by the type of the port that produced it. Values that
+    /// do not match their port type are kept as JSON.
+    pub fn typed(value: &Value, port_type: Option<&str>) -> Self {
+        match (port_type.map(str::to_ascii_lowercase).as_deref(), value) {
+            (Some("number" | "float" | "integer"), Value::Number(n)) => {
+                n.as_f64().map_or_else(|| OutputValue::Json(value.clone()), OutputValue::Number)
+            }
+            (Some("boolean" | "bool"), Value::Bool(b)) => OutputValue::Boolean(*b),
+            (Some("image" | "binary" | "bytes"), Value::String(s)) => {
+                // Binary outputs are base64; summarize the decoded bytes
+                match BASE64.decode(s) {
+                    Ok(bytes) => Self::binary(&bytes),
+                    Err(_) => Self::binary(s.as_bytes()),
+                }
+            }
+            (_, Value::String(s)) if s.len() > MAX_INLINE_STRING => Self::binary(s.as_bytes()),
+            (Some("string" | "text"), Value::String(s)) => OutputValue::String(s.clone()),
+            _ => OutputValue::Json(value.clone()),
+        }
+    }
+
+    /// The value as plain JSON, binary data as its size and hash
+    pub fn to_json(&self) -> Value {
+        match self {
+            OutputValue::Number(n) => Value::from(*n),
+            OutputValue::Boolean(b) => Value::Bool(*b),
+            OutputValue::String(s) => Value::String(s.clone()),
+            OutputValue::Binary { size, sha256 } => {
+                serde_json::json!({ "size": size, "sha256": sha256 })
+            }
+            OutputValue::Json(value) => value.clone(),
+        }
+    }
+
+    fn binary(bytes: &[u8]) -> Self {
+        OutputValue::Binary {
+            size: bytes.len(),
+            sha256: crate::utils::sha256_hash(bytes),
+        }
+    }
+}
+
+impl ExecutionResult {
+    /// Build the report of `execution`, typing outputs by the ports the
+    /// workflow declares
+    pub fn from_execution(execution: &WorkflowExecution) -> Self {
+        let nodes = execution
+            .workflow
+            .graph_data
+            .nodes
+            .iter()
+            .filter_map(|node| {
+                let state = execution.node_states.get(&node.id)?;
+                Some(NodeResult::new(state, &node.data.outputs))
+            })
+            .collect();
+
+        Self {
+            workflow_id: execution.workflow.id.clone(),
+            status: execution.state.clone(),
+            started_at: execution.started_at,
+            finished_at: execution.finished_at,
+            duration_ms: duration_ms(execution.started_at, execution.finished_at),
+            error: execution.error.clone(),
+            nodes,
+        }
+    }
+}
+
+impl NodeResult {
+    fn new(state: &NodeExecutionState, ports: &[Port]) -> Self {
+        let mut outputs: Vec<NodeOutput> = state
+            .outputs
+            .as_ref()
+            .and_then(Value::as_object)
+            .into_iter()
+            .flatten()
+            .map(|(port, value)| {
+                let port_type = ports
+                    .iter()
+                    .find(|p| &p.id == port || &p.name == port)
+                    .map(|p| p.port_type.as_str());
+                NodeOutput {
+                    port: port.clone(),
+                    value: OutputValue::typed(value, port_type),
+                }
+            })
+            .collect();
+        outputs.sort_by(|a, b| a.port.cmp(&b.port));
+
+        Self {
+            node_id: state.node_id.clone(),
+            status: state.state.clone(),
+            duration_ms: duration_ms(state.started_at, state.finished_at),
+            error: state.error.clone(),
+            outputs,
+        }
+    }
+}
+
+fn duration_ms(
+    started_at: Option<DateTime<Utc>>,
+    finished_at: Option<DateTime<Utc>>,
+) -> Option<u64> {
+    let elapsed = finished_at? - started_at?;
+    elapsed.num_milliseconds().try_into().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_outputs_are_typed_by_port() {
+        assert_eq!(OutputValue::typed(&json!(1.5), Some("number")), OutputValue::Number(1.5));
+        assert_eq!(OutputValue::typed(&json!(true), Some("Boolean")), OutputValue::Boolean(true));
+        assert_eq!(
+            OutputValue::typed(&json!("on"), Some("string")),
+            OutputValue::String("on".to_string())
+        );
+        // Mismatched or unknown port types keep the JSON value
+        assert_eq!(OutputValue::typed(&json!("1"), Some("number")), OutputValue::Json(json!("1")));
+        assert_eq!(OutputValue::typed(&json!(2), None), OutputValue::Json(json!(2)));
+
+        let frame = BASE64.encode(b"\xFF\xD8jpeg");
+        assert_eq!(
+            OutputValue::typed(&json!(frame), Some("image")),
+            OutputValue::Binary {
+                size: 6,
+                sha256: crate::utils::sha256_hash(b"\xFF\xD8jpeg"),
+            }
+        );
+        let large = "x".repeat(MAX_INLINE_STRING + 1);
+        assert!(matches!(
+            OutputValue::typed(&json!(large), None),
+            OutputValue::Binary { size, .. } if size == MAX_INLINE_STRING + 1
+        ));
+
+        let serialized = serde_json::to_value(NodeOutput {
+            port: "value".to_string(),
+            value: OutputValue::Boolean(false),
+        })
+        .unwrap();
+        assert_eq!(serialized, json!({ "port": "value", "type": "boolean", "value": false }));
+    }
+}
@@ -1,4 +1,9 @@
 //! Data models
 
 pub mod workflow;
+pub mod execution;
 pub mod deployment;
+pub mod relay;
+
+#[cfg(test)]
+pub(crate) mod test_utils;
@@ -0,0 +1,509 @@
+//! Relay message models
+//!
+//! The server wraps commands as
+//! `{"type": "command", "msg_id": "...", "command_type": "...", "payload": {...}}`
+//! and sends push messages with `type` set directly (e.g. `new_deployment`).
+//!
+//! Bulk data the agent sends (terminal output, file chunks) is built as a
+//! binary frame: a big-endian `u32` header length, the message as JSON
+//! without its data field, and the raw data. The header's `binary` field
+//! names the data field. Connections that did not negotiate binary frames
+//! get the usual JSON text message instead, with the data Base64-encoded;
+//! see `text_message`.
+
+use std::collections::BTreeMap;
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use serde::de::IgnoredAny;
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+use crate::filesys::relay::DEFAULT_CHUNK_SIZE;
+use crate::models::workflow::Workflow;
+use crate::terminal::exec::ExecRequest;
+
+/// A message received from the relay server
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RelayEnvelope {
+    /// Request the server expects a response to
+    Command(CommandEnvelope),
+
+    /// A deployment is pending (fire-and-forget)
+    NewDeployment(DeploymentPush),
+
+    /// Heartbeat reply
+    Pong,
+}
+
+/// A command and the ID its response is correlated by
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "RawCommandEnvelope")]
+pub struct CommandEnvelope {
+    pub msg_id: String,
+    pub command: RelayCommand,
+
+    /// `command_type` and `payload` as received, for signature checks
+    pub command_type: String,
+    pub payload: Value,
+
+    /// Backend signature over the command, see `authn::command_signing`
+    pub signature: Option<String>,
+}
+
+/// Wire form of a command; `payload` may be omitted when every argument has
+/// a default
+#[derive(Deserialize)]
+struct RawCommandEnvelope {
+    msg_id: String,
+    command_type: String,
+    #[serde(default)]
+    payload: Value,
+    #[serde(default)]
+    signature: Option<String>,
+}
+
+impl TryFrom<RawCommandEnvelope> for CommandEnvelope {
+    type Error = serde_json::Error;
+
+    fn try_from(raw: RawCommandEnvelope) -> Result<Self, Self::Error> {
+        let payload = match raw.payload {
+            Value::Null => Value::Object(Default::default()),
+            payload => payload,
+        };
+        let command = RelayCommand::deserialize(serde_json::json!({
+            "command_type": raw.command_type,
+            "payload": payload,
+        }))?;
+        Ok(Self {
+            msg_id: raw.msg_id,
+            command,
+            command_type: raw.command_type,
+            payload,
+            signature: raw.signature,
+        })
+    }
+}
+
+/// Commands the agent handles, keyed by `command_type` with their `payload`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "command_type", content = "payload", rename_all = "snake_case")]
+pub enum RelayCommand {
+    Drain(NoPayload),
+    TerminalCreate(TerminalCreate),
+    TerminalInput(TerminalInput),
+    TerminalResize(TerminalResize),
+    TerminalClose(SessionRef),
+    TerminalList(NoPayload),
+    CommandExec(ExecRequest),
+    FileList(FileList),
+    FileRead(FileRead),
+    FileWrite(FileWrite),
+    FileReadStream(FileReadStream),
+    FileWriteChunk(FileWriteChunk),
+    FileDelete(FilePath),
+    ScanNetwork(ScanNetwork),
+    ScanCancel(NoPayload),
+    DockerImages(NoPayload),
+    DeviceUpdate(DeviceUpdate),
+    SyncReset(SyncReset),
+    DeploymentListDirs(NoPayload),
+    DeploymentRemoveDir(DeploymentDirRef),
+    WorkflowCheck(Box<Workflow>),
+}
+
+impl RelayCommand {
+    /// Every `command_type` the agent handles
+    pub const NAMES: &'static [&'static str] = &[
+        "drain",
+        "terminal_create",
+        "terminal_input",
+        "terminal_resize",
+        "terminal_close",
+        "terminal_list",
+        "command_exec",
+        "file_list",
+        "file_read",
+        "file_write",
+        "file_read_stream",
+        "file_write_chunk",
+        "file_delete",
+        "scan_network",
+        "scan_cancel",
+        "docker_images",
+        "device_update",
+        "sync_reset",
+        "deployment_list_dirs",
+        "deployment_remove_dir",
+        "workflow_check",
+    ];
+}
+
+/// Payload of commands that take no arguments; whatever is sent is ignored
+#[derive(Debug, Clone, Default)]
+pub struct NoPayload;
+
+impl<'de> Deserialize<'de> for NoPayload {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        IgnoredAny::deserialize(deserializer)?;
+        Ok(NoPayload)
+    }
+}
+
+/// `new_deployment` push; the ID may sit at the top level or in the payload
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeploymentPush {
+    #[serde(default)]
+    deployment_id: Option<String>,
+
+    #[serde(default)]
+    payload: Option<DeploymentPushPayload>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct DeploymentPushPayload {
+    #[serde(default)]
+    deployment_id: Option<String>,
+}
+
+impl DeploymentPush {
+    pub fn deployment_id(&self) -> Option<&str> {
+        self.deployment_id
+            .as_deref()
+            .or_else(|| self.payload.as_ref()?.deployment_id.as_deref())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TerminalCreate {
+    /// Defaults to the command's msg_id
+    #[serde(default)]
+    pub session_id: Option<String>,
+
+    #[serde(default = "default_cols")]
+    pub cols: u16,
+
+    #[serde(default = "default_rows")]
+    pub rows: u16,
+
+    /// Starting directory; the terminal sandbox when unset or invalid
+    #[serde(default)]
+    pub cwd: Option<String>,
+
+    /// Shell to run instead of the default, as an absolute path
+    #[serde(default)]
+    pub shell: Option<String>,
+
+    /// Extra environment variables for the shell
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+}
+
+fn default_cols() -> u16 {
+    80
+}
+
+fn default_rows() -> u16 {
+    24
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TerminalInput {
+    pub session_id: String,
+
+    /// Base64-encoded keystrokes
+    pub data: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TerminalResize {
+    pub session_id: String,
+    pub cols: u16,
+    pub rows: u16,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SessionRef {
+    pub session_id: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FileList {
+    /// Directory to list; the file root when unset
+    #[serde(default = "default_list_path")]
+    pub path: String,
+}
+
+fn default_list_path() -> String {
+    ".".to_string()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FilePath {
+    pub path: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FileRead {
+    pub path: String,
+
+    /// Also return the SHA-256 of the content
+    #[serde(default)]
+    pub include_hash: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FileWrite {
+    pub path: String,
+
+    /// Base64-encoded content
+    pub content: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FileReadStream {
+    pub path: String,
+
+    /// Bytes per chunk, at most 1 MiB
+    #[serde(default = "default_chunk_size")]
+    pub chunk_size: usize,
+}
+
+fn default_chunk_size() -> usize {
+    DEFAULT_CHUNK_SIZE
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FileWriteChunk {
+    pub path: String,
+
+    /// Where the chunk starts; 0 starts the file over
+    pub offset: u64,
+
+    /// Base64-encoded content
+    pub content: String,
+
+    /// Last chunk: move the finished file into place
+    #[serde(default)]
+    pub commit: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeviceUpdate {
+    /// New label; null or blank clears it
+    #[serde(default)]
+    pub label: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SyncReset {
+    /// Sync right after clearing the cooldown
+    #[serde(default)]
+    pub sync: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeploymentDirRef {
+    /// Directory name within the deployment directory
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScanNetwork {
+    #[serde(default = "default_subnet")]
+    pub subnet: String,
+
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+
+    /// Discovery mode name
+    #[serde(default)]
+    pub mode: Option<String>,
+}
+
+fn default_subnet() -> String {
+    "192.168.1.0/24".to_string()
+}
+
+/// Header field of a binary frame naming the field its data belongs in
+const BINARY_FIELD: &str = "binary";
+
+/// Message `header` carrying `data` in its `field`, as a binary frame
+pub fn data_message(mut header: Value, field: &str, data: &[u8]) -> Message {
+    header[BINARY_FIELD] = Value::from(field);
+    let header = header.to_string();
+    let mut frame = Vec::with_capacity(4 + header.len() + data.len());
+    frame.extend((header.len() as u32).to_be_bytes());
+    frame.extend(header.as_bytes());
+    frame.extend(data);
+    Message::Binary(frame.into())
+}
+
+/// `msg` as a JSON text message, with the data of a binary frame
+/// Base64-encoded into its field. Other messages are returned as they are,
+/// and binary frames not built by `data_message` are dropped.
+pub fn text_message(msg: Message) -> Option<Message> {
+    let Message::Binary(frame) = msg else {
+        return Some(msg);
+    };
+    let (len, rest) = frame.split_first_chunk::<4>()?;
+    let len = u32::from_be_bytes(*len) as usize;
+    if rest.len() < len {
+        return None;
+    }
+    let (header, data) = rest.split_at(len);
+    let mut msg: serde_json::Map<String, Value> = serde_json::from_slice(header).ok()?;
+    let Some(Value::String(field)) = msg.remove(BINARY_FIELD) else {
+        return None;
+    };
+    msg.insert(field, Value::from(BASE64.encode(data)));
+    Some(Message::Text(Value::Object(msg).to_string().into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_names_are_handled() {
+        for name in RelayCommand::NAMES {
+            let command = serde_json::json!({ "command_type": name, "payload": {} });
+            if let Err(e) = serde_json::from_value::<RelayCommand>(command) {
+                assert!(!e.to_string().contains("unknown variant"), "{}: {}", name, e);
+            }
+        }
+    }
+
+    #[test]
+    fn test_every_command_is_named() {
+        // Serde lists every variant when rejecting an unknown one
+        let command = serde_json::json!({ "command_type": "no_such_command", "payload": {} });
+        let err = serde_json::from_value::<RelayCommand>(command).unwrap_err().to_string();
+        let (_, expected) = err.split_once("expected one of ").unwrap();
+        let mut variants: Vec<&str> = expected
+            .split(", ")
+            .map(|name| name.trim_matches(|c: char| c == '`' || !c.is_ascii_graphic()))
+            .collect();
+        variants.sort();
+        let mut names = RelayCommand::NAMES.to_vec();
+        names.sort();
+        assert_eq!(variants, names);
+    }
+
+    fn parse(value: serde_json::Value) -> Result<RelayEnvelope, serde_json::Error> {
+        serde_json::from_value(value)
+    }
+
+    #[test]
+    fn test_commands_parse_into_typed_variants() {
+        let envelope = parse(serde_json::json!({
+            "type": "command",
+            "msg_id": "m1",
+            "command_type": "terminal_create",
+            "payload": { "session_id": "s1", "cols": 120 },
+        }))
+        .unwrap();
+        let RelayEnvelope::Command(CommandEnvelope {
+            msg_id,
+            command: RelayCommand::TerminalCreate(create),
+            ..
+        }) = envelope
+        else {
+            panic!("unexpected envelope: {:?}", envelope);
+        };
+        assert_eq!(msg_id, "m1");
+        assert_eq!(create.session_id.as_deref(), Some("s1"));
+        assert_eq!((create.cols, create.rows), (120, 24));
+
+        // Commands without arguments accept a missing, null or empty payload
+        for payload in [None, Some(serde_json::Value::Null), Some(serde_json::json!({}))] {
+            let mut msg = serde_json::json!({ "type": "command", "msg_id": "m2", "command_type": "drain" });
+            if let Some(payload) = payload {
+                msg["payload"] = payload;
+            }
+            assert!(matches!(
+                parse(msg),
+                Ok(RelayEnvelope::Command(CommandEnvelope { command: RelayCommand::Drain(_), .. }))
+            ));
+        }
+
+        // ...as do commands whose arguments all have defaults
+        let msg = serde_json::json!({ "type": "command", "msg_id": "m3", "command_type": "sync_reset" });
+        assert!(matches!(
+            parse(msg),
+            Ok(RelayEnvelope::Command(CommandEnvelope {
+                command: RelayCommand::SyncReset(SyncReset { sync: false }),
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn test_pushes_parse() {
+        let top = parse(serde_json::json!({ "type": "new_deployment", "deployment_id": "d1" })).unwrap();
+        let nested = parse(serde_json::json!({
+            "type": "new_deployment",
+            "payload": { "deployment_id": "d2" },
+        }))
+        .unwrap();
+        let bare = parse(serde_json::json!({ "type": "new_deployment" })).unwrap();
+
+        let id = |envelope: &RelayEnvelope| match envelope {
+            RelayEnvelope::NewDeployment(push) => push.deployment_id().map(str::to_string),
+            other => panic!("unexpected envelope: {:?}", other),
+        };
+        assert_eq!(id(&top).as_deref(), Some("d1"));
+        assert_eq!(id(&nested).as_deref(), Some("d2"));
+        assert_eq!(id(&bare), None);
+
+        assert!(matches!(parse(serde_json::json!({ "type": "pong" })), Ok(RelayEnvelope::Pong)));
+    }
+
+    #[test]
+    fn test_malformed_envelopes_are_rejected() {
+        // Typo'd command type
+        assert!(parse(serde_json::json!({
+            "type": "command", "msg_id": "m1", "command_type": "file_raed", "payload": { "path": "/tmp" },
+        }))
+        .is_err());
+
+        // Missing required payload field
+        assert!(parse(serde_json::json!({
+            "type": "command", "msg_id": "m1", "command_type": "terminal_input",
+            "payload": { "session_id": "s1" },
+        }))
+        .is_err());
+
+        // Missing msg_id and unknown push type
+        assert!(parse(serde_json::json!({ "type": "command", "command_type": "drain" })).is_err());
+        assert!(parse(serde_json::json!({ "type": "new_deploymnet" })).is_err());
+    }
+
+    #[test]
+    fn test_data_messages_fall_back_to_base64_text() {
+        let header = serde_json::json!({ "type": "terminal_output", "session_id": "s1" });
+        let msg = data_message(header, "data", b"\x1b[1mhi");
+        let Message::Binary(frame) = &msg else {
+            panic!("unexpected message: {:?}", msg);
+        };
+        assert!(frame.ends_with(b"\x1b[1mhi"));
+
+        let Some(Message::Text(text)) = text_message(msg) else {
+            panic!("not converted to text");
+        };
+        let text: Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(
+            text,
+            serde_json::json!({
+                "type": "terminal_output",
+                "session_id": "s1",
+                "data": BASE64.encode("\x1b[1mhi"),
+            })
+        );
+
+        let ping = Message::Text("{}".into());
+        assert_eq!(text_message(ping.clone()), Some(ping));
+        assert_eq!(text_message(Message::Binary(vec![0, 0, 0, 9, b'{'].into())), None);
+    }
+}
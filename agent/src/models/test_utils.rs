@@ -0,0 +1,49 @@
+//! Workflow factories shared by the tests
+
+use crate::models::workflow::{Edge, GraphData, Node, NodeData, Workflow, WorkflowStatus};
+
+/// An active workflow named after its ID, with an empty graph
+pub fn workflow(id: &str) -> Workflow {
+    workflow_with_graph(id, vec![], vec![])
+}
+
+/// An active workflow named after its ID, with `nodes` and `edges`
+pub fn workflow_with_graph(id: &str, nodes: Vec<Node>, edges: Vec<Edge>) -> Workflow {
+    Workflow {
+        id: id.to_string(),
+        name: id.to_string(),
+        description: None,
+        owner_id: "owner".to_string(),
+        status: WorkflowStatus::Active,
+        graph_data: GraphData { nodes, edges },
+        logic_hash: None,
+        created_at: String::new(),
+        updated_at: String::new(),
+    }
+}
+
+/// A node without inputs, outputs or label
+pub fn node(id: &str, node_type: &str, config: serde_json::Value) -> Node {
+    Node {
+        id: id.to_string(),
+        node_type: node_type.to_string(),
+        label: None,
+        position: None,
+        data: NodeData {
+            config,
+            inputs: vec![],
+            outputs: vec![],
+        },
+    }
+}
+
+/// An edge between the default handles of `source` and `target`
+pub fn edge(source: &str, target: &str) -> Edge {
+    Edge {
+        id: format!("{}-{}", source, target),
+        source: source.to_string(),
+        source_handle: None,
+        target: target.to_string(),
+        target_handle: None,
+    }
+}
@@ -179,4 +179,12 @@ pub struct NodeExecutionState {
 
     /// Error message if failed
     pub error: Option<String>,
+
+    /// When the node started running
+    #[serde(default)]
+    pub started_at: Option<chrono::DateTime<chrono::Utc>>,
+
+    /// When the node finished
+    #[serde(default)]
+    pub finished_at: Option<chrono::DateTime<chrono::Utc>>,
 }
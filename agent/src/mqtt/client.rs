@@ -1,10 +1,11 @@
 //! MQTT client implementation
 
-use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Packet, QoS};
+use rumqttc::{AsyncClient, ConnectReturnCode, ConnectionError, Event, EventLoop, MqttOptions, Packet, QoS};
 use serde::{Deserialize, Serialize};
 use tracing::{debug, info, warn};
 
 use crate::errors::AgentError;
+use crate::mqtt::topics::Topics;
 
 /// MQTT broker address
 #[derive(Debug, Clone)]
@@ -28,6 +29,51 @@ impl Default for MqttAddress {
     }
 }
 
+/// MQTT client ID options
+///
+/// Brokers allow a single session per client ID, so two processes sharing a
+/// device ID (e.g. a cloned SD card) would keep kicking each other off.
+#[derive(Debug, Clone)]
+pub struct ClientIdOptions {
+    /// Use this exact client ID instead of deriving one from the device ID
+    pub override_id: Option<String>,
+
+    /// Fixed suffix appended to `ajigent-{device_id}`
+    pub suffix: Option<String>,
+
+    /// Append a short random suffix when no fixed suffix is configured
+    pub random_suffix: bool,
+}
+
+impl Default for ClientIdOptions {
+    fn default() -> Self {
+        Self {
+            override_id: None,
+            suffix: None,
+            random_suffix: true,
+        }
+    }
+}
+
+impl ClientIdOptions {
+    /// Build the client ID for the given device
+    pub fn client_id(&self, device_id: &str) -> String {
+        if let Some(id) = self.override_id.as_deref().filter(|id| !id.is_empty()) {
+            return id.to_string();
+        }
+
+        let base = format!("ajigent-{}", device_id);
+        match self.suffix.as_deref().filter(|s| !s.is_empty()) {
+            Some(suffix) => format!("{}-{}", base, suffix),
+            None if self.random_suffix => {
+                let random = uuid::Uuid::new_v4().simple().to_string();
+                format!("{}-{}", base, &random[..6])
+            }
+            None => base,
+        }
+    }
+}
+
 /// MQTT client wrapper
 pub struct MqttClient {
     client: AsyncClient,
@@ -39,6 +85,7 @@ impl MqttClient {
     /// Create a new MQTT client
     pub async fn new(
         address: &MqttAddress,
+        client_id: &str,
         device_id: &str,
         token: &str,
     ) -> Result<Self, AgentError> {
@@ -46,9 +93,7 @@ impl MqttClient {
             return Err(AgentError::MqttError("MQTT host is not configured".to_string()));
         }
 
-        let client_id = format!("ajigent-{}", device_id);
-
-        let mut options = MqttOptions::new(&client_id, &address.host, address.port);
+        let mut options = MqttOptions::new(client_id, &address.host, address.port);
         options.set_keep_alive(std::time::Duration::from_secs(30));
         options.set_credentials(device_id, token);
 
@@ -57,20 +102,7 @@ impl MqttClient {
             use rustls::ClientConfig;
             use std::sync::Arc;
 
-            let mut root_cert_store = rustls::RootCertStore::empty();
-
-            if let Some(ref ca_path) = address.ca_cert_path {
-                let ca_pem = std::fs::read(ca_path)
-                    .map_err(|e| AgentError::MqttError(format!("Failed to read CA cert {ca_path}: {e}")))?;
-                let mut cursor = std::io::Cursor::new(ca_pem);
-                for cert in rustls_pemfile::certs(&mut cursor).flatten() {
-                    let _ = root_cert_store.add(cert);
-                }
-            } else {
-                for cert in rustls_native_certs::load_native_certs().unwrap_or_default() {
-                    let _ = root_cert_store.add(cert);
-                }
-            }
+            let root_cert_store = load_root_certs(address.ca_cert_path.as_deref())?;
 
             let client_config = ClientConfig::builder()
                 .with_root_certificates(root_cert_store)
@@ -127,6 +159,25 @@ impl MqttClient {
         Ok(())
     }
 
+    /// Publish the capability manifest, retained so the backend always sees
+    /// the latest one
+    pub async fn publish_capabilities<T: Serialize>(
+        &self,
+        capabilities: &T,
+    ) -> Result<(), AgentError> {
+        let topic = Topics::device_capabilities(&self.device_id);
+        let payload = serde_json::to_vec(capabilities)
+            .map_err(|e| AgentError::MqttError(e.to_string()))?;
+
+        self.client
+            .publish(&topic, QoS::AtLeastOnce, true, payload)
+            .await
+            .map_err(|e| AgentError::MqttError(e.to_string()))?;
+
+        debug!("Published capabilities to: {}", topic);
+        Ok(())
+    }
+
     /// Publish telemetry data
     pub async fn publish_telemetry(&self, telemetry: &serde_json::Value) -> Result<(), AgentError> {
         let topic = format!("ajime/device/{}/telemetry", self.device_id);
@@ -162,6 +213,12 @@ impl MqttClient {
                 Ok(None)
             }
             Ok(_) => Ok(None),
+            Err(ConnectionError::ConnectionRefused(
+                code @ (ConnectReturnCode::BadUserNamePassword | ConnectReturnCode::NotAuthorized),
+            )) => Err(AgentError::AuthError(format!(
+                "MQTT broker rejected credentials: {:?}",
+                code
+            ))),
             Err(e) => {
                 warn!("MQTT poll error: {}", e);
                 Err(AgentError::MqttError(e.to_string()))
@@ -180,6 +237,39 @@ impl MqttClient {
     }
 }
 
+/// Build the root store for broker verification: the CA file when one is
+/// configured, the system store otherwise
+pub fn load_root_certs(ca_cert_path: Option<&str>) -> Result<rustls::RootCertStore, AgentError> {
+    let Some(ca_path) = ca_cert_path else {
+        let mut root_cert_store = rustls::RootCertStore::empty();
+        for cert in rustls_native_certs::load_native_certs().unwrap_or_default() {
+            let _ = root_cert_store.add(cert);
+        }
+        return Ok(root_cert_store);
+    };
+
+    let ca_pem = std::fs::read(ca_path)
+        .map_err(|e| AgentError::MqttError(format!("Failed to read CA cert {ca_path}: {e}")))?;
+    parse_ca_certs(&ca_pem)
+        .map_err(|e| AgentError::MqttError(format!("Invalid CA cert {ca_path}: {e}")))
+}
+
+/// Parse PEM-encoded CA certificates. Fails unless every certificate in the
+/// file is usable and there is at least one.
+fn parse_ca_certs(ca_pem: &[u8]) -> Result<rustls::RootCertStore, String> {
+    let mut root_cert_store = rustls::RootCertStore::empty();
+    for cert in rustls_pemfile::certs(&mut std::io::Cursor::new(ca_pem)) {
+        let cert = cert.map_err(|e| format!("malformed PEM: {e}"))?;
+        root_cert_store
+            .add(cert)
+            .map_err(|e| format!("unusable certificate: {e}"))?;
+    }
+    if root_cert_store.is_empty() {
+        return Err("no certificates found".to_string());
+    }
+    Ok(root_cert_store)
+}
+
 /// MQTT message
 #[derive(Debug, Clone)]
 pub struct MqttMessage {
@@ -210,3 +300,76 @@ pub struct MqttCommand {
     pub command: String,
     pub payload: Option<serde_json::Value>,
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::filesys::test_utils::TempFs;
+
+    /// Self-signed CA used by tests that need a certificate rustls accepts
+    pub(crate) const TEST_CA_PEM: &str = "\
+-----BEGIN CERTIFICATE-----
+MIIBhjCCAS2gAwIBAgIUUc7fo46Jef9NnWxbIJL2A9hjcOcwCgYIKoZIzj0EAwIw
+GDEWMBQGA1UEAwwNYWppbWUtdGVzdC1jYTAgFw0yNjEwMTYxNjA2MzlaGA8yMTI2
+MDkyMjE2MDYzOVowGDEWMBQGA1UEAwwNYWppbWUtdGVzdC1jYTBZMBMGByqGSM49
+AgEGCCqGSM49AwEHA0IABKulHnNsJW/VF2Jdzu4Y+W19NzBloo+ilzFz5tcLh6aP
+7sZNEmyxRdHxuBXRCUwaklVBj2qtMLlWE+ZznBHQXBGjUzBRMB0GA1UdDgQWBBTQ
+0zI6yYBa1NXQa1sS5viWGFK0RzAfBgNVHSMEGDAWgBTQ0zI6yYBa1NXQa1sS5viW
+GFK0RzAPBgNVHRMBAf8EBTADAQH/MAoGCCqGSM49BAMCA0cAMEQCIARiqXkrlhIz
+3pQcTklCdhrTPBV+bmNNLvLWFqyVY3a0AiA7/4Ad8isi27PGFoAN0lXwrmJJDuMI
+4g+1MggUb8rBfA==
+-----END CERTIFICATE-----
+";
+
+    #[test]
+    fn test_client_id_override() {
+        let options = ClientIdOptions {
+            override_id: Some("custom-id".to_string()),
+            suffix: Some("ignored".to_string()),
+            random_suffix: true,
+        };
+        assert_eq!(options.client_id("device-123"), "custom-id");
+    }
+
+    #[test]
+    fn test_client_id_suffix() {
+        let options = ClientIdOptions {
+            override_id: None,
+            suffix: Some("lab".to_string()),
+            random_suffix: true,
+        };
+        assert_eq!(options.client_id("device-123"), "ajigent-device-123-lab");
+
+        let options = ClientIdOptions {
+            random_suffix: false,
+            ..Default::default()
+        };
+        assert_eq!(options.client_id("device-123"), "ajigent-device-123");
+    }
+
+    #[test]
+    fn test_client_id_random_suffix() {
+        let options = ClientIdOptions::default();
+        let id = options.client_id("device-123");
+        assert!(id.starts_with("ajigent-device-123-"));
+        assert_eq!(id.len(), "ajigent-device-123-".len() + 6);
+    }
+
+    #[test]
+    fn test_load_root_certs_validates_ca_file() {
+        let fs = TempFs::new();
+        let valid = fs.write("ca.pem", TEST_CA_PEM);
+        let store = load_root_certs(valid.to_str()).unwrap();
+        assert_eq!(store.len(), 1);
+
+        let garbage = fs.write(
+            "garbage.pem",
+            "-----BEGIN CERTIFICATE-----\nbm90IGEgY2VydA==\n-----END CERTIFICATE-----\n",
+        );
+        let empty = fs.write("empty.pem", "");
+        for path in [garbage, empty, fs.path("missing.pem")] {
+            let err = load_root_certs(path.to_str()).unwrap_err();
+            assert!(matches!(err, AgentError::MqttError(_)), "{:?}: {}", path, err);
+        }
+    }
+}
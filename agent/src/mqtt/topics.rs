@@ -14,6 +14,11 @@ impl Topics {
         format!("ajime/device/{}/status", device_id)
     }
 
+    /// Device capability manifest topic (retained)
+    pub fn device_capabilities(device_id: &str) -> String {
+        format!("ajime/device/{}/capabilities", device_id)
+    }
+
     /// Device telemetry topic
     pub fn device_telemetry(device_id: &str) -> String {
         format!("ajime/device/{}/telemetry", device_id)
@@ -70,6 +75,10 @@ mod tests {
             Topics::device_command("device-123"),
             "ajime/device/device-123/command"
         );
+        assert_eq!(
+            Topics::device_capabilities("device-123"),
+            "ajime/device/device-123/capabilities"
+        );
         assert_eq!(
             Topics::workflow_control("workflow-456"),
             "ajime/workflow/workflow-456/control"
@@ -0,0 +1,78 @@
+//! ARP cache discovery.
+//!
+//! Reads the kernel neighbour table from `/proc/net/arp` (Linux only). Hosts
+//! appear here once anything on this device has talked to them, regardless of
+//! which ports they expose.
+
+use std::net::Ipv4Addr;
+
+use crate::errors::AgentError;
+
+/// Kernel ARP table location.
+const ARP_TABLE_PATH: &str = "/proc/net/arp";
+
+/// ATF_COM: the entry has a resolved hardware address.
+const ATF_COM: u32 = 0x2;
+
+/// A resolved entry in the ARP cache.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArpEntry {
+    /// IPv4 address of the neighbour.
+    pub ip: Ipv4Addr,
+
+    /// Hardware address, lowercase and colon-separated.
+    pub mac: String,
+}
+
+/// Read the system ARP cache.
+pub async fn read_arp_table() -> Result<Vec<ArpEntry>, AgentError> {
+    let contents = tokio::fs::read_to_string(ARP_TABLE_PATH).await?;
+    Ok(parse_arp_table(&contents))
+}
+
+/// Parse the contents of `/proc/net/arp`, skipping incomplete entries.
+fn parse_arp_table(contents: &str) -> Vec<ArpEntry> {
+    contents
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let ip = fields.first()?.parse().ok()?;
+            let flags = u32::from_str_radix(fields.get(2)?.trim_start_matches("0x"), 16).ok()?;
+            let mac = fields.get(3)?.to_lowercase();
+            if flags & ATF_COM == 0 || mac == "00:00:00:00:00:00" {
+                return None;
+            }
+            Some(ArpEntry { ip, mac })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_arp_table() {
+        let contents = "\
+IP address       HW type     Flags       HW address            Mask     Device
+192.168.1.1      0x1         0x2         AA:BB:CC:DD:EE:FF     *        eth0
+192.168.1.20     0x1         0x0         00:00:00:00:00:00     *        eth0
+192.168.1.30     0x1         0x6         11:22:33:44:55:66     *        wlan0
+";
+        let entries = parse_arp_table(contents);
+        assert_eq!(
+            entries,
+            vec![
+                ArpEntry {
+                    ip: Ipv4Addr::new(192, 168, 1, 1),
+                    mac: "aa:bb:cc:dd:ee:ff".to_string(),
+                },
+                ArpEntry {
+                    ip: Ipv4Addr::new(192, 168, 1, 30),
+                    mac: "11:22:33:44:55:66".to_string(),
+                },
+            ]
+        );
+    }
+}
@@ -1,99 +1,482 @@
 //! Local network scanner using pure async TCP probing.
 //!
 //! No external binaries (nmap, ping) are required. Concurrency is bounded
-//! by a semaphore to avoid flooding the network interface.
+//! by a semaphore to avoid flooding the network interface. On Linux the ARP
+//! cache can be used instead to pick candidate hosts (see [`DiscoveryMode`]).
+//! Hosts with the agent port open are fingerprinted over HTTP, so unrelated
+//! services on that port are not mistaken for agents.
 
+pub mod arp;
+pub mod oui;
+
+use std::collections::HashMap;
 use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 use std::time::Duration;
 
-use ipnet::Ipv4Net;
+use futures::future::join_all;
+use ipnet::IpNet;
 use serde::{Deserialize, Serialize};
 use tokio::net::TcpStream;
 use tokio::sync::Semaphore;
-use tracing::{debug, info};
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+use crate::server::handlers::SERVICE_NAME;
+
+/// Port the agent's local API listens on by default.
+const AGENT_PORT: u16 = 8080;
 
 /// Ports probed on each candidate host.
-const PROBE_PORTS: &[u16] = &[22, 80, 8080];
+const PROBE_PORTS: &[u16] = &[22, 80, AGENT_PORT];
 
 /// Max concurrent TCP probes to avoid overwhelming the local network.
 const MAX_CONCURRENT: usize = 64;
 
-/// Per-probe timeout.
+/// Default per-probe timeout.
 const PROBE_TIMEOUT_MS: u64 = 500;
 
+/// Longest per-probe timeout a scan request may ask for by default.
+const MAX_PROBE_TIMEOUT_MS: u64 = 5000;
+
+/// Host bits of the largest subnet scanned by default (an IPv4 /16).
+const MAX_HOST_BITS: u8 = 16;
+
+/// Timeout for the HTTP health check that identifies an agent.
+const FINGERPRINT_TIMEOUT_MS: u64 = 2000;
+
+/// How candidate hosts are discovered.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DiscoveryMode {
+    /// TCP-probe every host in the subnet.
+    #[default]
+    Tcp,
+
+    /// Take live hosts from the ARP cache and probe only those. Falls back to
+    /// `Tcp` when the ARP cache is unavailable.
+    Arp,
+}
+
+impl DiscoveryMode {
+    /// Parse a mode name ("tcp" or "arp").
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "tcp" => Some(DiscoveryMode::Tcp),
+            "arp" => Some(DiscoveryMode::Arp),
+            _ => None,
+        }
+    }
+}
+
+/// Options controlling a subnet scan.
+#[derive(Debug, Clone)]
+pub struct ScanOptions {
+    /// Ports probed on each candidate host.
+    pub ports: Vec<u16>,
+
+    /// Timeout for a single TCP connect attempt. A host's ports are probed
+    /// concurrently, so this is also the upper bound for one host.
+    pub timeout: Duration,
+
+    /// Longest per-probe timeout a scan request may ask for; longer ones
+    /// are clamped, as a /16 of silent hosts would otherwise run for days.
+    pub max_timeout: Duration,
+
+    /// Max hosts probed at once.
+    pub max_concurrent: usize,
+
+    /// How candidate hosts are discovered.
+    pub mode: DiscoveryMode,
+
+    /// Largest subnet scanned, in host bits: 16 allows an IPv4 /16 or an
+    /// IPv6 /112. Larger subnets are rejected.
+    pub max_host_bits: u8,
+
+    /// Port whose `/health` endpoint is checked for a running agent.
+    pub agent_port: u16,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self {
+            ports: PROBE_PORTS.to_vec(),
+            timeout: Duration::from_millis(PROBE_TIMEOUT_MS),
+            max_timeout: Duration::from_millis(MAX_PROBE_TIMEOUT_MS),
+            max_concurrent: MAX_CONCURRENT,
+            mode: DiscoveryMode::Tcp,
+            max_host_bits: MAX_HOST_BITS,
+            agent_port: AGENT_PORT,
+        }
+    }
+}
+
+impl ScanOptions {
+    /// Use the per-probe timeout a scan request asked for, up to
+    /// `max_timeout`.
+    pub fn request_timeout(&mut self, timeout_ms: u64) {
+        let requested = Duration::from_millis(timeout_ms);
+        if requested > self.max_timeout {
+            debug!("Scan timeout {:?} clamped to {:?}", requested, self.max_timeout);
+        }
+        self.timeout = requested.min(self.max_timeout);
+    }
+}
+
+/// Parse `cidr` and check it is small enough to scan.
+fn parse_subnet(cidr: &str, max_host_bits: u8) -> Result<IpNet, String> {
+    let net: IpNet = cidr.parse().map_err(|e| format!("Invalid CIDR {}: {}", cidr, e))?;
+    let host_bits = net.max_prefix_len() - net.prefix_len();
+    if host_bits > max_host_bits {
+        return Err(format!(
+            "Subnet {} is too large to scan: {} host bits, at most {} allowed",
+            cidr, host_bits, max_host_bits
+        ));
+    }
+    Ok(net)
+}
+
 /// A device discovered during a subnet scan.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DiscoveredDevice {
-    /// IPv4 address of the host.
+    /// IP address of the host.
     pub ip: String,
 
     /// Ports that accepted a TCP connection.
     pub open_ports: Vec<u16>,
 
-    /// True when port 8080 is open, indicating a running Ajime agent.
+    /// True when the agent port answered its health check as an Ajime agent.
     pub has_agent: bool,
+
+    /// Version reported by the agent, when one was found.
+    #[serde(default)]
+    pub agent_version: Option<String>,
+
+    /// Hostname reported by the agent, when one was found.
+    #[serde(default)]
+    pub hostname: Option<String>,
+
+    /// Hardware address, when known.
+    #[serde(default)]
+    pub mac: Option<String>,
+
+    /// Vendor resolved from the MAC address OUI, when known.
+    #[serde(default)]
+    pub vendor: Option<String>,
 }
 
-/// Scan all hosts in `cidr` (e.g. `"192.168.1.0/24"`) and return reachable devices.
+/// Scan all hosts in `cidr` (e.g. `"192.168.1.0/24"` or `"fd00::/120"`) and
+/// return reachable devices, sorted by address.
 ///
 /// The scan is best-effort: hosts that do not respond within the timeout are
-/// silently skipped.
-pub async fn scan_subnet(cidr: &str) -> Vec<DiscoveredDevice> {
-    let net: Ipv4Net = match cidr.parse() {
-        Ok(n) => n,
+/// silently skipped. In ARP mode every host in the ARP cache is reported, with
+/// whatever ports it has open; the ARP cache only covers IPv4, so IPv6
+/// subnets are always probed over TCP. Subnets larger than
+/// `options.max_host_bits` are rejected. Hosts are generated as probing
+/// proceeds, with at most `max_concurrent` probe tasks alive at once.
+/// Cancelling `cancel` aborts outstanding probes and returns the devices
+/// found so far.
+pub async fn scan_subnet(
+    cidr: &str,
+    options: &ScanOptions,
+    cancel: &CancellationToken,
+) -> Vec<DiscoveredDevice> {
+    let net = match parse_subnet(cidr, options.max_host_bits) {
+        Ok(net) => net,
         Err(e) => {
-            tracing::warn!("Invalid CIDR {}: {}", cidr, e);
+            warn!("{}", e);
             return vec![];
         }
     };
 
-    let hosts: Vec<IpAddr> = net.hosts().map(IpAddr::V4).collect();
-    info!("Scanning {} hosts in {}", hosts.len(), cidr);
-
-    let semaphore = Arc::new(Semaphore::new(MAX_CONCURRENT));
-    let mut handles = Vec::with_capacity(hosts.len());
-
-    for ip in hosts {
-        let sem = Arc::clone(&semaphore);
-        handles.push(tokio::spawn(async move {
-            let _permit = sem.acquire().await.ok()?;
-            let open_ports = probe_ports(ip).await;
-            if open_ports.is_empty() {
-                return None;
+    // Candidate hosts with their MAC address when discovered via ARP
+    let mut macs: HashMap<IpAddr, String> = HashMap::new();
+    let mut arp_discovered = false;
+    if options.mode == DiscoveryMode::Arp && matches!(net, IpNet::V4(_)) {
+        match arp::read_arp_table().await {
+            Ok(entries) => {
+                arp_discovered = true;
+                macs = entries
+                    .into_iter()
+                    .map(|e| (IpAddr::V4(e.ip), e.mac))
+                    .filter(|(ip, _)| net.contains(ip))
+                    .collect();
             }
-            let has_agent = open_ports.contains(&8080);
-            Some(DiscoveredDevice {
-                ip: ip.to_string(),
-                open_ports,
-                has_agent,
-            })
-        }));
+            Err(e) => warn!("ARP cache unavailable ({}), falling back to TCP discovery", e),
+        }
     }
 
+    let hosts: Box<dyn Iterator<Item = IpAddr> + Send> = if arp_discovered {
+        let mut hosts: Vec<IpAddr> = macs.keys().copied().collect();
+        hosts.sort();
+        Box::new(hosts.into_iter())
+    } else {
+        Box::new(net.hosts())
+    };
+    info!("Scanning {} ({})", cidr, if arp_discovered { "arp" } else { "tcp" });
+
+    let http = match fingerprint_client() {
+        Ok(http) => http,
+        Err(e) => {
+            warn!("Failed to create the fingerprint HTTP client: {}", e);
+            return vec![];
+        }
+    };
+    let semaphore = Arc::new(Semaphore::new(options.max_concurrent.max(1)));
+    let ports: Arc<[u16]> = options.ports.clone().into();
+    let mut probes = JoinSet::new();
     let mut results = Vec::new();
-    for handle in handles {
-        if let Ok(Some(device)) = handle.await {
+    let mut collect = |found: Option<DiscoveredDevice>| {
+        if let Some(device) = found {
             debug!("Found device: {} ports={:?}", device.ip, device.open_ports);
             results.push(device);
         }
+    };
+
+    for ip in hosts {
+        // Wait for a free slot before creating the next probe, so a large
+        // subnet never has more than `max_concurrent` tasks alive
+        let permit = tokio::select! {
+            _ = cancel.cancelled() => break,
+            permit = semaphore.clone().acquire_owned() => match permit {
+                Ok(permit) => permit,
+                Err(_) => break,
+            },
+        };
+        while let Some(finished) = probes.try_join_next() {
+            collect(finished.ok().flatten());
+        }
+
+        let ports = Arc::clone(&ports);
+        let timeout = options.timeout;
+        let agent_port = options.agent_port;
+        let http = http.clone();
+        let cancel = cancel.clone();
+        let mac = macs.remove(&ip);
+        probes.spawn(async move {
+            let _permit = permit;
+            // Dropping the probe future on cancellation closes any
+            // in-flight connect attempts
+            cancel
+                .run_until_cancelled(async move {
+                    let open_ports = probe_ports(ip, &ports, timeout).await;
+                    // ARP entries are live hosts even with every port closed
+                    if open_ports.is_empty() && mac.is_none() {
+                        return None;
+                    }
+                    let agent = if open_ports.contains(&agent_port) {
+                        fingerprint_agent(&http, SocketAddr::new(ip, agent_port)).await
+                    } else {
+                        None
+                    };
+                    Some(DiscoveredDevice {
+                        ip: ip.to_string(),
+                        open_ports,
+                        has_agent: agent.is_some(),
+                        agent_version: agent.as_ref().map(|a| a.version.clone()),
+                        hostname: agent.and_then(|a| a.hostname),
+                        mac,
+                        vendor: None,
+                    })
+                })
+                .await
+                .flatten()
+        });
+    }
+    while let Some(finished) = probes.join_next().await {
+        collect(finished.ok().flatten());
+    }
+    results.sort_by_key(|device| device.ip.parse::<IpAddr>().ok());
+
+    // Connecting populates the ARP cache, so TCP-discovered hosts can be
+    // matched to a MAC address now
+    if !arp_discovered && !results.is_empty() {
+        if let Ok(entries) = arp::read_arp_table().await {
+            let macs: HashMap<String, String> = entries
+                .into_iter()
+                .map(|e| (e.ip.to_string(), e.mac))
+                .collect();
+            for device in &mut results {
+                device.mac = macs.get(&device.ip).cloned();
+            }
+        }
+    }
+    for device in &mut results {
+        device.vendor = device
+            .mac
+            .as_deref()
+            .and_then(oui::lookup_vendor)
+            .map(String::from);
+    }
+
+    if cancel.is_cancelled() {
+        info!("Scan of {} cancelled: {} devices found", cidr, results.len());
+        return results;
     }
 
     info!("Scan complete: {} devices found", results.len());
     results
 }
 
-/// Probe a set of ports on `ip` and return those that accepted a connection.
-async fn probe_ports(ip: IpAddr) -> Vec<u16> {
-    let mut open = Vec::new();
-    let timeout = Duration::from_millis(PROBE_TIMEOUT_MS);
-
-    for &port in PROBE_PORTS {
+/// Probe `ports` on `ip` concurrently and return those that accepted a connection.
+async fn probe_ports(ip: IpAddr, ports: &[u16], timeout: Duration) -> Vec<u16> {
+    let probes = ports.iter().map(|&port| async move {
         let addr = SocketAddr::new(ip, port);
-        if let Ok(Ok(_)) = tokio::time::timeout(timeout, TcpStream::connect(addr)).await {
-            open.push(port);
+        match tokio::time::timeout(timeout, TcpStream::connect(addr)).await {
+            Ok(Ok(_)) => Some(port),
+            _ => None,
         }
+    });
+
+    join_all(probes).await.into_iter().flatten().collect()
+}
+
+/// The fields of an agent's health check used to identify it.
+#[derive(Debug, Deserialize)]
+struct AgentHealth {
+    service: String,
+    version: String,
+    #[serde(default)]
+    hostname: Option<String>,
+}
+
+/// HTTP client for fingerprinting. Proxies are bypassed since the hosts are
+/// on the local network.
+fn fingerprint_client() -> reqwest::Result<reqwest::Client> {
+    reqwest::Client::builder()
+        .timeout(Duration::from_millis(FINGERPRINT_TIMEOUT_MS))
+        .no_proxy()
+        .build()
+}
+
+/// Ask `addr` for its health check and return it when it is an agent's.
+/// Errors and unexpected bodies mean there is no agent.
+async fn fingerprint_agent(http: &reqwest::Client, addr: SocketAddr) -> Option<AgentHealth> {
+    let response = http.get(format!("http://{}/health", addr)).send().await.ok()?;
+    if !response.status().is_success() {
+        return None;
+    }
+    let health: AgentHealth = serde_json::from_slice(&response.bytes().await.ok()?).ok()?;
+    (health.service == SERVICE_NAME).then_some(health)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_probe_ports_reports_open_ports() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let open_port = listener.local_addr().unwrap().port();
+        let closed_port = {
+            let l = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            l.local_addr().unwrap().port()
+        };
+
+        let ip: IpAddr = "127.0.0.1".parse().unwrap();
+        let open = probe_ports(ip, &[open_port, closed_port], Duration::from_millis(200)).await;
+        assert_eq!(open, vec![open_port]);
+    }
+
+    #[test]
+    fn test_subnet_size_is_limited() {
+        assert!(parse_subnet("192.168.0.0/16", 16).is_ok());
+        assert!(parse_subnet("fd00::/112", 16).is_ok());
+        assert!(parse_subnet("10.0.0.0/8", 16).unwrap_err().contains("too large"));
+        assert!(parse_subnet("fd00::/64", 16).is_err());
+        assert!(parse_subnet("192.168.1.0/24", 4).is_err());
+        assert!(parse_subnet("not-a-subnet", 16).unwrap_err().contains("Invalid CIDR"));
+    }
+
+    #[test]
+    fn test_requested_timeout_is_clamped() {
+        let mut options = ScanOptions::default();
+        options.request_timeout(200);
+        assert_eq!(options.timeout, Duration::from_millis(200));
+        options.request_timeout(u64::MAX);
+        assert_eq!(options.timeout, options.max_timeout);
+    }
+
+    #[tokio::test]
+    async fn test_scan_finds_listening_host() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let options = ScanOptions {
+            ports: vec![listener.local_addr().unwrap().port()],
+            timeout: Duration::from_millis(200),
+            max_concurrent: 2,
+            ..Default::default()
+        };
+
+        // A /30 holds 127.0.0.1 and .2; only .1 is listening
+        let devices = scan_subnet("127.0.0.0/30", &options, &CancellationToken::new()).await;
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].ip, "127.0.0.1");
+    }
+
+    /// Answer every connection on a local port with `body`
+    async fn http_responder(body: &'static str) -> u16 {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = [0u8; 1024];
+                let _ = stream.read(&mut request).await;
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\n\
+                     content-length: {}\r\nconnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        port
     }
 
-    open
+    #[tokio::test]
+    async fn test_agents_are_fingerprinted() {
+        let agent = r#"{"status":"healthy","service":"ajigent","version":"1.2.3","hostname":"pi"}"#;
+        let port = http_responder(agent).await;
+        let options = ScanOptions {
+            ports: vec![port],
+            agent_port: port,
+            ..Default::default()
+        };
+        let devices = scan_subnet("127.0.0.1/32", &options, &CancellationToken::new()).await;
+        assert_eq!(devices.len(), 1);
+        assert!(devices[0].has_agent);
+        assert_eq!(devices[0].agent_version.as_deref(), Some("1.2.3"));
+        assert_eq!(devices[0].hostname.as_deref(), Some("pi"));
+
+        // Another service on the agent port is only an open port
+        let http = fingerprint_client().unwrap();
+        let ip: IpAddr = "127.0.0.1".parse().unwrap();
+        let other = http_responder(r#"{"status":"ok","service":"grafana","version":"10"}"#).await;
+        assert!(fingerprint_agent(&http, SocketAddr::new(ip, other)).await.is_none());
+        let html = http_responder("<html></html>").await;
+        assert!(fingerprint_agent(&http, SocketAddr::new(ip, html)).await.is_none());
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        assert!(fingerprint_agent(&http, closed).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_scan_returns_promptly() {
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+
+        let options = ScanOptions {
+            timeout: Duration::from_secs(30),
+            ..Default::default()
+        };
+        let devices = tokio::time::timeout(
+            Duration::from_secs(5),
+            scan_subnet("10.255.255.0/24", &options, &cancel),
+        )
+        .await
+        .expect("cancelled scan should not wait for probe timeouts");
+        assert!(devices.is_empty());
+    }
 }
@@ -0,0 +1,59 @@
+//! MAC vendor lookup from a bundled subset of the IEEE OUI registry.
+//!
+//! Only vendors commonly found next to robots and edge devices are included;
+//! anything else resolves to `None`.
+
+/// OUI prefix (uppercase, colon-separated) to vendor name.
+const OUI_VENDORS: &[(&str, &str)] = &[
+    ("00:04:4B", "NVIDIA"),
+    ("00:09:0F", "Fortinet"),
+    ("00:0C:29", "VMware"),
+    ("00:11:32", "Synology"),
+    ("00:17:88", "Philips Lighting"),
+    ("00:1A:11", "Google"),
+    ("00:50:56", "VMware"),
+    ("08:00:27", "Oracle VirtualBox"),
+    ("24:0A:C4", "Espressif"),
+    ("24:A4:3C", "Ubiquiti"),
+    ("28:CD:C1", "Raspberry Pi"),
+    ("2C:CF:67", "Raspberry Pi"),
+    ("30:AE:A4", "Espressif"),
+    ("3C:5A:B4", "Google"),
+    ("48:B0:2D", "NVIDIA"),
+    ("52:54:00", "QEMU/KVM"),
+    ("84:0D:8E", "Espressif"),
+    ("A4:CF:12", "Espressif"),
+    ("A8:61:0A", "Arduino"),
+    ("B8:27:EB", "Raspberry Pi"),
+    ("D8:3A:DD", "Raspberry Pi"),
+    ("DC:A6:32", "Raspberry Pi"),
+    ("E4:5F:01", "Raspberry Pi"),
+    ("F0:9F:C2", "Ubiquiti"),
+];
+
+/// Resolve the vendor of a MAC address (e.g. "b8:27:eb:12:34:56").
+pub fn lookup_vendor(mac: &str) -> Option<&'static str> {
+    let prefix = mac.get(..8)?.to_uppercase().replace('-', ":");
+    OUI_VENDORS
+        .binary_search_by(|(oui, _)| (*oui).cmp(prefix.as_str()))
+        .ok()
+        .map(|i| OUI_VENDORS[i].1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_table_is_sorted() {
+        assert!(OUI_VENDORS.windows(2).all(|w| w[0].0 < w[1].0));
+    }
+
+    #[test]
+    fn test_lookup_vendor() {
+        assert_eq!(lookup_vendor("b8:27:eb:12:34:56"), Some("Raspberry Pi"));
+        assert_eq!(lookup_vendor("48-B0-2D-00-00-01"), Some("NVIDIA"));
+        assert_eq!(lookup_vendor("ff:ff:ff:ff:ff:ff"), None);
+        assert_eq!(lookup_vendor("b8:27"), None);
+    }
+}
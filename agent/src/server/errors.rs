@@ -0,0 +1,209 @@
+//! Local API error responses
+//!
+//! Every error the local server returns carries an `ErrorResponse` body
+//! (`{"error": <code>, "message": ..., "details": ...}`) instead of a bare
+//! status, so callers can tell what failed.
+
+use axum::{
+    extract::{
+        rejection::{JsonRejection, QueryRejection},
+        FromRequest, FromRequestParts, Request,
+    },
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use openapi_client::models::ErrorResponse;
+use serde::de::DeserializeOwned;
+use tracing::warn;
+
+use crate::errors::AgentError;
+
+/// An error response from the local API
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl ApiError {
+    /// An error with the default code for `status`
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            code: status_code(status),
+            message: message.into(),
+        }
+    }
+
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::UNAUTHORIZED, message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_FOUND, message)
+    }
+
+    pub fn unavailable(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::SERVICE_UNAVAILABLE, message)
+    }
+}
+
+/// Default error code for a status
+fn status_code(status: StatusCode) -> &'static str {
+    match status {
+        StatusCode::BAD_REQUEST => "bad_request",
+        StatusCode::UNAUTHORIZED => "unauthorized",
+        StatusCode::FORBIDDEN => "forbidden",
+        StatusCode::NOT_FOUND => "not_found",
+        StatusCode::METHOD_NOT_ALLOWED => "method_not_allowed",
+        StatusCode::CONFLICT => "conflict",
+        StatusCode::UNPROCESSABLE_ENTITY => "unprocessable_entity",
+        StatusCode::BAD_GATEWAY => "bad_gateway",
+        StatusCode::SERVICE_UNAVAILABLE => "unavailable",
+        StatusCode::GATEWAY_TIMEOUT => "timeout",
+        s if s.is_client_error() => "bad_request",
+        _ => "internal_error",
+    }
+}
+
+impl From<AgentError> for ApiError {
+    fn from(err: AgentError) -> Self {
+        let (status, code) = match &err {
+            AgentError::ValidationError(_) => (StatusCode::BAD_REQUEST, "validation_error"),
+            AgentError::ConfigError(_) => (StatusCode::BAD_REQUEST, "config_error"),
+            AgentError::JsonError(_) => (StatusCode::BAD_REQUEST, "invalid_json"),
+            AgentError::AuthError(_) => (StatusCode::UNAUTHORIZED, "auth_error"),
+            AgentError::TokenError(_) => (StatusCode::UNAUTHORIZED, "token_error"),
+            AgentError::NotFound(_) => (StatusCode::NOT_FOUND, "not_found"),
+            AgentError::Conflict(_) => (StatusCode::CONFLICT, "conflict"),
+            AgentError::Timeout(_) => (StatusCode::GATEWAY_TIMEOUT, "timeout"),
+            AgentError::DeviceNotActivated(_) => (StatusCode::CONFLICT, "device_not_activated"),
+            AgentError::HttpError(_) => (StatusCode::BAD_GATEWAY, "backend_unreachable"),
+            AgentError::HttpStatus { .. } => (StatusCode::BAD_GATEWAY, "backend_error"),
+            AgentError::ShutdownError(_) => (StatusCode::SERVICE_UNAVAILABLE, "shutting_down"),
+            AgentError::IoError(_) => (StatusCode::INTERNAL_SERVER_ERROR, "io_error"),
+            AgentError::StorageError(_) => (StatusCode::INTERNAL_SERVER_ERROR, "storage_error"),
+            AgentError::SyncError(_) => (StatusCode::INTERNAL_SERVER_ERROR, "sync_error"),
+            AgentError::DeployError(_) => (StatusCode::INTERNAL_SERVER_ERROR, "deploy_error"),
+            AgentError::MqttError(_) => (StatusCode::INTERNAL_SERVER_ERROR, "mqtt_error"),
+            AgentError::ServerError(_) => (StatusCode::INTERNAL_SERVER_ERROR, "server_error"),
+            AgentError::HardwareError(_) => (StatusCode::INTERNAL_SERVER_ERROR, "hardware_error"),
+            AgentError::WorkflowError(_) => (StatusCode::INTERNAL_SERVER_ERROR, "workflow_error"),
+            AgentError::Internal(_) => (StatusCode::INTERNAL_SERVER_ERROR, "internal_error"),
+        };
+        Self {
+            status,
+            code,
+            message: err.to_string(),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        if self.status.is_server_error() {
+            warn!("Local API request failed: {}", self.message);
+        }
+        let body = ErrorResponse {
+            error: self.code.to_string(),
+            message: self.message,
+            details: None,
+        };
+        (self.status, Json(body)).into_response()
+    }
+}
+
+impl IntoResponse for AgentError {
+    fn into_response(self) -> Response {
+        ApiError::from(self).into_response()
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        Self::new(rejection.status(), rejection.body_text())
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(rejection: QueryRejection) -> Self {
+        Self::new(rejection.status(), rejection.body_text())
+    }
+}
+
+/// `Json` extractor whose rejection is an `ErrorResponse`
+pub struct ApiJson<T>(pub T);
+
+impl<T, S> FromRequest<S> for ApiJson<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<T>::from_request(request, state).await?;
+        Ok(Self(value))
+    }
+}
+
+/// `Query` extractor whose rejection is an `ErrorResponse`
+pub struct ApiQuery<T>(pub T);
+
+impl<T, S> FromRequestParts<S> for ApiQuery<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let axum::extract::Query(value) =
+            axum::extract::Query::<T>::from_request_parts(parts, state).await?;
+        Ok(Self(value))
+    }
+}
+
+/// Fallback for unknown routes
+pub async fn not_found_handler() -> ApiError {
+    ApiError::not_found("No such endpoint")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn parts(response: impl IntoResponse) -> (StatusCode, serde_json::Value) {
+        let response = response.into_response();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_errors_become_error_responses() {
+        let (status, body) = parts(AgentError::ValidationError("bad label".to_string())).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "validation_error");
+        assert_eq!(body["message"], "Validation error: bad label");
+
+        let (status, body) = parts(AgentError::NotFound("dir".to_string())).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error"], "not_found");
+
+        let (status, body) = parts(AgentError::StorageError("disk".to_string())).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(body["error"], "storage_error");
+
+        let (status, body) = parts(AgentError::Timeout("camera".to_string())).await;
+        assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(body["error"], "timeout");
+
+        let (status, body) = parts(ApiError::unauthorized("no token")).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["error"], "unauthorized");
+        assert_eq!(body["message"], "no token");
+    }
+}
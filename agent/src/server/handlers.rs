@@ -3,17 +3,32 @@
 use std::sync::Arc;
 
 use axum::{
-    extract::State,
+    extract::{Path, State},
     http::StatusCode,
     response::IntoResponse,
     Json,
 };
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use sysinfo::System;
+use tracing::warn;
 
+use crate::authn::local_token::rotate_and_audit;
+use crate::authn::token_mngr::TokenManagerExt;
+use crate::clock::ClockStatus;
+use crate::deploy::dirs::DeploymentDir;
+use crate::errors::AgentError;
+use crate::health::{ComponentHealth, HealthStatus};
+use crate::models::workflow::Workflow;
+use crate::server::errors::{ApiJson, ApiQuery};
 use crate::server::state::ServerState;
-use crate::storage::device::load_device;
-use crate::telemetry::collect_metrics;
+use crate::sync::syncer::{SyncState, WorkflowSyncError};
+use crate::terminal::sessions::SessionInfo;
 use crate::utils::version_info;
+use crate::workers::token_refresh::TokenRefreshState;
+
+/// Service name reported by the health check
+pub const SERVICE_NAME: &str = "ajigent";
 
 /// Health check response
 #[derive(Debug, Serialize)]
@@ -21,18 +36,54 @@ pub struct HealthResponse {
     pub status: String,
     pub service: String,
     pub version: String,
+    /// Lets network scans identify the device
+    pub hostname: Option<String>,
+    pub components: Vec<ComponentHealth>,
 }
 
 /// Health check handler
-pub async fn health_handler() -> impl IntoResponse {
+pub async fn health_handler(State(state): State<Arc<ServerState>>) -> impl IntoResponse {
     let version = version_info();
+    let status = match state.health.overall() {
+        HealthStatus::Healthy => "healthy",
+        HealthStatus::Degraded => "degraded",
+        HealthStatus::Unhealthy => "unhealthy",
+    };
     Json(HealthResponse {
-        status: "healthy".to_string(),
-        service: "ajigent".to_string(),
+        status: status.to_string(),
+        service: SERVICE_NAME.to_string(),
         version: version.version,
+        hostname: System::host_name(),
+        components: state.health.components(),
     })
 }
 
+/// Readiness response
+#[derive(Debug, Serialize)]
+pub struct ReadyResponse {
+    pub ready: bool,
+    pub draining: bool,
+    pub in_flight: usize,
+}
+
+/// Readiness handler: 503 while draining
+pub async fn ready_handler(State(state): State<Arc<ServerState>>) -> impl IntoResponse {
+    let draining = state.drain.is_draining();
+    let status = if draining {
+        StatusCode::SERVICE_UNAVAILABLE
+    } else {
+        StatusCode::OK
+    };
+    (
+        status,
+        Json(ReadyResponse {
+            ready: !draining,
+            draining,
+            in_flight: state.drain.in_flight(),
+        }),
+    )
+}
+
 /// Version response
 #[derive(Debug, Serialize)]
 pub struct VersionResponse {
@@ -56,6 +107,7 @@ pub async fn version_handler() -> impl IntoResponse {
 pub struct DeviceResponse {
     pub id: String,
     pub name: String,
+    pub label: Option<String>,
     pub device_type: Option<String>,
     pub status: String,
     pub owner_id: String,
@@ -64,22 +116,40 @@ pub struct DeviceResponse {
 /// Device info handler
 pub async fn device_handler(
     State(state): State<Arc<ServerState>>,
-) -> Result<impl IntoResponse, StatusCode> {
+) -> Result<Json<DeviceResponse>, AgentError> {
     state.activity_tracker.touch();
 
-    let device = load_device(&state.device_file)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let device = state.device.load().await?;
 
     Ok(Json(DeviceResponse {
         id: device.id,
         name: device.name,
+        label: state.device_label.get(),
         device_type: device.device_type,
         status: "online".to_string(),
         owner_id: device.owner_id,
     }))
 }
 
+/// Device update request
+#[derive(Debug, Deserialize)]
+pub struct DeviceUpdateRequest {
+    /// New label; null or blank clears it
+    pub label: Option<String>,
+}
+
+/// Device update handler: relabels the device without re-activation
+pub async fn update_device_handler(
+    State(state): State<Arc<ServerState>>,
+    ApiJson(request): ApiJson<DeviceUpdateRequest>,
+) -> Result<Json<DeviceResponse>, AgentError> {
+    state.activity_tracker.touch();
+
+    state.device_label.set(request.label.as_deref()).await?;
+
+    device_handler(State(state)).await
+}
+
 /// Sync request
 #[derive(Debug, Deserialize)]
 pub struct SyncRequest {
@@ -91,31 +161,189 @@ pub struct SyncRequest {
 pub struct SyncResponse {
     pub success: bool,
     pub message: String,
+
+    /// Workflows that could not be applied
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub failed_workflows: Vec<WorkflowSyncError>,
 }
 
-/// Sync handler
+/// Sync handler; `?force=true` clears any cooldown first
 pub async fn sync_handler(
     State(state): State<Arc<ServerState>>,
-) -> Result<impl IntoResponse, StatusCode> {
+    ApiQuery(request): ApiQuery<SyncRequest>,
+) -> Result<Json<SyncResponse>, AgentError> {
     state.activity_tracker.touch();
 
+    if request.force.unwrap_or(false) {
+        state.syncer.reset_cooldown().await;
+    }
+
     match state.syncer.trigger_sync().await {
+        Ok(report) if report.is_partial() => Ok(Json(SyncResponse {
+            success: true,
+            message: format!(
+                "Sync completed; {} workflow(s) failed to apply",
+                report.failed.len()
+            ),
+            failed_workflows: report.failed,
+        })),
         Ok(_) => Ok(Json(SyncResponse {
             success: true,
             message: "Sync completed successfully".to_string(),
+            failed_workflows: Vec::new(),
         })),
         Err(e) => Ok(Json(SyncResponse {
             success: false,
             message: format!("Sync failed: {}", e),
+            failed_workflows: Vec::new(),
         })),
     }
 }
 
+/// Sync status response
+#[derive(Debug, Serialize)]
+pub struct SyncStatusResponse {
+    pub last_attempted_sync_at: Option<DateTime<Utc>>,
+    pub last_synced_at: Option<DateTime<Utc>>,
+
+    /// Consecutive failed syncs
+    pub err_streak: u32,
+
+    pub in_cooldown: bool,
+    pub cooldown_ends_at: Option<DateTime<Utc>>,
+    pub cooldown_remaining_secs: u64,
+}
+
+impl From<SyncState> for SyncStatusResponse {
+    fn from(sync: SyncState) -> Self {
+        let known = |at: DateTime<Utc>| (at != DateTime::<Utc>::MIN_UTC).then_some(at);
+        Self {
+            last_attempted_sync_at: known(sync.last_attempted_sync_at),
+            last_synced_at: known(sync.last_synced_at),
+            err_streak: sync.err_streak,
+            in_cooldown: sync.is_in_cooldown(),
+            cooldown_ends_at: known(sync.cooldown_ends_at),
+            cooldown_remaining_secs: sync.cooldown_remaining().as_secs(),
+        }
+    }
+}
+
+/// Sync status handler
+pub async fn sync_status_handler(State(state): State<Arc<ServerState>>) -> impl IntoResponse {
+    Json(SyncStatusResponse::from(state.syncer.get_state().await))
+}
+
+/// Sync cooldown reset handler
+pub async fn sync_reset_handler(State(state): State<Arc<ServerState>>) -> impl IntoResponse {
+    state.activity_tracker.touch();
+    state.syncer.reset_cooldown().await;
+    Json(SyncStatusResponse::from(state.syncer.get_state().await))
+}
+
+/// Token status response
+#[derive(Debug, Serialize)]
+pub struct TokenStatusResponse {
+    #[serde(flatten)]
+    pub refresh: TokenRefreshState,
+
+    /// Runway left on the current token (negative once expired)
+    pub token_expires_in_secs: Option<i64>,
+}
+
+/// Token status handler
+pub async fn token_status_handler(State(state): State<Arc<ServerState>>) -> impl IntoResponse {
+    let refresh = state
+        .token_refresh
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone();
+    Json(TokenStatusResponse {
+        token_expires_in_secs: refresh.token_expires_in_secs(),
+        refresh,
+    })
+}
+
+/// Refresh the device token now instead of waiting for the refresh
+/// worker. Responds with the token status, never the token itself.
+pub async fn token_refresh_handler(
+    State(state): State<Arc<ServerState>>,
+) -> Result<Json<TokenStatusResponse>, AgentError> {
+    state.activity_tracker.touch();
+
+    let result = state.token_mngr.refresh_token().await;
+    let refresh = {
+        let mut refresh = state.token_refresh.write().unwrap_or_else(|e| e.into_inner());
+        match &result {
+            Ok(token) => refresh.record_refresh(token.expires_at()),
+            Err(e) => refresh.record_failure(format!("Failed to refresh token: {}", e)),
+        }
+        refresh.clone()
+    };
+    result?;
+    Ok(Json(TokenStatusResponse {
+        token_expires_in_secs: refresh.token_expires_in_secs(),
+        refresh,
+    }))
+}
+
+/// Local API token rotation response
+#[derive(Debug, Serialize)]
+pub struct LocalTokenResponse {
+    /// The new token; it is not shown again
+    pub token: String,
+}
+
+/// Rotate the local API token. Only available once a token exists, since
+/// the request must then have been authenticated with the current one.
+pub async fn rotate_local_token_handler(
+    State(state): State<Arc<ServerState>>,
+) -> Result<Json<LocalTokenResponse>, AgentError> {
+    state.activity_tracker.touch();
+
+    if state.local_token.current().await.is_none() {
+        return Err(AgentError::Conflict("No local API token has been created".to_string()));
+    }
+    let token = rotate_and_audit(&state.local_token, &state.audit, "api").await?;
+    Ok(Json(LocalTokenResponse { token }))
+}
+
+/// Default workflows page size
+pub const DEFAULT_PAGE_SIZE: usize = 50;
+
+/// Largest workflows page size
+pub const MAX_PAGE_SIZE: usize = 200;
+
+/// Pagination query (`?limit=&offset=`)
+#[derive(Debug, Default, Deserialize)]
+pub struct Pagination {
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+}
+
+impl Pagination {
+    /// Page size, defaulted and clamped to `1..=MAX_PAGE_SIZE`
+    pub fn limit(&self) -> usize {
+        self.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE)
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset.unwrap_or(0)
+    }
+
+    /// Select the requested page
+    pub fn apply<T>(&self, items: Vec<T>) -> Vec<T> {
+        items.into_iter().skip(self.offset()).take(self.limit()).collect()
+    }
+}
+
 /// Workflows response
 #[derive(Debug, Serialize)]
 pub struct WorkflowsResponse {
     pub workflows: Vec<WorkflowInfo>,
+    /// Number of workflows across all pages
     pub total: usize,
+    pub limit: usize,
+    pub offset: usize,
 }
 
 #[derive(Debug, Serialize)]
@@ -128,33 +356,108 @@ pub struct WorkflowInfo {
 /// Workflows handler
 pub async fn workflows_handler(
     State(state): State<Arc<ServerState>>,
-) -> Result<impl IntoResponse, StatusCode> {
+    ApiQuery(pagination): ApiQuery<Pagination>,
+) -> Result<Json<WorkflowsResponse>, AgentError> {
     state.activity_tracker.touch();
 
-    let workflow_ids = state.syncer.get_cached_workflows();
-    let workflows: Vec<WorkflowInfo> = workflow_ids
+    // Report the executor's deployment state; workflows without an executor
+    // are only cached
+    let states = state.executors.states().await;
+    let entries = state.caches.workflows.entries();
+    let total = entries.len();
+    let workflows: Vec<WorkflowInfo> = pagination
+        .apply(entries)
         .into_iter()
-        .map(|id| {
-            // Get workflow from cache
-            if let Some(entry) = state.caches.workflows.get(&id) {
-                WorkflowInfo {
-                    id: entry.workflow.id.clone(),
-                    name: entry.workflow.name.clone(),
-                    status: "deployed".to_string(),
-                }
-            } else {
-                WorkflowInfo {
-                    id: id.clone(),
-                    name: "Unknown".to_string(),
-                    status: "unknown".to_string(),
-                }
-            }
+        .map(|entry| WorkflowInfo {
+            status: states
+                .get(&entry.workflow.id)
+                .map_or("cached", |s| s.as_str())
+                .to_string(),
+            id: entry.workflow.id,
+            name: entry.workflow.name,
         })
         .collect();
 
-    let total = workflows.len();
+    Ok(Json(WorkflowsResponse {
+        workflows,
+        total,
+        limit: pagination.limit(),
+        offset: pagination.offset(),
+    }))
+}
+
+/// Deployment directories response
+#[derive(Debug, Serialize)]
+pub struct DeploymentDirsResponse {
+    pub dirs: Vec<DeploymentDir>,
+}
+
+/// Deployment directories handler
+pub async fn deployment_dirs_handler(
+    State(state): State<Arc<ServerState>>,
+) -> Result<Json<DeploymentDirsResponse>, AgentError> {
+    state.activity_tracker.touch();
 
-    Ok(Json(WorkflowsResponse { workflows, total }))
+    let dirs = state.deployment_dirs.list().await?;
+    Ok(Json(DeploymentDirsResponse { dirs }))
+}
+
+/// Deployment directory removal handler; 409 while the deployment is active
+pub async fn remove_deployment_dir_handler(
+    State(state): State<Arc<ServerState>>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, AgentError> {
+    state.activity_tracker.touch();
+
+    state.deployment_dirs.remove(&name).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Terminal sessions response
+#[derive(Debug, Serialize)]
+pub struct TerminalSessionsResponse {
+    pub sessions: Vec<SessionInfo>,
+}
+
+/// Open terminal sessions handler
+pub async fn terminal_sessions_handler(
+    State(state): State<Arc<ServerState>>,
+) -> Json<TerminalSessionsResponse> {
+    state.activity_tracker.touch();
+
+    let sessions = state.terminal_sessions.list().await;
+    Json(TerminalSessionsResponse { sessions })
+}
+
+/// Terminal session force-close handler; the shell is closed in the
+/// background
+pub async fn close_terminal_session_handler(
+    State(state): State<Arc<ServerState>>,
+    Path(session_id): Path<String>,
+) -> Result<StatusCode, AgentError> {
+    state.activity_tracker.touch();
+
+    if !state.terminal_sessions.close(&session_id).await {
+        return Err(AgentError::NotFound(format!(
+            "Terminal session {} is not open",
+            session_id
+        )));
+    }
+    let details = serde_json::json!({ "session_id": session_id, "source": "api" });
+    if let Err(e) = state.audit.record("terminal_session_closed", details).await {
+        warn!("Failed to write the audit log: {}", e);
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Workflow deployability precheck handler; blockers are reported in the
+/// body, not as an error status
+pub async fn check_workflow_handler(
+    State(state): State<Arc<ServerState>>,
+    ApiJson(workflow): ApiJson<Workflow>,
+) -> impl IntoResponse {
+    state.activity_tracker.touch();
+    Json(state.workflow_checker.check(&workflow).await)
 }
 
 /// Metrics response
@@ -169,6 +472,18 @@ pub struct MetricsResponse {
     pub disk_percent: f32,
     pub uptime_secs: u64,
     pub hostname: String,
+    /// Operator-assigned device label
+    pub label: Option<String>,
+    /// Disk used by the agent's log files
+    pub log_disk_usage: u64,
+    /// Free space is below the threshold and deployments are paused
+    pub storage_full: bool,
+    /// Workflows currently running
+    pub running_executions: usize,
+    /// Workflows allowed to run at once
+    pub max_concurrent_executions: usize,
+    /// Clock synchronization as of the last check
+    pub clock: ClockStatus,
 }
 
 /// Metrics handler
@@ -177,7 +492,11 @@ pub async fn metrics_handler(
 ) -> impl IntoResponse {
     state.activity_tracker.touch();
 
-    let metrics = collect_metrics();
+    let metrics = state.metrics.latest();
+    let log_disk_usage = state.logs_dir.size().await.unwrap_or_else(|e| {
+        warn!("Failed to measure log directory: {}", e);
+        0
+    });
 
     Json(MetricsResponse {
         cpu_usage: metrics.cpu_usage,
@@ -189,5 +508,32 @@ pub async fn metrics_handler(
         disk_percent: metrics.disk_percent,
         uptime_secs: metrics.uptime_secs,
         hostname: metrics.hostname,
+        label: state.device_label.get(),
+        log_disk_usage,
+        storage_full: state.storage.is_full(),
+        running_executions: state.executors.running(),
+        max_concurrent_executions: state.executors.max_concurrent_executions(),
+        clock: state.clock.status(),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pagination_defaults_and_bounds() {
+        let items: Vec<usize> = (0..300).collect();
+
+        let page = Pagination::default().apply(items.clone());
+        assert_eq!(page.len(), DEFAULT_PAGE_SIZE);
+        assert_eq!(page[0], 0);
+
+        let page = Pagination { limit: Some(10), offset: Some(295) }.apply(items.clone());
+        assert_eq!(page, vec![295, 296, 297, 298, 299]);
+
+        assert_eq!(Pagination { limit: Some(0), offset: None }.limit(), 1);
+        assert_eq!(Pagination { limit: Some(10_000), offset: None }.limit(), MAX_PAGE_SIZE);
+        assert!(Pagination { limit: None, offset: Some(400) }.apply(items).is_empty());
+    }
+}
@@ -1,5 +1,6 @@
 //! Local HTTP server module
 
+pub mod errors;
 pub mod handlers;
 pub mod serve;
 pub mod state;
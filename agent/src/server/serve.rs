@@ -4,7 +4,11 @@ use std::future::Future;
 use std::sync::Arc;
 
 use axum::{
-    routing::{get, post},
+    extract::{Request, State},
+    http::{header, Method},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
     Router,
 };
 use tokio::net::TcpListener;
@@ -14,9 +18,13 @@ use tracing::info;
 
 use crate::app::options::ServerOptions;
 use crate::errors::AgentError;
+use crate::server::errors::{not_found_handler, ApiError};
 use crate::server::handlers::{
-    device_handler, health_handler, metrics_handler, sync_handler, version_handler,
-    workflows_handler,
+    check_workflow_handler, close_terminal_session_handler, deployment_dirs_handler,
+    device_handler, health_handler, metrics_handler, ready_handler, remove_deployment_dir_handler,
+    rotate_local_token_handler, sync_handler, sync_reset_handler, sync_status_handler,
+    terminal_sessions_handler, token_refresh_handler, token_status_handler,
+    update_device_handler, version_handler, workflows_handler,
 };
 use crate::server::state::ServerState;
 
@@ -26,18 +34,37 @@ pub async fn serve(
     state: Arc<ServerState>,
     shutdown_signal: impl Future<Output = ()> + Send + 'static,
 ) -> Result<JoinHandle<Result<(), AgentError>>, AgentError> {
-    let app = Router::new()
-        // Health and version
-        .route("/health", get(health_handler))
-        .route("/version", get(version_handler))
+    let protected = Router::new()
         // Device
-        .route("/device", get(device_handler))
-        .route("/device/sync", post(sync_handler))
+        .route("/device", get(device_handler).patch(update_device_handler))
+        .route("/device/sync", get(sync_status_handler).post(sync_handler))
+        .route("/device/sync/reset", post(sync_reset_handler))
+        // Token
+        .route("/token/status", get(token_status_handler))
+        .route("/token/refresh", post(token_refresh_handler))
+        .route("/token/local/rotate", post(rotate_local_token_handler))
         // Workflows
         .route("/workflows/deployed", get(workflows_handler))
+        .route("/workflows/check", post(check_workflow_handler))
+        // Deployment directories
+        .route("/deployments/dirs", get(deployment_dirs_handler))
+        .route("/deployments/dirs/{name}", delete(remove_deployment_dir_handler))
+        // Terminal
+        .route("/terminal/sessions", get(terminal_sessions_handler))
+        .route("/terminal/sessions/{id}", delete(close_terminal_session_handler))
         // Telemetry
         .route("/telemetry/metrics", get(metrics_handler))
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_local_token));
+
+    let app = Router::new()
+        // Health and version stay open for probes
+        .route("/health", get(health_handler))
+        .route("/ready", get(ready_handler))
+        .route("/version", get(version_handler))
+        .merge(protected)
+        .fallback(not_found_handler)
         // State and middleware
+        .layer(middleware::from_fn_with_state(state.clone(), reject_when_draining))
         .with_state(state)
         .layer(TraceLayer::new_for_http());
 
@@ -57,3 +84,33 @@ pub async fn serve(
 
     Ok(handle)
 }
+
+/// Reject mutating requests with 503 while the agent is draining
+async fn reject_when_draining(
+    State(state): State<Arc<ServerState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let read_only = matches!(*request.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+    if !read_only && state.drain.is_draining() {
+        return ApiError::unavailable("Agent is draining").into_response();
+    }
+    next.run(request).await
+}
+
+/// Reject requests without the local API token once one has been created
+async fn require_local_token(
+    State(state): State<Arc<ServerState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let presented = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if !state.local_token.verify(presented).await {
+        return ApiError::unauthorized("Invalid or missing local API token").into_response();
+    }
+    next.run(request).await
+}
@@ -1,39 +1,79 @@
 //! Server state
 
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 
-use crate::app::state::{ActivityTracker, Caches};
+use crate::app::drain::DrainState;
+use crate::app::state::{ActivityTracker, AppState, Caches};
+use crate::audit::AuditLog;
+use crate::authn::local_token::LocalApiToken;
 use crate::authn::token_mngr::TokenManager;
-use crate::filesys::file::File;
+use crate::clock::ClockMonitor;
+use crate::deploy::check::WorkflowChecker;
+use crate::deploy::dirs::DeploymentDirs;
+use crate::deploy::registry::ExecutorRegistry;
+use crate::filesys::dir::Dir;
+use crate::health::HealthRegistry;
 use crate::http::client::HttpClient;
+use crate::storage::device::DeviceCache;
+use crate::storage::label::DeviceLabel;
+use crate::storage::space::StorageMonitor;
 use crate::sync::syncer::Syncer;
+use crate::telemetry::MetricsCollector;
+use crate::terminal::sessions::SessionRegistry;
+use crate::workers::token_refresh::TokenRefreshState;
 
 /// Server state shared across handlers
 pub struct ServerState {
-    pub device_file: Arc<File>,
+    pub device: DeviceCache,
     pub http_client: Arc<HttpClient>,
     pub syncer: Arc<Syncer>,
     pub caches: Arc<Caches>,
     pub token_mngr: Arc<TokenManager>,
     pub activity_tracker: Arc<ActivityTracker>,
+    pub health: Arc<HealthRegistry>,
+    pub drain: Arc<DrainState>,
+    pub executors: Arc<ExecutorRegistry>,
+    pub device_label: Arc<DeviceLabel>,
+    pub token_refresh: Arc<RwLock<TokenRefreshState>>,
+    pub logs_dir: Dir,
+    pub storage: Arc<StorageMonitor>,
+    pub clock: Arc<ClockMonitor>,
+    pub deployment_dirs: Arc<DeploymentDirs>,
+    pub metrics: Arc<MetricsCollector>,
+    pub workflow_checker: Arc<WorkflowChecker>,
+    pub terminal_sessions: Arc<SessionRegistry>,
+    pub local_token: Arc<LocalApiToken>,
+    pub audit: Arc<AuditLog>,
 }
 
 impl ServerState {
+    /// Share `app_state` with the handlers
     pub fn new(
-        device_file: Arc<File>,
-        http_client: Arc<HttpClient>,
-        syncer: Arc<Syncer>,
-        caches: Arc<Caches>,
-        token_mngr: Arc<TokenManager>,
-        activity_tracker: Arc<ActivityTracker>,
+        app_state: &AppState,
+        local_token: Arc<LocalApiToken>,
+        audit: Arc<AuditLog>,
     ) -> Self {
         Self {
-            device_file,
-            http_client,
-            syncer,
-            caches,
-            token_mngr,
-            activity_tracker,
+            device: DeviceCache::new(app_state.device_file.clone()),
+            http_client: app_state.http_client.clone(),
+            syncer: app_state.syncer.clone(),
+            caches: app_state.caches.clone(),
+            token_mngr: app_state.token_mngr.clone(),
+            activity_tracker: app_state.activity_tracker.clone(),
+            health: app_state.health.clone(),
+            drain: app_state.drain.clone(),
+            executors: app_state.executors.clone(),
+            device_label: app_state.device_label.clone(),
+            token_refresh: app_state.token_refresh.clone(),
+            logs_dir: app_state.logs_dir.clone(),
+            storage: app_state.storage.clone(),
+            clock: app_state.clock.clone(),
+            deployment_dirs: app_state.deployment_dirs.clone(),
+            metrics: app_state.metrics.clone(),
+            workflow_checker: app_state.workflow_checker.clone(),
+            terminal_sessions: app_state.terminal_sessions.clone(),
+            local_token,
+            audit,
         }
     }
 }
@@ -1,9 +1,13 @@
 //! Device file management
 
+use std::sync::{Arc, RwLock};
+
 use serde::{Deserialize, Serialize};
+use tracing::warn;
 
 use crate::errors::AgentError;
 use crate::filesys::file::File;
+use crate::storage::space::ensure_free_space;
 
 /// Device information stored locally
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -92,6 +96,76 @@ pub async fn load_device(device_file: &File) -> Result<Device, AgentError> {
 
 /// Save device to file
 pub async fn save_device(device_file: &File, device: &Device) -> Result<(), AgentError> {
-    device_file.write_json(device).await?;
+    // Written atomically and only with room to spare, so a full disk cannot
+    // leave the credentials truncated
+    let contents = serde_json::to_vec_pretty(device)?;
+    ensure_free_space(device_file.path(), contents.len() as u64)?;
+    device_file.write_atomic(&contents).await?;
     device_file.set_permissions_600().await
 }
+
+/// The device file with the last successfully loaded device
+///
+/// A read can fail briefly while the file is rewritten, e.g. on a token
+/// refresh. Callers that only show device info get the last good copy in
+/// that case instead of an error.
+pub struct DeviceCache {
+    file: Arc<File>,
+    last: RwLock<Option<Device>>,
+}
+
+impl DeviceCache {
+    pub fn new(file: Arc<File>) -> Self {
+        Self {
+            file,
+            last: RwLock::new(None),
+        }
+    }
+
+    /// Load the device, falling back to the last good copy when the read
+    /// fails. Errors only when nothing has been loaded yet.
+    pub async fn load(&self) -> Result<Device, AgentError> {
+        match load_device(&self.file).await {
+            Ok(device) => {
+                *self.last.write().unwrap_or_else(|e| e.into_inner()) = Some(device.clone());
+                Ok(device)
+            }
+            Err(e) => {
+                let last = self.last.read().unwrap_or_else(|e| e.into_inner()).clone();
+                match last {
+                    Some(device) => {
+                        warn!("Failed to read device file, serving cached device: {}", e);
+                        Ok(device)
+                    }
+                    None => Err(e),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesys::test_utils::TempFs;
+
+    #[tokio::test]
+    async fn test_device_cache_serves_last_good_device() {
+        let fs = TempFs::new();
+        let file = Arc::new(File::new(fs.path("device.json")));
+        let cache = DeviceCache::new(file.clone());
+        assert!(cache.load().await.is_err());
+
+        let device = Device::new("d1".into(), "pi".into(), "u1".into(), "t1".into());
+        save_device(&file, &device).await.unwrap();
+        assert_eq!(cache.load().await.unwrap().name, "pi");
+
+        // A half-written file is bridged by the cached copy
+        std::fs::write(file.path(), b"{\"id\": ").unwrap();
+        assert_eq!(cache.load().await.unwrap().name, "pi");
+
+        let renamed = Device::new("d1".into(), "kitchen".into(), "u1".into(), "t2".into());
+        save_device(&file, &renamed).await.unwrap();
+        assert_eq!(cache.load().await.unwrap().name, "kitchen");
+    }
+}
@@ -0,0 +1,137 @@
+//! Operator-assigned device label
+//!
+//! The label is a human name shown in status and telemetry. Unlike the device
+//! name recorded at activation it can be changed at any time; the backend
+//! device ID never changes.
+
+use std::sync::RwLock;
+
+use tokio::sync::Mutex;
+use tracing::info;
+
+use crate::errors::AgentError;
+use crate::filesys::file::File;
+use crate::storage::space::ensure_free_space;
+
+/// Longest accepted label, in characters
+pub const MAX_LABEL_LEN: usize = 64;
+
+/// Settings key the label is stored under
+const SETTINGS_KEY: &str = "device_label";
+
+/// The current label, persisted to the settings file
+pub struct DeviceLabel {
+    settings_file: File,
+    label: RwLock<Option<String>>,
+    /// Serializes read-modify-write cycles of the settings file
+    write_lock: Mutex<()>,
+}
+
+impl DeviceLabel {
+    pub fn new(settings_file: File, label: Option<String>) -> Self {
+        Self {
+            settings_file,
+            label: RwLock::new(label),
+            write_lock: Mutex::new(()),
+        }
+    }
+
+    /// Load the label from the settings file; no label if it cannot be read
+    pub async fn load(settings_file: File) -> Self {
+        let label = settings_file
+            .read_json::<serde_json::Value>()
+            .await
+            .ok()
+            .and_then(|settings| settings.get(SETTINGS_KEY)?.as_str().map(str::to_string));
+        Self::new(settings_file, label)
+    }
+
+    pub fn get(&self) -> Option<String> {
+        self.label.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Change the label (`None` or blank clears it) and persist it. Other
+    /// settings are kept as they are.
+    pub async fn set(&self, label: Option<&str>) -> Result<Option<String>, AgentError> {
+        let label = normalize(label)?;
+
+        let _guard = self.write_lock.lock().await;
+        let mut settings = if self.settings_file.exists().await {
+            self.settings_file.read_json::<serde_json::Value>().await?
+        } else {
+            serde_json::json!({})
+        };
+        let Some(map) = settings.as_object_mut() else {
+            return Err(AgentError::ConfigError(format!(
+                "Settings file {:?} is not a JSON object",
+                self.settings_file.path()
+            )));
+        };
+        match &label {
+            Some(label) => map.insert(SETTINGS_KEY.to_string(), label.clone().into()),
+            None => map.remove(SETTINGS_KEY),
+        };
+        let contents = serde_json::to_string_pretty(&settings)?;
+        ensure_free_space(self.settings_file.path(), contents.len() as u64)?;
+        self.settings_file.write_atomic(contents.as_bytes()).await?;
+
+        *self.label.write().unwrap_or_else(|e| e.into_inner()) = label.clone();
+        info!("Device label set to {:?}", label);
+        Ok(label)
+    }
+}
+
+/// Trim a label and check it is printable and not too long
+fn normalize(label: Option<&str>) -> Result<Option<String>, AgentError> {
+    let Some(label) = label.map(str::trim).filter(|l| !l.is_empty()) else {
+        return Ok(None);
+    };
+    if label.chars().count() > MAX_LABEL_LEN {
+        return Err(AgentError::ValidationError(format!(
+            "Device label must be at most {} characters",
+            MAX_LABEL_LEN
+        )));
+    }
+    if label.chars().any(char::is_control) {
+        return Err(AgentError::ValidationError(
+            "Device label must not contain control characters".to_string(),
+        ));
+    }
+    Ok(Some(label.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesys::test_utils::TempFs;
+
+    #[tokio::test]
+    async fn test_set_persists_and_keeps_other_settings() {
+        let fs = TempFs::new();
+        fs.write("settings.json", r#"{"log_level": "debug", "device_label": "old"}"#);
+        let file = File::new(fs.path("settings.json"));
+
+        let label = DeviceLabel::load(file.clone()).await;
+        assert_eq!(label.get().as_deref(), Some("old"));
+
+        label.set(Some("  Greenhouse 2 ")).await.unwrap();
+        assert_eq!(label.get().as_deref(), Some("Greenhouse 2"));
+        let settings: serde_json::Value = file.read_json().await.unwrap();
+        assert_eq!(settings["device_label"], "Greenhouse 2");
+        assert_eq!(settings["log_level"], "debug");
+
+        label.set(Some("")).await.unwrap();
+        assert_eq!(DeviceLabel::load(file.clone()).await.get(), None);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_labels_are_rejected() {
+        let fs = TempFs::new();
+        let label = DeviceLabel::new(File::new(fs.path("settings.json")), Some("kept".to_string()));
+
+        assert!(label.set(Some(&"x".repeat(MAX_LABEL_LEN + 1))).await.is_err());
+        assert!(label.set(Some("line\nbreak")).await.is_err());
+        assert_eq!(label.get().as_deref(), Some("kept"));
+        assert!(!fs.path("settings.json").exists());
+    }
+}
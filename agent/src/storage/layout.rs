@@ -1,10 +1,24 @@
 //! Storage layout configuration
+//!
+//! The layout is versioned by a `layout_version` marker file. `setup` runs
+//! at install and on every startup, creating missing directories and
+//! migrating installs from older versions forward one step at a time, so
+//! every install converges on the current layout.
 
 use std::path::PathBuf;
 
+use tracing::{info, warn};
+
+use crate::errors::AgentError;
 use crate::filesys::dir::Dir;
 use crate::filesys::file::File;
 
+/// Current storage layout version
+pub const LAYOUT_VERSION: u32 = 1;
+
+/// Layout changes, by the version they introduce
+const MIGRATIONS: &[(u32, &str)] = &[(1, "record the layout version")];
+
 /// Storage layout for the agent
 #[derive(Debug, Clone)]
 pub struct StorageLayout {
@@ -50,26 +64,131 @@ impl StorageLayout {
         Dir::new(self.base_dir.join("deployments"))
     }
 
+    /// Get the persisted deployment state of a workflow
+    pub fn workflow_state_file(&self, workflow_id: &str) -> File {
+        File::new(self.base_dir.join("deployments").join(workflow_id).join("state.json"))
+    }
+
+    /// Get the directory a workflow's stateful nodes persist their state in
+    pub fn workflow_node_state_dir(&self, workflow_id: &str) -> Dir {
+        Dir::new(self.base_dir.join("deployments").join(workflow_id).join("nodes"))
+    }
+
+    /// Get the deployment ledger file (processed deployment IDs)
+    pub fn deployment_ledger_file(&self) -> File {
+        File::new(self.base_dir.join("deployment_ledger.json"))
+    }
+
     /// Get the logs directory
     pub fn logs_dir(&self) -> Dir {
         Dir::new(self.base_dir.join("logs"))
     }
 
+    /// Get the terminal sandbox directory
+    pub fn terminal_dir(&self) -> Dir {
+        Dir::new(self.base_dir.join("terminal"))
+    }
+
+    /// Get the root of relay file operations
+    pub fn files_dir(&self) -> Dir {
+        Dir::new(self.base_dir.join("files"))
+    }
+
     /// Get the tokens directory (for secure token storage)
     pub fn tokens_dir(&self) -> Dir {
         Dir::new(self.base_dir.join("tokens"))
     }
 
-    /// Setup the storage layout (create directories)
-    pub async fn setup(&self) -> Result<(), crate::errors::AgentError> {
-        self.cache_dir().create().await?;
-        self.workflows_cache_dir().create().await?;
-        self.configs_cache_dir().create().await?;
-        self.deployment_dir().create().await?;
-        self.logs_dir().create().await?;
-        self.tokens_dir().create().await?;
+    /// Get the activation token kept for re-activation, if the installer
+    /// was asked to keep it
+    pub fn activation_token_file(&self) -> File {
+        self.tokens_dir().file("activation_token")
+    }
+
+    /// Get the bearer token guarding the local API
+    pub fn local_api_token_file(&self) -> File {
+        self.tokens_dir().file("local_api_token")
+    }
+
+    /// Get the audit log file
+    pub fn audit_log_file(&self) -> File {
+        File::new(self.base_dir.join("audit.log"))
+    }
+
+    /// Get the layout version marker file
+    pub fn layout_version_file(&self) -> File {
+        File::new(self.base_dir.join("layout_version"))
+    }
+
+    /// Setup the storage layout: create missing directories and migrate an
+    /// older layout to the current version. Safe to run repeatedly.
+    pub async fn setup(&self) -> Result<(), AgentError> {
+        for dir in [
+            self.cache_dir(),
+            self.workflows_cache_dir(),
+            self.configs_cache_dir(),
+            self.deployment_dir(),
+            self.logs_dir(),
+            self.terminal_dir(),
+            self.files_dir(),
+            self.tokens_dir(),
+        ] {
+            if !dir.exists().await {
+                info!("Creating storage directory {:?}", dir.path());
+                dir.create().await?;
+            }
+        }
+
+        let version = self.layout_version().await?;
+        if version > LAYOUT_VERSION {
+            warn!(
+                "Storage layout version {} is newer than this agent's ({}); leaving it as is",
+                version, LAYOUT_VERSION
+            );
+            return Ok(());
+        }
+
+        for (target, description) in MIGRATIONS.iter().filter(|(v, _)| *v > version) {
+            info!("Migrating storage layout to version {}: {}", target, description);
+            self.migrate(*target).await?;
+            // Record each step, so an interrupted migration resumes after it
+            self.layout_version_file()
+                .write_atomic(target.to_string().as_bytes())
+                .await?;
+        }
+        if version < LAYOUT_VERSION {
+            info!("Storage layout migrated from version {} to {}", version, LAYOUT_VERSION);
+        }
         Ok(())
     }
+
+    /// Recorded layout version; 0 for installs that predate the marker
+    pub async fn layout_version(&self) -> Result<u32, AgentError> {
+        let file = self.layout_version_file();
+        if !file.exists().await {
+            return Ok(0);
+        }
+        let contents = file.read_string().await?;
+        contents.trim().parse().map_err(|_| {
+            AgentError::StorageError(format!(
+                "Invalid storage layout version {:?} in {:?}",
+                contents.trim(),
+                file.path()
+            ))
+        })
+    }
+
+    /// Bring the layout from the previous version to `version`
+    async fn migrate(&self, version: u32) -> Result<(), AgentError> {
+        match version {
+            // Version 1 only adds the marker and the directories created above
+            1 => Ok(()),
+            _ => Err(AgentError::StorageError(format!(
+                "No migration to storage layout version {}",
+                version
+            ))),
+        }
+    }
 }
 
 impl Default for StorageLayout {
@@ -98,3 +217,36 @@ mod dirs {
             .map(PathBuf::from)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesys::test_utils::TempFs;
+
+    #[tokio::test]
+    async fn test_setup_converges_old_installs_idempotently() {
+        let fs = TempFs::new();
+        // An install from before the marker, missing newer directories
+        fs.write("ajime/device.json", "{}");
+        fs.mkdir("ajime/cache/workflows");
+        let layout = StorageLayout::new(fs.path("ajime"));
+        assert_eq!(layout.layout_version().await.unwrap(), 0);
+
+        layout.setup().await.unwrap();
+        assert_eq!(layout.layout_version().await.unwrap(), LAYOUT_VERSION);
+        assert!(layout.terminal_dir().exists().await);
+        assert!(layout.files_dir().exists().await);
+        assert!(layout.tokens_dir().exists().await);
+        assert!(fs.path("ajime/device.json").exists());
+
+        layout.setup().await.unwrap();
+        assert_eq!(layout.layout_version().await.unwrap(), LAYOUT_VERSION);
+
+        // A newer layout is left alone; a corrupt marker is an error
+        fs.write("ajime/layout_version", "99\n");
+        layout.setup().await.unwrap();
+        assert_eq!(layout.layout_version().await.unwrap(), 99);
+        fs.write("ajime/layout_version", "two");
+        assert!(matches!(layout.setup().await, Err(AgentError::StorageError(_))));
+    }
+}
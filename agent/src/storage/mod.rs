@@ -1,5 +1,7 @@
 //! Local storage management
 
 pub mod device;
+pub mod label;
 pub mod layout;
 pub mod settings;
+pub mod space;
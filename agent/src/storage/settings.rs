@@ -1,8 +1,16 @@
 //! Settings file management
 
+use std::collections::BTreeMap;
+
 use serde::{Deserialize, Serialize};
 
+use crate::app::options::ShutdownStage;
+use crate::authn::command_signing::{DEFAULT_SIGNATURE_MAX_AGE, DEFAULT_SIGNED_COMMANDS};
+use crate::deploy::limits::ResourceLimits;
+use crate::deploy::registry::DEFAULT_MAX_CONCURRENT_EXECUTIONS;
 use crate::logs::LogLevel;
+use crate::terminal::DEFAULT_ENV_ALLOWLIST;
+use crate::workers::relay::{RelayEncoding, RelayTransport};
 
 /// Agent settings
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -11,6 +19,10 @@ pub struct Settings {
     #[serde(default)]
     pub log_level: LogLevel,
 
+    /// Operator-assigned label, reported alongside the device name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_label: Option<String>,
+
     /// Backend configuration
     #[serde(default)]
     pub backend: BackendSettings,
@@ -19,6 +31,42 @@ pub struct Settings {
     #[serde(default)]
     pub mqtt_broker: MqttBrokerSettings,
 
+    /// Relay configuration
+    #[serde(default)]
+    pub relay: RelaySettings,
+
+    /// Deployer configuration
+    #[serde(default)]
+    pub deployer: DeployerSettings,
+
+    /// Workflow sync configuration
+    #[serde(default)]
+    pub sync: SyncSettings,
+
+    /// Remote terminal configuration
+    #[serde(default)]
+    pub terminal: TerminalSettings,
+
+    /// Token refresh configuration
+    #[serde(default)]
+    pub token_refresh: TokenRefreshSettings,
+
+    /// Log file retention
+    #[serde(default)]
+    pub logs: LogSettings,
+
+    /// Low disk space handling
+    #[serde(default)]
+    pub storage: StorageSettings,
+
+    /// Detection of stalled workers
+    #[serde(default)]
+    pub watchdog: WatchdogSettings,
+
+    /// Clock synchronization checks
+    #[serde(default)]
+    pub clock: ClockSettings,
+
     /// Whether the agent runs persistently
     #[serde(default = "default_true")]
     pub is_persistent: bool,
@@ -35,13 +83,46 @@ pub struct Settings {
     #[serde(default = "default_true")]
     pub enable_poller: bool,
 
+    /// Enable deployer worker
+    #[serde(default = "default_true")]
+    pub enable_deployer: bool,
+
+    /// Enable WebSocket relay worker
+    #[serde(default = "default_true")]
+    pub enable_relay_worker: bool,
+
     /// Polling interval in seconds
     #[serde(default = "default_polling_interval")]
     pub polling_interval_secs: u64,
 
+    /// Enable heartbeat worker
+    #[serde(default = "default_true")]
+    pub enable_heartbeat: bool,
+
+    /// Heartbeat interval in seconds
+    #[serde(default = "default_heartbeat_interval")]
+    pub heartbeat_interval_secs: u64,
+
+    /// System metrics sampling interval in seconds; 0 collects them on demand
+    #[serde(default = "default_metrics_interval")]
+    pub metrics_interval_secs: u64,
+
+    /// Workflows allowed to run at once; further starts are refused
+    #[serde(default = "default_max_concurrent_executions")]
+    pub max_concurrent_executions: usize,
+
+    /// Independent nodes of one workflow run at once; 1 runs them in order
+    #[serde(default = "default_max_concurrent_nodes")]
+    pub max_concurrent_nodes: usize,
+
     /// Hardware configuration
     #[serde(default)]
     pub hardware: HardwareSettings,
+
+    /// Order in which components are stopped on shutdown. Unlisted components
+    /// follow in the default order.
+    #[serde(default = "default_shutdown_order")]
+    pub shutdown_order: Vec<ShutdownStage>,
 }
 
 fn default_true() -> bool {
@@ -52,18 +133,56 @@ fn default_polling_interval() -> u64 {
     30
 }
 
+fn default_heartbeat_interval() -> u64 {
+    300
+}
+
+fn default_metrics_interval() -> u64 {
+    30
+}
+
+fn default_max_concurrent_executions() -> usize {
+    DEFAULT_MAX_CONCURRENT_EXECUTIONS
+}
+
+fn default_max_concurrent_nodes() -> usize {
+    1
+}
+
+fn default_shutdown_order() -> Vec<ShutdownStage> {
+    ShutdownStage::DEFAULT_ORDER.to_vec()
+}
+
 impl Default for Settings {
     fn default() -> Self {
         Self {
             log_level: LogLevel::Info,
+            device_label: None,
             backend: BackendSettings::default(),
             mqtt_broker: MqttBrokerSettings::default(),
+            relay: RelaySettings::default(),
+            deployer: DeployerSettings::default(),
+            sync: SyncSettings::default(),
+            terminal: TerminalSettings::default(),
+            token_refresh: TokenRefreshSettings::default(),
+            logs: LogSettings::default(),
+            storage: StorageSettings::default(),
+            watchdog: WatchdogSettings::default(),
+            clock: ClockSettings::default(),
             is_persistent: true,
             enable_socket_server: true,
             enable_mqtt_worker: true,
             enable_poller: true,
+            enable_deployer: true,
+            enable_relay_worker: true,
             polling_interval_secs: 30,
+            enable_heartbeat: true,
+            heartbeat_interval_secs: default_heartbeat_interval(),
+            metrics_interval_secs: default_metrics_interval(),
+            max_concurrent_executions: default_max_concurrent_executions(),
+            max_concurrent_nodes: default_max_concurrent_nodes(),
             hardware: HardwareSettings::default(),
+            shutdown_order: default_shutdown_order(),
         }
     }
 }
@@ -74,16 +193,79 @@ pub struct BackendSettings {
     /// Base URL for the backend API
     #[serde(default = "default_backend_url")]
     pub base_url: String,
+
+    /// Longest a request may take in seconds, including its body
+    #[serde(default = "default_backend_request_timeout")]
+    pub request_timeout_secs: u64,
+
+    /// Longest connecting may take in seconds (0 = only bounded by the
+    /// request timeout)
+    #[serde(default)]
+    pub connect_timeout_secs: u64,
+
+    /// How long idle connections are kept for reuse in seconds
+    #[serde(default = "default_backend_pool_idle_timeout")]
+    pub pool_idle_timeout_secs: u64,
+
+    /// Idle connections kept to the backend; unlimited when unset
+    #[serde(default)]
+    pub pool_max_idle_per_host: Option<usize>,
+
+    /// Retries of a request that failed to connect or got a 5xx or 429;
+    /// 0 disables retrying
+    #[serde(default = "default_backend_max_retries")]
+    pub max_retries: u32,
+
+    /// Delay before the first retry in milliseconds, doubling per retry
+    #[serde(default = "default_backend_retry_base_delay")]
+    pub retry_base_delay_ms: u64,
+
+    /// Longest delay between retries in seconds, also capping Retry-After
+    #[serde(default = "default_backend_retry_max_delay")]
+    pub retry_max_delay_secs: u64,
+
+    /// Also retry POST, PUT and PATCH requests, which may then take effect
+    /// twice
+    #[serde(default)]
+    pub retry_non_idempotent: bool,
 }
 
 fn default_backend_url() -> String {
     "http://localhost:8000/api/v1".to_string()
 }
 
+fn default_backend_request_timeout() -> u64 {
+    30
+}
+
+fn default_backend_pool_idle_timeout() -> u64 {
+    90
+}
+
+fn default_backend_max_retries() -> u32 {
+    3
+}
+
+fn default_backend_retry_base_delay() -> u64 {
+    500
+}
+
+fn default_backend_retry_max_delay() -> u64 {
+    10
+}
+
 impl Default for BackendSettings {
     fn default() -> Self {
         Self {
             base_url: default_backend_url(),
+            request_timeout_secs: default_backend_request_timeout(),
+            connect_timeout_secs: 0,
+            pool_idle_timeout_secs: default_backend_pool_idle_timeout(),
+            pool_max_idle_per_host: None,
+            max_retries: default_backend_max_retries(),
+            retry_base_delay_ms: default_backend_retry_base_delay(),
+            retry_max_delay_secs: default_backend_retry_max_delay(),
+            retry_non_idempotent: false,
         }
     }
 }
@@ -107,6 +289,24 @@ pub struct MqttBrokerSettings {
     /// When absent, the system certificate store is used.
     #[serde(default)]
     pub ca_cert_path: Option<String>,
+
+    /// How often to check the CA certificate for changes and reconnect with
+    /// it; 0 disables the check
+    #[serde(default = "default_ca_reload_interval_secs")]
+    pub ca_reload_interval_secs: u64,
+
+    /// Explicit MQTT client ID, replacing the derived `ajigent-{device_id}`
+    #[serde(default)]
+    pub client_id: Option<String>,
+
+    /// Fixed suffix appended to the derived client ID
+    #[serde(default)]
+    pub client_id_suffix: Option<String>,
+
+    /// Append a short random suffix to the derived client ID when no fixed
+    /// suffix is set, so cloned devices do not steal each other's session
+    #[serde(default = "default_true")]
+    pub random_client_id_suffix: bool,
 }
 
 fn default_mqtt_host() -> String {
@@ -117,6 +317,10 @@ fn default_mqtt_port() -> u16 {
     8883
 }
 
+fn default_ca_reload_interval_secs() -> u64 {
+    60
+}
+
 impl Default for MqttBrokerSettings {
     fn default() -> Self {
         Self {
@@ -124,6 +328,552 @@ impl Default for MqttBrokerSettings {
             port: default_mqtt_port(),
             tls: true,
             ca_cert_path: None,
+            ca_reload_interval_secs: default_ca_reload_interval_secs(),
+            client_id: None,
+            client_id_suffix: None,
+            random_client_id_suffix: true,
+        }
+    }
+}
+
+/// Relay settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RelaySettings {
+    /// Transport override: "auto" (WebSocket with long-poll fallback),
+    /// "websocket" or "poll"
+    #[serde(default)]
+    pub transport: RelayTransport,
+
+    /// Bulk data encoding: "base64", or "binary" to send terminal output
+    /// and file chunks as binary frames when the backend supports them
+    #[serde(default)]
+    pub encoding: RelayEncoding,
+
+    /// How long `auto` keeps long-polling after WebSocket upgrades were
+    /// rejected before trying WebSocket again, in seconds
+    #[serde(default = "default_relay_ws_retry_interval")]
+    pub ws_retry_interval_secs: u64,
+
+    /// Base reconnect delay in seconds
+    #[serde(default = "default_relay_reconnect_delay")]
+    pub reconnect_delay_secs: u64,
+
+    /// Heartbeat interval in seconds
+    #[serde(default = "default_relay_heartbeat_interval")]
+    pub heartbeat_interval_secs: u64,
+
+    /// High-privilege commands must be signed with one of the keys the
+    /// backend publishes in its JWKS; unset disables signature checks
+    #[serde(default)]
+    pub require_signed_commands: bool,
+
+    /// Commands that must be signed when signatures are required
+    #[serde(default = "default_signed_commands")]
+    pub signed_commands: Vec<String>,
+
+    /// Longest a command signature may be valid for, in seconds; signatures
+    /// expiring later are rejected
+    #[serde(default = "default_signature_max_age")]
+    pub signature_max_age_secs: u64,
+
+    /// Directory file commands are confined to; the files directory under
+    /// the storage directory when unset
+    #[serde(default)]
+    pub files_root: Option<String>,
+
+    /// Largest file file commands read or write, in MiB
+    #[serde(default = "default_max_file_size")]
+    pub max_file_size_mb: u64,
+
+    /// Pace of streamed file reads in bytes per second (0 = unlimited)
+    #[serde(default = "default_file_transfer_rate")]
+    pub file_transfer_bytes_per_sec: u64,
+
+    /// Largest subnet a network scan covers, in host bits (16 allows an
+    /// IPv4 /16 or an IPv6 /112)
+    #[serde(default = "default_scan_max_host_bits")]
+    pub scan_max_host_bits: u8,
+
+    /// Longest per-probe timeout a network scan may ask for, in
+    /// milliseconds; longer ones are clamped
+    #[serde(default = "default_scan_max_timeout_ms")]
+    pub scan_max_timeout_ms: u64,
+}
+
+fn default_max_file_size() -> u64 {
+    512
+}
+
+fn default_file_transfer_rate() -> u64 {
+    1024 * 1024
+}
+
+fn default_scan_max_host_bits() -> u8 {
+    16
+}
+
+fn default_scan_max_timeout_ms() -> u64 {
+    5000
+}
+
+fn default_signature_max_age() -> u64 {
+    DEFAULT_SIGNATURE_MAX_AGE.as_secs()
+}
+
+fn default_signed_commands() -> Vec<String> {
+    DEFAULT_SIGNED_COMMANDS.iter().map(|c| c.to_string()).collect()
+}
+
+fn default_relay_ws_retry_interval() -> u64 {
+    600
+}
+
+fn default_relay_reconnect_delay() -> u64 {
+    5
+}
+
+fn default_relay_heartbeat_interval() -> u64 {
+    30
+}
+
+impl Default for RelaySettings {
+    fn default() -> Self {
+        Self {
+            transport: RelayTransport::default(),
+            encoding: RelayEncoding::default(),
+            ws_retry_interval_secs: default_relay_ws_retry_interval(),
+            reconnect_delay_secs: default_relay_reconnect_delay(),
+            heartbeat_interval_secs: default_relay_heartbeat_interval(),
+            require_signed_commands: false,
+            signed_commands: default_signed_commands(),
+            signature_max_age_secs: default_signature_max_age(),
+            files_root: None,
+            max_file_size_mb: default_max_file_size(),
+            file_transfer_bytes_per_sec: default_file_transfer_rate(),
+            scan_max_host_bits: default_scan_max_host_bits(),
+            scan_max_timeout_ms: default_scan_max_timeout_ms(),
+        }
+    }
+}
+
+/// Deployer settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeployerSettings {
+    /// Interval between deployment checks in seconds
+    #[serde(default = "default_deployer_interval")]
+    pub interval_secs: u64,
+
+    /// Attempts per deployment, including the first
+    #[serde(default = "default_deployer_max_attempts")]
+    pub max_attempts: u32,
+
+    /// Delay between attempts in seconds
+    #[serde(default = "default_deployer_retry_delay")]
+    pub retry_delay_secs: u64,
+
+    /// Upper bound in seconds of the random delay added to each retry, so
+    /// devices that failed together do not retry in lockstep
+    #[serde(default = "default_deployer_retry_jitter")]
+    pub retry_jitter_secs: u64,
+
+    /// Seconds a new container must stay up (or until its healthcheck
+    /// passes) before a Docker deployment succeeds; 0 disables the check
+    #[serde(default = "default_deployer_container_verify_timeout")]
+    pub container_verify_timeout_secs: u64,
+
+    /// CPU and memory limits of deployments that do not set their own
+    #[serde(default)]
+    pub resource_limits: ResourceLimits,
+
+    /// Seconds one deployment attempt may run before it fails as timed out
+    #[serde(default = "default_deployer_attempt_timeout")]
+    pub attempt_timeout_secs: u64,
+}
+
+fn default_deployer_attempt_timeout() -> u64 {
+    30 * 60
+}
+
+fn default_deployer_max_attempts() -> u32 {
+    3
+}
+
+fn default_deployer_retry_delay() -> u64 {
+    5
+}
+
+fn default_deployer_retry_jitter() -> u64 {
+    5
+}
+
+fn default_deployer_container_verify_timeout() -> u64 {
+    10
+}
+
+fn default_deployer_interval() -> u64 {
+    10
+}
+
+impl Default for DeployerSettings {
+    fn default() -> Self {
+        Self {
+            interval_secs: default_deployer_interval(),
+            max_attempts: default_deployer_max_attempts(),
+            retry_delay_secs: default_deployer_retry_delay(),
+            retry_jitter_secs: default_deployer_retry_jitter(),
+            container_verify_timeout_secs: default_deployer_container_verify_timeout(),
+            resource_limits: ResourceLimits::default(),
+            attempt_timeout_secs: default_deployer_attempt_timeout(),
+        }
+    }
+}
+
+/// Workflow sync settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncSettings {
+    /// Cooldown after the first failed sync in seconds
+    #[serde(default = "default_sync_cooldown_base")]
+    pub cooldown_base_secs: u64,
+
+    /// Longest cooldown in seconds
+    #[serde(default = "default_sync_cooldown_max")]
+    pub cooldown_max_secs: u64,
+
+    /// Growth factor of the cooldown per consecutive failure
+    #[serde(default = "default_sync_cooldown_multiplier")]
+    pub cooldown_multiplier: f64,
+
+    /// Most nodes a workflow may have; 0 for no limit
+    #[serde(default = "default_max_workflow_nodes")]
+    pub max_workflow_nodes: usize,
+
+    /// Most edges a workflow may have; 0 for no limit
+    #[serde(default = "default_max_workflow_edges")]
+    pub max_workflow_edges: usize,
+
+    /// Largest workflow JSON in KiB; 0 for no limit
+    #[serde(default = "default_max_workflow_size_kb")]
+    pub max_workflow_size_kb: usize,
+}
+
+fn default_sync_cooldown_base() -> u64 {
+    1
+}
+
+fn default_sync_cooldown_max() -> u64 {
+    300
+}
+
+fn default_sync_cooldown_multiplier() -> f64 {
+    2.0
+}
+
+fn default_max_workflow_nodes() -> usize {
+    1000
+}
+
+fn default_max_workflow_edges() -> usize {
+    5000
+}
+
+fn default_max_workflow_size_kb() -> usize {
+    4096
+}
+
+impl Default for SyncSettings {
+    fn default() -> Self {
+        Self {
+            cooldown_base_secs: default_sync_cooldown_base(),
+            cooldown_max_secs: default_sync_cooldown_max(),
+            cooldown_multiplier: default_sync_cooldown_multiplier(),
+            max_workflow_nodes: default_max_workflow_nodes(),
+            max_workflow_edges: default_max_workflow_edges(),
+            max_workflow_size_kb: default_max_workflow_size_kb(),
+        }
+    }
+}
+
+/// Remote terminal settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TerminalSettings {
+    /// Default starting directory; the terminal sandbox under the storage
+    /// directory when unset
+    #[serde(default)]
+    pub working_dir: Option<String>,
+
+    /// Give each session a private HOME/HISTFILE that is removed afterwards
+    #[serde(default)]
+    pub isolate_home: bool,
+
+    /// Agent environment variables passed to shells and commands; all
+    /// others are scrubbed
+    #[serde(default = "default_env_allowlist")]
+    pub env_allowlist: Vec<String>,
+
+    /// Extra environment variables for shells and commands
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+
+    /// Per-session output limit in bytes per second (0 = unlimited)
+    #[serde(default = "default_terminal_output_rate")]
+    pub max_output_bytes_per_sec: u64,
+
+    /// Seconds a closed session's shell may take to exit before it is killed
+    #[serde(default = "default_terminal_close_grace")]
+    pub close_grace_secs: u64,
+
+    /// Sessions open at once per relay connection
+    #[serde(default = "default_terminal_max_sessions")]
+    pub max_sessions: usize,
+
+    /// Minutes without input or output before a session is closed (0 = never)
+    #[serde(default = "default_terminal_idle_timeout")]
+    pub idle_timeout_mins: u64,
+
+    /// Seconds sessions of a dropped relay connection are kept for the next
+    /// connection to re-attach (0 = close them with the connection)
+    #[serde(default)]
+    pub reconnect_grace_secs: u64,
+
+    /// Output kept per session while it waits for a reconnect, in KiB
+    #[serde(default = "default_terminal_reconnect_buffer")]
+    pub reconnect_buffer_kb: usize,
+
+    /// Only let sessions run `allowed_shells` (the first by default), and
+    /// not set environment variables
+    #[serde(default)]
+    pub restrict_shell: bool,
+
+    /// Shells sessions may request when `restrict_shell` is set
+    #[serde(default)]
+    pub allowed_shells: Vec<String>,
+
+    /// Programs `command_exec` may run; empty disables it
+    #[serde(default)]
+    pub exec_allowed_commands: Vec<String>,
+
+    /// Default `command_exec` timeout in seconds
+    #[serde(default = "default_exec_timeout")]
+    pub exec_timeout_secs: u64,
+}
+
+fn default_env_allowlist() -> Vec<String> {
+    DEFAULT_ENV_ALLOWLIST.iter().map(|s| s.to_string()).collect()
+}
+
+fn default_terminal_output_rate() -> u64 {
+    128 * 1024
+}
+
+fn default_terminal_close_grace() -> u64 {
+    2
+}
+
+fn default_terminal_max_sessions() -> usize {
+    8
+}
+
+fn default_terminal_idle_timeout() -> u64 {
+    30
+}
+
+fn default_terminal_reconnect_buffer() -> usize {
+    64
+}
+
+fn default_exec_timeout() -> u64 {
+    30
+}
+
+impl Default for TerminalSettings {
+    fn default() -> Self {
+        Self {
+            working_dir: None,
+            isolate_home: false,
+            env_allowlist: default_env_allowlist(),
+            env: BTreeMap::new(),
+            max_output_bytes_per_sec: default_terminal_output_rate(),
+            close_grace_secs: default_terminal_close_grace(),
+            max_sessions: default_terminal_max_sessions(),
+            idle_timeout_mins: default_terminal_idle_timeout(),
+            reconnect_grace_secs: 0,
+            reconnect_buffer_kb: default_terminal_reconnect_buffer(),
+            restrict_shell: false,
+            allowed_shells: Vec::new(),
+            exec_allowed_commands: Vec::new(),
+            exec_timeout_secs: default_exec_timeout(),
+        }
+    }
+}
+
+/// Token refresh settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenRefreshSettings {
+    /// Consecutive rejected refreshes before re-activating with the token
+    /// kept by `--keep-activation-token`, or deactivating the device without
+    /// one (0 = keep retrying)
+    #[serde(default = "default_reactivate_after_failures")]
+    pub reactivate_after_failures: u32,
+}
+
+fn default_reactivate_after_failures() -> u32 {
+    3
+}
+
+impl Default for TokenRefreshSettings {
+    fn default() -> Self {
+        Self {
+            reactivate_after_failures: default_reactivate_after_failures(),
+        }
+    }
+}
+
+/// Log retention settings; 0 disables a limit
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogSettings {
+    /// Total size of the log directory in MiB
+    #[serde(default = "default_log_max_total_mb")]
+    pub max_total_mb: u64,
+
+    /// Number of log files kept
+    #[serde(default = "default_log_max_files")]
+    pub max_files: usize,
+
+    /// Age in days after which log files are deleted
+    #[serde(default = "default_log_max_age_days")]
+    pub max_age_days: u64,
+
+    /// How often the limits are enforced, in seconds
+    #[serde(default = "default_log_retention_interval")]
+    pub retention_check_interval_secs: u64,
+}
+
+fn default_log_max_total_mb() -> u64 {
+    50
+}
+
+fn default_log_max_files() -> usize {
+    10
+}
+
+fn default_log_max_age_days() -> u64 {
+    14
+}
+
+fn default_log_retention_interval() -> u64 {
+    3600
+}
+
+impl Default for LogSettings {
+    fn default() -> Self {
+        Self {
+            max_total_mb: default_log_max_total_mb(),
+            max_files: default_log_max_files(),
+            max_age_days: default_log_max_age_days(),
+            retention_check_interval_secs: default_log_retention_interval(),
+        }
+    }
+}
+
+/// Storage settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StorageSettings {
+    /// Free space in MiB below which deployments pause and health reports
+    /// storage as full
+    #[serde(default = "default_min_free_mb")]
+    pub min_free_mb: u64,
+
+    /// How often free space is checked, in seconds
+    #[serde(default = "default_space_check_interval")]
+    pub space_check_interval_secs: u64,
+
+    /// Gzip the workflow cache files; uncompressed ones are still read
+    #[serde(default = "default_compress_workflow_cache")]
+    pub compress_workflow_cache: bool,
+}
+
+fn default_min_free_mb() -> u64 {
+    50
+}
+
+fn default_space_check_interval() -> u64 {
+    60
+}
+
+fn default_compress_workflow_cache() -> bool {
+    true
+}
+
+impl Default for StorageSettings {
+    fn default() -> Self {
+        Self {
+            min_free_mb: default_min_free_mb(),
+            space_check_interval_secs: default_space_check_interval(),
+            compress_workflow_cache: default_compress_workflow_cache(),
+        }
+    }
+}
+
+/// Watchdog settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatchdogSettings {
+    /// Seconds a worker may overrun its expected wait before it counts as
+    /// stalled
+    #[serde(default = "default_stall_timeout")]
+    pub stall_timeout_secs: u64,
+
+    /// How often worker heartbeats are checked, in seconds
+    #[serde(default = "default_watchdog_check_interval")]
+    pub check_interval_secs: u64,
+
+    /// Exit on a stall so the service manager restarts the agent
+    #[serde(default)]
+    pub restart_on_stall: bool,
+}
+
+fn default_stall_timeout() -> u64 {
+    300
+}
+
+fn default_watchdog_check_interval() -> u64 {
+    30
+}
+
+impl Default for WatchdogSettings {
+    fn default() -> Self {
+        Self {
+            stall_timeout_secs: default_stall_timeout(),
+            check_interval_secs: default_watchdog_check_interval(),
+            restart_on_stall: false,
+        }
+    }
+}
+
+/// Clock check settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClockSettings {
+    /// Seconds the clock may differ from the backend's before it is reported
+    #[serde(default = "default_max_clock_skew")]
+    pub max_skew_secs: u64,
+
+    /// How often the clock is checked, in seconds
+    #[serde(default = "default_clock_check_interval")]
+    pub check_interval_secs: u64,
+}
+
+fn default_max_clock_skew() -> u64 {
+    60
+}
+
+fn default_clock_check_interval() -> u64 {
+    900
+}
+
+impl Default for ClockSettings {
+    fn default() -> Self {
+        Self {
+            max_skew_secs: default_max_clock_skew(),
+            check_interval_secs: default_clock_check_interval(),
         }
     }
 }
@@ -0,0 +1,223 @@
+//! Free disk space monitoring
+//!
+//! On a full disk every write fails, so credentials and settings are only
+//! written when there is room for them, and the agent stops taking on
+//! deployments while free space is below a threshold.
+
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+
+use sysinfo::Disks;
+use tracing::{error, info};
+
+use crate::errors::AgentError;
+use crate::health::HealthRegistry;
+
+/// Name of the storage monitor in the health registry
+const HEALTH_COMPONENT: &str = "storage";
+
+/// Space left over after a critical write, so the write itself cannot be
+/// the one that fills the disk
+const WRITE_RESERVE_BYTES: u64 = 1024 * 1024;
+
+/// Free space unknown
+const UNKNOWN: u64 = u64::MAX;
+
+/// Low disk space options
+#[derive(Debug, Clone)]
+pub struct SpaceOptions {
+    /// Free space below which the agent enters storage-full mode
+    pub min_free_bytes: u64,
+
+    /// How often free space is checked
+    pub check_interval: Duration,
+}
+
+impl Default for SpaceOptions {
+    fn default() -> Self {
+        Self {
+            min_free_bytes: 50 * 1024 * 1024,
+            check_interval: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Free bytes on the filesystem holding `path`, if it can be determined
+pub fn free_space(path: &Path) -> Option<u64> {
+    // The file may not exist yet; its closest existing ancestor is on the
+    // same filesystem
+    let path = path
+        .ancestors()
+        .find_map(|ancestor| std::fs::canonicalize(ancestor).ok())?;
+    let disks = Disks::new_with_refreshed_list();
+    disks
+        .iter()
+        .filter(|disk| path.starts_with(disk.mount_point()))
+        .max_by_key(|disk| disk.mount_point().as_os_str().len())
+        .map(|disk| disk.available_space())
+}
+
+/// Fail unless `len` bytes can be written to `path` with room to spare.
+/// Unknown free space is not an error.
+pub fn ensure_free_space(path: &Path, len: u64) -> Result<(), AgentError> {
+    require(free_space(path), len, path)
+}
+
+fn require(free: Option<u64>, len: u64, path: &Path) -> Result<(), AgentError> {
+    match free {
+        Some(free) if free < len.saturating_add(WRITE_RESERVE_BYTES) => {
+            Err(AgentError::StorageError(format!(
+                "Not enough free space to write {:?} ({} bytes free)",
+                path, free
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Tracks whether the agent's storage is running out of space
+pub struct StorageMonitor {
+    path: PathBuf,
+    options: SpaceOptions,
+    free_bytes: AtomicU64,
+    full: AtomicBool,
+}
+
+impl StorageMonitor {
+    pub fn new(path: impl Into<PathBuf>, options: SpaceOptions) -> Self {
+        Self {
+            path: path.into(),
+            options,
+            free_bytes: AtomicU64::new(UNKNOWN),
+            full: AtomicBool::new(false),
+        }
+    }
+
+    /// Free space is below the threshold
+    pub fn is_full(&self) -> bool {
+        self.full.load(Ordering::Relaxed)
+    }
+
+    /// Free bytes as of the last check
+    pub fn free_bytes(&self) -> Option<u64> {
+        Some(self.free_bytes.load(Ordering::Relaxed)).filter(|free| *free != UNKNOWN)
+    }
+
+    /// Measure free space and update the state and health
+    pub fn check(&self, health: &HealthRegistry) {
+        self.record(free_space(&self.path), health);
+    }
+
+    fn record(&self, free: Option<u64>, health: &HealthRegistry) {
+        self.free_bytes.store(free.unwrap_or(UNKNOWN), Ordering::Relaxed);
+        let full = free.is_some_and(|free| free < self.options.min_free_bytes);
+        let was_full = self.full.swap(full, Ordering::Relaxed);
+
+        if full {
+            let message = format!(
+                "Only {} MiB free on {:?} (minimum {} MiB); not accepting deployments",
+                free.unwrap_or_default() / (1024 * 1024),
+                self.path,
+                self.options.min_free_bytes / (1024 * 1024)
+            );
+            if !was_full {
+                error!("STORAGE FULL: {}", message);
+            }
+            health.set_unhealthy(HEALTH_COMPONENT, message);
+        } else {
+            if was_full {
+                info!("Free space recovered on {:?}, accepting deployments again", self.path);
+            }
+            health.set_healthy(HEALTH_COMPONENT);
+        }
+    }
+}
+
+/// Check free space every `check_interval` until shut down
+pub async fn run_monitor<S, F>(
+    monitor: &StorageMonitor,
+    health: &HealthRegistry,
+    sleep_fn: S,
+    mut shutdown_signal: Pin<Box<dyn Future<Output = ()> + Send>>,
+) where
+    S: Fn(Duration) -> F,
+    F: Future<Output = ()>,
+{
+    loop {
+        monitor.check(health);
+        tokio::select! {
+            _ = &mut shutdown_signal => {
+                info!("Storage monitor shutting down...");
+                return;
+            }
+            _ = sleep_fn(monitor.options.check_interval) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health::HealthStatus;
+
+    #[test]
+    fn test_critical_writes_need_room_to_spare() {
+        let path = Path::new("/etc/ajime/device.json");
+        assert!(require(Some(10 * WRITE_RESERVE_BYTES), 1024, path).is_ok());
+        assert!(matches!(
+            require(Some(WRITE_RESERVE_BYTES), 1024, path),
+            Err(AgentError::StorageError(_))
+        ));
+        assert!(require(None, 1024, path).is_ok());
+    }
+
+    #[test]
+    fn test_monitor_enters_and_leaves_storage_full_mode() {
+        let health = HealthRegistry::new();
+        let monitor = StorageMonitor::new(
+            "/etc/ajime",
+            SpaceOptions {
+                min_free_bytes: 100,
+                ..Default::default()
+            },
+        );
+
+        monitor.record(Some(50), &health);
+        assert!(monitor.is_full());
+        assert_eq!(monitor.free_bytes(), Some(50));
+        assert_eq!(health.get(HEALTH_COMPONENT).unwrap().status, HealthStatus::Unhealthy);
+
+        monitor.record(Some(500), &health);
+        assert!(!monitor.is_full());
+        assert_eq!(health.get(HEALTH_COMPONENT).unwrap().status, HealthStatus::Healthy);
+
+        // An unknown reading does not block deployments
+        monitor.record(None, &health);
+        assert!(!monitor.is_full());
+        assert_eq!(monitor.free_bytes(), None);
+    }
+
+    #[tokio::test]
+    async fn test_monitor_checks_then_waits_until_shut_down() {
+        let health = HealthRegistry::new();
+        let monitor = StorageMonitor::new("/", SpaceOptions::default());
+        let waits = std::sync::Mutex::new(Vec::new());
+
+        run_monitor(
+            &monitor,
+            &health,
+            |wait| {
+                waits.lock().unwrap().push(wait);
+                std::future::pending()
+            },
+            Box::pin(async {}),
+        )
+        .await;
+
+        assert!(health.get(HEALTH_COMPONENT).is_some());
+        assert_eq!(*waits.lock().unwrap(), [SpaceOptions::default().check_interval]);
+    }
+}
@@ -1,19 +1,29 @@
 //! Workflow synchronization
 
+use std::collections::HashSet;
+use std::future::Future;
+use std::pin::Pin;
 use std::sync::Arc;
+use std::time::Duration;
 
 use chrono::{DateTime, Utc};
-use tokio::sync::RwLock;
-use tracing::{debug, error, info};
+use tokio::sync::{broadcast, RwLock};
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+use tracing::{debug, error, info, warn};
 
 use crate::authn::token_mngr::{TokenManager, TokenManagerExt};
+use crate::cache::limits::WorkflowLimits;
+use crate::cache::store::WorkflowStore;
 use crate::cache::workflow::WorkflowCache;
-use crate::deploy::fsm::FsmSettings;
+use crate::deploy::executor::WorkflowExecutor;
+use crate::deploy::fsm::{DeploymentState, FsmSettings};
+use crate::deploy::registry::ExecutorRegistry;
 use crate::errors::AgentError;
 use crate::filesys::dir::Dir;
-use crate::filesys::file::File;
 use crate::http::client::HttpClient;
-use crate::http::workflows::WorkflowDigest;
+use crate::http::workflows::{SyncErrorReport, WorkflowDigest};
+use crate::models::workflow::{Workflow, WorkflowStatus};
 use crate::utils::{calc_exp_backoff, sha256_hash, CooldownOptions};
 
 /// Sync state
@@ -40,53 +50,126 @@ impl SyncState {
     pub fn is_in_cooldown(&self) -> bool {
         Utc::now() < self.cooldown_ends_at
     }
+
+    /// Time left until the next sync is allowed
+    pub fn cooldown_remaining(&self) -> std::time::Duration {
+        (self.cooldown_ends_at - Utc::now()).to_std().unwrap_or_default()
+    }
+}
+
+/// Outcome of a sync whose backend call succeeded
+#[derive(Debug, Clone, Default)]
+pub struct SyncReport {
+    /// Workflows added or updated in the cache
+    pub applied: Vec<String>,
+
+    /// Workflows removed because they are no longer assigned
+    pub removed: Vec<String>,
+
+    /// Workflows that could not be applied; their previous version is kept
+    pub failed: Vec<WorkflowSyncError>,
+}
+
+impl SyncReport {
+    /// Some workflows failed to apply
+    pub fn is_partial(&self) -> bool {
+        !self.failed.is_empty()
+    }
+}
+
+/// Why a single workflow could not be applied
+#[derive(Debug, Clone, Serialize)]
+pub struct WorkflowSyncError {
+    pub workflow_id: String,
+    pub error: String,
+
+    /// Field of a workflow that could not be parsed, e.g.
+    /// `graph_data.nodes[2].data`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+}
+
+/// Waits out a delay; `tokio::time::sleep` unless replaced
+pub type SleepFn = Arc<dyn Fn(Duration) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync>;
+
+/// Where the syncer keeps synced workflows and deploys them
+pub struct SyncStorage {
+    pub workflow_cache: Arc<WorkflowCache>,
+    pub workflow_store: WorkflowStore,
+    pub workflow_limits: WorkflowLimits,
+    pub deployment_dir: Dir,
 }
 
 /// Workflow syncer
 pub struct Syncer {
-    device_file: Arc<File>,
     http_client: Arc<HttpClient>,
     token_mngr: Arc<TokenManager>,
     workflow_cache: Arc<WorkflowCache>,
+    workflow_store: WorkflowStore,
+    workflow_limits: WorkflowLimits,
     deployment_dir: Dir,
+    executors: Arc<ExecutorRegistry>,
     fsm_settings: FsmSettings,
     agent_version: String,
     state: RwLock<SyncState>,
     cooldown_options: CooldownOptions,
+    /// Waits between deployment retries
+    sleep_fn: SleepFn,
+    /// Stops the deployment retries of the background runners
+    shutdown_rx: Option<broadcast::Receiver<()>>,
 }
 
 impl Syncer {
     /// Create a new syncer
     pub fn new(
-        device_file: Arc<File>,
         http_client: Arc<HttpClient>,
         token_mngr: Arc<TokenManager>,
-        workflow_cache: Arc<WorkflowCache>,
-        deployment_dir: Dir,
+        storage: SyncStorage,
+        executors: Arc<ExecutorRegistry>,
         fsm_settings: FsmSettings,
+        cooldown_options: CooldownOptions,
         agent_version: String,
     ) -> Self {
+        let SyncStorage { workflow_cache, workflow_store, workflow_limits, deployment_dir } =
+            storage;
         Self {
-            device_file,
             http_client,
             token_mngr,
             workflow_cache,
+            workflow_store,
+            workflow_limits,
             deployment_dir,
+            executors,
             fsm_settings,
             agent_version,
             state: RwLock::new(SyncState::default()),
-            cooldown_options: CooldownOptions::default(),
+            cooldown_options,
+            sleep_fn: Arc::new(|delay| Box::pin(tokio::time::sleep(delay))),
+            shutdown_rx: None,
         }
     }
 
-    /// Trigger a sync
-    pub async fn trigger_sync(&self) -> Result<(), AgentError> {
+    /// Wait between deployment retries with `sleep_fn`
+    pub fn with_sleep_fn(mut self, sleep_fn: SleepFn) -> Self {
+        self.sleep_fn = sleep_fn;
+        self
+    }
+
+    /// Stop retrying deployments once `shutdown_rx` is signalled
+    pub fn with_shutdown_signal(mut self, shutdown_rx: broadcast::Receiver<()>) -> Self {
+        self.shutdown_rx = Some(shutdown_rx);
+        self
+    }
+
+    /// Trigger a sync. Workflows that fail to apply are reported without
+    /// failing the sync; only a failed backend call enters cooldown.
+    pub async fn trigger_sync(&self) -> Result<SyncReport, AgentError> {
         // Check cooldown
         {
             let state = self.state.read().await;
             if state.is_in_cooldown() {
                 debug!("Sync in cooldown, skipping...");
-                return Ok(());
+                return Ok(SyncReport::default());
             }
         }
 
@@ -96,14 +179,33 @@ impl Syncer {
             state.last_attempted_sync_at = Utc::now();
         }
 
-        // Perform sync
-        match self.sync_impl().await {
-            Ok(_) => {
+        // Perform sync. A rejected token may have been rotated or revoked
+        // since it was loaded; a fresh one gets a second try, not a cooldown.
+        let result = match self.sync_impl().await {
+            Err(AgentError::HttpStatus { status: 401, .. }) => {
+                warn!("Backend rejected the device token; refreshing it and syncing again");
+                match self.token_mngr.refresh_token().await {
+                    Ok(_) => self.sync_impl().await,
+                    Err(e) => Err(e),
+                }
+            }
+            result => result,
+        };
+        match result {
+            Ok(report) => {
                 let mut state = self.state.write().await;
                 state.last_synced_at = Utc::now();
                 state.err_streak = 0;
-                info!("Sync completed successfully");
-                Ok(())
+                if report.is_partial() {
+                    warn!(
+                        "Sync completed with {} of {} workflows failing",
+                        report.failed.len(),
+                        report.failed.len() + report.applied.len()
+                    );
+                } else {
+                    info!("Sync completed successfully");
+                }
+                Ok(report)
             }
             Err(e) => {
                 let mut state = self.state.write().await;
@@ -122,7 +224,7 @@ impl Syncer {
         }
     }
 
-    async fn sync_impl(&self) -> Result<(), AgentError> {
+    async fn sync_impl(&self) -> Result<SyncReport, AgentError> {
         info!("Starting workflow sync...");
 
         // Get device ID and token
@@ -156,27 +258,186 @@ impl Syncer {
         );
 
         // Update cache with new workflows
-        for workflow in sync_response.workflows {
-            let digest = sha256_hash(serde_json::to_string(&workflow)?.as_bytes());
-            info!("Caching workflow: {} ({})", workflow.name, workflow.id);
-            self.workflow_cache.insert(workflow, digest);
+        let limits = &self.workflow_limits;
+        let (workflows, unreadable) = parse_workflows(sync_response.workflows, limits);
+        let mut report = apply_workflows(&self.workflow_cache, workflows, limits);
+        report.failed.splice(0..0, unreadable);
+        self.persist(&report.applied).await;
+        self.deploy(&report.applied).await;
+        if report.is_partial() {
+            self.report_failures(&device_id, &token.raw, &report.failed).await;
         }
 
         // Remove workflows that are no longer assigned
-        let remote_ids: std::collections::HashSet<_> = sync_response
+        let remote_ids: HashSet<String> = sync_response
             .digests
             .iter()
             .map(|d| d.workflow_id.clone())
             .collect();
+        report.removed = self.remove_unassigned(&remote_ids).await;
+
+        Ok(report)
+    }
+
+    /// Tell the backend which workflows failed, so operators see devices
+    /// that cannot run them (say, an agent too old for their schema).
+    /// Failing to report is logged; the sync stands.
+    async fn report_failures(&self, device_id: &str, token: &str, failed: &[WorkflowSyncError]) {
+        let report = SyncErrorReport {
+            agent_version: &self.agent_version,
+            errors: failed,
+        };
+        if let Err(e) = self.http_client.report_sync_errors(device_id, token, &report).await {
+            warn!("Failed to report {} workflow sync error(s): {}", failed.len(), e);
+        }
+    }
+
+    /// Write cached workflows to disk. Failures are logged; the workflows
+    /// are still cached in memory and fetched again after a restart.
+    async fn persist(&self, workflow_ids: &[String]) {
+        for workflow_id in workflow_ids {
+            let Some(entry) = self.workflow_cache.get(workflow_id) else {
+                continue;
+            };
+            if let Err(e) = self.workflow_store.save(&entry).await {
+                warn!("Failed to persist workflow {}: {}", workflow_id, e);
+            }
+        }
+    }
 
+    /// Replace the executors of `workflow_ids` with new ones for their
+    /// cached version, stopping the old ones. The new executors are deployed
+    /// and, for active workflows, started in the background.
+    async fn deploy(&self, workflow_ids: &[String]) {
+        for workflow_id in workflow_ids {
+            let Some(entry) = self.workflow_cache.get(workflow_id) else {
+                continue;
+            };
+            if let Some(previous) = self.executors.remove(workflow_id) {
+                stop_executor(&previous).await;
+            }
+            let executor = Arc::new(self.executors.create(entry.workflow));
+            self.executors.insert(executor.clone());
+            tokio::spawn(self.runner().deploy_and_run(executor));
+        }
+    }
+
+    fn runner(&self) -> WorkflowRunner {
+        WorkflowRunner {
+            executors: self.executors.clone(),
+            http_client: self.http_client.clone(),
+            token_mngr: self.token_mngr.clone(),
+            settings: self.fsm_settings.clone(),
+            sleep_fn: self.sleep_fn.clone(),
+            shutdown_rx: self.shutdown_rx.as_ref().map(|rx| rx.resubscribe()),
+        }
+    }
+
+    /// Load the workflows persisted by a previous run into the cache
+    pub async fn restore_cache(&self) -> usize {
+        let entries = self.workflow_store.load_all().await;
+        let restored = entries.len();
+        for entry in entries {
+            self.workflow_cache.restore(entry);
+        }
+        if restored > 0 {
+            info!("Restored {} cached workflow(s)", restored);
+        }
+        restored
+    }
+
+    /// Recreate the executors of the cached workflows after a restart, each
+    /// in the state it was persisted in: running workflows run again and
+    /// paused ones wait to be resumed. Workflows without a persisted state
+    /// are deployed as after a sync.
+    pub async fn restore_executors(&self) {
+        for entry in self.workflow_cache.entries() {
+            let executor = Arc::new(self.executors.create(entry.workflow));
+            self.executors.insert(executor.clone());
+            let paused = match executor.restore().await {
+                Ok(None) => {
+                    tokio::spawn(self.runner().deploy_and_run(executor));
+                    continue;
+                }
+                Ok(Some(DeploymentState::Running)) => false,
+                Ok(Some(DeploymentState::Paused)) => true,
+                Ok(Some(_)) => continue,
+                Err(e) => {
+                    warn!("Failed to restore workflow {}: {}", executor.workflow().name, e);
+                    continue;
+                }
+            };
+            tokio::spawn(self.runner().run(executor, paused));
+        }
+    }
+
+    /// Drop cached workflows missing from `remote_ids` along with their files.
+    /// Workflows still executing are kept until a later sync.
+    async fn remove_unassigned(&self, remote_ids: &HashSet<String>) -> Vec<String> {
+        let mut removed = Vec::new();
         for local_id in self.workflow_cache.keys() {
-            if !remote_ids.contains(&local_id) {
-                info!("Removing workflow from cache: {}", local_id);
-                self.workflow_cache.remove(&local_id);
+            if remote_ids.contains(&local_id) {
+                continue;
+            }
+            if self.is_executing(&local_id).await {
+                warn!(
+                    "Workflow {} is no longer assigned but still executing; keeping it for now",
+                    local_id
+                );
+                continue;
+            }
+            info!("Removing workflow from cache: {}", local_id);
+            if let Some(executor) = self.executors.remove(&local_id) {
+                stop_executor(&executor).await;
             }
+            self.workflow_cache.remove(&local_id);
+            self.remove_artifacts(&local_id).await;
+            removed.push(local_id);
         }
+        removed
+    }
 
-        Ok(())
+    /// The workflow has an executor that is deploying or mid-execution
+    async fn is_executing(&self, workflow_id: &str) -> bool {
+        let Some(executor) = self.executors.get(workflow_id) else {
+            return false;
+        };
+        matches!(
+            executor.state().await,
+            DeploymentState::Deploying | DeploymentState::Running | DeploymentState::Paused
+        )
+    }
+
+    /// Delete the persisted cache entry and deployment directory of a
+    /// workflow. Failures are logged; the workflow is gone from the cache
+    /// either way.
+    async fn remove_artifacts(&self, workflow_id: &str) {
+        if let Err(e) = validate_workflow_id(workflow_id) {
+            warn!("Not removing artifacts of workflow: {}", e);
+            return;
+        }
+
+        if let Err(e) = self.workflow_store.remove(workflow_id).await {
+            warn!("Failed to delete cached workflow {}: {}", workflow_id, e);
+        }
+        let deployment_dir = self.deployment_dir.subdir(workflow_id);
+        if let Err(e) = deployment_dir.delete().await {
+            warn!("Failed to delete {:?}: {}", deployment_dir.path(), e);
+        }
+    }
+
+    /// Clear the error streak and cooldown so the next trigger syncs
+    /// immediately
+    pub async fn reset_cooldown(&self) {
+        let mut state = self.state.write().await;
+        if state.err_streak > 0 || state.is_in_cooldown() {
+            info!(
+                "Sync cooldown reset (error streak was {}, cooldown until {})",
+                state.err_streak, state.cooldown_ends_at
+            );
+        }
+        state.err_streak = 0;
+        state.cooldown_ends_at = DateTime::<Utc>::MIN_UTC;
     }
 
     /// Get sync state
@@ -189,3 +450,660 @@ impl Syncer {
         self.workflow_cache.keys()
     }
 }
+
+/// Deploys and runs workflows in the background, reporting how each
+/// execution ends
+struct WorkflowRunner {
+    executors: Arc<ExecutorRegistry>,
+    http_client: Arc<HttpClient>,
+    token_mngr: Arc<TokenManager>,
+    settings: FsmSettings,
+    sleep_fn: SleepFn,
+    shutdown_rx: Option<broadcast::Receiver<()>>,
+}
+
+impl WorkflowRunner {
+    /// Deploy `executor`, retrying as the FSM settings allow, then run it if
+    /// its workflow is active. Gives up once another executor replaces it
+    /// or the agent shuts down.
+    async fn deploy_and_run(mut self, executor: Arc<WorkflowExecutor>) {
+        let workflow = executor.workflow();
+        let mut shutdown_rx = self.shutdown_rx.take();
+        let mut shutdown_signal = std::pin::pin!(async move {
+            match &mut shutdown_rx {
+                Some(shutdown_rx) => {
+                    let _ = shutdown_rx.recv().await;
+                }
+                None => std::future::pending().await,
+            }
+        });
+        let is_current = || {
+            self.executors
+                .get(&workflow.id)
+                .is_some_and(|current| Arc::ptr_eq(&current, &executor))
+        };
+
+        let mut attempt = 0;
+        while let Err(e) = executor.deploy().await {
+            if attempt >= self.settings.retry_count || !is_current() {
+                error!("Failed to deploy workflow {}: {}", workflow.name, e);
+                return;
+            }
+            attempt += 1;
+            let delay = self.settings.next_retry_delay();
+            warn!(
+                "Failed to deploy workflow {} (attempt {}), retrying in {:?}: {}",
+                workflow.name, attempt, delay, e
+            );
+            tokio::select! {
+                _ = shutdown_signal.as_mut() => {
+                    info!("Shutting down; not retrying deployment of workflow {}", workflow.name);
+                    return;
+                }
+                _ = (self.sleep_fn)(delay) => {}
+            }
+        }
+
+        if !is_current() {
+            // Replaced while deploying; release what the runners hold
+            stop_executor(&executor).await;
+            return;
+        }
+        if workflow.status != WorkflowStatus::Active {
+            debug!("Workflow {} is not active; not starting it", workflow.name);
+            return;
+        }
+        self.run(executor, false).await;
+    }
+
+    /// Run a deployed workflow through the registry until it finishes,
+    /// paused to begin with if `paused`, and report the result
+    async fn run(self, executor: Arc<WorkflowExecutor>, paused: bool) {
+        let workflow = executor.workflow();
+        let result = if paused {
+            self.executors.start_paused(&workflow.id).await
+        } else {
+            self.executors.start(&workflow.id).await
+        };
+        if let Err(e) = result {
+            error!("Workflow {} failed: {}", workflow.name, e);
+        }
+        self.report(&executor).await;
+    }
+
+    /// Report the finished execution of `executor` to the backend. Failing
+    /// to report is logged; the result stays available locally.
+    async fn report(&self, executor: &WorkflowExecutor) {
+        let Some(result) = executor.execution_result().await else {
+            return;
+        };
+        if result.finished_at.is_none() {
+            return;
+        }
+        let reported = async {
+            let device_id = self.token_mngr.get_device_id().await?;
+            let token = self.token_mngr.get_token().await?;
+            self.http_client
+                .report_workflow_status(&device_id, &result.workflow_id, &token.raw, &result)
+                .await
+        };
+        if let Err(e) = reported.await {
+            warn!("Failed to report the result of workflow {}: {}", executor.workflow().name, e);
+        }
+    }
+}
+
+/// Stop an executor whose node runners may hold hardware or containers.
+/// Failures are logged; the executor is being discarded either way.
+async fn stop_executor(executor: &WorkflowExecutor) {
+    let state = executor.state().await;
+    if !matches!(
+        state,
+        DeploymentState::Deployed | DeploymentState::Running | DeploymentState::Paused
+    ) {
+        return;
+    }
+    if let Err(e) = executor.stop().await {
+        warn!("Failed to stop workflow {}: {}", executor.workflow().name, e);
+    }
+}
+
+/// Parse the workflows of a sync response one by one, so a workflow this
+/// agent cannot read fails alone, reported with the field that failed.
+/// Workflows whose JSON exceeds the size limit are not parsed at all.
+fn parse_workflows(
+    raws: Vec<Box<RawValue>>,
+    limits: &WorkflowLimits,
+) -> (Vec<Workflow>, Vec<WorkflowSyncError>) {
+    let mut workflows = Vec::new();
+    let mut failed = Vec::new();
+    for (index, raw) in raws.into_iter().enumerate() {
+        let workflow_id = match serde_json::from_str::<WorkflowId>(raw.get()) {
+            Ok(WorkflowId { id: Some(id) }) => id,
+            _ => format!("#{}", index),
+        };
+        if let Err(e) = limits.check_json_len(raw.get().len()) {
+            warn!("Skipping workflow {}: {}", workflow_id, e);
+            failed.push(WorkflowSyncError {
+                workflow_id,
+                error: e.to_string(),
+                path: None,
+            });
+            continue;
+        }
+        let mut deserializer = serde_json::Deserializer::from_str(raw.get());
+        match serde_path_to_error::deserialize::<_, Workflow>(&mut deserializer) {
+            Ok(workflow) => workflows.push(workflow),
+            Err(e) => {
+                let path = e.path().to_string();
+                warn!("Skipping workflow {}: invalid {}: {}", workflow_id, path, e.inner());
+                failed.push(WorkflowSyncError {
+                    workflow_id,
+                    error: format!("Unsupported workflow at {}: {}", path, e.inner()),
+                    path: Some(path),
+                });
+            }
+        }
+    }
+    (workflows, failed)
+}
+
+/// Just the ID of a workflow, to name one that fails to parse
+#[derive(Deserialize)]
+struct WorkflowId {
+    #[serde(default)]
+    id: Option<String>,
+}
+
+/// Cache each workflow, collecting the ones that fail instead of aborting
+fn apply_workflows(
+    cache: &WorkflowCache,
+    workflows: Vec<Workflow>,
+    limits: &WorkflowLimits,
+) -> SyncReport {
+    let mut report = SyncReport::default();
+    for workflow in workflows {
+        let workflow_id = workflow.id.clone();
+        match apply_workflow(cache, workflow, limits) {
+            Ok(()) => report.applied.push(workflow_id),
+            Err(e) => {
+                warn!("Failed to apply workflow {}: {}", workflow_id, e);
+                report.failed.push(WorkflowSyncError {
+                    workflow_id,
+                    error: e.to_string(),
+                    path: None,
+                });
+            }
+        }
+    }
+    report
+}
+
+fn apply_workflow(
+    cache: &WorkflowCache,
+    workflow: Workflow,
+    limits: &WorkflowLimits,
+) -> Result<(), AgentError> {
+    validate_workflow_id(&workflow.id)?;
+    limits.check(&workflow)?;
+    let digest = sha256_hash(serde_json::to_string(&workflow)?.as_bytes());
+    info!("Caching workflow: {} ({})", workflow.name, workflow.id);
+    cache.insert(workflow, digest);
+    Ok(())
+}
+
+/// Workflow IDs name files and directories, so they must be a single plain
+/// path component
+fn validate_workflow_id(id: &str) -> Result<(), AgentError> {
+    let valid = !id.is_empty()
+        && id != "."
+        && id != ".."
+        && !id.contains(['/', '\\', '\0']);
+    if valid {
+        Ok(())
+    } else {
+        Err(AgentError::ValidationError(format!("Invalid workflow ID {:?}", id)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    use crate::authn::test_utils::token_manager;
+    use crate::filesys::test_utils::TempFs;
+    use crate::http::client::HttpClientOptions;
+    use crate::models::test_utils::{edge, node, workflow, workflow_with_graph};
+    use crate::models::workflow::ExecutionState;
+    use crate::storage::layout::StorageLayout;
+
+    async fn syncer(
+        fs: &TempFs,
+        executors: Arc<ExecutorRegistry>,
+        cooldown: CooldownOptions,
+    ) -> Syncer {
+        syncer_with_backend(fs, executors, cooldown, "http://127.0.0.1:1", "secret").await
+    }
+
+    async fn syncer_with_backend(
+        fs: &TempFs,
+        executors: Arc<ExecutorRegistry>,
+        cooldown: CooldownOptions,
+        backend: &str,
+        token: &str,
+    ) -> Syncer {
+        let layout = StorageLayout::new(fs.path("ajime"));
+        let token_mngr = token_manager(fs, backend, token).await;
+        let options = HttpClientOptions::without_retries();
+        Syncer::new(
+            Arc::new(HttpClient::new(backend, options).await.unwrap()),
+            token_mngr,
+            SyncStorage {
+                workflow_cache: Arc::new(WorkflowCache::new(0)),
+                workflow_store: WorkflowStore::new(
+                    layout.workflows_cache_dir(),
+                    true,
+                    WorkflowLimits::default(),
+                ),
+                workflow_limits: WorkflowLimits::default(),
+                deployment_dir: layout.deployment_dir(),
+            },
+            executors,
+            FsmSettings::default(),
+            cooldown,
+            "test".to_string(),
+        )
+    }
+
+    fn raw(values: Vec<Value>) -> Vec<Box<RawValue>> {
+        values.iter().map(|v| serde_json::value::to_raw_value(v).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_failed_workflow_does_not_abort_the_rest() {
+        let cache = WorkflowCache::new(0);
+        cache.insert(workflow("wf2"), "old".to_string());
+
+        let report = apply_workflows(
+            &cache,
+            vec![workflow("wf1"), workflow("../wf"), workflow("wf2")],
+            &WorkflowLimits::default(),
+        );
+
+        assert!(report.is_partial());
+        assert_eq!(report.applied, vec!["wf1", "wf2"]);
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].workflow_id, "../wf");
+        assert!(report.failed[0].error.contains("Invalid workflow ID"));
+
+        assert!(cache.get("wf1").is_some());
+        assert_ne!(cache.get("wf2").unwrap().digest, "old");
+        assert!(cache.get("../wf").is_none());
+    }
+
+    #[test]
+    fn test_unreadable_workflows_are_skipped_with_their_path() {
+        let mut newer = serde_json::to_value(workflow("wf2")).unwrap();
+        newer["graph_data"]["nodes"] = serde_json::json!([{ "id": "n1", "type": "camera" }]);
+        let values = vec![
+            serde_json::to_value(workflow("wf1")).unwrap(),
+            newer,
+            serde_json::json!({ "name": "no id" }),
+        ];
+
+        let (workflows, failed) = parse_workflows(raw(values), &WorkflowLimits::default());
+        assert_eq!(workflows.len(), 1);
+        assert_eq!(workflows[0].id, "wf1");
+        let failed: Vec<(&str, Option<&str>)> = failed
+            .iter()
+            .map(|f| (f.workflow_id.as_str(), f.path.as_deref()))
+            .collect();
+        assert_eq!(failed, [("wf2", Some("graph_data.nodes[0]")), ("#2", Some("."))]);
+    }
+
+    #[test]
+    fn test_oversized_workflows_are_not_cached() {
+        let mut long = workflow("long");
+        long.description = Some("x".repeat(1000));
+        let mut wide = workflow("wide");
+        wide.graph_data.nodes = serde_json::from_value(serde_json::json!([
+            { "id": "n1", "type": "camera", "data": {} },
+            { "id": "n2", "type": "camera", "data": {} },
+        ]))
+        .unwrap();
+        let values = [workflow("wf1"), long, wide]
+            .iter()
+            .map(|w| serde_json::to_value(w).unwrap())
+            .collect();
+        let limits = WorkflowLimits {
+            max_nodes: 1,
+            max_json_bytes: 1000,
+            ..Default::default()
+        };
+
+        let (workflows, failed) = parse_workflows(raw(values), &limits);
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].workflow_id, "long");
+        assert!(failed[0].error.contains("more than the limit of 1000"));
+
+        let cache = WorkflowCache::new(0);
+        let report = apply_workflows(&cache, workflows, &limits);
+        assert_eq!(report.applied, ["wf1"]);
+        assert_eq!(report.failed[0].workflow_id, "wide");
+        assert!(cache.get("wide").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_unassigned_workflow_artifacts_are_removed() {
+        let fs = TempFs::new();
+        let executors = Arc::new(ExecutorRegistry::new());
+        let syncer = syncer(&fs, executors.clone(), CooldownOptions::default()).await;
+        for id in ["gone", "busy"] {
+            syncer.workflow_cache.insert(workflow(id), "digest".to_string());
+            fs.write(&format!("ajime/cache/workflows/{}.json", id), "{}");
+            fs.write(&format!("ajime/deployments/{}/workflow.json", id), "{}");
+        }
+        let mut busy = workflow("busy");
+        busy.graph_data.nodes = serde_json::from_value(serde_json::json!([
+            { "id": "wait", "type": "delay", "data": { "delay_ms": 10_000 } },
+        ]))
+        .unwrap();
+        let busy = Arc::new(executors.create(busy));
+        busy.deploy().await.unwrap();
+        executors.insert(busy.clone());
+        let running = tokio::spawn({
+            let executors = executors.clone();
+            async move { executors.start("busy").await }
+        });
+        while busy.state().await != DeploymentState::Running {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        let remote_ids = HashSet::new();
+        assert_eq!(syncer.remove_unassigned(&remote_ids).await, vec!["gone"]);
+        assert!(!fs.path("ajime/cache/workflows/gone.json").exists());
+        assert!(!fs.path("ajime/deployments/gone").exists());
+
+        // The running workflow keeps its files until it stops
+        assert!(syncer.workflow_cache.get("busy").is_some());
+        assert!(fs.path("ajime/cache/workflows/busy.json").exists());
+        assert!(fs.path("ajime/deployments/busy").exists());
+        running.abort();
+    }
+
+    #[tokio::test]
+    async fn test_cached_workflows_are_restored_after_restart() {
+        let fs = TempFs::new();
+        let syncer_before = syncer(&fs, Arc::new(ExecutorRegistry::new()), Default::default()).await;
+        let limits = WorkflowLimits::default();
+        let report = apply_workflows(&syncer_before.workflow_cache, vec![workflow("wf1")], &limits);
+        syncer_before.persist(&report.applied).await;
+        let digest = syncer_before.workflow_cache.get("wf1").unwrap().digest;
+
+        let syncer = syncer(&fs, Arc::new(ExecutorRegistry::new()), Default::default()).await;
+        assert_eq!(syncer.restore_cache().await, 1);
+        assert_eq!(syncer.workflow_cache.get("wf1").unwrap().digest, digest);
+    }
+
+    #[tokio::test]
+    async fn test_executors_resume_their_persisted_state_after_restart() {
+        let fs = TempFs::new();
+        let layout = StorageLayout::new(fs.path("ajime"));
+        let executors = Arc::new(ExecutorRegistry::new().with_layout(layout));
+        let syncer = syncer(&fs, executors.clone(), Default::default()).await;
+        for id in ["running", "paused", "fresh"] {
+            let mut workflow = workflow(id);
+            workflow.graph_data.nodes = serde_json::from_value(serde_json::json!([
+                { "id": "wait", "type": "delay", "data": { "delay_ms": 0 } },
+            ]))
+            .unwrap();
+            syncer.workflow_cache.insert(workflow, "digest".to_string());
+        }
+        for (id, state) in [("running", "running"), ("paused", "paused")] {
+            let fsm = format!(r#"{{"state":"{}","error":null,"retry_count":0}}"#, state);
+            fs.write(&format!("ajime/deployments/{}/state.json", id), &fsm);
+        }
+
+        syncer.restore_executors().await;
+        assert_eq!(executors.len(), 3);
+
+        // The interrupted run and the never deployed workflow run again
+        for id in ["running", "fresh"] {
+            let executor = executors.get(id).unwrap();
+            while executor.get_execution().await.is_none() {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+            wait_for_state(&executor, DeploymentState::Deployed).await;
+        }
+
+        // The paused one waits to be resumed
+        let paused = executors.get("paused").unwrap();
+        wait_for_state(&paused, DeploymentState::Paused).await;
+        assert_eq!(paused.get_execution().await.unwrap().state, ExecutionState::Paused);
+        assert_eq!(executors.running(), 1);
+        paused.resume().await.unwrap();
+        wait_for_state(&paused, DeploymentState::Deployed).await;
+        let result = paused.execution_result().await.unwrap();
+        assert_eq!(result.status, ExecutionState::Completed);
+    }
+
+    #[tokio::test]
+    async fn test_failed_sync_uses_configured_cooldown() {
+        let fs = TempFs::new();
+        let cooldown = CooldownOptions {
+            base_delay: std::time::Duration::from_secs(60),
+            max_delay: std::time::Duration::from_secs(90),
+            multiplier: 2.0,
+        };
+        let syncer = syncer(&fs, Arc::new(ExecutorRegistry::new()), cooldown).await;
+
+        // The backend is unreachable, so the sync call itself fails
+        assert!(syncer.trigger_sync().await.is_err());
+        let state = syncer.get_state().await;
+        assert_eq!(state.err_streak, 1);
+        assert!(state.is_in_cooldown());
+        let remaining = state.cooldown_remaining().as_secs();
+        assert!((80..=90).contains(&remaining), "remaining {}", remaining);
+
+        // Further triggers are skipped while cooling down
+        assert!(syncer.trigger_sync().await.is_ok());
+        assert_eq!(syncer.get_state().await.err_streak, 1);
+
+        // After a reset the next trigger tries again
+        syncer.reset_cooldown().await;
+        let state = syncer.get_state().await;
+        assert_eq!(state.err_streak, 0);
+        assert!(!state.is_in_cooldown());
+        assert!(syncer.trigger_sync().await.is_err());
+        assert_eq!(syncer.get_state().await.err_streak, 1);
+    }
+
+    /// An executor whose deployment always fails, as its graph has a cycle
+    fn undeployable(executors: &ExecutorRegistry) -> Arc<WorkflowExecutor> {
+        let delay = serde_json::json!({ "delay_ms": 0 });
+        let nodes = vec![node("a", "delay", delay.clone()), node("b", "delay", delay)];
+        let cyclic = workflow_with_graph("cyclic", nodes, vec![edge("a", "b"), edge("b", "a")]);
+        let executor = Arc::new(executors.create(cyclic));
+        executors.insert(executor.clone());
+        executor
+    }
+
+    #[tokio::test]
+    async fn test_deploy_retries_wait_with_the_sleep_fn() {
+        let fs = TempFs::new();
+        let executors = Arc::new(ExecutorRegistry::new());
+        let delays = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sleep_fn: SleepFn = Arc::new({
+            let delays = delays.clone();
+            move |delay| {
+                delays.lock().unwrap().push(delay);
+                Box::pin(async {})
+            }
+        });
+        let syncer = syncer(&fs, executors.clone(), Default::default()).await;
+        let syncer = syncer.with_sleep_fn(sleep_fn);
+
+        let executor = undeployable(&executors);
+        syncer.runner().deploy_and_run(executor.clone()).await;
+        assert_eq!(executor.state().await, DeploymentState::Failed);
+
+        let settings = FsmSettings::default();
+        let delays = delays.lock().unwrap();
+        assert_eq!(delays.len(), settings.retry_count as usize);
+        let max_delay = settings.retry_delay + settings.retry_jitter;
+        assert!(delays.iter().all(|d| (settings.retry_delay..max_delay).contains(d)));
+    }
+
+    #[tokio::test]
+    async fn test_deploy_retries_stop_on_shutdown() {
+        let fs = TempFs::new();
+        let executors = Arc::new(ExecutorRegistry::new());
+        let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
+        let syncer = syncer(&fs, executors.clone(), Default::default())
+            .await
+            .with_sleep_fn(Arc::new(|_| Box::pin(std::future::pending())))
+            .with_shutdown_signal(shutdown_rx);
+
+        let runner = syncer.runner();
+        shutdown_tx.send(()).unwrap();
+        let deployed = runner.deploy_and_run(undeployable(&executors));
+        tokio::time::timeout(std::time::Duration::from_secs(5), deployed)
+            .await
+            .expect("retry wait did not stop on shutdown");
+    }
+
+    async fn wait_for_state(executor: &WorkflowExecutor, state: DeploymentState) {
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while executor.state().await != state {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap_or_else(|_| panic!("workflow {} never became {:?}", executor.workflow().id, state));
+    }
+
+    /// A device token issued at `iat` that is valid for another hour
+    fn jwt(iat: i64) -> String {
+        use jsonwebtoken::{encode, EncodingKey, Header};
+
+        let claims = crate::authn::device_token::DeviceTokenClaims {
+            sub: "device-1".to_string(),
+            owner_id: "owner".to_string(),
+            capabilities: vec![],
+            iat,
+            exp: Utc::now().timestamp() + 3600,
+            iss: None,
+        };
+        encode(&Header::default(), &claims, &EncodingKey::from_secret(b"backend")).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_synced_workflows_are_deployed_and_active_ones_run() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let mut active = workflow("active");
+        active.graph_data.nodes = serde_json::from_value(serde_json::json!([
+            { "id": "wait", "type": "delay", "data": { "delay_ms": 0 } },
+        ]))
+        .unwrap();
+        let mut draft = workflow("draft");
+        draft.status = WorkflowStatus::Draft;
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/agent/devices/device-1/workflows/sync"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "workflows": [active, draft],
+                "digests": [{ "workflow_id": "active", "digest": "a", "updated_at": "" },
+                            { "workflow_id": "draft", "digest": "d", "updated_at": "" }],
+            })))
+            .mount(&server)
+            .await;
+        let status_path = "/agent/devices/device-1/workflows/active/status";
+        Mock::given(method("POST"))
+            .and(path(status_path))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({})))
+            .mount(&server)
+            .await;
+
+        let fs = TempFs::new();
+        let executors = Arc::new(ExecutorRegistry::new());
+        let cooldown = CooldownOptions::default();
+        let token = jwt(Utc::now().timestamp());
+        let syncer =
+            syncer_with_backend(&fs, executors.clone(), cooldown, &server.uri(), &token).await;
+        syncer.trigger_sync().await.unwrap();
+        assert_eq!(executors.len(), 2);
+        let first = executors.get("active").unwrap();
+        let draft = executors.get("draft").unwrap();
+        wait_for_state(&draft, DeploymentState::Deployed).await;
+        assert!(draft.get_execution().await.is_none());
+        while first.get_execution().await.is_none() {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        wait_for_state(&first, DeploymentState::Deployed).await;
+        assert_eq!(first.execution_result().await.unwrap().status, ExecutionState::Completed);
+
+        // The result is reported, in both the current and the legacy form
+        let report = loop {
+            let requests = server.received_requests().await.unwrap();
+            if let Some(request) = requests.iter().find(|r| r.url.path() == status_path) {
+                break request.body_json::<Value>().unwrap();
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        };
+        assert_eq!(report["status"], "completed");
+        assert_eq!(report["nodes"][0]["node_id"], "wait");
+        assert_eq!(report["node_statuses"][0]["node_id"], "wait");
+        assert_eq!(report["node_statuses"][0]["status"], "completed");
+
+        // A new version replaces the executor
+        syncer.trigger_sync().await.unwrap();
+        assert!(!Arc::ptr_eq(&first, &executors.get("active").unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_rejected_token_is_refreshed_instead_of_cooling_down() {
+        use wiremock::matchers::{header, method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let stale = jwt(Utc::now().timestamp() - 60);
+        let fresh = jwt(Utc::now().timestamp());
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/agent/devices/device-1/token/refresh"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({ "token": fresh })),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/agent/devices/device-1/workflows/sync"))
+            .and(header("Authorization", format!("Bearer {}", fresh).as_str()))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "workflows": [],
+                "digests": [],
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/agent/devices/device-1/workflows/sync"))
+            .respond_with(ResponseTemplate::new(401).set_body_json(serde_json::json!({
+                "error": "unauthorized",
+                "message": "Token revoked",
+            })))
+            .mount(&server)
+            .await;
+
+        let fs = TempFs::new();
+        let executors = Arc::new(ExecutorRegistry::new());
+        let cooldown = CooldownOptions::default();
+        let syncer = syncer_with_backend(&fs, executors, cooldown, &server.uri(), &stale).await;
+        syncer.trigger_sync().await.unwrap();
+        let state = syncer.get_state().await;
+        assert_eq!(state.err_streak, 0);
+        assert!(!state.is_in_cooldown());
+    }
+}
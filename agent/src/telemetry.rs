@@ -1,7 +1,13 @@
 //! Telemetry and metrics collection
 
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Mutex, RwLock};
+use std::time::Duration;
+
 use serde::{Deserialize, Serialize};
 use sysinfo::{System, Disks};
+use tracing::info;
 
 /// System metrics
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -41,7 +47,11 @@ pub struct SystemMetrics {
 pub fn collect_metrics() -> SystemMetrics {
     let mut sys = System::new_all();
     sys.refresh_all();
+    metrics_from(&sys)
+}
 
+/// Metrics from a refreshed `System`, plus disk usage
+fn metrics_from(sys: &System) -> SystemMetrics {
     let disks = Disks::new_with_refreshed_list();
 
     // Calculate total disk usage
@@ -77,6 +87,87 @@ pub fn collect_metrics() -> SystemMetrics {
     }
 }
 
+/// Samples system metrics on an interval
+///
+/// CPU usage is measured between two refreshes, so a one-off sample taken
+/// on demand has nothing to compare against and under-reports it. Sampling
+/// periodically keeps it accurate, but wakes the device up regularly, which
+/// costs power on battery or solar installs. With a zero interval nothing is
+/// sampled and metrics are collected on demand instead.
+#[derive(Debug)]
+pub struct MetricsCollector {
+    interval: Duration,
+    system: Mutex<System>,
+    latest: RwLock<Option<SystemMetrics>>,
+}
+
+impl MetricsCollector {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            system: Mutex::new(System::new_all()),
+            latest: RwLock::new(None),
+        }
+    }
+
+    /// Metrics are sampled periodically rather than on demand
+    pub fn is_periodic(&self) -> bool {
+        !self.interval.is_zero()
+    }
+
+    /// The latest sample, or fresh metrics when sampling is disabled or has
+    /// not happened yet
+    pub fn latest(&self) -> SystemMetrics {
+        if self.is_periodic() {
+            let latest = self.latest.read().unwrap_or_else(|e| e.into_inner());
+            if let Some(metrics) = latest.as_ref() {
+                return metrics.clone();
+            }
+        }
+        self.refresh()
+    }
+
+    /// Refresh the kept `System` and read metrics from it. Cheaper than
+    /// `collect_metrics`, and CPU usage is measured since the last refresh.
+    fn refresh(&self) -> SystemMetrics {
+        let mut sys = self.system.lock().unwrap_or_else(|e| e.into_inner());
+        sys.refresh_cpu_usage();
+        sys.refresh_memory();
+        metrics_from(&sys)
+    }
+
+    /// Refresh the kept `System` and store a new sample
+    fn sample(&self) {
+        let metrics = self.refresh();
+        *self.latest.write().unwrap_or_else(|e| e.into_inner()) = Some(metrics);
+    }
+}
+
+/// Sample metrics every `interval` until shut down; returns at once when
+/// sampling is disabled
+pub async fn run_collector<S, F>(
+    collector: &MetricsCollector,
+    sleep_fn: S,
+    mut shutdown_signal: Pin<Box<dyn Future<Output = ()> + Send>>,
+) where
+    S: Fn(Duration) -> F,
+    F: Future<Output = ()>,
+{
+    if !collector.is_periodic() {
+        return;
+    }
+    loop {
+        collector.sample();
+        tokio::select! {
+            _ = &mut shutdown_signal => {
+                info!("Metrics collector shutting down...");
+                return;
+            }
+            _ = sleep_fn(collector.interval) => {}
+        }
+    }
+}
+
 /// Agent metrics
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AgentMetrics {
@@ -101,3 +192,42 @@ pub struct AgentMetrics {
     /// Sync error count
     pub sync_error_count: u32,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collector_serves_samples_only_when_periodic() {
+        for (interval, serves_sample) in [(Duration::from_secs(30), true), (Duration::ZERO, false)] {
+            let collector = MetricsCollector::new(interval);
+            collector.sample();
+            let mut sample = collector.latest.read().unwrap().clone().unwrap();
+            sample.hostname = "sampled".to_string();
+            *collector.latest.write().unwrap() = Some(sample);
+
+            assert_eq!(collector.latest().hostname == "sampled", serves_sample);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_collector_samples_then_waits_until_shut_down() {
+        for (interval, waits_for) in [(Duration::from_secs(30), 1), (Duration::ZERO, 0)] {
+            let collector = MetricsCollector::new(interval);
+            let waits = Mutex::new(Vec::new());
+
+            run_collector(
+                &collector,
+                |wait| {
+                    waits.lock().unwrap().push(wait);
+                    std::future::pending()
+                },
+                Box::pin(async {}),
+            )
+            .await;
+
+            assert_eq!(collector.latest.read().unwrap().is_some(), waits_for == 1);
+            assert_eq!(*waits.lock().unwrap(), vec![interval; waits_for]);
+        }
+    }
+}
@@ -0,0 +1,313 @@
+//! One-shot command execution for the relay `command_exec` command.
+//!
+//! Runs a single allowlisted program with an argument array, without a shell
+//! or PTY, and returns its captured output once it exits or times out. The
+//! program runs in a process group of its own, so a timeout also kills
+//! whatever it started.
+
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::process::Command;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::errors::AgentError;
+use crate::terminal::{resolve_working_dir, TerminalOptions};
+
+/// How long output is still read after the command ended, in case something
+/// that outlived it holds its pipes open.
+const OUTPUT_GRACE: Duration = Duration::from_secs(2);
+
+/// Command execution policy.
+#[derive(Debug, Clone)]
+pub struct ExecOptions {
+    /// Programs that may be run, as bare names or absolute paths. Empty
+    /// disables `command_exec`.
+    pub allowed_commands: Vec<String>,
+
+    /// Timeout when the request does not set one.
+    pub default_timeout: Duration,
+
+    /// Upper bound on a requested timeout.
+    pub max_timeout: Duration,
+
+    /// Bytes kept from each of stdout and stderr.
+    pub max_output_bytes: usize,
+}
+
+impl Default for ExecOptions {
+    fn default() -> Self {
+        Self {
+            allowed_commands: Vec::new(),
+            default_timeout: Duration::from_secs(30),
+            max_timeout: Duration::from_secs(300),
+            max_output_bytes: 64 * 1024,
+        }
+    }
+}
+
+impl ExecOptions {
+    pub fn is_enabled(&self) -> bool {
+        !self.allowed_commands.is_empty()
+    }
+
+    /// Check the program against the allowlist.
+    fn check(&self, program: &str) -> Result<(), AgentError> {
+        if !self.is_enabled() {
+            return Err(AgentError::ValidationError(
+                "Command execution is disabled on this device".to_string(),
+            ));
+        }
+        if self.allowed_commands.iter().any(|allowed| allowed == program) {
+            Ok(())
+        } else {
+            Err(AgentError::ValidationError(format!(
+                "Command '{}' is not allowed",
+                program
+            )))
+        }
+    }
+}
+
+/// A `command_exec` request payload.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExecRequest {
+    /// Program followed by its arguments.
+    pub command: Vec<String>,
+
+    /// Timeout in seconds, capped by the policy.
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
+
+    /// Working directory; the terminal sandbox when unset or invalid.
+    #[serde(default)]
+    pub cwd: Option<String>,
+}
+
+/// Result of a finished (or timed out) command.
+#[derive(Debug, Clone, Serialize)]
+pub struct ExecOutput {
+    /// Exit code, or None when killed by a signal or the timeout.
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    pub timed_out: bool,
+    /// Output was cut at the policy's byte limit.
+    pub truncated: bool,
+}
+
+/// Run a command according to the policy and collect its output.
+pub async fn run_command(
+    request: &ExecRequest,
+    options: &ExecOptions,
+    terminal: &TerminalOptions,
+) -> Result<ExecOutput, AgentError> {
+    let Some((program, args)) = request.command.split_first() else {
+        return Err(AgentError::ValidationError("Command is empty".to_string()));
+    };
+    options.check(program)?;
+
+    let timeout = request
+        .timeout_secs
+        .map(Duration::from_secs)
+        .unwrap_or(options.default_timeout)
+        .min(options.max_timeout);
+    let cwd = resolve_working_dir(request.cwd.as_deref(), terminal);
+
+    info!("Executing {:?} in {:?} (timeout {:?})", request.command, cwd, timeout);
+    let mut command = Command::new(Path::new(program));
+    command
+        .args(args)
+        .current_dir(&cwd)
+        .env_clear()
+        .envs(terminal.child_env())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    #[cfg(unix)]
+    command.process_group(0);
+    let mut child = command.spawn()?;
+
+    let limit = options.max_output_bytes;
+    let stdout = tokio::spawn(read_capped(child.stdout.take(), limit));
+    let stderr = tokio::spawn(read_capped(child.stderr.take(), limit));
+
+    let (exit_code, timed_out) = match tokio::time::timeout(timeout, child.wait()).await {
+        Ok(status) => (status?.code(), false),
+        Err(_) => {
+            warn!("Command {:?} timed out after {:?}", request.command, timeout);
+            #[cfg(unix)]
+            kill_group(&child);
+            if let Err(e) = child.kill().await {
+                warn!("Failed to kill timed out command: {}", e);
+            }
+            (None, true)
+        }
+    };
+
+    let grace = tokio::time::Instant::now() + OUTPUT_GRACE;
+    let (stdout, stdout_truncated) = join_reader(stdout, "stdout", grace).await?;
+    let (stderr, stderr_truncated) = join_reader(stderr, "stderr", grace).await?;
+
+    Ok(ExecOutput {
+        exit_code,
+        stdout,
+        stderr,
+        timed_out,
+        truncated: stdout_truncated || stderr_truncated,
+    })
+}
+
+/// Kill the command's process group, i.e. the command and everything it
+/// started that did not leave the group.
+#[cfg(unix)]
+pub(crate) fn kill_group(child: &tokio::process::Child) {
+    if let Some(pid) = child.id() {
+        // SAFETY: killpg(2) has no memory safety requirements
+        unsafe {
+            libc::killpg(pid as libc::pid_t, libc::SIGKILL);
+        }
+    }
+}
+
+/// Output collected by a pipe reader, or what is known by `deadline`: the
+/// reader is aborted and its output reported as empty and truncated.
+async fn join_reader(
+    reader: JoinHandle<Result<(String, bool), AgentError>>,
+    name: &str,
+    deadline: tokio::time::Instant,
+) -> Result<(String, bool), AgentError> {
+    let abort = reader.abort_handle();
+    match tokio::time::timeout_at(deadline, reader).await {
+        Ok(joined) => {
+            joined.map_err(|e| AgentError::Internal(format!("{name} reader failed: {e}")))?
+        }
+        Err(_) => {
+            warn!("Gave up reading {} of a command whose pipe stayed open", name);
+            abort.abort();
+            Ok((String::new(), true))
+        }
+    }
+}
+
+/// Read a pipe to the end, keeping at most `limit` bytes. The rest is drained
+/// so the child never blocks on a full pipe.
+async fn read_capped<R: AsyncRead + Unpin>(
+    pipe: Option<R>,
+    limit: usize,
+) -> Result<(String, bool), AgentError> {
+    let Some(mut pipe) = pipe else {
+        return Ok((String::new(), false));
+    };
+
+    let mut kept = Vec::new();
+    let mut truncated = false;
+    let mut buf = [0u8; 4096];
+    loop {
+        let n = pipe.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        let room = limit.saturating_sub(kept.len());
+        if n > room {
+            truncated = true;
+        }
+        kept.extend_from_slice(&buf[..n.min(room)]);
+    }
+    Ok((String::from_utf8_lossy(&kept).into_owned(), truncated))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesys::test_utils::TempFs;
+
+    fn request(command: &[&str]) -> ExecRequest {
+        ExecRequest {
+            command: command.iter().map(|s| s.to_string()).collect(),
+            timeout_secs: None,
+            cwd: None,
+        }
+    }
+
+    fn options(allowed: &[&str]) -> ExecOptions {
+        ExecOptions {
+            allowed_commands: allowed.iter().map(|s| s.to_string()).collect(),
+            max_output_bytes: 8,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_runs_allowlisted_command_without_shell() {
+        let fs = TempFs::new();
+        let terminal = TerminalOptions {
+            working_dir: fs.path("sandbox"),
+            ..Default::default()
+        };
+
+        // The argument reaches the program verbatim, not through a shell
+        let output = run_command(&request(&["echo", "$HOME;", "x"]), &options(&["echo"]), &terminal)
+            .await
+            .unwrap();
+        assert_eq!(output.exit_code, Some(0));
+        assert_eq!(output.stdout, "$HOME; x");
+        assert!(output.truncated);
+        assert!(!output.timed_out);
+
+        let err = run_command(&request(&["sh", "-c", "id"]), &options(&["echo"]), &terminal)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AgentError::ValidationError(msg) if msg.contains("'sh'")));
+
+        let err = run_command(&request(&["echo"]), &options(&[]), &terminal)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AgentError::ValidationError(msg) if msg.contains("disabled")));
+    }
+
+    #[tokio::test]
+    async fn test_timeout_kills_command() {
+        let fs = TempFs::new();
+        let terminal = TerminalOptions {
+            working_dir: fs.path("sandbox"),
+            ..Default::default()
+        };
+        let mut request = request(&["sleep", "10"]);
+        request.timeout_secs = Some(0);
+
+        let output = run_command(&request, &options(&["sleep"]), &terminal).await.unwrap();
+        assert!(output.timed_out);
+        assert_eq!(output.exit_code, None);
+
+        // Processes the command started die with it, so their open pipes do
+        // not hold up the output
+        let mut request = self::request(&["sh", "-c", "sleep 10 & sleep 10"]);
+        request.timeout_secs = Some(0);
+        let started = std::time::Instant::now();
+        let output = run_command(&request, &options(&["sh"]), &terminal).await.unwrap();
+        assert!(output.timed_out);
+        assert!(started.elapsed() < OUTPUT_GRACE, "{:?}", started.elapsed());
+    }
+
+    #[tokio::test]
+    async fn test_output_held_open_by_a_leftover_process_is_abandoned() {
+        let fs = TempFs::new();
+        let terminal = TerminalOptions {
+            working_dir: fs.path("sandbox"),
+            ..Default::default()
+        };
+        let request = request(&["sh", "-c", "sleep 10 &"]);
+
+        let started = std::time::Instant::now();
+        let output = run_command(&request, &options(&["sh"]), &terminal).await.unwrap();
+        assert_eq!(output.exit_code, Some(0));
+        assert!(output.truncated);
+        assert!(started.elapsed() < OUTPUT_GRACE + Duration::from_secs(1));
+    }
+}
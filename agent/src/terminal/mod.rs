@@ -1,24 +1,515 @@
 //! PTY-based terminal session for remote access via the relay channel.
 //!
 //! Each session spawns a shell inside a pseudo-terminal and forwards I/O
-//! through the WebSocket relay sender channel.
+//! through the WebSocket relay sender channel. A session can be detached
+//! from its connection and attached to the next one, buffering output in
+//! between.
 
+pub mod exec;
+pub mod sessions;
+
+use std::collections::{BTreeMap, VecDeque};
 use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
-use portable_pty::{native_pty_system, CommandBuilder, PtySize};
+use chrono::{DateTime, Utc};
+use portable_pty::{native_pty_system, Child, CommandBuilder, MasterPty, PtySize};
 use tokio::sync::mpsc;
 use tokio_tungstenite::tungstenite::protocol::Message;
 use tracing::{info, warn};
 
 use crate::errors::AgentError;
+use crate::filesys::relay::validate_path;
+use crate::models::relay::data_message;
+use crate::storage::layout::StorageLayout;
+use crate::terminal::sessions::SessionInfo;
+
+/// Agent environment variables passed to terminal children by default.
+pub const DEFAULT_ENV_ALLOWLIST: &[&str] = &[
+    "PATH", "HOME", "USER", "LOGNAME", "SHELL", "LANG", "LC_ALL", "LC_CTYPE", "TZ",
+];
+
+/// Largest terminal width, in columns.
+pub const MAX_COLS: u16 = 1000;
+
+/// Largest terminal height, in rows.
+pub const MAX_ROWS: u16 = 500;
+
+/// Where terminal shells start and keep their state.
+#[derive(Debug, Clone)]
+pub struct TerminalOptions {
+    /// Default starting directory (the terminal sandbox).
+    pub working_dir: PathBuf,
+
+    /// Give each session its own `HOME`/`HISTFILE` under the sandbox,
+    /// removed when the session ends.
+    pub isolate_home: bool,
+
+    /// Agent environment variables children inherit; everything else (tokens,
+    /// backend URLs) is scrubbed.
+    pub env_allowlist: Vec<String>,
+
+    /// Extra variables set for every child.
+    pub env: BTreeMap<String, String>,
+
+    /// Output forwarded per session, in bytes per second (0 = unlimited).
+    /// Reading from the PTY pauses while a session is over budget.
+    pub max_output_rate: u64,
+
+    /// How long a closed session's shell may take to exit after SIGHUP and
+    /// SIGTERM before it is killed.
+    pub close_grace: Duration,
+
+    /// Sessions open at once per relay connection; more are refused.
+    pub max_sessions: usize,
+
+    /// Sessions without input or output for this long are closed (zero
+    /// keeps them open until closed).
+    pub idle_timeout: Duration,
+
+    /// How long sessions outlive their relay connection, waiting for the
+    /// next connection to re-attach them (zero closes them with it).
+    pub reconnect_grace: Duration,
+
+    /// Output kept per session while it waits for a reconnect, in bytes;
+    /// older output is dropped.
+    pub reconnect_buffer: usize,
+
+    /// Only shells in `allowed_shells` may run, the first being the default,
+    /// and sessions may not add environment variables.
+    pub restrict_shell: bool,
+
+    /// Shells sessions may request when `restrict_shell` is set.
+    pub allowed_shells: Vec<PathBuf>,
+}
+
+impl Default for TerminalOptions {
+    fn default() -> Self {
+        Self {
+            working_dir: StorageLayout::default().terminal_dir().path().to_path_buf(),
+            isolate_home: false,
+            env_allowlist: DEFAULT_ENV_ALLOWLIST.iter().map(|s| s.to_string()).collect(),
+            env: BTreeMap::new(),
+            max_output_rate: 128 * 1024,
+            close_grace: Duration::from_secs(2),
+            max_sessions: 8,
+            idle_timeout: Duration::from_secs(30 * 60),
+            reconnect_grace: Duration::ZERO,
+            reconnect_buffer: 64 * 1024,
+            restrict_shell: false,
+            allowed_shells: Vec::new(),
+        }
+    }
+}
+
+impl TerminalOptions {
+    pub fn validate(&self) -> Result<(), AgentError> {
+        if self.restrict_shell && self.allowed_shells.is_empty() {
+            return Err(AgentError::ConfigError(
+                "restrict_shell is set but no allowed_shells are listed".to_string(),
+            ));
+        }
+        if let Some(shell) = self.allowed_shells.iter().find(|shell| !shell.is_absolute()) {
+            return Err(AgentError::ConfigError(format!(
+                "Allowed shell {:?} must be an absolute path",
+                shell
+            )));
+        }
+        Ok(())
+    }
+
+    /// Environment for a terminal or command child: the allowlisted part of
+    /// the agent's environment plus the configured variables.
+    pub fn child_env(&self) -> BTreeMap<String, String> {
+        Self::scrub(std::env::vars(), &self.env_allowlist, &self.env)
+    }
+
+    fn scrub(
+        vars: impl Iterator<Item = (String, String)>,
+        allowlist: &[String],
+        extra: &BTreeMap<String, String>,
+    ) -> BTreeMap<String, String> {
+        let mut env: BTreeMap<String, String> =
+            vars.filter(|(key, _)| allowlist.contains(key)).collect();
+        env.extend(extra.iter().map(|(k, v)| (k.clone(), v.clone())));
+        env
+    }
+}
+
+/// Pick the directory a shell starts in. A requested directory must pass the
+/// path policy and exist; anything else falls back to the sandbox with a
+/// warning.
+pub fn resolve_working_dir(requested: Option<&str>, options: &TerminalOptions) -> PathBuf {
+    if let Some(dir) = requested {
+        let path = Path::new(dir);
+        match validate_path(dir) {
+            Ok(()) if path.is_absolute() && path.is_dir() => return path.to_path_buf(),
+            Ok(()) => warn!("Terminal directory {} is not an existing absolute directory", dir),
+            Err(e) => warn!("Terminal directory {} rejected: {}", dir, e),
+        }
+    }
+
+    match std::fs::create_dir_all(&options.working_dir) {
+        Ok(()) => options.working_dir.clone(),
+        Err(e) => {
+            let fallback = std::env::temp_dir();
+            warn!(
+                "Terminal sandbox {:?} unavailable ({}); using {:?}",
+                options.working_dir, e, fallback
+            );
+            fallback
+        }
+    }
+}
+
+/// What a session runs: the shell, where it starts and the environment it
+/// adds.
+#[derive(Debug, Clone)]
+pub struct ShellSpec {
+    pub shell: PathBuf,
+    pub cwd: PathBuf,
+    pub env: BTreeMap<String, String>,
+}
+
+impl ShellSpec {
+    /// Resolve a session's requested shell, directory and environment. An
+    /// invalid directory falls back to the sandbox; an invalid or disallowed
+    /// shell or environment is an error.
+    pub fn new(
+        shell: Option<&str>,
+        cwd: Option<&str>,
+        env: BTreeMap<String, String>,
+        options: &TerminalOptions,
+    ) -> Result<Self, AgentError> {
+        let shell = resolve_shell(shell, options)?;
+        if !env.is_empty() && options.restrict_shell {
+            return Err(AgentError::ValidationError(
+                "Sessions may not set environment variables while the shell is restricted"
+                    .to_string(),
+            ));
+        }
+        if let Some(key) = env.keys().find(|key| {
+            key.is_empty() || key.contains(['=', '\0']) || env[*key].contains('\0')
+        }) {
+            return Err(AgentError::ValidationError(format!(
+                "Invalid environment variable {:?}",
+                key
+            )));
+        }
+        Ok(Self {
+            shell,
+            cwd: resolve_working_dir(cwd, options),
+            env,
+        })
+    }
+}
+
+/// Pick the shell a session runs. A requested shell must be an absolute path
+/// to an executable file, and one of the allowed shells when they are
+/// restricted. By default the first allowed shell runs when restricted, and
+/// bash or sh otherwise.
+fn resolve_shell(
+    requested: Option<&str>,
+    options: &TerminalOptions,
+) -> Result<PathBuf, AgentError> {
+    let Some(requested) = requested else {
+        if options.restrict_shell {
+            return options.allowed_shells.first().cloned().ok_or_else(|| {
+                AgentError::ConfigError("No allowed shells are configured".to_string())
+            });
+        }
+        let bash = Path::new("/bin/bash");
+        return Ok(if bash.exists() { bash } else { Path::new("/bin/sh") }.to_path_buf());
+    };
+
+    let shell = PathBuf::from(requested);
+    if options.restrict_shell && !options.allowed_shells.contains(&shell) {
+        return Err(AgentError::ValidationError(format!(
+            "Shell {} is not allowed",
+            requested
+        )));
+    }
+    if !shell.is_absolute() || !is_executable(&shell) {
+        return Err(AgentError::ValidationError(format!(
+            "Shell {} is not an absolute path to an executable",
+            requested
+        )));
+    }
+    Ok(shell)
+}
+
+fn is_executable(path: &Path) -> bool {
+    let Ok(metadata) = std::fs::metadata(path) else {
+        return false;
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+    }
+    #[cfg(not(unix))]
+    {
+        metadata.is_file()
+    }
+}
+
+/// PTY size for `cols` x `rows`, clamped to `MAX_COLS` x `MAX_ROWS`.
+fn pty_size(cols: u16, rows: u16) -> Result<PtySize, AgentError> {
+    if cols == 0 || rows == 0 {
+        return Err(AgentError::ValidationError(format!(
+            "Terminal size {}x{} must be non-zero",
+            cols, rows
+        )));
+    }
+    Ok(PtySize {
+        rows: rows.min(MAX_ROWS),
+        cols: cols.min(MAX_COLS),
+        pixel_width: 0,
+        pixel_height: 0,
+    })
+}
+
+/// Token bucket limiting a session's output rate, with one second of burst.
+struct OutputLimiter {
+    rate: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl OutputLimiter {
+    fn new(rate: u64, now: Instant) -> Self {
+        Self {
+            rate: rate as f64,
+            tokens: rate as f64,
+            last: now,
+        }
+    }
+
+    /// Charge `bytes` of output and return how long to pause reading
+    fn charge(&mut self, bytes: usize, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate) - bytes as f64;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+/// Per-session home directory, removed on drop.
+struct SessionHome {
+    path: PathBuf,
+}
+
+impl SessionHome {
+    fn create(session_id: &str, options: &TerminalOptions) -> Result<Self, AgentError> {
+        // Session IDs come from the server, so keep them to a single safe
+        // path component
+        let name: String = session_id
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+            .collect();
+        let path = options.working_dir.join(".sessions").join(name);
+        std::fs::create_dir_all(&path)?;
+        Ok(Self { path })
+    }
+}
+
+impl Drop for SessionHome {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_dir_all(&self.path) {
+            warn!("Failed to remove terminal home {:?}: {}", self.path, e);
+        }
+    }
+}
+
+/// Where a session's output goes: the relay connection it is attached to,
+/// or while detached, a buffer handed to the connection that attaches it
+/// next. The `terminal_closed` message is sent once, whether the shell exits
+/// by itself or the session is closed.
+struct Output {
+    session_id: String,
+    tx: Option<mpsc::UnboundedSender<Message>>,
+
+    /// Output produced while detached, up to `max_pending` bytes
+    pending: VecDeque<u8>,
+    max_pending: usize,
+
+    /// Output dropped from `pending` for lack of room
+    dropped_bytes: u64,
+
+    /// The session has ended
+    closed: bool,
+
+    /// `terminal_closed` was sent
+    closed_sent: bool,
+}
+
+impl Output {
+    fn new(session_id: String, tx: mpsc::UnboundedSender<Message>, max_pending: usize) -> Self {
+        Self {
+            session_id,
+            tx: Some(tx),
+            pending: VecDeque::new(),
+            max_pending,
+            dropped_bytes: 0,
+            closed: false,
+            closed_sent: false,
+        }
+    }
+
+    fn send(&self, msg: Message) -> bool {
+        self.tx.as_ref().is_some_and(|tx| tx.send(msg).is_ok())
+    }
+
+    fn output_message(&self, data: &[u8]) -> Message {
+        let header = serde_json::json!({
+            "type": "terminal_output",
+            "session_id": &self.session_id,
+        });
+        data_message(header, "data", data)
+    }
+
+    fn closed_message(&self) -> Message {
+        let msg = serde_json::json!({
+            "type": "terminal_closed",
+            "session_id": &self.session_id,
+        });
+        Message::Text(msg.to_string().into())
+    }
+
+    /// Forward `data`, or keep it for the next connection while detached
+    fn write(&mut self, data: &[u8]) {
+        if self.send(self.output_message(data)) {
+            return;
+        }
+        // Detached, or the connection is gone without detaching first
+        self.tx = None;
+        self.pending.extend(data);
+        let excess = self.pending.len().saturating_sub(self.max_pending);
+        self.pending.drain(..excess);
+        self.dropped_bytes += excess as u64;
+    }
+
+    /// Report that the session has ended, now or on attaching
+    fn close(&mut self) {
+        self.closed = true;
+        if !self.closed_sent {
+            self.closed_sent = self.send(self.closed_message());
+        }
+    }
+
+    /// Send output to `tx`, starting with `terminal_reattached` and what was
+    /// kept while detached
+    fn attach(&mut self, tx: mpsc::UnboundedSender<Message>) {
+        self.tx = Some(tx);
+        let reattached = serde_json::json!({
+            "type": "terminal_reattached",
+            "session_id": &self.session_id,
+            "dropped_bytes": self.dropped_bytes,
+        });
+        self.send(Message::Text(reattached.to_string().into()));
+        self.dropped_bytes = 0;
+        if !self.pending.is_empty() {
+            let pending: Vec<u8> = self.pending.drain(..).collect();
+            self.send(self.output_message(&pending));
+        }
+        if self.closed && !self.closed_sent {
+            self.closed_sent = self.send(self.closed_message());
+        }
+    }
+}
+
+/// When a session last had input or output.
+struct Activity {
+    started: Instant,
+    last_ms: AtomicU64,
+}
+
+impl Activity {
+    fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            last_ms: AtomicU64::new(0),
+        }
+    }
+
+    fn touch(&self) {
+        let ms = self.started.elapsed().as_millis() as u64;
+        self.last_ms.fetch_max(ms, Ordering::Relaxed);
+    }
+
+    fn idle_for(&self) -> Duration {
+        let last = Duration::from_millis(self.last_ms.load(Ordering::Relaxed));
+        self.started.elapsed().saturating_sub(last)
+    }
+}
+
+/// Send `signal` to the child, if it has a process ID.
+#[cfg(unix)]
+fn signal(child: &dyn Child, signal: libc::c_int) {
+    if let Some(pid) = child.process_id() {
+        // SAFETY: kill(2) has no memory safety requirements
+        unsafe {
+            libc::kill(pid as libc::pid_t, signal);
+        }
+    }
+}
+
+/// Ask the shell to exit, then kill it if it is still running after
+/// `grace`, and reap it. Returns whether it had to be killed.
+fn terminate(child: &mut dyn Child, grace: Duration) -> bool {
+    if matches!(child.try_wait(), Ok(Some(_))) {
+        return false;
+    }
+
+    #[cfg(unix)]
+    {
+        signal(child, libc::SIGHUP);
+        signal(child, libc::SIGTERM);
+        let deadline = Instant::now() + grace;
+        while Instant::now() < deadline {
+            if !matches!(child.try_wait(), Ok(None)) {
+                return false;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        signal(child, libc::SIGKILL);
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = grace;
+        let _ = child.kill();
+    }
+
+    let _ = child.wait();
+    true
+}
 
 /// An active terminal session backed by a PTY.
 pub struct TerminalSession {
+    session_id: String,
+
     /// Write end of the PTY master — protected by a mutex so it can be used
     /// from async context without blocking the executor.
     writer: Arc<std::sync::Mutex<Box<dyn std::io::Write + Send>>>,
+
+    /// Master side of the PTY, kept to resize it
+    master: Box<dyn MasterPty + Send>,
+
+    /// The shell
+    child: Box<dyn Child + Send + Sync>,
+
+    output: Arc<std::sync::Mutex<Output>>,
+
+    activity: Arc<Activity>,
+
+    created_at: DateTime<Utc>,
 }
 
 impl TerminalSession {
@@ -29,31 +520,38 @@ impl TerminalSession {
         session_id: String,
         cols: u16,
         rows: u16,
+        spec: &ShellSpec,
+        options: &TerminalOptions,
         tx: mpsc::UnboundedSender<Message>,
     ) -> Result<Self, AgentError> {
         let pty_system = native_pty_system();
 
         let pair = pty_system
-            .openpty(PtySize {
-                rows,
-                cols,
-                pixel_width: 0,
-                pixel_height: 0,
-            })
+            .openpty(pty_size(cols, rows)?)
             .map_err(|e| AgentError::Internal(format!("openpty failed: {e}")))?;
 
-        // Detect available shell
-        let shell = if std::path::Path::new("/bin/bash").exists() {
-            "/bin/bash"
+        let mut cmd = CommandBuilder::new(&spec.shell);
+        cmd.env_clear();
+        for (key, value) in options.child_env() {
+            cmd.env(key, value);
+        }
+        cmd.env("TERM", "xterm-256color");
+        for (key, value) in &spec.env {
+            cmd.env(key, value);
+        }
+        cmd.cwd(&spec.cwd);
+
+        let home = if options.isolate_home {
+            let home = SessionHome::create(&session_id, options)?;
+            cmd.env("HOME", &home.path);
+            cmd.env("HISTFILE", home.path.join(".history"));
+            Some(home)
         } else {
-            "/bin/sh"
+            None
         };
 
-        let mut cmd = CommandBuilder::new(shell);
-        cmd.env("TERM", "xterm-256color");
-
         // Spawn shell inside the slave PTY (slave is consumed here)
-        let _child = pair
+        let child = pair
             .slave
             .spawn_command(cmd)
             .map_err(|e| AgentError::Internal(format!("spawn_command failed: {e}")))?;
@@ -71,11 +569,25 @@ impl TerminalSession {
 
         let writer = Arc::new(std::sync::Mutex::new(writer));
 
+        let output = Arc::new(std::sync::Mutex::new(Output::new(
+            session_id.clone(),
+            tx,
+            options.reconnect_buffer,
+        )));
+
+        let activity = Arc::new(Activity::new());
+
         // Spawn a blocking thread to read PTY output and forward it
         let sid = session_id.clone();
+        let output_activity = activity.clone();
+        let session_output = output.clone();
+        let max_output_rate = options.max_output_rate;
         tokio::task::spawn_blocking(move || {
             let mut reader = reader;
             let mut buf = [0u8; 4096];
+            let mut limiter =
+                (max_output_rate > 0).then(|| OutputLimiter::new(max_output_rate, Instant::now()));
+            let mut throttled = false;
 
             info!("Terminal read loop started for session {}", sid);
 
@@ -83,16 +595,22 @@ impl TerminalSession {
                 match reader.read(&mut buf) {
                     Ok(0) => break,
                     Ok(n) => {
-                        let data = BASE64.encode(&buf[..n]);
-                        let msg = serde_json::json!({
-                            "type": "terminal_output",
-                            "session_id": &sid,
-                            "data": data,
-                        })
-                        .to_string();
-
-                        if tx.send(Message::Text(msg.into())).is_err() {
-                            break;
+                        output_activity.touch();
+                        session_output.lock().unwrap_or_else(|e| e.into_inner()).write(&buf[..n]);
+
+                        // Pausing the read lets the PTY buffer fill, which
+                        // blocks the writing process until we catch up
+                        let pause = limiter
+                            .as_mut()
+                            .map_or(Duration::ZERO, |l| l.charge(n, Instant::now()));
+                        if pause.is_zero() {
+                            throttled = false;
+                        } else {
+                            if !throttled {
+                                warn!("Terminal session {} output throttled", sid);
+                                throttled = true;
+                            }
+                            std::thread::sleep(pause);
                         }
                     }
                     Err(e) => {
@@ -103,17 +621,59 @@ impl TerminalSession {
             }
 
             // Notify the server that this session has ended
-            let close_msg = serde_json::json!({
-                "type": "terminal_closed",
-                "session_id": &sid,
-            })
-            .to_string();
-            let _ = tx.send(Message::Text(close_msg.into()));
+            session_output.lock().unwrap_or_else(|e| e.into_inner()).close();
 
+            drop(home);
             info!("Terminal read loop ended for session {}", sid);
         });
 
-        Ok(Self { writer })
+        Ok(Self {
+            session_id,
+            writer,
+            master: pair.master,
+            child,
+            output,
+            activity,
+            created_at: Utc::now(),
+        })
+    }
+
+    pub fn id(&self) -> &str {
+        &self.session_id
+    }
+
+    /// Read-only view of the session for listings.
+    pub fn info(&self) -> SessionInfo {
+        let size = self.master.get_size().ok();
+        let idle_for = chrono::Duration::from_std(self.idle_for()).unwrap_or_default();
+        SessionInfo {
+            session_id: self.session_id.clone(),
+            created_at: self.created_at,
+            cols: size.map_or(0, |size| size.cols),
+            rows: size.map_or(0, |size| size.rows),
+            last_activity_at: (Utc::now() - idle_for).max(self.created_at),
+            attached: self.output().tx.is_some(),
+        }
+    }
+
+    fn output(&self) -> std::sync::MutexGuard<'_, Output> {
+        self.output.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Keep output from here on for the next `attach`.
+    pub fn detach(&self) {
+        self.output().tx = None;
+    }
+
+    /// Send output through `tx` from here on, after what was kept while
+    /// detached.
+    pub fn attach(&self, tx: mpsc::UnboundedSender<Message>) {
+        self.output().attach(tx);
+    }
+
+    /// Time since the session last had input or output.
+    pub fn idle_for(&self) -> Duration {
+        self.activity.idle_for()
     }
 
     /// Write raw bytes (keystrokes) into the PTY.
@@ -123,8 +683,251 @@ impl TerminalSession {
             .writer
             .lock()
             .map_err(|_| AgentError::Internal("Terminal writer lock poisoned".into()))?;
+        self.activity.touch();
         writer.write_all(data)?;
         writer.flush()?;
         Ok(())
     }
+
+    /// Resize the PTY, clamped to `MAX_COLS` x `MAX_ROWS`. The shell gets
+    /// SIGWINCH so full-screen programs redraw.
+    pub fn resize(&self, cols: u16, rows: u16) -> Result<(), AgentError> {
+        self.activity.touch();
+        self.master
+            .resize(pty_size(cols, rows)?)
+            .map_err(|e| AgentError::Internal(format!("PTY resize failed: {e}")))
+    }
+
+    /// End the session: SIGHUP and SIGTERM the shell, SIGKILL it if it has
+    /// not exited after `grace`, and report `terminal_closed`.
+    pub async fn close(self, grace: Duration) {
+        let Self {
+            session_id,
+            writer,
+            master,
+            mut child,
+            output,
+            activity: _,
+            created_at: _,
+        } = self;
+        drop(writer);
+        drop(master);
+
+        let killed = tokio::task::spawn_blocking(move || terminate(child.as_mut(), grace)).await;
+        if matches!(killed, Ok(true)) {
+            warn!("Terminal session {} did not exit within {:?}; killed", session_id, grace);
+        }
+        output.lock().unwrap_or_else(|e| e.into_inner()).close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+
+    use crate::filesys::test_utils::TempFs;
+    use crate::models::relay::text_message;
+
+    fn options(fs: &TempFs) -> TerminalOptions {
+        TerminalOptions {
+            working_dir: fs.path("sandbox"),
+            isolate_home: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_output_limiter_pauses_over_budget() {
+        let start = Instant::now();
+        let mut limiter = OutputLimiter::new(1000, start);
+
+        // The first second's worth is a free burst
+        assert_eq!(limiter.charge(1000, start), Duration::ZERO);
+        assert_eq!(limiter.charge(500, start), Duration::from_millis(500));
+
+        // After pausing, the budget has refilled to zero debt
+        let resumed = start + Duration::from_millis(500);
+        assert_eq!(limiter.charge(100, resumed), Duration::from_millis(100));
+
+        // Idle time refills up to the burst, not beyond
+        let idle = resumed + Duration::from_secs(10);
+        assert_eq!(limiter.charge(1000, idle), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_output_is_kept_while_detached() {
+        let received = |rx: &mut mpsc::UnboundedReceiver<Message>| -> Vec<serde_json::Value> {
+            std::iter::from_fn(|| rx.try_recv().ok())
+                .map(|msg| serde_json::from_str(text_message(msg).unwrap().to_text().unwrap()))
+                .map(Result::unwrap)
+                .collect()
+        };
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut output = Output::new("s1".to_string(), tx, 4);
+        output.write(b"ab");
+        assert_eq!(received(&mut rx)[0]["data"], BASE64.encode("ab"));
+
+        // Only the newest output fits, and the end is reported on attaching
+        drop(rx);
+        output.write(b"cdef");
+        output.write(b"gh");
+        output.close();
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        output.attach(tx);
+        let messages = received(&mut rx);
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[0]["type"], "terminal_reattached");
+        assert_eq!(messages[0]["dropped_bytes"], 2);
+        assert_eq!(messages[1]["data"], BASE64.encode("efgh"));
+        assert_eq!(messages[2]["type"], "terminal_closed");
+
+        output.close();
+        output.attach(mpsc::unbounded_channel().0);
+        assert!(received(&mut rx).is_empty());
+    }
+
+    #[test]
+    fn test_env_is_scrubbed_to_allowlist() {
+        let vars = [
+            ("PATH", "/usr/bin"),
+            ("GHCR_TOKEN", "secret"),
+            ("AJIME_BACKEND_URL", "https://api"),
+            ("LANG", "C.UTF-8"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()));
+        let allowlist = vec!["PATH".to_string(), "LANG".to_string()];
+        let extra = BTreeMap::from([("LANG".to_string(), "en_GB.UTF-8".to_string())]);
+
+        let env = TerminalOptions::scrub(vars, &allowlist, &extra);
+        assert_eq!(
+            env,
+            BTreeMap::from([
+                ("LANG".to_string(), "en_GB.UTF-8".to_string()),
+                ("PATH".to_string(), "/usr/bin".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_working_dir_falls_back_to_sandbox() {
+        let fs = TempFs::new();
+        fs.mkdir("projects");
+        let options = options(&fs);
+
+        let projects = fs.path_str("projects");
+        assert_eq!(resolve_working_dir(Some(&projects), &options), fs.path("projects"));
+
+        let sandbox = fs.path("sandbox");
+        assert_eq!(resolve_working_dir(None, &options), sandbox);
+        assert!(sandbox.is_dir());
+
+        let traversal = format!("{}/../projects", sandbox.display());
+        assert_eq!(resolve_working_dir(Some(&traversal), &options), sandbox);
+        assert_eq!(resolve_working_dir(Some("projects"), &options), sandbox);
+        assert_eq!(resolve_working_dir(Some(&fs.path_str("missing")), &options), sandbox);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_shell_spec_validates_shell_and_env() {
+        let fs = TempFs::new();
+        fs.write("menu", b"#!/bin/sh\n");
+        let executable = std::os::unix::fs::PermissionsExt::from_mode(0o755);
+        std::fs::set_permissions(fs.path("menu"), executable).unwrap();
+        fs.write("notes.txt", b"");
+        let mut options = options(&fs);
+        let menu = fs.path_str("menu");
+        let env = BTreeMap::from([("MENU_MODE".to_string(), "diag".to_string())]);
+
+        let spec = ShellSpec::new(Some(&menu), None, env.clone(), &options).unwrap();
+        assert_eq!(spec.shell, fs.path("menu"));
+        assert_eq!(spec.cwd, fs.path("sandbox"));
+        assert!(ShellSpec::new(None, None, BTreeMap::new(), &options).is_ok());
+        for shell in ["menu", "/nonexistent/sh", fs.path_str("notes.txt").as_str()] {
+            assert!(ShellSpec::new(Some(shell), None, BTreeMap::new(), &options).is_err());
+        }
+        let bad_env = BTreeMap::from([("A=B".to_string(), "x".to_string())]);
+        assert!(ShellSpec::new(None, None, bad_env, &options).is_err());
+
+        // Restricted sessions get the first allowed shell and no extra env
+        options.restrict_shell = true;
+        assert!(options.validate().is_err());
+        options.allowed_shells = vec![fs.path("menu")];
+        options.validate().unwrap();
+        let spec = ShellSpec::new(None, None, BTreeMap::new(), &options).unwrap();
+        assert_eq!(spec.shell, fs.path("menu"));
+        assert!(ShellSpec::new(Some("/bin/sh"), None, BTreeMap::new(), &options).is_err());
+        assert!(ShellSpec::new(Some(&menu), None, env, &options).is_err());
+    }
+
+    #[test]
+    fn test_session_home_is_removed_on_drop() {
+        let fs = TempFs::new();
+        let home = SessionHome::create("../escape", &options(&fs)).unwrap();
+        let path = home.path.clone();
+        assert_eq!(path, fs.path("sandbox/.sessions/___escape"));
+        assert!(path.is_dir());
+
+        drop(home);
+        assert!(!path.exists());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_resize_is_validated_and_clamped() {
+        let fs = TempFs::new();
+        let options = options(&fs);
+        let spec = ShellSpec::new(None, None, BTreeMap::new(), &options).unwrap();
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let session = TerminalSession::new("s1".to_string(), 80, 24, &spec, &options, tx).unwrap();
+        let size = |session: &TerminalSession| {
+            let size = session.master.get_size().unwrap();
+            (size.cols, size.rows)
+        };
+
+        session.resize(132, 43).unwrap();
+        assert_eq!(size(&session), (132, 43));
+        session.resize(u16::MAX, u16::MAX).unwrap();
+        assert_eq!(size(&session), (MAX_COLS, MAX_ROWS));
+        assert!(matches!(session.resize(0, 43), Err(AgentError::ValidationError(_))));
+        assert_eq!(size(&session), (MAX_COLS, MAX_ROWS));
+
+        session.close(Duration::from_millis(100)).await;
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_close_kills_a_shell_ignoring_signals() {
+        let fs = TempFs::new();
+        let options = options(&fs);
+        let spec = ShellSpec::new(None, None, BTreeMap::new(), &options).unwrap();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let session = TerminalSession::new("s1".to_string(), 80, 24, &spec, &options, tx).unwrap();
+        let pid = session.child.process_id().unwrap();
+
+        // Wait until the shell ignores SIGHUP and SIGTERM
+        session.write_input(b"trap '' HUP TERM; echo tr''apped\n").unwrap();
+        let mut output = String::new();
+        while !output.contains("trapped") {
+            let msg = tokio::time::timeout(Duration::from_secs(5), rx.recv()).await.unwrap();
+            let msg = text_message(msg.unwrap()).unwrap();
+            let msg: serde_json::Value = serde_json::from_str(msg.to_text().unwrap()).unwrap();
+            let data = BASE64.decode(msg["data"].as_str().unwrap()).unwrap();
+            output.push_str(&String::from_utf8_lossy(&data));
+        }
+
+        session.close(Duration::from_millis(100)).await;
+        // SAFETY: signal 0 only checks that the process exists
+        assert_ne!(unsafe { libc::kill(pid as libc::pid_t, 0) }, 0, "shell was not reaped");
+
+        let mut closed = 0;
+        while let Ok(Some(msg)) = tokio::time::timeout(Duration::from_secs(5), rx.recv()).await {
+            let is_closed = matches!(msg, Message::Text(text) if text.contains("terminal_closed"));
+            closed += is_closed as usize;
+        }
+        assert_eq!(closed, 1);
+    }
 }
@@ -0,0 +1,343 @@
+//! Registry of the open terminal sessions.
+//!
+//! Sessions belong to the relay connection that created them and are closed
+//! with it, so each connection keeps its own map. The registry holds the map
+//! of the current connection, letting the local API and relay commands list
+//! the open sessions and force-close one.
+//!
+//! With a reconnect grace period, the sessions of a dropped connection are
+//! parked instead: detached from it, buffering their output, until the next
+//! connection re-attaches them. Sessions still parked when the grace period
+//! ends, or when the relay worker stops, are closed.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use futures::future::join_all;
+use serde::Serialize;
+use tokio::sync::{mpsc, Mutex};
+use tokio_tungstenite::tungstenite::protocol::Message;
+use tokio_util::sync::{CancellationToken, DropGuard};
+use tracing::info;
+
+use crate::terminal::{TerminalOptions, TerminalSession};
+
+/// Terminal sessions of one relay connection: session_id -> TerminalSession.
+pub type Sessions = Arc<Mutex<HashMap<String, TerminalSession>>>;
+
+/// What an open session looks like from outside.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SessionInfo {
+    pub session_id: String,
+    pub created_at: DateTime<Utc>,
+    pub cols: u16,
+    pub rows: u16,
+
+    /// Last input, output or resize
+    pub last_activity_at: DateTime<Utc>,
+
+    /// Attached to a relay connection; parked sessions wait for one
+    pub attached: bool,
+}
+
+/// The session map of the current relay connection.
+struct Connection {
+    sessions: Sessions,
+    close_grace: Duration,
+}
+
+/// A session of a dropped connection, waiting for the next one.
+struct Parked {
+    session: TerminalSession,
+    until: Instant,
+    close_grace: Duration,
+}
+
+/// Open terminal sessions, shared between the relay worker and the local API.
+#[derive(Default)]
+pub struct SessionRegistry {
+    current: std::sync::Mutex<Option<Connection>>,
+
+    /// Parked sessions: session_id -> session
+    parked: Arc<std::sync::Mutex<HashMap<String, Parked>>>,
+
+    /// Cancelled when the relay worker stops; parked sessions are closed
+    stopped: CancellationToken,
+}
+
+impl SessionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start the session map of a new relay connection sending through `tx`,
+    /// re-attaching the parked sessions. The previous connection's other
+    /// sessions are closed with that connection, not here.
+    pub fn connect(&self, close_grace: Duration, tx: &mpsc::UnboundedSender<Message>) -> Sessions {
+        let parked: Vec<(String, Parked)> =
+            self.parked.lock().unwrap_or_else(|e| e.into_inner()).drain().collect();
+        let mut reattached = HashMap::new();
+        for (session_id, parked) in parked {
+            parked.session.attach(tx.clone());
+            info!("Terminal session re-attached: {}", session_id);
+            reattached.insert(session_id, parked.session);
+        }
+
+        let sessions: Sessions = Arc::new(Mutex::new(reattached));
+        *self.current.lock().unwrap_or_else(|e| e.into_inner()) = Some(Connection {
+            sessions: Arc::clone(&sessions),
+            close_grace,
+        });
+        sessions
+    }
+
+    fn current(&self) -> Option<(Sessions, Duration)> {
+        let current = self.current.lock().unwrap_or_else(|e| e.into_inner());
+        current
+            .as_ref()
+            .map(|connection| (Arc::clone(&connection.sessions), connection.close_grace))
+    }
+
+    /// Park the sessions left in `sessions` by a dropped connection for
+    /// `options.reconnect_grace`, after which those not re-attached are
+    /// closed. Without a grace period they stay, to be closed with the
+    /// connection.
+    pub async fn park(&self, sessions: &Sessions, options: &TerminalOptions) {
+        let window = options.reconnect_grace;
+        if window.is_zero() {
+            return;
+        }
+        let open: Vec<(String, TerminalSession)> = sessions.lock().await.drain().collect();
+        if open.is_empty() {
+            return;
+        }
+        info!("Keeping {} terminal session(s) for {:?} for a reconnect", open.len(), window);
+
+        let until = Instant::now() + window;
+        let close_grace = options.close_grace;
+        {
+            let mut parked = self.parked.lock().unwrap_or_else(|e| e.into_inner());
+            for (session_id, session) in open {
+                session.detach();
+                let parked_session = Parked {
+                    session,
+                    until,
+                    close_grace,
+                };
+                if let Some(replaced) = parked.insert(session_id, parked_session) {
+                    tokio::spawn(replaced.session.close(close_grace));
+                }
+            }
+        }
+
+        let parked = Arc::clone(&self.parked);
+        let stopped = self.stopped.clone();
+        tokio::spawn(async move {
+            let stopping = tokio::select! {
+                _ = stopped.cancelled() => true,
+                _ = tokio::time::sleep(window) => false,
+            };
+            let expired: Vec<Parked> = {
+                let mut parked = parked.lock().unwrap_or_else(|e| e.into_inner());
+                let now = Instant::now();
+                let ids: Vec<String> = parked
+                    .iter()
+                    .filter(|(_, parked)| stopping || parked.until <= now)
+                    .map(|(id, _)| id.clone())
+                    .collect();
+                ids.iter().filter_map(|id| parked.remove(id)).collect()
+            };
+            if !expired.is_empty() {
+                info!("Closing {} terminal session(s) not re-attached", expired.len());
+            }
+            join_all(expired.into_iter().map(|parked| parked.session.close(parked.close_grace)))
+                .await;
+        });
+    }
+
+    /// Close the parked sessions, and those parked later right away, once
+    /// the returned guard is dropped with the relay worker.
+    pub fn stop_on_drop(&self) -> DropGuard {
+        self.stopped.clone().drop_guard()
+    }
+
+    /// Open sessions, oldest first; parked ones included.
+    pub async fn list(&self) -> Vec<SessionInfo> {
+        let mut list = match self.current() {
+            Some((sessions, _)) => list_sessions(&sessions).await,
+            None => Vec::new(),
+        };
+        let parked = self.parked.lock().unwrap_or_else(|e| e.into_inner());
+        list.extend(parked.values().map(|parked| parked.session.info()));
+        sort(&mut list);
+        list
+    }
+
+    /// Close session `session_id` in the background. Returns whether it was
+    /// open.
+    pub async fn close(&self, session_id: &str) -> bool {
+        let parked = self.parked.lock().unwrap_or_else(|e| e.into_inner()).remove(session_id);
+        let (session, grace) = match (parked, self.current()) {
+            (Some(parked), _) => (parked.session, parked.close_grace),
+            (None, Some((sessions, grace))) => match sessions.lock().await.remove(session_id) {
+                Some(session) => (session, grace),
+                None => return false,
+            },
+            (None, None) => return false,
+        };
+        let session_id = session_id.to_string();
+        tokio::spawn(async move {
+            session.close(grace).await;
+            info!("Terminal session force-closed: {}", session_id);
+        });
+        true
+    }
+}
+
+/// The sessions in `sessions`, oldest first.
+pub async fn list_sessions(sessions: &Sessions) -> Vec<SessionInfo> {
+    let mut list: Vec<SessionInfo> =
+        sessions.lock().await.values().map(TerminalSession::info).collect();
+    sort(&mut list);
+    list
+}
+
+fn sort(list: &mut [SessionInfo]) {
+    list.sort_by(|a, b| (a.created_at, &a.session_id).cmp(&(b.created_at, &b.session_id)));
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+
+    use super::*;
+    use crate::filesys::test_utils::TempFs;
+    use crate::models::relay::text_message;
+    use crate::terminal::{ShellSpec, TerminalOptions};
+
+    #[tokio::test]
+    async fn test_sessions_of_the_current_connection_are_listed_and_closed() {
+        let fs = TempFs::new();
+        let options = TerminalOptions {
+            working_dir: fs.path("sandbox"),
+            ..Default::default()
+        };
+        let spec = ShellSpec::new(None, None, BTreeMap::new(), &options).unwrap();
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+
+        let registry = SessionRegistry::new();
+        assert!(registry.list().await.is_empty());
+        assert!(!registry.close("s1").await);
+
+        let sessions = registry.connect(Duration::from_millis(100), &tx);
+        for (id, cols) in [("s1", 80), ("s2", 132)] {
+            let session =
+                TerminalSession::new(id.to_string(), cols, 24, &spec, &options, tx.clone())
+                    .unwrap();
+            sessions.lock().await.insert(id.to_string(), session);
+        }
+
+        let list = registry.list().await;
+        let ids: Vec<&str> = list.iter().map(|info| info.session_id.as_str()).collect();
+        assert_eq!(ids, ["s1", "s2"]);
+        assert_eq!((list[1].cols, list[1].rows), (132, 24));
+        assert!(list[0].last_activity_at >= list[0].created_at);
+
+        assert!(registry.close("s1").await);
+        assert!(!registry.close("s1").await);
+        assert_eq!(registry.list().await.len(), 1);
+
+        // A new connection starts empty
+        let old = sessions;
+        registry.connect(Duration::from_millis(100), &tx);
+        assert!(registry.list().await.is_empty());
+        for (_, session) in old.lock().await.drain() {
+            session.close(Duration::from_millis(100)).await;
+        }
+    }
+
+    /// Text of the `terminal_output` messages in `messages`
+    fn output_of(messages: &[serde_json::Value]) -> String {
+        messages
+            .iter()
+            .filter(|msg| msg["type"] == "terminal_output")
+            .map(|msg| BASE64.decode(msg["data"].as_str().unwrap()).unwrap())
+            .map(|data| String::from_utf8_lossy(&data).into_owned())
+            .collect()
+    }
+
+    /// A message in its JSON text form
+    fn json(msg: Message) -> serde_json::Value {
+        serde_json::from_str(text_message(msg).unwrap().to_text().unwrap()).unwrap()
+    }
+
+    /// Messages received so far, as JSON
+    fn received(rx: &mut mpsc::UnboundedReceiver<Message>) -> Vec<serde_json::Value> {
+        std::iter::from_fn(|| rx.try_recv().ok()).map(json).collect()
+    }
+
+    #[tokio::test]
+    async fn test_parked_sessions_are_reattached_or_closed() {
+        let fs = TempFs::new();
+        let mut options = TerminalOptions {
+            working_dir: fs.path("sandbox"),
+            close_grace: Duration::from_millis(100),
+            reconnect_grace: Duration::from_secs(5),
+            ..Default::default()
+        };
+        let spec = ShellSpec::new(None, None, BTreeMap::new(), &options).unwrap();
+        let registry = SessionRegistry::new();
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let sessions = registry.connect(options.close_grace, &tx);
+        let session =
+            TerminalSession::new("s1".to_string(), 80, 24, &spec, &options, tx.clone()).unwrap();
+        session.write_input(b"echo rea''dy; sleep 0.3; echo par''ked\n").unwrap();
+        sessions.lock().await.insert("s1".to_string(), session);
+        let mut output = String::new();
+        while !output.contains("ready") {
+            let msg = tokio::time::timeout(Duration::from_secs(5), rx.recv()).await.unwrap();
+            output += &output_of(&[json(msg.unwrap())]);
+        }
+
+        // The connection drops; the shell keeps running while parked
+        drop((tx, rx));
+        registry.park(&sessions, &options).await;
+        assert!(sessions.lock().await.is_empty());
+        let list = registry.list().await;
+        assert_eq!(list.len(), 1);
+        assert!(!list[0].attached);
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        // The next connection gets it back with the output it missed
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let sessions = registry.connect(options.close_grace, &tx);
+        assert!(sessions.lock().await.contains_key("s1"));
+        assert!(registry.list().await[0].attached);
+        let messages = received(&mut rx);
+        assert_eq!(messages[0]["type"], "terminal_reattached");
+        assert_eq!(messages[0]["dropped_bytes"], 0);
+        assert!(output_of(&messages).contains("parked"), "{:?}", messages);
+
+        // Not re-attached within the grace period, it is closed
+        options.reconnect_grace = Duration::from_millis(200);
+        registry.park(&sessions, &options).await;
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert!(registry.list().await.is_empty());
+        assert!(registry.connect(options.close_grace, &tx).lock().await.is_empty());
+
+        // Once the relay worker stops, sessions are not kept
+        let session =
+            TerminalSession::new("s2".to_string(), 80, 24, &spec, &options, tx.clone()).unwrap();
+        let sessions = registry.connect(options.close_grace, &tx);
+        sessions.lock().await.insert("s2".to_string(), session);
+        drop(registry.stop_on_drop());
+        registry.park(&sessions, &options).await;
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(registry.list().await.is_empty());
+    }
+}
@@ -3,6 +3,8 @@
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
 
+use crate::errors::AgentError;
+
 /// Version information for the agent
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VersionInfo {
@@ -38,6 +40,25 @@ impl Default for CooldownOptions {
     }
 }
 
+impl CooldownOptions {
+    /// Reject options whose delay would shrink or exceed its cap from the start
+    pub fn validate(&self) -> Result<(), AgentError> {
+        if self.base_delay > self.max_delay {
+            return Err(AgentError::ConfigError(format!(
+                "Cooldown base delay {:?} exceeds the max delay {:?}",
+                self.base_delay, self.max_delay
+            )));
+        }
+        if !(self.multiplier >= 1.0 && self.multiplier.is_finite()) {
+            return Err(AgentError::ConfigError(format!(
+                "Cooldown multiplier must be at least 1, got {}",
+                self.multiplier
+            )));
+        }
+        Ok(())
+    }
+}
+
 /// Calculate exponential backoff delay
 pub fn calc_exp_backoff(options: &CooldownOptions, attempt: u32) -> Duration {
     let delay_secs = options.base_delay.as_secs_f64() * options.multiplier.powi(attempt as i32);
@@ -45,6 +66,23 @@ pub fn calc_exp_backoff(options: &CooldownOptions, attempt: u32) -> Duration {
     Duration::from_secs_f64(capped_delay)
 }
 
+/// Random delay in [0, max), in whole milliseconds, to spread out retries
+/// that would otherwise happen at the same moment across a fleet
+pub fn jitter(max: Duration) -> Duration {
+    let max_ms = max.as_millis().min(u64::MAX as u128) as u64;
+    if max_ms == 0 {
+        return Duration::ZERO;
+    }
+    use std::time::{SystemTime, UNIX_EPOCH};
+    let seed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos() as u64;
+    Duration::from_millis(
+        (seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407) >> 33) % max_ms,
+    )
+}
+
 /// Generate a random UUID v4
 pub fn generate_uuid() -> String {
     uuid::Uuid::new_v4().to_string()
@@ -74,32 +112,15 @@ mod hex {
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_exp_backoff() {
-        let options = CooldownOptions::default();
-        
-        assert_eq!(calc_exp_backoff(&options, 0), Duration::from_secs(1));
-        assert_eq!(calc_exp_backoff(&options, 1), Duration::from_secs(2));
-        assert_eq!(calc_exp_backoff(&options, 2), Duration::from_secs(4));
-        assert_eq!(calc_exp_backoff(&options, 10), Duration::from_secs(300)); // Capped at max
-    }
-
-    #[test]
-    fn test_sha256_hash() {
-        let hash = sha256_hash(b"hello world");
-        assert_eq!(hash.len(), 64);
-    }
-}
-
 /// Run diagnostics on the agent
 pub async fn run_diagnostic() {
+    use crate::app::options::ServerOptions;
+    use crate::clock::{ntp_synced, skew_from_date};
+    use crate::deploy::capabilities::DeployCapabilities;
+    use crate::deploy::docker::MIN_DOCKER_VERSION;
     use crate::storage::layout::StorageLayout;
     use crate::storage::device::Device;
-    use crate::storage::settings::Settings;
+    use crate::storage::settings::{ClockSettings, Settings};
     use colored::*;
 
     println!("{}", "=== Ajime Agent Diagnostic ===".bold().cyan());
@@ -134,8 +155,13 @@ pub async fn run_diagnostic() {
         }
     };
 
+    // Filled in from the backend's Date header when it is reachable
+    let mut clock_skew = None;
+    let mut max_skew_secs = ClockSettings::default().max_skew_secs;
+
     if let (Some(device), Some(settings)) = (device, settings) {
         println!("\n{}", "--- Connectivity ---".bold());
+        max_skew_secs = settings.clock.max_skew_secs;
         
         let backend_url = &settings.backend.base_url;
         println!("Backend URL: {}", backend_url);
@@ -148,8 +174,14 @@ pub async fn run_diagnostic() {
             .build()
             .unwrap();
 
+        let sent = chrono::Utc::now();
         match client.get(backend_url.trim_end_matches("/api/v1")).send().await {
             Ok(resp) => {
+                clock_skew = resp
+                    .headers()
+                    .get(reqwest::header::DATE)
+                    .and_then(|date| date.to_str().ok())
+                    .and_then(|date| skew_from_date(date, sent, chrono::Utc::now()));
                 if resp.status().is_success() {
                     println!("{}", "OK".green());
                 } else {
@@ -191,9 +223,136 @@ pub async fn run_diagnostic() {
                 println!("{} ({})", "FAILED".red(), e);
             }
         }
+
+        // 5. Relay transport, as reported by the running agent
+        println!("Relay transport (configured): {}", settings.relay.transport.as_str());
+        print!("Checking active relay transport... ");
+        let server = ServerOptions::default();
+        let health_url = format!("http://{}:{}/health", server.host, server.port);
+        match client.get(&health_url).send().await {
+            Ok(resp) => {
+                let body: serde_json::Value = resp.json().await.unwrap_or_default();
+                let relay = body["components"]
+                    .as_array()
+                    .and_then(|c| c.iter().find(|c| c["name"] == "relay"));
+                match relay.and_then(|r| r["message"].as_str()) {
+                    Some(message) => println!("{} ({})", "OK".green(), message),
+                    None => println!("{} (relay not connected)", "WARNING".yellow()),
+                }
+            },
+            Err(_) => {
+                println!("{} (agent is not running)", "UNKNOWN".yellow());
+            }
+        }
     } else {
         println!("\n{}", "Cannot proceed with connectivity tests due to missing configuration.".yellow());
     }
 
+    // 6. Check the clock
+    println!("\n{}", "--- Clock ---".bold());
+    print!("Checking NTP synchronization... ");
+    match ntp_synced().await {
+        Some(true) => println!("{}", "OK".green()),
+        Some(false) => println!("{} (tokens and TLS may fail)", "NOT SYNCED".red().bold()),
+        None => println!("{} (cannot tell without systemd)", "UNKNOWN".yellow()),
+    }
+
+    print!("Comparing the clock with the backend... ");
+    match clock_skew {
+        Some(skew) if skew.unsigned_abs() > max_skew_secs => println!(
+            "{} ({}s off, tolerance {}s)",
+            "SKEWED".red().bold(),
+            skew,
+            max_skew_secs
+        ),
+        Some(skew) => println!("{} ({}s off)", "OK".green(), skew),
+        None => println!("{} (backend not reached or sent no date)", "UNKNOWN".yellow()),
+    }
+
+    // 7. Check deployment tooling
+    println!("\n{}", "--- Deployment ---".bold());
+    let capabilities = DeployCapabilities::probe().await;
+
+    let docker = &capabilities.docker;
+    print!("Checking docker CLI... ");
+    match (docker.installed, docker.client_version.as_deref()) {
+        (true, Some(version)) => println!("{} ({})", "OK".green(), version),
+        (true, None) => println!("{}", "OK".green()),
+        (false, _) => println!("{} (docker deployments will fail)", "MISSING".red()),
+    }
+
+    if docker.installed {
+        print!("Checking Docker daemon... ");
+        if let Some(version) = docker.server_version.as_deref() {
+            match docker.meets_minimum_version() {
+                Some(false) => println!(
+                    "{} (version {} is below the minimum {}.{})",
+                    "WARNING".yellow(),
+                    version,
+                    MIN_DOCKER_VERSION.0,
+                    MIN_DOCKER_VERSION.1
+                ),
+                _ => println!("{} (version {})", "OK".green(), version),
+            }
+        } else if docker.permission_denied {
+            println!(
+                "{} (permission denied on the Docker socket; add this user to the docker group)",
+                "FAILED".red()
+            );
+        } else {
+            println!(
+                "{} ({})",
+                "UNREACHABLE".red(),
+                docker.error.as_deref().unwrap_or("daemon did not respond")
+            );
+        }
+    }
+
+    print!("Checking Docker Compose... ");
+    match capabilities.compose {
+        Some(compose) => println!("{} ({})", "OK".green(), compose.as_str()),
+        None => println!("{} (compose deployments will fail)", "MISSING".red()),
+    }
+
     println!("\n{}", "==============================".bold().cyan());
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exp_backoff() {
+        let options = CooldownOptions::default();
+        
+        assert_eq!(calc_exp_backoff(&options, 0), Duration::from_secs(1));
+        assert_eq!(calc_exp_backoff(&options, 1), Duration::from_secs(2));
+        assert_eq!(calc_exp_backoff(&options, 2), Duration::from_secs(4));
+        assert_eq!(calc_exp_backoff(&options, 10), Duration::from_secs(300)); // Capped at max
+    }
+
+    #[test]
+    fn test_cooldown_validation() {
+        assert!(CooldownOptions::default().validate().is_ok());
+
+        let inverted = CooldownOptions {
+            base_delay: Duration::from_secs(600),
+            ..Default::default()
+        };
+        assert!(inverted.validate().is_err());
+
+        for multiplier in [0.5, f64::NAN] {
+            let shrinking = CooldownOptions {
+                multiplier,
+                ..Default::default()
+            };
+            assert!(shrinking.validate().is_err());
+        }
+    }
+
+    #[test]
+    fn test_sha256_hash() {
+        let hash = sha256_hash(b"hello world");
+        assert_eq!(hash.len(), 64);
+    }
+}
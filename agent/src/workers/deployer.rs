@@ -1,38 +1,124 @@
 //! Deployment worker for orchestration
 
 use std::future::Future;
+use std::path::Path;
 use std::pin::Pin;
 use std::time::Duration;
 use std::sync::Arc;
 
-use tracing::{debug, error, info};
+use tokio::sync::{mpsc, Notify};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
 
+use tracing::{debug, error, info, warn};
+
+use crate::app::drain::DrainState;
 use crate::errors::AgentError;
 use crate::http::client::HttpClient;
+use crate::http::retry::is_retryable_status;
 use crate::authn::token_mngr::{TokenManager, TokenManagerExt};
 use crate::models::deployment::{Deployment, DeploymentStatusUpdate, DeploymentLog};
 use crate::deploy::{docker, git, compose};
+use crate::deploy::docker::DockerImage;
+use crate::deploy::git::GitDeployment;
+use crate::deploy::env_file::EnvFile;
+use crate::deploy::output::LogSink;
+use crate::deploy::fsm::{DeploymentEvent, DeploymentFsm, FsmSettings};
+use crate::deploy::ledger::{DeploymentLedger, DeploymentOutcome};
+use crate::deploy::limits::ResourceLimits;
+use crate::storage::space::StorageMonitor;
 
 /// Deployer worker options
 #[derive(Debug, Clone)]
 pub struct Options {
-    /// Polling interval
+    /// Polling interval when idle
     pub interval: Duration,
+
+    /// Polling interval while a deployment is expected
+    pub fast_interval: Duration,
+
+    /// How long to poll at `fast_interval` after a trigger
+    pub fast_window: Duration,
+
+    /// How long a new container is watched before a Docker deployment
+    /// counts as successful; zero skips the check
+    pub container_verify_timeout: Duration,
+
+    /// Limits of deployments that do not set their own
+    pub resource_limits: ResourceLimits,
+
+    /// How long one deployment attempt may run before it fails as timed out
+    pub attempt_timeout: Duration,
 }
 
 impl Default for Options {
     fn default() -> Self {
         Self {
             interval: Duration::from_secs(10),
+            fast_interval: Duration::from_secs(2),
+            fast_window: Duration::from_secs(60),
+            container_verify_timeout: Duration::from_secs(10),
+            resource_limits: ResourceLimits::default(),
+            attempt_timeout: Duration::from_secs(30 * 60),
         }
     }
 }
 
-/// Run the deployer worker
+/// What the deployer works with
+pub struct DeployerContext {
+    pub http_client: Arc<HttpClient>,
+    pub token_mngr: Arc<TokenManager>,
+    pub ledger: Arc<DeploymentLedger>,
+    pub drain: Arc<DrainState>,
+    pub trigger: Arc<DeployTrigger>,
+    pub storage: Arc<StorageMonitor>,
+}
+
+/// Signal telling the deployer a deployment is pending
+#[derive(Default)]
+pub struct DeployTrigger {
+    notify: Notify,
+    pending: std::sync::Mutex<Vec<String>>,
+}
+
+impl DeployTrigger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Make the deployer poll now. A trigger sent while the deployer is busy
+    /// is kept until its next wait.
+    pub fn trigger(&self, deployment_id: Option<&str>) {
+        if let Some(id) = deployment_id {
+            self.pending
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .push(id.to_string());
+        }
+        self.notify.notify_one();
+    }
+
+    /// Wait for a trigger and return the deployment IDs pushed since the last one
+    async fn triggered(&self) -> Vec<String> {
+        self.notify.notified().await;
+        std::mem::take(&mut *self.pending.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+/// Polling interval given when the fast window (if any) ends
+fn poll_interval(options: &Options, fast_until: Option<Instant>) -> Duration {
+    match fast_until {
+        Some(until) if Instant::now() < until => options.fast_interval,
+        _ => options.interval,
+    }
+}
+
+/// Run the deployer worker, retrying failed deployments as `fsm_settings`
+/// allow
 pub async fn run<S, F>(
     options: &Options,
-    http_client: Arc<HttpClient>,
-    token_mngr: Arc<TokenManager>,
+    fsm_settings: &FsmSettings,
+    context: &DeployerContext,
     sleep_fn: S,
     mut shutdown_signal: Pin<Box<dyn Future<Output = ()> + Send>>,
 ) where
@@ -40,6 +126,20 @@ pub async fn run<S, F>(
     F: Future<Output = ()>,
 {
     info!("Deployer worker starting...");
+    let DeployerContext {
+        http_client,
+        token_mngr,
+        ledger,
+        drain,
+        trigger,
+        storage,
+    } = context;
+
+    // Poll quickly until this instant after a trigger
+    let mut fast_until: Option<Instant> = None;
+
+    // Deployment IDs pushed in real time and not yet picked up
+    let mut pushed: Vec<String> = Vec::new();
 
     loop {
         // Check for shutdown
@@ -48,9 +148,33 @@ pub async fn run<S, F>(
                 info!("Deployer worker shutting down...");
                 return;
             }
-            _ = sleep_fn(options.interval) => {
+            _ = sleep_fn(poll_interval(options, fast_until)) => {
                 // Continue with check
             }
+            ids = trigger.triggered() => {
+                if ids.is_empty() {
+                    info!("Deployment trigger received, polling now");
+                } else {
+                    info!("Deployment trigger received for {}, polling now", ids.join(", "));
+                }
+                pushed.extend(ids);
+                fast_until = Some(Instant::now() + options.fast_window);
+            }
+        }
+
+        // Forget pushed IDs that never showed up within the fast window
+        if fast_until.is_some_and(|until| Instant::now() >= until) {
+            pushed.clear();
+        }
+
+        if drain.is_draining() {
+            debug!("Draining: not picking up new deployments");
+            continue;
+        }
+
+        if storage.is_full() {
+            debug!("Storage full: not picking up new deployments");
+            continue;
         }
 
         let device_id: String = match token_mngr.get_device_id().await {
@@ -76,6 +200,30 @@ pub async fn run<S, F>(
                 // #endregion
                 
                 for deployment in deployments {
+                    if let Some(i) = pushed.iter().position(|id| *id == deployment.id) {
+                        info!("Picked up pushed deployment {}", pushed.swap_remove(i));
+                    }
+
+                    // Already ran (e.g. before a crash): report instead of re-running
+                    if let Some(entry) = ledger.completed(&deployment).await {
+                        info!(
+                            "Deployment {} already processed ({}), reporting recorded status",
+                            deployment.id,
+                            entry.outcome.as_status()
+                        );
+                        let _ = http_client.update_deployment_status(&deployment.id, &token, DeploymentStatusUpdate {
+                            status: entry.outcome.as_status().to_string(),
+                            error_message: entry.error_message,
+                            attempt: None,
+                            max_attempts: None,
+                        }).await;
+                        continue;
+                    }
+
+                    let Some(_work) = drain.begin_work() else {
+                        info!("Draining: leaving deployment {} pending", deployment.id);
+                        continue;
+                    };
                     info!("Received deployment task: {} ({})", deployment.id, deployment.deployment_type);
                     
                     // #region agent log
@@ -85,7 +233,21 @@ pub async fn run<S, F>(
                     });
                     // #endregion
                     
-                    if let Err(e) = execute_deployment(deployment, http_client.clone(), &token).await {
+                    let result = execute_deployment(
+                        options,
+                        fsm_settings,
+                        context,
+                        deployment,
+                        &token,
+                        &sleep_fn,
+                        &mut shutdown_signal,
+                    )
+                    .await;
+                    if let Err(AgentError::ShutdownError(_)) = result {
+                        info!("Deployer worker shutting down...");
+                        return;
+                    }
+                    if let Err(e) = result {
                         error!("Deployment failed: {}", e);
                         // #region agent log
                         let _ = std::fs::OpenOptions::new().create(true).append(true).open(r"c:\Users\shach\Desktop\Projects\Ajime\.cursor\debug.log").and_then(|mut f| {
@@ -103,33 +265,168 @@ pub async fn run<S, F>(
     }
 }
 
-async fn execute_deployment(
-    deployment: Deployment, 
-    http_client: Arc<HttpClient>, 
-    token: &str
-) -> Result<(), AgentError> {
+async fn execute_deployment<S, F>(
+    options: &Options,
+    fsm_settings: &FsmSettings,
+    context: &DeployerContext,
+    deployment: Deployment,
+    token: &str,
+    sleep_fn: &S,
+    shutdown_signal: &mut Pin<Box<dyn Future<Output = ()> + Send>>,
+) -> Result<(), AgentError>
+where
+    S: Fn(Duration) -> F,
+    F: Future<Output = ()>,
+{
+    let http_client = &context.http_client;
+    let ledger = context.ledger.as_ref();
     let id = deployment.id.clone();
 
-    // 1. Mark as in_progress
-    let _ = http_client.update_deployment_status(&id, token, DeploymentStatusUpdate {
-        status: "in_progress".to_string(),
-        error_message: None,
-    }).await;
+    // 0. Record the attempt so a crash mid-deployment is detectable
+    if ledger.get(&id).await.is_some_and(|e| e.outcome == DeploymentOutcome::InProgress) {
+        warn!("Deployment {} was interrupted by a restart, running it again", id);
+    }
+    if let Err(e) = ledger.record(&id, DeploymentOutcome::InProgress, None).await {
+        warn!("Failed to record deployment {} in the ledger: {}", id, e);
+    }
 
-    // 2. Stream initial log
-    let _ = http_client.send_deployment_log(&id, token, DeploymentLog {
-        level: "info".to_string(),
-        message: format!("Starting {} deployment...", deployment.deployment_type),
-    }).await;
+    let max_attempts = fsm_settings.retry_count.saturating_add(1);
+    let mut fsm = DeploymentFsm::new();
+    let mut attempt = 0;
+    let result = loop {
+        attempt += 1;
+        let _ = fsm.process(DeploymentEvent::Deploy);
+
+        // 1. Mark as in_progress, with the attempt so retries are visible
+        let _ = http_client.update_deployment_status(&id, token, DeploymentStatusUpdate {
+            status: "in_progress".to_string(),
+            error_message: None,
+            attempt: Some(attempt),
+            max_attempts: Some(max_attempts),
+        }).await;
 
-    // 3. Execute based on type
-    let result = match deployment.deployment_type.as_str() {
+        // 2. Stream initial log
+        let _ = http_client.send_deployment_log(&id, token, DeploymentLog {
+            level: "info".to_string(),
+            message: format!(
+                "Starting {} deployment (attempt {} of {})...",
+                deployment.deployment_type, attempt, max_attempts
+            ),
+        }).await;
+
+        // 3. Execute based on type, relaying command output as it is printed.
+        // The timeout runs on the worker's sleep, which also tells the
+        // watchdog how long the attempt may take.
+        let (log, forwarder) = deployment_log_sink(http_client.clone(), &id, token);
+        let result = tokio::select! {
+            result = run_deployment(options, &deployment, http_client, ledger, token, &log) => {
+                result
+            }
+            _ = sleep_fn(options.attempt_timeout) => Err(AgentError::Timeout(format!(
+                "Deployment attempt timed out after {:?}",
+                options.attempt_timeout
+            ))),
+        };
+        drop(log);
+        let _ = forwarder.await;
+        match result {
+            Ok(()) => {
+                let _ = fsm.process(DeploymentEvent::DeploySuccess);
+                break Ok(());
+            }
+            Err(e) => {
+                let _ = fsm.process(DeploymentEvent::DeployFailed(e.to_string()));
+                if !is_retryable(&e) || !fsm.can_retry(max_attempts) {
+                    break Err(e);
+                }
+                warn!("Deployment {} attempt {} of {} failed: {}", id, attempt, max_attempts, e);
+                let retry_delay = fsm_settings.next_retry_delay();
+                let _ = http_client.send_deployment_log(&id, token, DeploymentLog {
+                    level: "warn".to_string(),
+                    message: format!(
+                        "Attempt {} of {} failed: {}; retrying in {:?}",
+                        attempt, max_attempts, e, retry_delay
+                    ),
+                }).await;
+                // Left in progress in the ledger, so it runs again after a restart
+                tokio::select! {
+                    _ = shutdown_signal.as_mut() => {
+                        return Err(AgentError::ShutdownError(format!(
+                            "Shut down before retrying deployment {}",
+                            id
+                        )));
+                    }
+                    _ = sleep_fn(retry_delay) => {}
+                }
+            }
+        }
+    };
+
+    // 4. Record the outcome before reporting it, so it is not re-run if
+    // the report is lost
+    let (outcome, error_message) = match &result {
+        Ok(_) => (DeploymentOutcome::Success, None),
+        Err(e) => (DeploymentOutcome::Failed, Some(e.to_string())),
+    };
+    if let Err(e) = ledger.record(&id, outcome, error_message).await {
+        warn!("Failed to record deployment {} in the ledger: {}", id, e);
+    }
+
+    // 5. Update final status
+    match result {
+        Ok(_) => {
+            let _ = http_client.update_deployment_status(&id, token, DeploymentStatusUpdate {
+                status: "success".to_string(),
+                error_message: None,
+                attempt: Some(attempt),
+                max_attempts: Some(max_attempts),
+            }).await;
+            
+            let _ = http_client.send_deployment_log(&id, token, DeploymentLog {
+                level: "info".to_string(),
+                message: "Deployment completed successfully!".to_string(),
+            }).await;
+            Ok(())
+        }
+        Err(e) => {
+            let _ = http_client.update_deployment_status(&id, token, DeploymentStatusUpdate {
+                status: "failed".to_string(),
+                error_message: Some(e.to_string()),
+                attempt: Some(attempt),
+                max_attempts: Some(max_attempts),
+            }).await;
+            
+            let _ = http_client.send_deployment_log(&id, token, DeploymentLog {
+                level: "error".to_string(),
+                message: format!("Deployment failed: {}", e),
+            }).await;
+            Err(e)
+        }
+    }
+}
+
+/// Run the type-specific deployment steps once
+async fn run_deployment(
+    options: &Options,
+    deployment: &Deployment,
+    http_client: &HttpClient,
+    ledger: &DeploymentLedger,
+    token: &str,
+    log: &LogSink,
+) -> Result<(), AgentError> {
+    let id = &deployment.id;
+    match deployment.deployment_type.as_str() {
         "docker" => {
             let image = deployment.config.get("image").and_then(|v| v.as_str()).unwrap_or("");
             let tag = deployment.config.get("tag").and_then(|v| v.as_str()).unwrap_or("latest");
             let registry_token = deployment.config.get("registry_token").and_then(|v| v.as_str()).map(|s| s.to_string());
             let registry_username = deployment.config.get("registry_username").and_then(|v| v.as_str()).map(|s| s.to_string());
-            docker::deploy_docker(image, tag, registry_token, registry_username).await
+            let digest = deployment.config.get("digest").and_then(|v| v.as_str());
+            let limits = ResourceLimits::from_config(&deployment.config, &options.resource_limits)?;
+            let container = deployment_container(ledger, id).await;
+            let image = DockerImage { image, tag, digest, registry_token, registry_username };
+            docker::deploy_docker(&container, image, options.container_verify_timeout, &limits, log)
+                .await
         }
         "git" => {
             let repo_url = deployment.config.get("repo_url").and_then(|v| v.as_str()).unwrap_or("");
@@ -137,11 +434,25 @@ async fn execute_deployment(
             let install_cmd = deployment.config.get("install_cmd").and_then(|v| v.as_str()).unwrap_or("");
             let run_cmd = deployment.config.get("run_cmd").and_then(|v| v.as_str()).unwrap_or("");
             let target_dir = format!("/etc/ajime/deployments/{}", deployment.id);
-            git::deploy_git(repo_url, branch, install_cmd, run_cmd, &target_dir).await
+            let env_file = EnvFile::from_config(&deployment.config)?;
+            let limits = ResourceLimits::from_config(&deployment.config, &options.resource_limits)?;
+            let git_deployment = GitDeployment {
+                repo_url,
+                branch,
+                install_cmd,
+                run_cmd,
+                target_dir: &target_dir,
+                env_file: env_file.as_ref(),
+            };
+            let result = git::deploy_git(id, &git_deployment, &limits, log).await;
+            remove_env_file_on_failure(result, env_file.is_some(), &target_dir).await
         }
         "docker_compose" => {
             let target_dir = format!("/etc/ajime/deployments/{}", deployment.id);
-            compose::deploy_compose(&target_dir).await
+            let env_file = EnvFile::from_config(&deployment.config)?;
+            write_env_file(env_file.as_ref(), &target_dir, http_client, id, token).await?;
+            let result = compose::deploy_compose(&target_dir, log).await;
+            remove_env_file_on_failure(result, env_file.is_some(), &target_dir).await
         }
         "docker_build" => {
             // Ajime-managed build: pull pre-built image from GHCR and deploy
@@ -152,14 +463,19 @@ async fn execute_deployment(
             }
 
             // Send log
-            let _ = http_client.send_deployment_log(&id, token, DeploymentLog {
+            let _ = http_client.send_deployment_log(id, token, DeploymentLog {
                 level: "info".to_string(),
                 message: format!("Pulling pre-built image: {}", image),
             }).await;
 
             let registry_token = deployment.config.get("registry_token").and_then(|v| v.as_str()).map(|s| s.to_string());
             let registry_username = deployment.config.get("registry_username").and_then(|v| v.as_str()).map(|s| s.to_string());
-            docker::deploy_docker(image, "", registry_token, registry_username).await
+            let digest = deployment.config.get("digest").and_then(|v| v.as_str());
+            let limits = ResourceLimits::from_config(&deployment.config, &options.resource_limits)?;
+            let container = deployment_container(ledger, id).await;
+            let image = DockerImage { image, tag: "", digest, registry_token, registry_username };
+            docker::deploy_docker(&container, image, options.container_verify_timeout, &limits, log)
+                .await
         }
         "git_compose" => {
             // Unified workflow deployment: git sync + docker-compose
@@ -172,51 +488,148 @@ async fn execute_deployment(
             }
             
             // Log git sync start
-            let _ = http_client.send_deployment_log(&id, token, DeploymentLog {
+            let _ = http_client.send_deployment_log(id, token, DeploymentLog {
                 level: "info".to_string(),
                 message: format!("Syncing repository {} (branch: {}) to {}", repo_url, branch, project_dir),
             }).await;
             
             // Execute git sync
-            git::sync_repository(repo_url, branch, project_dir).await?;
+            git::sync_repository(repo_url, branch, project_dir, log).await?;
+
+            let env_file = EnvFile::from_config(&deployment.config)?;
+            write_env_file(env_file.as_ref(), project_dir, http_client, id, token).await?;
             
             // Log compose start
-            let _ = http_client.send_deployment_log(&id, token, DeploymentLog {
+            let _ = http_client.send_deployment_log(id, token, DeploymentLog {
                 level: "info".to_string(),
                 message: format!("Running docker-compose in {}", project_dir),
             }).await;
             
             // Execute docker-compose
-            compose::deploy_compose(project_dir).await
+            let result = compose::deploy_compose(project_dir, log).await;
+            remove_env_file_on_failure(result, env_file.is_some(), project_dir).await
         }
         _ => Err(AgentError::DeployError(format!("Unsupported deployment type: {}", deployment.deployment_type))),
+    }
+}
+
+/// A log sink relaying lines to the backend as deployment logs, in order,
+/// and the task doing so. The task ends once every clone of the sink is
+/// dropped and the queued lines are sent.
+fn deployment_log_sink(
+    http_client: Arc<HttpClient>,
+    id: &str,
+    token: &str,
+) -> (LogSink, JoinHandle<()>) {
+    let (tx, mut rx) = mpsc::unbounded_channel::<DeploymentLog>();
+    let (id, token) = (id.to_string(), token.to_string());
+    let forwarder = tokio::spawn(async move {
+        while let Some(log) = rx.recv().await {
+            let _ = http_client.send_deployment_log(&id, &token, log).await;
+        }
+    });
+    let sink: LogSink = Arc::new(move |log| {
+        let _ = tx.send(log);
+    });
+    (sink, forwarder)
+}
+
+/// Write a deployment's env file into `dir`, logging how many variables it
+/// sets but never their values. A deployment without one deletes the file
+/// an earlier deployment left there.
+async fn write_env_file(
+    env_file: Option<&EnvFile>,
+    dir: &str,
+    http_client: &HttpClient,
+    id: &str,
+    token: &str,
+) -> Result<(), AgentError> {
+    let Some(env_file) = env_file else {
+        return EnvFile::remove(Path::new(dir)).await;
     };
+    env_file.write(Path::new(dir)).await?;
+    let _ = http_client.send_deployment_log(id, token, DeploymentLog {
+        level: "info".to_string(),
+        message: format!("Wrote environment file ({} variables)", env_file.variables()),
+    }).await;
+    Ok(())
+}
 
-    // 4. Update final status
-    match result {
-        Ok(_) => {
-            let _ = http_client.update_deployment_status(&id, token, DeploymentStatusUpdate {
-                status: "success".to_string(),
-                error_message: None,
-            }).await;
-            
-            let _ = http_client.send_deployment_log(&id, token, DeploymentLog {
-                level: "info".to_string(),
-                message: "Deployment completed successfully!".to_string(),
-            }).await;
-            Ok(())
+/// Delete the env file of a failed deployment, so secrets do not linger in
+/// a directory nothing runs from
+async fn remove_env_file_on_failure(
+    result: Result<(), AgentError>,
+    written: bool,
+    dir: &str,
+) -> Result<(), AgentError> {
+    if written && result.is_err() {
+        if let Err(e) = EnvFile::remove(Path::new(dir)).await {
+            warn!("Failed to remove environment file from {}: {}", dir, e);
         }
-        Err(e) => {
-            let _ = http_client.update_deployment_status(&id, token, DeploymentStatusUpdate {
-                status: "failed".to_string(),
-                error_message: Some(e.to_string()),
-            }).await;
-            
-            let _ = http_client.send_deployment_log(&id, token, DeploymentLog {
-                level: "error".to_string(),
-                message: format!("Deployment failed: {}", e),
-            }).await;
-            Err(e)
+    }
+    result
+}
+
+/// Container of a Docker deployment: the one recorded in the ledger, or a
+/// new one recorded before it is created so it can be found after a crash
+async fn deployment_container(ledger: &DeploymentLedger, deployment_id: &str) -> String {
+    if let Some(container) = ledger.container(deployment_id).await {
+        return container;
+    }
+    let container = docker::container_name(deployment_id);
+    if let Err(e) = ledger.set_container(deployment_id, &container).await {
+        warn!("Failed to record container of deployment {}: {}", deployment_id, e);
+    }
+    container
+}
+
+/// Configuration errors fail the same way on every attempt
+fn is_retryable(error: &AgentError) -> bool {
+    match error {
+        AgentError::ConfigError(_) => false,
+        // The backend gives the same answer to a request it rejected
+        AgentError::HttpStatus { status, .. } => {
+            reqwest::StatusCode::from_u16(*status).is_ok_and(is_retryable_status)
         }
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_poll_interval_is_fast_within_window() {
+        let options = Options::default();
+        assert_eq!(poll_interval(&options, None), options.interval);
+
+        let now = Instant::now();
+        assert_eq!(poll_interval(&options, Some(now + options.fast_window)), options.fast_interval);
+        assert_eq!(poll_interval(&options, Some(now)), options.interval);
+    }
+
+    #[test]
+    fn test_config_errors_are_not_retried() {
+        assert!(!is_retryable(&AgentError::ConfigError("no image".to_string())));
+        assert!(is_retryable(&AgentError::DeployError("pull failed".to_string())));
+        let status = |status| AgentError::HttpStatus { status, error: None };
+        assert!(!is_retryable(&status(404)));
+        assert!(is_retryable(&status(503)));
+        assert!(is_retryable(&status(429)));
+    }
+
+    #[tokio::test]
+    async fn test_trigger_collects_pushed_ids() {
+        let trigger = DeployTrigger::new();
+        trigger.trigger(Some("d1"));
+        trigger.trigger(None);
+        trigger.trigger(Some("d2"));
+
+        // Triggers sent before the deployer waits are not lost
+        let ids = tokio::time::timeout(Duration::from_secs(1), trigger.triggered())
+            .await
+            .unwrap();
+        assert_eq!(ids, vec!["d1".to_string(), "d2".to_string()]);
     }
 }
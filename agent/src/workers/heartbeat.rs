@@ -0,0 +1,193 @@
+//! Heartbeat worker keeping the backend device record fresh
+//!
+//! Other workers only touch the device record as a side effect of their own
+//! requests, so an idle device would drift towards "last seen hours ago".
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use tracing::{debug, info, warn};
+
+use crate::authn::token_mngr::{TokenManager, TokenManagerExt};
+use crate::clock::ClockMonitor;
+use crate::deploy::capabilities::CapabilityManifest;
+use crate::errors::AgentError;
+use crate::filesys::file::File;
+use crate::health::{HealthRegistry, HealthStatus};
+use crate::http::client::HttpClient;
+use crate::http::devices::DeviceHeartbeat;
+use crate::storage::device::load_device;
+use crate::storage::label::DeviceLabel;
+use crate::storage::space::StorageMonitor;
+
+/// Name of the heartbeat worker in the health registry
+const HEALTH_COMPONENT: &str = "heartbeat";
+
+/// MQTT worker's health component; a healthy one has published capabilities
+const MQTT_COMPONENT: &str = "mqtt";
+
+/// Heartbeat worker options
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Interval between heartbeats
+    pub interval: Duration,
+
+    /// Initial delay before the first heartbeat
+    pub initial_delay: Duration,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(300),
+            initial_delay: Duration::from_secs(10),
+        }
+    }
+}
+
+/// What the heartbeat reports
+pub struct HeartbeatContext {
+    pub http_client: Arc<HttpClient>,
+    pub token_mngr: Arc<TokenManager>,
+    pub device_file: Arc<File>,
+    pub device_label: Arc<DeviceLabel>,
+    pub capabilities: Arc<CapabilityManifest>,
+    pub health: Arc<HealthRegistry>,
+    pub storage: Arc<StorageMonitor>,
+    pub clock: Arc<ClockMonitor>,
+}
+
+/// Run the heartbeat worker
+pub async fn run<S, F>(
+    options: &Options,
+    context: &HeartbeatContext,
+    sleep_fn: S,
+    mut shutdown_signal: Pin<Box<dyn Future<Output = ()> + Send>>,
+) where
+    S: Fn(Duration) -> F,
+    F: Future<Output = ()>,
+{
+    info!("Heartbeat worker starting...");
+
+    let mut wait = options.initial_delay;
+    loop {
+        tokio::select! {
+            _ = &mut shutdown_signal => {
+                info!("Heartbeat worker shutting down...");
+                return;
+            }
+            _ = sleep_fn(wait) => {}
+        }
+        wait = options.interval;
+
+        match beat(context).await {
+            Ok(()) => {
+                debug!("Heartbeat sent");
+                context.health.set_healthy(HEALTH_COMPONENT);
+            }
+            Err(e) => {
+                warn!("Heartbeat failed: {}", e);
+                context
+                    .health
+                    .set_degraded(HEALTH_COMPONENT, format!("Heartbeat failed: {}", e));
+            }
+        }
+    }
+}
+
+/// Send one heartbeat
+async fn beat(context: &HeartbeatContext) -> Result<(), AgentError> {
+    let token = context.token_mngr.get_token().await?;
+    let device = load_device(&context.device_file).await?;
+
+    // A connected MQTT worker has already published the retained capability
+    // manifest, so only send capabilities when it has not
+    let mqtt_connected = context
+        .health
+        .get(MQTT_COMPONENT)
+        .is_some_and(|mqtt| mqtt.status == HealthStatus::Healthy);
+    let clock = context.clock.status();
+
+    let heartbeat = DeviceHeartbeat {
+        last_seen: Utc::now(),
+        agent_version: context.capabilities.agent_version.clone(),
+        label: context.device_label.get(),
+        capabilities: (!mqtt_connected).then(|| context.capabilities.names()),
+        metadata: device.metadata,
+        storage_full: context.storage.is_full(),
+        clock_skew_secs: clock.skew_secs,
+        clock_unsynced: clock.ntp_synced == Some(false),
+    };
+    context
+        .http_client
+        .send_heartbeat(token.device_id(), &token.raw, &heartbeat)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::authn::test_utils::token_manager;
+    use crate::filesys::test_utils::TempFs;
+    use crate::http::client::HttpClientOptions;
+    use crate::storage::device::save_device;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+    async fn context(fs: &TempFs, backend: &str) -> HeartbeatContext {
+        let token_mngr = token_manager(fs, backend, "secret").await;
+        let device_file = Arc::new(File::new(fs.path("device.json")));
+        let mut device = load_device(&device_file).await.unwrap();
+        device.metadata = serde_json::json!({ "location": "lab" });
+        save_device(&device_file, &device).await.unwrap();
+
+        let options = HttpClientOptions::without_retries();
+        HeartbeatContext {
+            token_mngr,
+            http_client: Arc::new(HttpClient::new(backend, options).await.unwrap()),
+            device_file,
+            device_label: Arc::new(DeviceLabel::new(File::new(fs.path("settings.json")), None)),
+            capabilities: Arc::new(CapabilityManifest {
+                agent_version: "1.2.3".to_string(),
+                terminal: true,
+                ..Default::default()
+            }),
+            health: Arc::new(HealthRegistry::new()),
+            storage: Arc::new(StorageMonitor::new(fs.path(""), Default::default())),
+            clock: Arc::new(ClockMonitor::new(Default::default())),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_skips_capabilities_while_mqtt_is_connected() {
+        let server = MockServer::start().await;
+        Mock::given(method("PATCH"))
+            .and(path("/agent/devices/device-1"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({})))
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let fs = TempFs::new();
+        let context = context(&fs, &server.uri()).await;
+        beat(&context).await.unwrap();
+        context.health.set_healthy(MQTT_COMPONENT);
+        beat(&context).await.unwrap();
+
+        let requests: Vec<Request> = server.received_requests().await.unwrap();
+        let requests: Vec<&Request> = requests.iter().filter(|r| r.method == "PATCH").collect();
+        let first: serde_json::Value = requests[0].body_json().unwrap();
+        assert_eq!(first["agent_version"], "1.2.3");
+        assert_eq!(first["capabilities"], serde_json::json!(["terminal"]));
+        assert_eq!(first["metadata"]["location"], "lab");
+        assert!(first["last_seen"].is_string());
+        assert!(first.get("storage_full").is_none());
+        assert!(first.get("clock_skew_secs").is_none());
+
+        let second: serde_json::Value = requests[1].body_json().unwrap();
+        assert!(second.get("capabilities").is_none());
+    }
+}
@@ -4,4 +4,5 @@ pub mod mqtt;
 pub mod poller;
 pub mod token_refresh;
 pub mod deployer;
-pub mod relay;
\ No newline at end of file
+pub mod relay;
+pub mod heartbeat;
\ No newline at end of file
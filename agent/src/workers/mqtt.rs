@@ -2,15 +2,19 @@
 
 use std::future::Future;
 use std::pin::Pin;
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use tracing::{debug, error, info, warn};
 
 use crate::authn::token_mngr::TokenManagerExt;
-use crate::filesys::file::File;
-use crate::mqtt::client::{MqttAddress, MqttClient, MqttCommand};
+use crate::deploy::capabilities::CapabilityManifest;
+use crate::errors::AgentError;
+use crate::health::HealthRegistry;
+use crate::mqtt::client::{load_root_certs, ClientIdOptions, MqttAddress, MqttClient, MqttCommand};
 use crate::mqtt::topics::Topics;
 use crate::sync::syncer::Syncer;
+use crate::utils::sha256_hash;
 
 /// MQTT worker options
 #[derive(Debug, Clone)]
@@ -26,6 +30,23 @@ pub struct Options {
 
     /// Status publish interval
     pub status_interval: Duration,
+
+    /// Client ID configuration
+    pub client_id: ClientIdOptions,
+
+    /// Connections shorter than this count as a flap
+    pub flap_threshold: Duration,
+
+    /// Consecutive flaps before warning about a duplicate client ID
+    pub max_flaps: u32,
+
+    /// Consecutive auth failures (each followed by a token refresh) before
+    /// the worker reports itself as degraded
+    pub max_auth_failures: u32,
+
+    /// How often the broker CA file is checked for changes; zero disables
+    /// the check
+    pub ca_reload_interval: Duration,
 }
 
 impl Default for Options {
@@ -35,16 +56,30 @@ impl Default for Options {
             reconnect_delay: Duration::from_secs(5),
             max_reconnect_attempts: 10,
             status_interval: Duration::from_secs(60),
+            client_id: ClientIdOptions::default(),
+            flap_threshold: Duration::from_secs(10),
+            max_flaps: 3,
+            max_auth_failures: 3,
+            ca_reload_interval: Duration::from_secs(60),
         }
     }
 }
 
+/// Name of the MQTT worker in the health registry
+const HEALTH_COMPONENT: &str = "mqtt";
+
+/// What the MQTT worker works with
+pub struct MqttContext {
+    pub syncer: Arc<Syncer>,
+    pub health: Arc<HealthRegistry>,
+    pub capabilities: Arc<CapabilityManifest>,
+}
+
 /// Run the MQTT worker
 pub async fn run<S, T, F>(
     options: &Options,
     token_mngr: &T,
-    syncer: &Syncer,
-    _device_file: &File,
+    context: &MqttContext,
     sleep_fn: S,
     _shutdown_signal: Pin<Box<dyn Future<Output = ()> + Send>>,
 ) where
@@ -52,6 +87,8 @@ pub async fn run<S, T, F>(
     F: Future<Output = ()>,
     T: TokenManagerExt,
 {
+    let (syncer, health, capabilities) =
+        (context.syncer.as_ref(), context.health.as_ref(), context.capabilities.as_ref());
     if options.broker_address.host.is_empty() {
         info!("MQTT host not configured, MQTT worker will not start.");
         return;
@@ -60,6 +97,10 @@ pub async fn run<S, T, F>(
     info!("MQTT worker starting...");
 
     let mut reconnect_attempts = 0;
+    let mut client_id: Option<String> = None;
+    let mut flaps = FlapDetector::new(options.flap_threshold, options.max_flaps);
+    let mut auth_failures: u32 = 0;
+    let mut ca_watcher = CaWatcher::new(&options.broker_address);
 
     loop {
         // Check for shutdown
@@ -78,7 +119,7 @@ pub async fn run<S, T, F>(
             }
         };
 
-        let token = match token_mngr.get_token().await {
+        let token = match token_mngr.get_valid_token().await {
             Ok(t) => t,
             Err(e) => {
                 error!("Failed to get token: {}", e);
@@ -87,9 +128,21 @@ pub async fn run<S, T, F>(
             }
         };
 
+        // Keep the same client ID across reconnects so the broker replaces our
+        // own stale session instead of treating it as a second client
+        let client_id = client_id
+            .get_or_insert_with(|| options.client_id.client_id(&device_id))
+            .clone();
+
         // Connect to MQTT broker
-        info!("Connecting to MQTT broker: {}:{}", options.broker_address.host, options.broker_address.port);
-        let mut client = match MqttClient::new(&options.broker_address, &device_id, &token.raw).await {
+        info!(
+            "Connecting to MQTT broker: {}:{} (client ID: {})",
+            options.broker_address.host, options.broker_address.port, client_id
+        );
+        // Taken before the client reads the file, so a change made while
+        // connecting is still noticed
+        ca_watcher.mark_applied();
+        let mut client = match MqttClient::new(&options.broker_address, &client_id, &device_id, &token.raw).await {
             Ok(c) => c,
             Err(e) => {
                 error!("Failed to create MQTT client: {}", e);
@@ -110,18 +163,37 @@ pub async fn run<S, T, F>(
             continue;
         }
 
+        if let Err(e) = client.publish_capabilities(capabilities).await {
+            warn!("Failed to publish capabilities: {}", e);
+        }
+
         reconnect_attempts = 0;
         info!("MQTT worker connected and subscribed");
+        let connected_at = Instant::now();
 
         // Main event loop
+        let mut authenticated = false;
+        let mut ca_checked_at = Instant::now();
         loop {
-            match client.poll().await {
+            let mut ca_check_requested = false;
+            let event = client.poll().await;
+            if event.is_ok() && !authenticated {
+                // The first successful event is the broker's ConnAck
+                authenticated = true;
+                if auth_failures > 0 {
+                    info!("MQTT authentication recovered after {} failures", auth_failures);
+                }
+                auth_failures = 0;
+                health.set_healthy(HEALTH_COMPONENT);
+            }
+
+            match event {
                 Ok(Some(msg)) => {
                     debug!("Received MQTT message on topic: {}", msg.topic);
                     
                     if Topics::is_command_topic(&msg.topic) {
                         if let Ok(command) = msg.parse_json::<MqttCommand>() {
-                            handle_command(&command, syncer).await;
+                            ca_check_requested = handle_command(&command, syncer).await;
                         }
                     } else if Topics::is_control_topic(&msg.topic) {
                         if let Some(workflow_id) = Topics::parse_workflow_id(&msg.topic) {
@@ -134,8 +206,43 @@ pub async fn run<S, T, F>(
                 Ok(None) => {
                     // No message, continue
                 }
+                Err(AgentError::AuthError(e)) => {
+                    auth_failures += 1;
+                    error!(
+                        "MQTT authentication failed ({} consecutive): {}",
+                        auth_failures, e
+                    );
+                    if auth_failures >= options.max_auth_failures {
+                        error!("MQTT credentials still rejected after token refresh, marking MQTT as degraded");
+                        health.set_degraded(
+                            HEALTH_COMPONENT,
+                            format!("Broker rejected credentials {} times in a row", auth_failures),
+                        );
+                    }
+
+                    // Retrying with the same token is pointless; refresh first
+                    match token_mngr.refresh_token().await {
+                        Ok(_) => info!("Token refreshed after MQTT auth failure"),
+                        Err(e) => error!("Token refresh after MQTT auth failure failed: {}", e),
+                    }
+                    break;
+                }
                 Err(e) => {
-                    warn!("MQTT poll error: {}, reconnecting...", e);
+                    warn!("MQTT network error: {}, reconnecting...", e);
+                    break;
+                }
+            }
+
+            let ca_check_due = !options.ca_reload_interval.is_zero()
+                && ca_checked_at.elapsed() >= options.ca_reload_interval;
+            if ca_check_requested || ca_check_due {
+                ca_checked_at = Instant::now();
+                if ca_watcher.changed() {
+                    // The new CA loaded, so the reconnect will not strand us
+                    // with a config that cannot verify the broker
+                    if let Err(e) = client.disconnect().await {
+                        warn!("Failed to disconnect before applying new CA: {}", e);
+                    }
                     break;
                 }
             }
@@ -144,12 +251,118 @@ pub async fn run<S, T, F>(
             sleep_fn(Duration::from_millis(10)).await;
         }
 
+        // Only sessions the broker accepted can be kicked by a duplicate client ID
+        if authenticated && flaps.record(connected_at.elapsed()) {
+            warn!(
+                "MQTT connection dropped {} times in a row within {:?} of connecting. \
+                 Another client is likely using the same client ID ({}); \
+                 check for cloned devices or set a unique client ID suffix.",
+                flaps.streak(), options.flap_threshold, client_id
+            );
+        }
+
         // Reconnect delay
         sleep_fn(options.reconnect_delay).await;
     }
 }
 
-async fn handle_command(command: &MqttCommand, syncer: &Syncer) {
+/// Detects rapid disconnect/reconnect cycles, the typical symptom of two
+/// clients fighting over the same client ID
+struct FlapDetector {
+    threshold: Duration,
+    max_flaps: u32,
+    streak: u32,
+}
+
+impl FlapDetector {
+    fn new(threshold: Duration, max_flaps: u32) -> Self {
+        Self {
+            threshold,
+            max_flaps: max_flaps.max(1),
+            streak: 0,
+        }
+    }
+
+    /// Record how long a connection lasted. Returns true when the flap streak
+    /// reaches a multiple of `max_flaps`, so the warning repeats but does not spam.
+    fn record(&mut self, connected_for: Duration) -> bool {
+        if connected_for < self.threshold {
+            self.streak += 1;
+            self.streak % self.max_flaps == 0
+        } else {
+            self.streak = 0;
+            false
+        }
+    }
+
+    fn streak(&self) -> u32 {
+        self.streak
+    }
+}
+
+/// Watches the broker CA file so a rotated certificate is applied without a
+/// restart. A changed file is only applied once it loads; until then the
+/// existing connection is kept.
+struct CaWatcher {
+    path: Option<String>,
+    /// Digest of the file the current connection was built from
+    applied: Option<String>,
+    /// Digest of the file as last checked, so a bad file is reported once
+    seen: Option<String>,
+}
+
+impl CaWatcher {
+    fn new(address: &MqttAddress) -> Self {
+        Self {
+            path: address.ca_cert_path.clone().filter(|_| address.use_tls),
+            applied: None,
+            seen: None,
+        }
+    }
+
+    /// Record the file as it is now as the one in use
+    fn mark_applied(&mut self) {
+        self.applied = self.digest();
+        self.seen = self.applied.clone();
+    }
+
+    /// True when the file changed since it was applied and the new
+    /// certificate loads
+    fn changed(&mut self) -> bool {
+        let Some(path) = &self.path else {
+            return false;
+        };
+        let digest = self.digest();
+        if digest == self.seen {
+            return false;
+        }
+        self.seen = digest.clone();
+        if digest == self.applied {
+            info!("CA certificate {} restored to the one in use", path);
+            return false;
+        }
+
+        match load_root_certs(Some(path)) {
+            Ok(_) => {
+                info!("CA certificate {} changed, reconnecting to apply it", path);
+                true
+            }
+            Err(e) => {
+                warn!("Ignoring changed CA certificate, keeping current connection: {}", e);
+                false
+            }
+        }
+    }
+
+    fn digest(&self) -> Option<String> {
+        let contents = std::fs::read(self.path.as_ref()?).ok()?;
+        Some(sha256_hash(&contents))
+    }
+}
+
+/// Handle a device command. Returns true when connection settings such as
+/// the broker CA should be re-checked right away.
+async fn handle_command(command: &MqttCommand, syncer: &Syncer) -> bool {
     info!("Handling command: {}", command.command);
 
     match command.command.as_str() {
@@ -158,17 +371,20 @@ async fn handle_command(command: &MqttCommand, syncer: &Syncer) {
             if let Err(e) = syncer.trigger_sync().await {
                 error!("Sync failed: {}", e);
             }
+            false
         }
         "restart" => {
             info!("Restart command received");
             // In production, this would trigger a graceful restart
+            false
         }
         "update_settings" => {
-            info!("Update settings command received");
-            // Handle settings update
+            info!("Update settings command received, re-checking broker CA");
+            true
         }
         _ => {
             warn!("Unknown command: {}", command.command);
+            false
         }
     }
 }
@@ -198,3 +414,56 @@ async fn handle_workflow_control(workflow_id: &str, command: &MqttCommand, _sync
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesys::test_utils::TempFs;
+    use crate::mqtt::client::tests::TEST_CA_PEM;
+
+    #[test]
+    fn test_flap_detector() {
+        let mut flaps = FlapDetector::new(Duration::from_secs(10), 3);
+        assert!(!flaps.record(Duration::from_secs(1)));
+        assert!(!flaps.record(Duration::from_secs(1)));
+        assert!(flaps.record(Duration::from_secs(1)));
+
+        // A stable connection resets the streak
+        assert!(!flaps.record(Duration::from_secs(60)));
+        assert_eq!(flaps.streak(), 0);
+    }
+
+    #[test]
+    fn test_ca_watcher_applies_only_loadable_changes() {
+        let fs = TempFs::new();
+        let path = fs.write("ca.pem", TEST_CA_PEM);
+        let mut watcher = CaWatcher::new(&MqttAddress {
+            ca_cert_path: Some(fs.path_str("ca.pem")),
+            ..Default::default()
+        });
+        watcher.mark_applied();
+        assert!(!watcher.changed());
+
+        // A broken replacement keeps the connection and is reported once
+        std::fs::write(&path, "not a certificate").unwrap();
+        assert!(!watcher.changed());
+        assert_eq!(watcher.seen, Some(sha256_hash(b"not a certificate")));
+        assert!(!watcher.changed());
+
+        // Fixing the file (here: a bundle with an extra copy) triggers a reconnect
+        std::fs::write(&path, format!("{TEST_CA_PEM}{TEST_CA_PEM}")).unwrap();
+        assert!(watcher.changed());
+        watcher.mark_applied();
+        assert!(!watcher.changed());
+
+        // Without TLS the file is not used, so it is not watched
+        let mut watcher = CaWatcher::new(&MqttAddress {
+            use_tls: false,
+            ca_cert_path: Some(fs.path_str("ca.pem")),
+            ..Default::default()
+        });
+        watcher.mark_applied();
+        std::fs::write(&path, TEST_CA_PEM).unwrap();
+        assert!(!watcher.changed());
+    }
+}
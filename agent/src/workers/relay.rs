@@ -3,62 +3,218 @@
 //! Maintains a persistent connection to the backend relay endpoint. Incoming
 //! commands are dispatched to handlers for: deployments, terminal sessions,
 //! file operations, and network scanning.
+//!
+//! When WebSocket upgrades are rejected (e.g. by a corporate proxy) the worker
+//! falls back to HTTP long-polling against the same relay protocol, trying
+//! WebSocket again every `ws_retry_interval` in case the proxy let up.
+//!
+//! Terminal output and file chunks are sent as binary frames when
+//! `RelayEncoding::Binary` is set and the backend confirms it in the upgrade
+//! response (see `ENCODING_HEADER`), and as Base64 in JSON otherwise,
+//! including over long-polling.
 
-use std::collections::HashMap;
 use std::future::Future;
 use std::pin::Pin;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+/// Longest wait between reconnect attempts.
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
 
 /// Exponential backoff with full jitter.
 /// Returns a delay in the range [0, min(cap, base * 2^attempt)].
-fn backoff_delay(attempt: u32, base_secs: u64, cap_secs: u64) -> Duration {
-    let exp = base_secs.saturating_mul(1u64.checked_shl(attempt.min(62)).unwrap_or(u64::MAX));
-    let ceiling = exp.min(cap_secs);
-    // Full jitter: pick uniformly from [0, ceiling]
-    let jitter_ms = if ceiling > 0 {
-        let ceiling_ms = ceiling.saturating_mul(1000);
-        use std::time::{SystemTime, UNIX_EPOCH};
-        let seed = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or_default()
-            .subsec_nanos() as u64;
-        (seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407) >> 33)
-            % ceiling_ms
-    } else {
-        0
-    };
-    Duration::from_millis(jitter_ms)
+fn backoff_delay(attempt: u32, base: Duration, cap: Duration) -> Duration {
+    let exp = base.saturating_mul(1u32.checked_shl(attempt.min(31)).unwrap_or(u32::MAX));
+    // Full jitter: pick uniformly from [0, ceiling)
+    crate::utils::jitter(exp.min(cap))
 }
 
 use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
-use futures::{SinkExt, StreamExt};
+use futures::future::join_all;
+use futures::{Sink, SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
 use tokio::sync::{mpsc, Mutex};
 use tokio_tungstenite::{
     connect_async,
-    tungstenite::{handshake::client::generate_key, http::Request, protocol::Message},
+    tungstenite::{self, handshake::client::generate_key, http::Request, protocol::Message},
 };
+use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, info, warn};
 use url::Url;
 
+use crate::app::drain::DrainState;
+use crate::deploy::capabilities::CapabilityManifest;
+use crate::deploy::check::WorkflowChecker;
+use crate::deploy::dirs::DeploymentDirs;
+use crate::workers::deployer::DeployTrigger;
+use crate::authn::command_signing::{
+    CommandVerifier, DEFAULT_SIGNATURE_MAX_AGE, DEFAULT_SIGNED_COMMANDS,
+};
 use crate::authn::token_mngr::{TokenManager, TokenManagerExt};
 use crate::errors::AgentError;
-use crate::terminal::TerminalSession;
+use crate::filesys::relay::FileOptions;
+use crate::health::{HealthRegistry, HealthStatus};
+use crate::models::relay::{data_message, text_message, RelayCommand, RelayEnvelope};
+use crate::storage::label::DeviceLabel;
+use crate::sync::syncer::Syncer;
+use crate::scanner::ScanOptions;
+use crate::terminal::exec::{run_command, ExecOptions};
+use crate::terminal::sessions::{list_sessions, SessionRegistry, Sessions};
+use crate::terminal::{ShellSpec, TerminalOptions, TerminalSession};
+
+/// Health registry component name
+const HEALTH_COMPONENT: &str = "relay";
+
+/// Upgrade request header offering binary frames, and response header
+/// accepting them
+const ENCODING_HEADER: &str = "X-Relay-Encoding";
+
+/// How much longer than the server's hold time a poll request may take.
+const POLL_GRACE: Duration = Duration::from_secs(10);
 
 /// Alias for the WS outgoing message sender.
 type WsTx = mpsc::UnboundedSender<Message>;
 
-/// Shared terminal session map: session_id -> TerminalSession.
-type Sessions = Arc<Mutex<HashMap<String, TerminalSession>>>;
+/// Cancellation handle of the in-flight network scan, if any.
+type ActiveScan = Arc<Mutex<Option<CancellationToken>>>;
+
+/// Agent-wide state the relay's message handlers act on.
+#[derive(Clone)]
+pub struct RelayContext {
+    /// Drain mode state.
+    pub drain: Arc<DrainState>,
+
+    /// Wakes the deployer when a deployment is pushed.
+    pub deploy_trigger: Arc<DeployTrigger>,
+
+    /// Capabilities advertised on every connect.
+    pub capabilities: Arc<CapabilityManifest>,
+
+    /// Terminal session settings.
+    pub terminal: TerminalOptions,
+
+    /// Open terminal sessions, shared with the local API.
+    pub terminal_sessions: Arc<SessionRegistry>,
+
+    /// One-shot command execution policy.
+    pub exec: ExecOptions,
+
+    /// Network scan defaults and limits.
+    pub scan: ScanOptions,
+
+    /// File command root and limits.
+    pub files: FileOptions,
+
+    /// Operator-assigned device label.
+    pub device_label: Arc<DeviceLabel>,
+
+    /// Workflow syncer, for clearing its cooldown.
+    pub syncer: Arc<Syncer>,
+
+    /// Deployment directories, for manual cleanup.
+    pub deployment_dirs: Arc<DeploymentDirs>,
+
+    /// Workflow deployability prechecks.
+    pub workflow_checker: Arc<WorkflowChecker>,
+
+    /// Signature checks for high-privilege commands.
+    pub command_verifier: Arc<CommandVerifier>,
+}
+
+/// Transport used to reach the relay.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RelayTransport {
+    /// WebSocket, falling back to long-polling when upgrades are rejected.
+    #[default]
+    Auto,
+
+    /// WebSocket only.
+    WebSocket,
+
+    /// HTTP long-polling only.
+    Poll,
+}
+
+impl RelayTransport {
+    /// Transport name as used in settings.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RelayTransport::Auto => "auto",
+            RelayTransport::WebSocket => "websocket",
+            RelayTransport::Poll => "poll",
+        }
+    }
+}
+
+/// How bulk data (terminal output, file chunks) is sent to the relay.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RelayEncoding {
+    /// Base64 in JSON text messages.
+    #[default]
+    Base64,
+
+    /// Binary WebSocket frames, when the backend accepts them; Base64
+    /// otherwise.
+    Binary,
+}
+
+impl RelayEncoding {
+    /// Encoding name as used in settings and the upgrade headers.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RelayEncoding::Base64 => "base64",
+            RelayEncoding::Binary => "binary",
+        }
+    }
+}
 
 /// Relay worker options.
 #[derive(Debug, Clone)]
 pub struct Options {
-    /// Reconnect delay on failure.
+    /// Base of the exponential reconnect backoff.
     pub reconnect_delay: Duration,
 
     /// Heartbeat interval.
     pub heartbeat_interval: Duration,
+
+    /// Transport selection.
+    pub transport: RelayTransport,
+
+    /// Encoding offered for bulk data.
+    pub encoding: RelayEncoding,
+
+    /// Rejected WebSocket upgrades before `Auto` switches to long-polling.
+    pub poll_fallback_after: u32,
+
+    /// How long `Auto` long-polls before trying WebSocket again.
+    pub ws_retry_interval: Duration,
+
+    /// How long the server may hold a poll request open.
+    pub poll_timeout: Duration,
+
+    /// Terminal session settings.
+    pub terminal: TerminalOptions,
+
+    /// One-shot command execution policy.
+    pub exec: ExecOptions,
+
+    /// Network scan defaults and limits.
+    pub scan: ScanOptions,
+
+    /// File command root and limits.
+    pub files: FileOptions,
+
+    /// High-privilege commands must be signed with one of the backend's
+    /// published keys; signatures are not checked when unset.
+    pub require_signed_commands: bool,
+
+    /// Commands that must be signed when signatures are required.
+    pub signed_commands: Vec<String>,
+
+    /// Longest a command signature may be valid for.
+    pub signature_max_age: Duration,
 }
 
 impl Default for Options {
@@ -66,32 +222,78 @@ impl Default for Options {
         Self {
             reconnect_delay: Duration::from_secs(5),
             heartbeat_interval: Duration::from_secs(30),
+            transport: RelayTransport::Auto,
+            encoding: RelayEncoding::Base64,
+            poll_fallback_after: 3,
+            ws_retry_interval: Duration::from_secs(600),
+            poll_timeout: Duration::from_secs(30),
+            terminal: TerminalOptions::default(),
+            exec: ExecOptions::default(),
+            scan: ScanOptions::default(),
+            files: FileOptions::default(),
+            require_signed_commands: false,
+            signed_commands: DEFAULT_SIGNED_COMMANDS.iter().map(|c| c.to_string()).collect(),
+            signature_max_age: DEFAULT_SIGNATURE_MAX_AGE,
         }
     }
 }
 
+impl Options {
+    /// Wait before reconnect attempt `attempt`, backing off from
+    /// `reconnect_delay`.
+    fn reconnect_backoff(&self, attempt: u32) -> Duration {
+        backoff_delay(attempt, self.reconnect_delay, MAX_RECONNECT_DELAY)
+    }
+}
+
 /// Run the relay worker. Reconnects automatically on failure with exponential
 /// backoff and full jitter to prevent thundering-herd storms when the server
 /// restarts across a large fleet.
-pub async fn run(
+pub async fn run<S, F>(
     options: &Options,
     token_mngr: Arc<TokenManager>,
     backend_url: String,
+    health: &HealthRegistry,
+    context: RelayContext,
+    sleep_fn: S,
     mut shutdown_signal: Pin<Box<dyn Future<Output = ()> + Send>>,
-) {
-    info!("Relay worker starting...");
+) where
+    S: Fn(Duration) -> F,
+    F: Future<Output = ()>,
+{
+    info!("Relay worker starting (transport: {})...", options.transport.as_str());
 
-    let relay_url = match build_relay_url(&backend_url) {
-        Ok(url) => url,
-        Err(e) => {
+    let (relay_url, poll_url) = match (build_relay_url(&backend_url), build_poll_url(&backend_url)) {
+        (Ok(relay_url), Ok(poll_url)) => (relay_url, poll_url),
+        (Err(e), _) | (_, Err(e)) => {
             error!("Failed to build relay URL: {}", e);
             return;
         }
     };
 
+    let http = match reqwest::Client::builder()
+        .timeout(options.poll_timeout + POLL_GRACE)
+        .build()
+    {
+        Ok(client) => client,
+        Err(e) => {
+            error!("Failed to build relay HTTP client: {}", e);
+            return;
+        }
+    };
+
+    // Terminal sessions kept for a reconnect end with the worker
+    let _parked_sessions_guard = context.terminal_sessions.stop_on_drop();
+
     // Backoff state: resets to 0 on every successful connection.
     let mut attempt: u32 = 0;
 
+    // Consecutive rejected WebSocket upgrades, used by `RelayTransport::Auto`.
+    let mut ws_rejections: u32 = 0;
+
+    // When `RelayTransport::Auto` last fell back to long-polling.
+    let mut fell_back_at: Option<Instant> = None;
+
     loop {
         // Exit immediately if shutdown has been signalled.
         tokio::select! {
@@ -106,26 +308,77 @@ pub async fn run(
             Ok(id) => id,
             Err(e) => {
                 error!("Failed to get device ID: {}", e);
-                let delay = backoff_delay(attempt, 2, 60);
+                let delay = options.reconnect_backoff(attempt);
                 info!("Retrying in {:.1}s (attempt {})", delay.as_secs_f32(), attempt + 1);
-                tokio::time::sleep(delay).await;
+                if !wait_unless_shutdown(sleep_fn(delay), &mut shutdown_signal).await {
+                    info!("Relay worker shutting down...");
+                    return;
+                }
                 attempt = attempt.saturating_add(1);
                 continue;
             }
         };
 
-        let token = match token_mngr.get_token().await {
+        let token = match token_mngr.get_valid_token().await {
             Ok(t) => t.raw,
             Err(e) => {
                 error!("Failed to get token: {}", e);
-                let delay = backoff_delay(attempt, 2, 60);
+                let delay = options.reconnect_backoff(attempt);
                 info!("Retrying in {:.1}s (attempt {})", delay.as_secs_f32(), attempt + 1);
-                tokio::time::sleep(delay).await;
+                if !wait_unless_shutdown(sleep_fn(delay), &mut shutdown_signal).await {
+                    info!("Relay worker shutting down...");
+                    return;
+                }
                 attempt = attempt.saturating_add(1);
                 continue;
             }
         };
 
+        let use_poll = match options.transport {
+            RelayTransport::Poll => true,
+            RelayTransport::WebSocket => false,
+            RelayTransport::Auto => ws_rejections >= options.poll_fallback_after,
+        };
+
+        if use_poll {
+            info!("Polling relay: {} (attempt {})", poll_url, attempt + 1);
+            set_transport_health(health, RelayTransport::Poll);
+            let session = PollSession {
+                http: &http,
+                poll_url: &poll_url,
+                device_id: &device_id,
+                token: &token,
+                retry_ws_at: fell_back_at.map(|at| at + options.ws_retry_interval),
+            };
+            match run_poll_session(options, &session, &context, &sleep_fn, &mut shutdown_signal)
+                .await
+            {
+                PollExit::Shutdown => {
+                    info!("Relay worker shutting down connection...");
+                    return;
+                }
+                PollExit::RetryWebSocket => {
+                    info!("Trying the WebSocket relay again");
+                    // One more rejection goes back to long-polling
+                    ws_rejections = options.poll_fallback_after.saturating_sub(1);
+                    continue;
+                }
+                PollExit::Error(e) => {
+                    let delay = options.reconnect_backoff(attempt);
+                    error!(
+                        "Relay poll failed: {}. Retrying in {:.1}s (attempt {})",
+                        e, delay.as_secs_f32(), attempt + 1
+                    );
+                    if !wait_unless_shutdown(sleep_fn(delay), &mut shutdown_signal).await {
+                        info!("Relay worker shutting down...");
+                        return;
+                    }
+                    attempt = attempt.saturating_add(1);
+                    continue;
+                }
+            }
+        }
+
         info!("Connecting to relay: {} (attempt {})", relay_url, attempt + 1);
 
         let ws_key = generate_key();
@@ -139,34 +392,50 @@ pub async fn run(
             .header("Sec-WebSocket-Key", &ws_key)
             .header("X-Device-ID", &device_id)
             .header("X-Device-Secret", &token)
+            .header(ENCODING_HEADER, options.encoding.as_str())
             .body(())
             .expect("hardcoded HTTP request builder fields are always valid");
 
         match connect_async(request).await {
-            Ok((ws_stream, _)) => {
-                info!("Connected to WebSocket relay");
+            Ok((ws_stream, response)) => {
+                let binary = options.encoding == RelayEncoding::Binary
+                    && response
+                        .headers()
+                        .get(ENCODING_HEADER)
+                        .is_some_and(|value| value == RelayEncoding::Binary.as_str());
+                info!(
+                    "Connected to WebSocket relay (encoding: {})",
+                    if binary { "binary" } else { "base64" }
+                );
                 // Connection established — reset backoff counter.
                 attempt = 0;
+                ws_rejections = 0;
+                set_transport_health(health, RelayTransport::WebSocket);
 
                 let (ws_sink, mut ws_rx) = ws_stream.split();
 
-                // Channel for sending outgoing WS messages from handlers
-                let (tx, mut rx) = mpsc::unbounded_channel::<Message>();
+                // Channel for sending outgoing WS messages from handlers,
+                // forwarded to the WS sink for as long as the connection lasts
+                let (tx, rx) = mpsc::unbounded_channel::<Message>();
+                let _sink_guard = spawn_sink(rx, ws_sink, binary);
 
-                // Spawn a task that forwards channel messages to the WS sink
-                tokio::spawn(async move {
-                    let mut sink = ws_sink;
-                    while let Some(msg) = rx.recv().await {
-                        if sink.send(msg).await.is_err() {
-                            break;
-                        }
-                    }
-                });
+                let _ = tx.send(capabilities_message(&context.capabilities));
 
-                // Terminal sessions are scoped to this connection
-                let sessions: Sessions = Arc::new(Mutex::new(HashMap::new()));
+                // Terminal sessions are scoped to this connection; their
+                // shells are closed when it ends, unless kept for a reconnect
+                let sessions = context.terminal_sessions.connect(context.terminal.close_grace, &tx);
+                let _sessions_guard = supervise_sessions(&sessions, &context.terminal);
 
-                let mut heartbeat_tick = tokio::time::interval(options.heartbeat_interval);
+                // Scans are scoped to this connection and cancelled when it
+                // ends, whether by disconnect or shutdown
+                let scan_token = CancellationToken::new();
+                let _scan_guard = scan_token.clone().drop_guard();
+                let active_scan: ActiveScan = Arc::new(Mutex::new(None));
+
+                // Pings are timed with the injected sleep, which also tells
+                // the watchdog the connection is being served
+                let ping_due = sleep_fn(options.heartbeat_interval);
+                tokio::pin!(ping_due);
 
                 'inner: loop {
                     tokio::select! {
@@ -174,9 +443,10 @@ pub async fn run(
                             info!("Relay worker shutting down connection...");
                             return;
                         }
-                        _ = heartbeat_tick.tick() => {
+                        _ = &mut ping_due => {
                             let ping = serde_json::json!({"type": "ping"}).to_string();
                             let _ = tx.send(Message::Text(ping.into()));
+                            ping_due.set(sleep_fn(options.heartbeat_interval));
                         }
                         msg = ws_rx.next() => {
                             match msg {
@@ -185,6 +455,9 @@ pub async fn run(
                                         &text,
                                         tx.clone(),
                                         Arc::clone(&sessions),
+                                        Arc::clone(&active_scan),
+                                        &scan_token,
+                                        &context,
                                     )
                                     .await;
                                 }
@@ -201,27 +474,251 @@ pub async fn run(
                         }
                     }
                 }
+
+                context.terminal_sessions.park(&sessions, &context.terminal).await;
             }
             Err(e) => {
-                let delay = backoff_delay(attempt, 2, 60);
+                if is_upgrade_rejected(&e) {
+                    ws_rejections = ws_rejections.saturating_add(1);
+                    if options.transport == RelayTransport::Auto
+                        && ws_rejections == options.poll_fallback_after
+                    {
+                        warn!(
+                            "WebSocket upgrade rejected, switching relay to HTTP long-polling for {:?}",
+                            options.ws_retry_interval
+                        );
+                        fell_back_at = Some(Instant::now());
+                    }
+                }
+                let delay = options.reconnect_backoff(attempt);
                 error!(
                     "Failed to connect to relay: {}. Retrying in {:.1}s (attempt {})",
                     e, delay.as_secs_f32(), attempt + 1
                 );
-                tokio::time::sleep(delay).await;
+                if !wait_unless_shutdown(sleep_fn(delay), &mut shutdown_signal).await {
+                    info!("Relay worker shutting down...");
+                    return;
+                }
                 attempt = attempt.saturating_add(1);
                 continue;
             }
         }
 
         // Graceful disconnect — apply a short jittered delay before reconnecting.
-        let delay = backoff_delay(attempt, 2, 60);
+        let delay = options.reconnect_backoff(attempt);
         info!("Relay disconnected. Reconnecting in {:.1}s...", delay.as_secs_f32());
-        tokio::time::sleep(delay).await;
+        if !wait_unless_shutdown(sleep_fn(delay), &mut shutdown_signal).await {
+            info!("Relay worker shutting down...");
+            return;
+        }
         attempt = attempt.saturating_add(1);
     }
 }
 
+/// Forward messages from `rx` to `sink` until the returned guard is dropped
+/// with the connection, or the sink fails. The receiver goes with the task,
+/// so senders kept past the connection (e.g. by terminal sessions) see it
+/// closed instead of keeping the task alive.
+fn spawn_sink<S>(
+    mut rx: mpsc::UnboundedReceiver<Message>,
+    mut sink: S,
+    binary: bool,
+) -> tokio_util::sync::DropGuard
+where
+    S: Sink<Message> + Unpin + Send + 'static,
+{
+    let cancel = CancellationToken::new();
+    let guard = cancel.clone().drop_guard();
+    tokio::spawn(async move {
+        let forward = async {
+            while let Some(msg) = rx.recv().await {
+                let msg = if binary { Some(msg) } else { text_message(msg) };
+                let Some(msg) = msg else { continue };
+                if sink.send(msg).await.is_err() {
+                    break;
+                }
+            }
+        };
+        tokio::select! {
+            _ = cancel.cancelled() => {}
+            _ = forward => {}
+        }
+    });
+    guard
+}
+
+/// Wait out a retry delay. Returns false if shutdown was signalled first.
+async fn wait_unless_shutdown<F: Future<Output = ()>>(
+    sleep: F,
+    shutdown_signal: &mut Pin<Box<dyn Future<Output = ()> + Send>>,
+) -> bool {
+    tokio::select! {
+        _ = shutdown_signal => false,
+        _ = sleep => true,
+    }
+}
+
+/// Whether a handshake error means the upgrade itself was refused (as opposed
+/// to the server being unreachable).
+fn is_upgrade_rejected(error: &tungstenite::Error) -> bool {
+    match error {
+        tungstenite::Error::Http(response) => response.status().is_client_error(),
+        tungstenite::Error::Protocol(_) => true,
+        _ => false,
+    }
+}
+
+/// Capability manifest sent right after connecting.
+fn capabilities_message(capabilities: &CapabilityManifest) -> Message {
+    let msg = serde_json::json!({"type": "capabilities", "payload": capabilities});
+    Message::Text(msg.to_string().into())
+}
+
+/// Record the active transport in the health registry.
+fn set_transport_health(health: &HealthRegistry, transport: RelayTransport) {
+    health.set(
+        HEALTH_COMPONENT,
+        HealthStatus::Healthy,
+        Some(format!("transport: {}", transport.as_str())),
+    );
+}
+
+// ---------------------------------------------------------------------------
+// Long-poll transport
+// ---------------------------------------------------------------------------
+
+/// Why a poll session ended.
+enum PollExit {
+    Shutdown,
+    /// Time for `RelayTransport::Auto` to try WebSocket again.
+    RetryWebSocket,
+    Error(AgentError),
+}
+
+/// Body returned by `GET /agent-relay/poll`.
+#[derive(Deserialize)]
+struct PollBatch {
+    #[serde(default)]
+    messages: Vec<serde_json::Value>,
+}
+
+/// Where and as whom a poll session polls.
+struct PollSession<'a> {
+    http: &'a reqwest::Client,
+    poll_url: &'a Url,
+    device_id: &'a str,
+    token: &'a str,
+    /// When `RelayTransport::Auto` should try WebSocket again.
+    retry_ws_at: Option<Instant>,
+}
+
+/// Relay messages over HTTP: commands are fetched with long-polling GETs and
+/// responses are POSTed back. Messages go through the same `handle_message`
+/// dispatcher as the WebSocket transport. The session ends after the first
+/// poll completed once `retry_ws_at` has passed.
+async fn run_poll_session<S, F>(
+    options: &Options,
+    session: &PollSession<'_>,
+    context: &RelayContext,
+    sleep_fn: &S,
+    shutdown_signal: &mut Pin<Box<dyn Future<Output = ()> + Send>>,
+) -> PollExit
+where
+    S: Fn(Duration) -> F,
+    F: Future<Output = ()>,
+{
+    let PollSession { http, poll_url, device_id, token, retry_ws_at } = *session;
+    let (tx, mut rx) = mpsc::unbounded_channel::<Message>();
+
+    // Forward outgoing messages as POSTs until the session ends
+    let post_client = http.clone();
+    let post_url = poll_url.clone();
+    let post_headers = [
+        ("X-Device-ID", device_id.to_string()),
+        ("X-Device-Secret", token.to_string()),
+    ];
+    let post_cancel = CancellationToken::new();
+    let _post_guard = post_cancel.clone().drop_guard();
+    tokio::spawn(async move {
+        let forward = async {
+            while let Some(msg) = rx.recv().await {
+                let Some(Message::Text(text)) = text_message(msg) else { continue };
+                let mut request = post_client
+                    .post(post_url.clone())
+                    .header("Content-Type", "application/json")
+                    .body(text.to_string());
+                for (name, value) in &post_headers {
+                    request = request.header(*name, value);
+                }
+                if let Err(e) = request.send().await.and_then(|r| r.error_for_status()) {
+                    warn!("Failed to post relay message: {}", e);
+                }
+            }
+        };
+        tokio::select! {
+            _ = post_cancel.cancelled() => {}
+            _ = forward => {}
+        }
+    });
+
+    let _ = tx.send(capabilities_message(&context.capabilities));
+
+    let sessions = context.terminal_sessions.connect(context.terminal.close_grace, &tx);
+    let _sessions_guard = supervise_sessions(&sessions, &context.terminal);
+    let scan_token = CancellationToken::new();
+    let _scan_guard = scan_token.clone().drop_guard();
+    let active_scan: ActiveScan = Arc::new(Mutex::new(None));
+
+    let exit = loop {
+        let request = http
+            .get(poll_url.clone())
+            .query(&[("timeout", options.poll_timeout.as_secs())])
+            .header("X-Device-ID", device_id)
+            .header("X-Device-Secret", token)
+            .send();
+
+        // Bounded with the injected sleep, which also tells the watchdog
+        // the session is being served
+        let poll_limit = options.poll_timeout + POLL_GRACE;
+        let response = tokio::select! {
+            _ = &mut *shutdown_signal => return PollExit::Shutdown,
+            response = request => response,
+            _ = sleep_fn(poll_limit) => {
+                let e = AgentError::Timeout(format!("Relay poll took over {:?}", poll_limit));
+                break PollExit::Error(e);
+            }
+        };
+
+        let messages = match response.and_then(|r| r.error_for_status()) {
+            Ok(r) if r.status() == reqwest::StatusCode::NO_CONTENT => Vec::new(),
+            Ok(r) => match r.json::<PollBatch>().await {
+                Ok(batch) => batch.messages,
+                Err(e) => break PollExit::Error(e.into()),
+            },
+            Err(e) => break PollExit::Error(e.into()),
+        };
+
+        for msg in messages {
+            handle_message(
+                &msg.to_string(),
+                tx.clone(),
+                Arc::clone(&sessions),
+                Arc::clone(&active_scan),
+                &scan_token,
+                context,
+            )
+            .await;
+        }
+
+        if retry_ws_at.is_some_and(|at| Instant::now() >= at) {
+            break PollExit::RetryWebSocket;
+        }
+    };
+
+    context.terminal_sessions.park(&sessions, &context.terminal).await;
+    exit
+}
+
 // ---------------------------------------------------------------------------
 // URL helpers
 // ---------------------------------------------------------------------------
@@ -251,72 +748,197 @@ fn build_relay_url(backend_url: &str) -> Result<Url, AgentError> {
     Ok(url)
 }
 
+fn build_poll_url(backend_url: &str) -> Result<Url, AgentError> {
+    let mut url =
+        Url::parse(backend_url).map_err(|e| AgentError::ConfigError(e.to_string()))?;
+
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(AgentError::ConfigError(
+            "Invalid backend URL scheme".to_string(),
+        ));
+    }
+
+    url.set_path(&format!(
+        "{}/agent-relay/poll",
+        url.path().trim_end_matches('/')
+    ));
+
+    Ok(url)
+}
+
 // ---------------------------------------------------------------------------
-// Message dispatcher
+// Terminal session lifetime
 // ---------------------------------------------------------------------------
 
-async fn handle_message(text: &str, tx: WsTx, sessions: Sessions) {
-    debug!("Received relay message: {}", text);
+/// Close sessions idle for longer than `options.idle_timeout`, and every
+/// session once the returned guard is dropped with the connection.
+fn supervise_sessions(
+    sessions: &Sessions,
+    options: &TerminalOptions,
+) -> tokio_util::sync::DropGuard {
+    let cancel = CancellationToken::new();
+    let guard = cancel.clone().drop_guard();
+    let sessions = Arc::clone(sessions);
+    let (idle_timeout, grace) = (options.idle_timeout, options.close_grace);
+    tokio::spawn(async move {
+        let check_every = (idle_timeout / 4).clamp(Duration::from_secs(1), Duration::from_secs(60));
+        let mut tick = tokio::time::interval(check_every);
+        loop {
+            tokio::select! {
+                _ = cancel.cancelled() => break,
+                _ = tick.tick(), if !idle_timeout.is_zero() => {
+                    let idle: Vec<_> = {
+                        let mut sessions = sessions.lock().await;
+                        let ids: Vec<String> = sessions
+                            .iter()
+                            .filter(|(_, session)| session.idle_for() >= idle_timeout)
+                            .map(|(id, _)| id.clone())
+                            .collect();
+                        ids.iter().filter_map(|id| sessions.remove(id)).collect()
+                    };
+                    for session in &idle {
+                        info!("Terminal session {} is idle; closing", session.id());
+                    }
+                    join_all(idle.into_iter().map(|session| session.close(grace))).await;
+                }
+            }
+        }
 
-    let msg: serde_json::Value = match serde_json::from_str(text) {
-        Ok(m) => m,
-        Err(_) => return,
-    };
+        let open: Vec<_> = sessions.lock().await.drain().map(|(_, session)| session).collect();
+        if !open.is_empty() {
+            info!("Closing {} terminal session(s) of the ended relay connection", open.len());
+            join_all(open.into_iter().map(|session| session.close(grace))).await;
+        }
+    });
+    guard
+}
 
-    // The server wraps commands as:
-    //   {"type": "command", "msg_id": "...", "command_type": "...", "payload": {...}}
-    // Push messages have "type" set directly to the message type (e.g. "new_deployment").
-    let msg_type = if msg.get("type").and_then(|t| t.as_str()) == Some("command") {
-        msg.get("command_type").and_then(|t| t.as_str())
-    } else {
-        msg.get("type").and_then(|t| t.as_str())
-    };
+// ---------------------------------------------------------------------------
+// Message dispatcher
+// ---------------------------------------------------------------------------
 
-    // msg_id is used to correlate request/response pairs
-    let msg_id = msg
-        .get("msg_id")
-        .and_then(|v| v.as_str())
-        .unwrap_or("")
-        .to_string();
+async fn handle_message(
+    text: &str,
+    tx: WsTx,
+    sessions: Sessions,
+    active_scan: ActiveScan,
+    scan_token: &CancellationToken,
+    context: &RelayContext,
+) {
+    debug!("Received relay message: {}", text);
+    let drain = &context.drain;
 
-    let payload = &msg["payload"];
+    let (msg_id, command) = match serde_json::from_str::<RelayEnvelope>(text) {
+        Ok(RelayEnvelope::Command(envelope)) => {
+            if let Err(e) = context.command_verifier.verify(&envelope) {
+                warn!("Rejected relay command {}: {}", envelope.command_type, e);
+                send_forbidden(&tx, &envelope.msg_id, &e.to_string());
+                return;
+            }
+            (envelope.msg_id, envelope.command)
+        }
 
-    match msg_type {
         // ── Legacy: deployment trigger (fire-and-forget) ──────────────────
-        Some("new_deployment") => {
-            let id = msg
-                .get("deployment_id")
-                .and_then(|v| v.as_str())
-                .unwrap_or("unknown");
-            info!("Real-time trigger: new deployment pending: {}", id);
+        Ok(RelayEnvelope::NewDeployment(push)) => {
+            let id = push.deployment_id();
+            info!("Real-time trigger: new deployment pending: {}", id.unwrap_or("unknown"));
+            context.deploy_trigger.trigger(id);
+            return;
         }
 
-        Some("pong") => {
+        Ok(RelayEnvelope::Pong) => {
             debug!("Relay pong received");
+            return;
+        }
+
+        Err(e) => {
+            // Only a command the backend can correlate gets a rejection;
+            // anything else (say, a message type added after this agent)
+            // is ignored
+            let msg = serde_json::from_str::<serde_json::Value>(text).ok();
+            let field = |name: &str| {
+                msg.as_ref().and_then(|msg| msg.get(name)?.as_str().map(str::to_string))
+            };
+            match (field("type").as_deref(), field("msg_id")) {
+                (Some("command"), Some(msg_id)) => {
+                    warn!("Malformed relay command {}: {}", msg_id, e);
+                    send_bad_request(&tx, &msg_id, &e.to_string());
+                }
+                _ => warn!("Ignoring unrecognized relay message: {}", e),
+            }
+            return;
+        }
+    };
+
+    match command {
+        // ── Drain: stop accepting new work ────────────────────────────────
+        RelayCommand::Drain(_) => {
+            drain.start();
+            send_response(
+                &tx,
+                &msg_id,
+                Ok(serde_json::json!({ "draining": true, "in_flight": drain.in_flight() })),
+            );
         }
 
         // ── Terminal: create session ──────────────────────────────────────
-        Some("terminal_create") => {
-            let session_id = payload["session_id"]
-                .as_str()
-                .unwrap_or(&msg_id)
-                .to_string();
-            let cols = payload["cols"].as_u64().unwrap_or(80) as u16;
-            let rows = payload["rows"].as_u64().unwrap_or(24) as u16;
-
-            let resp = match TerminalSession::new(
-                session_id.clone(),
-                cols,
-                rows,
-                tx.clone(),
-            ) {
-                Ok(session) => {
-                    sessions.lock().await.insert(session_id.clone(), session);
-                    info!("Terminal session created: {}", session_id);
+        RelayCommand::TerminalCreate(create) => {
+            if drain.is_draining() {
+                send_draining(&tx, &msg_id);
+                return;
+            }
+            let session_id = create.session_id.unwrap_or_else(|| msg_id.clone());
+            let max_sessions = context.terminal.max_sessions;
+            let open = sessions.lock().await.len();
+            if open >= max_sessions {
+                warn!("Terminal create refused: {} sessions already open", open);
+                send_response(
+                    &tx,
+                    &msg_id,
+                    Err(AgentError::Conflict(format!(
+                        "Too many terminal sessions (at most {})",
+                        max_sessions
+                    ))),
+                );
+                return;
+            }
+            let session = ShellSpec::new(
+                create.shell.as_deref(),
+                create.cwd.as_deref(),
+                create.env,
+                &context.terminal,
+            )
+            .and_then(|spec| {
+                let session = TerminalSession::new(
+                    session_id.clone(),
+                    create.cols,
+                    create.rows,
+                    &spec,
+                    &context.terminal,
+                    tx.clone(),
+                )?;
+                Ok((session, spec))
+            });
+
+            let resp = match session {
+                Ok((session, spec)) => {
+                    // A reused session ID replaces the old shell
+                    let replaced = sessions.lock().await.insert(session_id.clone(), session);
+                    if let Some(replaced) = replaced {
+                        tokio::spawn(replaced.close(context.terminal.close_grace));
+                    }
+                    info!(
+                        "Terminal session created: {} running {:?} in {:?}",
+                        session_id, spec.shell, spec.cwd
+                    );
                     serde_json::json!({
                         "type": "response",
                         "msg_id": msg_id,
-                        "result": { "session_id": session_id },
+                        "result": {
+                            "session_id": session_id,
+                            "shell": spec.shell,
+                            "cwd": spec.cwd,
+                        },
                         "error": null
                     })
                 }
@@ -334,70 +956,238 @@ async fn handle_message(text: &str, tx: WsTx, sessions: Sessions) {
         }
 
         // ── Terminal: send keystrokes ─────────────────────────────────────
-        Some("terminal_input") => {
-            let session_id = payload["session_id"].as_str().unwrap_or_default();
-            let data_b64 = payload["data"].as_str().unwrap_or_default();
-
-            if let Ok(bytes) = BASE64.decode(data_b64) {
+        RelayCommand::TerminalInput(input) => {
+            if let Ok(bytes) = BASE64.decode(&input.data) {
                 let sessions_guard = sessions.lock().await;
-                if let Some(session) = sessions_guard.get(session_id) {
+                if let Some(session) = sessions_guard.get(&input.session_id) {
                     if let Err(e) = session.write_input(&bytes) {
-                        warn!("Terminal input error for {}: {}", session_id, e);
+                        warn!("Terminal input error for {}: {}", input.session_id, e);
                     }
                 }
             }
         }
 
+        // ── Terminal: resize session ──────────────────────────────────────
+        RelayCommand::TerminalResize(resize) => {
+            let sessions_guard = sessions.lock().await;
+            if let Some(session) = sessions_guard.get(&resize.session_id) {
+                if let Err(e) = session.resize(resize.cols, resize.rows) {
+                    warn!("Terminal resize error for {}: {}", resize.session_id, e);
+                }
+            }
+        }
+
         // ── Terminal: close session ───────────────────────────────────────
-        Some("terminal_close") => {
-            let session_id = payload["session_id"].as_str().unwrap_or_default();
-            sessions.lock().await.remove(session_id);
-            info!("Terminal session closed: {}", session_id);
+        RelayCommand::TerminalClose(close) => {
+            let session = sessions.lock().await.remove(&close.session_id);
+            if let Some(session) = session {
+                // Closing waits for the shell to exit, which may take the
+                // whole grace period
+                let grace = context.terminal.close_grace;
+                tokio::spawn(async move {
+                    session.close(grace).await;
+                    info!("Terminal session closed: {}", close.session_id);
+                });
+            }
+        }
+
+        // ── Terminal: list open sessions ──────────────────────────────────
+        RelayCommand::TerminalList(_) => {
+            let sessions = list_sessions(&sessions).await;
+            send_response(&tx, &msg_id, Ok(serde_json::json!({ "sessions": sessions })));
+        }
+
+        // ── Command: run one non-interactive command ─────────────────────
+        RelayCommand::CommandExec(request) => {
+            let Some(work) = drain.begin_work() else {
+                send_draining(&tx, &msg_id);
+                return;
+            };
+            let exec = context.exec.clone();
+            let terminal = context.terminal.clone();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let _work = work;
+                let result = run_command(&request, &exec, &terminal).await;
+                send_response(&tx, &msg_id, result.map(|output| serde_json::json!(output)));
+            });
+        }
+
+        // ── Device: relabel ──────────────────────────────────────────────
+        RelayCommand::DeviceUpdate(update) => {
+            let result = context.device_label.set(update.label.as_deref()).await;
+            send_response(&tx, &msg_id, result.map(|label| serde_json::json!({ "label": label })));
+        }
+
+        // ── Sync: clear the cooldown, optionally syncing right away ─────
+        RelayCommand::SyncReset(reset) => {
+            context.syncer.reset_cooldown().await;
+            if !reset.sync {
+                send_response(&tx, &msg_id, Ok(serde_json::json!({ "reset": true })));
+                return;
+            }
+            let syncer = context.syncer.clone();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let result = syncer.trigger_sync().await.map(|report| {
+                    serde_json::json!({
+                        "reset": true,
+                        "applied": report.applied,
+                        "removed": report.removed,
+                        "failed": report.failed,
+                    })
+                });
+                send_response(&tx, &msg_id, result);
+            });
         }
 
         // ── File: list directory ──────────────────────────────────────────
-        Some("file_list") => {
-            let path = payload["path"].as_str().unwrap_or("/");
-            let result = crate::filesys::relay::list_directory(path).await;
+        RelayCommand::FileList(list) => {
+            let result = crate::filesys::relay::list_directory(&context.files, &list.path).await;
             send_response(&tx, &msg_id, result.map(|files| serde_json::json!({ "files": files })));
         }
 
         // ── File: read (returns Base64 content) ───────────────────────────
-        Some("file_read") => {
-            let path = payload["path"].as_str().unwrap_or("");
-            let result = crate::filesys::relay::read_file(path).await;
-            send_response(&tx, &msg_id, result.map(|content| serde_json::json!({ "content": content })));
+        RelayCommand::FileRead(file) => {
+            let (files, path) = (&context.files, &file.path);
+            let result = crate::filesys::relay::read_file(files, path, file.include_hash).await;
+            send_response(&tx, &msg_id, result.map(|content| serde_json::json!(content)));
         }
 
         // ── File: write (Base64-encoded content) ─────────────────────────
-        Some("file_write") => {
-            let path = payload["path"].as_str().unwrap_or("");
-            let content = payload["content"].as_str().unwrap_or("");
-            let result = crate::filesys::relay::write_file(path, content).await;
+        RelayCommand::FileWrite(write) => {
+            let (files, path) = (&context.files, &write.path);
+            let result = crate::filesys::relay::write_file(files, path, &write.content).await;
             send_response(&tx, &msg_id, result.map(|_| serde_json::json!({ "ok": true })));
         }
 
+        // ── File: streamed read (sequential file_chunk messages) ─────────
+        RelayCommand::FileReadStream(stream) => {
+            let files = context.files.clone();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let result = crate::filesys::relay::stream_file(
+                    &files,
+                    &stream.path,
+                    stream.chunk_size,
+                    |chunk| {
+                        let header = serde_json::json!({
+                            "type": "file_chunk",
+                            "msg_id": &msg_id,
+                            "offset": chunk.offset,
+                            "total": chunk.total,
+                        });
+                        tx.send(data_message(header, "chunk", &chunk.chunk)).is_ok()
+                    },
+                )
+                .await;
+                let result = result.map(|(total, chunks)| {
+                    serde_json::json!({ "path": stream.path, "total": total, "chunks": chunks })
+                });
+                send_response(&tx, &msg_id, result);
+            });
+        }
+
+        // ── File: chunked write, moved into place on commit ──────────────
+        RelayCommand::FileWriteChunk(chunk) => {
+            let result = crate::filesys::relay::write_chunk(
+                &context.files,
+                &chunk.path,
+                chunk.offset,
+                &chunk.content,
+                chunk.commit,
+            )
+            .await;
+            let result = result.map(|written| {
+                serde_json::json!({ "written": written, "committed": chunk.commit })
+            });
+            send_response(&tx, &msg_id, result);
+        }
+
         // ── File: delete ──────────────────────────────────────────────────
-        Some("file_delete") => {
-            let path = payload["path"].as_str().unwrap_or("");
-            let result = crate::filesys::relay::delete_path(path).await;
+        RelayCommand::FileDelete(file) => {
+            let result = crate::filesys::relay::delete_path(&context.files, &file.path).await;
             send_response(&tx, &msg_id, result.map(|_| serde_json::json!({ "ok": true })));
         }
 
+        // ── Deployments: list directories ────────────────────────────────
+        RelayCommand::DeploymentListDirs(_) => {
+            let result = context.deployment_dirs.list().await;
+            send_response(&tx, &msg_id, result.map(|dirs| serde_json::json!({ "dirs": dirs })));
+        }
+
+        // ── Deployments: remove an inactive deployment's directory ───────
+        RelayCommand::DeploymentRemoveDir(dir) => {
+            let result = context.deployment_dirs.remove(&dir.name).await;
+            send_response(&tx, &msg_id, result.map(|_| serde_json::json!({ "ok": true })));
+        }
+
+        // ── Workflows: deployability precheck ─────────────────────────────
+        RelayCommand::WorkflowCheck(workflow) => {
+            let report = context.workflow_checker.check(&workflow).await;
+            send_response(&tx, &msg_id, serde_json::to_value(report).map_err(AgentError::from));
+        }
+
         // ── Network scan ──────────────────────────────────────────────────
-        Some("scan_network") => {
-            let subnet = payload["subnet"].as_str().unwrap_or("192.168.1.0/24");
-            info!("Starting network scan on subnet: {}", subnet);
-            let devices = crate::scanner::scan_subnet(subnet).await;
-            send_response(
-                &tx,
-                &msg_id,
-                Ok(serde_json::json!({ "devices": devices })),
-            );
+        RelayCommand::ScanNetwork(scan) => {
+            let Some(work) = drain.begin_work() else {
+                send_draining(&tx, &msg_id);
+                return;
+            };
+            let mut options = context.scan.clone();
+            if let Some(ms) = scan.timeout_ms {
+                options.request_timeout(ms);
+            }
+            if let Some(mode) = scan.mode.as_deref().and_then(crate::scanner::DiscoveryMode::from_name) {
+                options.mode = mode;
+            }
+            let subnet = scan.subnet;
+
+            // A newer scan supersedes the one in flight
+            let cancel = scan_token.child_token();
+            if let Some(previous) = active_scan.lock().await.replace(cancel.clone()) {
+                info!("Cancelling previous network scan");
+                previous.cancel();
+            }
+
+            // Run the scan in the background so `scan_cancel` can be received
+            tokio::spawn(async move {
+                let _work = work;
+                info!("Starting network scan on subnet: {}", subnet);
+                let devices = crate::scanner::scan_subnet(&subnet, &options, &cancel).await;
+                let cancelled = {
+                    // Superseding scans cancel under this lock, so an
+                    // uncancelled token here is still the active one
+                    let mut active = active_scan.lock().await;
+                    let cancelled = cancel.is_cancelled();
+                    if !cancelled {
+                        *active = None;
+                    }
+                    cancelled
+                };
+                send_response(
+                    &tx,
+                    &msg_id,
+                    Ok(serde_json::json!({ "devices": devices, "cancelled": cancelled })),
+                );
+            });
+        }
+
+        // ── Network scan: cancel ──────────────────────────────────────────
+        RelayCommand::ScanCancel(_) => {
+            let cancelled = match active_scan.lock().await.take() {
+                Some(token) => {
+                    info!("Cancelling network scan");
+                    token.cancel();
+                    true
+                }
+                None => false,
+            };
+            send_response(&tx, &msg_id, Ok(serde_json::json!({ "cancelled": cancelled })));
         }
 
         // ── Docker: list locally pulled images ───────────────────────────────
-        Some("docker_images") => {
+        RelayCommand::DockerImages(_) => {
             let output = tokio::process::Command::new("docker")
                 .args(["images", "--format", "{{.Repository}}:{{.Tag}}"])
                 .output()
@@ -415,10 +1205,6 @@ async fn handle_message(text: &str, tx: WsTx, sessions: Sessions) {
                 });
             send_response(&tx, &msg_id, result);
         }
-
-        _ => {
-            warn!("Unknown relay message type: {:?}", msg_type);
-        }
     }
 }
 
@@ -448,3 +1234,509 @@ fn send_response(
     };
     let _ = tx.send(Message::Text(resp.to_string().into()));
 }
+
+/// Reject a message that does not parse as a known envelope.
+fn send_bad_request(tx: &WsTx, msg_id: &str, error: &str) {
+    let resp = serde_json::json!({
+        "type": "response",
+        "msg_id": msg_id,
+        "result": null,
+        "error": format!("Malformed relay message: {error}"),
+        "code": "bad_request"
+    });
+    let _ = tx.send(Message::Text(resp.to_string().into()));
+}
+
+/// Reject a command whose signature is missing or invalid.
+fn send_forbidden(tx: &WsTx, msg_id: &str, error: &str) {
+    let resp = serde_json::json!({
+        "type": "response",
+        "msg_id": msg_id,
+        "result": null,
+        "error": error,
+        "code": "forbidden"
+    });
+    let _ = tx.send(Message::Text(resp.to_string().into()));
+}
+
+/// Reject a command because the agent is draining.
+fn send_draining(tx: &WsTx, msg_id: &str) {
+    let resp = serde_json::json!({
+        "type": "response",
+        "msg_id": msg_id,
+        "result": null,
+        "error": "Agent is draining and not accepting new work",
+        "code": "draining"
+    });
+    let _ = tx.send(Message::Text(resp.to_string().into()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::Mutex as StdMutex;
+
+    use crate::cache::limits::WorkflowLimits;
+    use crate::cache::store::WorkflowStore;
+    use crate::cache::workflow::WorkflowCache;
+    use crate::deploy::fsm::FsmSettings;
+    use crate::deploy::ledger::DeploymentLedger;
+    use crate::deploy::node_runner::NodeContext;
+    use crate::deploy::registry::ExecutorRegistry;
+    use crate::authn::test_utils::token_manager;
+    use crate::filesys::test_utils::TempFs;
+    use crate::http::client::{HttpClient, HttpClientOptions};
+    use crate::storage::layout::StorageLayout;
+    use crate::sync::syncer::SyncStorage;
+    use crate::utils::CooldownOptions;
+
+    /// A backend nothing listens on
+    const UNREACHABLE: &str = "http://127.0.0.1:1";
+
+    /// Upper bound of the backoff delay for `attempt` with the relay's base/cap.
+    fn ceiling(base: Duration, attempt: u32) -> Duration {
+        (base * 2u32.pow(attempt.min(10))).min(MAX_RECONNECT_DELAY)
+    }
+
+    async fn context(fs: &TempFs, token_mngr: Arc<TokenManager>) -> RelayContext {
+        let layout = StorageLayout::new(fs.path(""));
+        let executors = Arc::new(ExecutorRegistry::new());
+        let syncer = Syncer::new(
+            Arc::new(
+                HttpClient::new(UNREACHABLE, HttpClientOptions::without_retries())
+                    .await
+                    .unwrap(),
+            ),
+            token_mngr,
+            SyncStorage {
+                workflow_cache: Arc::new(WorkflowCache::new(0)),
+                workflow_store: WorkflowStore::new(
+                    layout.workflows_cache_dir(),
+                    true,
+                    WorkflowLimits::default(),
+                ),
+                workflow_limits: WorkflowLimits::default(),
+                deployment_dir: layout.deployment_dir(),
+            },
+            executors.clone(),
+            FsmSettings::default(),
+            CooldownOptions::default(),
+            "test".to_string(),
+        );
+        RelayContext {
+            drain: Arc::new(DrainState::new()),
+            deploy_trigger: Arc::new(DeployTrigger::new()),
+            capabilities: Arc::new(CapabilityManifest::default()),
+            terminal: TerminalOptions::default(),
+            terminal_sessions: Arc::new(SessionRegistry::new()),
+            exec: ExecOptions::default(),
+            scan: ScanOptions::default(),
+            files: FileOptions {
+                root: layout.files_dir().path().to_path_buf(),
+                ..Default::default()
+            },
+            device_label: Arc::new(DeviceLabel::new(layout.settings_file(), None)),
+            syncer: Arc::new(syncer),
+            deployment_dirs: Arc::new(DeploymentDirs::new(
+                layout.deployment_dir(),
+                executors,
+                Arc::new(DeploymentLedger::load(layout.deployment_ledger_file()).await),
+            )),
+            workflow_checker: Arc::new(WorkflowChecker::new(
+                Arc::new(CapabilityManifest::default()),
+                NodeContext::default(),
+            )),
+            command_verifier: Arc::new(CommandVerifier::disabled()),
+        }
+    }
+
+    /// A backend URL nothing listens on.
+    async fn unreachable_backend() -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+        format!("http://127.0.0.1:{}/api/v1", port)
+    }
+
+    #[test]
+    fn test_backoff_delay_stays_within_ceiling() {
+        for attempt in 0..12 {
+            for _ in 0..20 {
+                let base = Duration::from_secs(2);
+                assert!(backoff_delay(attempt, base, MAX_RECONNECT_DELAY) < ceiling(base, attempt));
+            }
+        }
+        assert_eq!(backoff_delay(3, Duration::ZERO, MAX_RECONNECT_DELAY), Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_backoff_schedule_and_shutdown_while_waiting() {
+        let fs = TempFs::new();
+        let token_mngr = token_manager(&fs, UNREACHABLE, "secret").await;
+
+        // The first few waits elapse immediately; the last one hangs until
+        // shutdown, which must interrupt it.
+        const IMMEDIATE: usize = 4;
+        let delays = Arc::new(StdMutex::new(Vec::new()));
+        let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+        let shutdown_tx = StdMutex::new(Some(shutdown_tx));
+        let sleep_fn = {
+            let delays = delays.clone();
+            move |delay: Duration| {
+                let hang = {
+                    let mut delays = delays.lock().unwrap();
+                    delays.push(delay);
+                    delays.len() > IMMEDIATE
+                };
+                if hang {
+                    if let Some(tx) = shutdown_tx.lock().unwrap().take() {
+                        let _ = tx.send(());
+                    }
+                }
+                async move {
+                    if hang {
+                        std::future::pending::<()>().await;
+                    }
+                }
+            }
+        };
+
+        // Back off from a configured delay far below the default
+        let options = Options {
+            transport: RelayTransport::WebSocket,
+            reconnect_delay: Duration::from_millis(3),
+            ..Default::default()
+        };
+        let health = HealthRegistry::new();
+        tokio::time::timeout(
+            Duration::from_secs(10),
+            run(
+                &options,
+                token_mngr.clone(),
+                unreachable_backend().await,
+                &health,
+                context(&fs, token_mngr).await,
+                sleep_fn,
+                Box::pin(async move {
+                    let _ = shutdown_rx.await;
+                }),
+            ),
+        )
+        .await
+        .expect("relay worker should stop while waiting to reconnect");
+
+        let delays = delays.lock().unwrap().clone();
+        assert_eq!(delays.len(), IMMEDIATE + 1);
+        for (attempt, delay) in delays.iter().enumerate() {
+            let ceiling = ceiling(options.reconnect_delay, attempt as u32);
+            assert!(*delay < ceiling, "attempt {} waited {:?}", attempt, delay);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_malformed_message_gets_bad_request() {
+        let fs = TempFs::new();
+        let context = context(&fs, token_manager(&fs, UNREACHABLE, "secret").await).await;
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let sessions: Sessions = Arc::new(Mutex::new(HashMap::new()));
+        let active_scan: ActiveScan = Arc::new(Mutex::new(None));
+        let messages = [
+            // Not a command, or nothing to correlate a rejection with
+            serde_json::json!({ "type": "config_changed", "msg_id": "m0" }),
+            serde_json::json!({ "type": "command", "command_type": "file_raed" }),
+            serde_json::json!({
+                "type": "command",
+                "msg_id": "m1",
+                "command_type": "file_raed",
+                "payload": { "path": "/tmp" },
+            }),
+        ];
+
+        for msg in messages {
+            handle_message(
+                &msg.to_string(),
+                tx.clone(),
+                sessions.clone(),
+                active_scan.clone(),
+                &CancellationToken::new(),
+                &context,
+            )
+            .await;
+        }
+
+        let Some(Message::Text(resp)) = rx.recv().await else {
+            panic!("expected a text response");
+        };
+        let resp: serde_json::Value = serde_json::from_str(&resp).unwrap();
+        assert_eq!(resp["msg_id"], "m1");
+        assert_eq!(resp["code"], "bad_request");
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_terminal_sessions_are_capped_and_reaped() {
+        let fs = TempFs::new();
+        let mut context = context(&fs, token_manager(&fs, UNREACHABLE, "secret").await).await;
+        context.terminal = TerminalOptions {
+            working_dir: fs.path("sandbox"),
+            close_grace: Duration::from_millis(100),
+            max_sessions: 1,
+            idle_timeout: Duration::from_millis(300),
+            ..Default::default()
+        };
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let sessions: Sessions = Arc::new(Mutex::new(HashMap::new()));
+        let active_scan: ActiveScan = Arc::new(Mutex::new(None));
+        let _guard = supervise_sessions(&sessions, &context.terminal);
+
+        for (msg_id, command_type) in
+            [("m1", "terminal_create"), ("m2", "terminal_create"), ("m3", "terminal_list")]
+        {
+            let msg = serde_json::json!({
+                "type": "command", "msg_id": msg_id, "command_type": command_type,
+            });
+            handle_message(
+                &msg.to_string(),
+                tx.clone(),
+                Arc::clone(&sessions),
+                Arc::clone(&active_scan),
+                &CancellationToken::new(),
+                &context,
+            )
+            .await;
+        }
+
+        // The second session is refused, and the idle first one is listed and
+        // then closed
+        let mut responses = HashMap::new();
+        let mut closed = None;
+        while closed.is_none() {
+            let msg = tokio::time::timeout(Duration::from_secs(5), rx.recv()).await.unwrap();
+            let msg = text_message(msg.unwrap()).unwrap();
+            let msg: serde_json::Value = serde_json::from_str(msg.to_text().unwrap()).unwrap();
+            match msg["type"].as_str() {
+                Some("response") => {
+                    let msg_id = msg["msg_id"].as_str().unwrap().to_string();
+                    responses.insert(msg_id, msg);
+                }
+                Some("terminal_closed") => closed = Some(msg["session_id"].clone()),
+                _ => {}
+            }
+        }
+        assert_eq!(responses["m1"]["error"], serde_json::Value::Null);
+        let refused = responses["m2"]["error"].as_str().unwrap();
+        assert!(refused.contains("Too many terminal sessions"));
+        let listed = &responses["m3"]["result"]["sessions"];
+        assert_eq!(listed.as_array().unwrap().len(), 1);
+        assert_eq!(listed[0]["session_id"], "m1");
+        assert_eq!(listed[0]["cols"], 80);
+        assert_eq!(closed.unwrap(), "m1");
+        assert!(sessions.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_sync_reset_clears_cooldown() {
+        let fs = TempFs::new();
+        let context = context(&fs, token_manager(&fs, UNREACHABLE, "secret").await).await;
+        assert!(context.syncer.trigger_sync().await.is_err());
+        assert!(context.syncer.get_state().await.is_in_cooldown());
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let msg = serde_json::json!({ "type": "command", "msg_id": "m1", "command_type": "sync_reset" });
+        handle_message(
+            &msg.to_string(),
+            tx,
+            Arc::new(Mutex::new(HashMap::new())),
+            Arc::new(Mutex::new(None)),
+            &CancellationToken::new(),
+            &context,
+        )
+        .await;
+
+        let Some(Message::Text(resp)) = rx.recv().await else {
+            panic!("expected a text response");
+        };
+        let resp: serde_json::Value = serde_json::from_str(&resp).unwrap();
+        assert_eq!(resp["result"]["reset"], true);
+        let state = context.syncer.get_state().await;
+        assert_eq!(state.err_streak, 0);
+        assert!(!state.is_in_cooldown());
+    }
+
+    #[tokio::test]
+    async fn test_unsigned_high_privilege_command_is_forbidden() {
+        let fs = TempFs::new();
+        fs.write("deployments/wf-old/data.bin", "data");
+        let mut context = context(&fs, token_manager(&fs, UNREACHABLE, "secret").await).await;
+        let signed = vec!["deployment_remove_dir".to_string()];
+        let keys = crate::authn::command_signing::tests::signing_keys();
+        let verifier = CommandVerifier::new(keys, &signed, "device-1", DEFAULT_SIGNATURE_MAX_AGE);
+        context.command_verifier = Arc::new(verifier.unwrap());
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let msg = serde_json::json!({
+            "type": "command",
+            "msg_id": "m1",
+            "command_type": "deployment_remove_dir",
+            "payload": { "name": "wf-old" },
+        });
+        handle_message(
+            &msg.to_string(),
+            tx,
+            Arc::new(Mutex::new(HashMap::new())),
+            Arc::new(Mutex::new(None)),
+            &CancellationToken::new(),
+            &context,
+        )
+        .await;
+
+        let Some(Message::Text(resp)) = rx.recv().await else {
+            panic!("expected a text response");
+        };
+        let resp: serde_json::Value = serde_json::from_str(&resp).unwrap();
+        assert_eq!(resp["msg_id"], "m1");
+        assert_eq!(resp["code"], "forbidden");
+        assert!(fs.path("deployments/wf-old").exists());
+    }
+
+    #[tokio::test]
+    async fn test_sink_task_ends_with_the_connection() {
+        let metrics = tokio::runtime::Handle::current().metrics();
+        let tasks_before = metrics.num_alive_tasks();
+        let mut session_txs = Vec::new();
+        for _ in 0..100 {
+            let (tx, rx) = mpsc::unbounded_channel();
+            let (sink, mut sent) = futures::channel::mpsc::unbounded();
+            let guard = spawn_sink(rx, sink, false);
+            tx.send(Message::Text("hello".into())).unwrap();
+            assert!(sent.next().await.is_some());
+
+            // A sender kept by a session neither keeps the task running nor
+            // queues messages nobody will send
+            let session_tx = tx.clone();
+            drop(guard);
+            assert!(sent.next().await.is_none());
+            assert!(session_tx.send(Message::Text("late".into())).is_err());
+            session_txs.push(session_tx);
+        }
+        assert_eq!(metrics.num_alive_tasks(), tasks_before);
+    }
+
+    #[tokio::test]
+    async fn test_auto_transport_retries_websocket_after_falling_back() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let fs = TempFs::new();
+        let token_mngr = token_manager(&fs, UNREACHABLE, "secret").await;
+        let server = MockServer::start().await;
+        Mock::given(path("/api/v1/agent-relay/ws"))
+            .respond_with(ResponseTemplate::new(403))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/agent-relay/poll"))
+            .respond_with(ResponseTemplate::new(204))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/agent-relay/poll"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+
+        let options = Options {
+            reconnect_delay: Duration::from_millis(1),
+            poll_fallback_after: 2,
+            ws_retry_interval: Duration::ZERO,
+            ..Default::default()
+        };
+        let health = HealthRegistry::new();
+        let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+        let worker = run(
+            &options,
+            token_mngr.clone(),
+            format!("{}/api/v1", server.uri()),
+            &health,
+            context(&fs, token_mngr).await,
+            tokio::time::sleep,
+            Box::pin(async move {
+                let _ = shutdown_rx.await;
+            }),
+        );
+        let upgrades_after_polling = async {
+            loop {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                let requests = server.received_requests().await.unwrap();
+                let gets: Vec<&str> = requests
+                    .iter()
+                    .filter(|r| r.method == wiremock::http::Method::GET)
+                    .map(|r| r.url.path())
+                    .collect();
+                // Upgrades before the first poll, once another one followed it
+                if let Some(polled) = gets.iter().position(|p| p.ends_with("/poll")) {
+                    if gets[polled..].iter().any(|p| p.ends_with("/ws")) {
+                        return polled;
+                    }
+                }
+            }
+        };
+        let upgrades = tokio::time::timeout(Duration::from_secs(10), async {
+            tokio::select! {
+                _ = worker => panic!("relay worker stopped on its own"),
+                upgrades = upgrades_after_polling => upgrades,
+            }
+        })
+        .await
+        .expect("relay worker should try WebSocket again after falling back");
+        let _ = shutdown_tx.send(());
+
+        assert_eq!(upgrades, options.poll_fallback_after as usize);
+    }
+
+    #[tokio::test]
+    async fn test_poll_fallback_wait_honors_shutdown() {
+        let fs = TempFs::new();
+        let token_mngr = token_manager(&fs, UNREACHABLE, "secret").await;
+
+        // Polling an unreachable backend fails and waits; shutdown during that
+        // wait must stop the worker.
+        let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+        let shutdown_tx = StdMutex::new(Some(shutdown_tx));
+        let sleep_fn = move |_: Duration| {
+            if let Some(tx) = shutdown_tx.lock().unwrap().take() {
+                let _ = tx.send(());
+            }
+            std::future::pending::<()>()
+        };
+
+        let options = Options {
+            transport: RelayTransport::Poll,
+            ..Default::default()
+        };
+        let health = HealthRegistry::new();
+        tokio::time::timeout(
+            Duration::from_secs(10),
+            run(
+                &options,
+                token_mngr.clone(),
+                unreachable_backend().await,
+                &health,
+                context(&fs, token_mngr).await,
+                sleep_fn,
+                Box::pin(async move {
+                    let _ = shutdown_rx.await;
+                }),
+            ),
+        )
+        .await
+        .expect("relay worker should stop while waiting to re-poll");
+
+        assert_eq!(
+            health.get(HEALTH_COMPONENT).and_then(|c| c.message).as_deref(),
+            Some("transport: poll")
+        );
+    }
+}
@@ -1,12 +1,30 @@
 //! Token refresh worker
+//!
+//! A refresh the backend rejects (401/403) means the token was revoked, and
+//! retrying with it only hammers the backend. After enough rejections in a
+//! row the worker re-activates the device with a kept activation token, or
+//! failing that deactivates it: it stops, and the reason is sent on the
+//! deactivation channel so the agent stops its other backend workers too.
 
 use std::future::Future;
 use std::pin::Pin;
+use std::sync::{RwLock, RwLockWriteGuard};
 use std::time::Duration;
 
-use tracing::{debug, error, info};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::sync::watch;
+use tracing::{debug, error, info, warn};
 
+use crate::authn::device_token::DeviceToken;
 use crate::authn::token_mngr::TokenManagerExt;
+use crate::errors::AgentError;
+use crate::filesys::file::File;
+use crate::health::HealthRegistry;
+use crate::storage::layout::StorageLayout;
+
+/// Health registry component name
+const HEALTH_COMPONENT: &str = "token_refresh";
 
 /// Token refresh worker options
 #[derive(Debug, Clone)]
@@ -16,6 +34,13 @@ pub struct Options {
 
     /// Refresh when token expires within this duration
     pub refresh_threshold: Duration,
+
+    /// Consecutive rejected refreshes before the device re-activates, or
+    /// deactivates without an activation token (0 = keep retrying)
+    pub reactivate_after_failures: u32,
+
+    /// Activation token kept by the installer for re-activation
+    pub activation_token_file: File,
 }
 
 impl Default for Options {
@@ -23,14 +48,73 @@ impl Default for Options {
         Self {
             check_interval: Duration::from_secs(3600), // 1 hour
             refresh_threshold: Duration::from_secs(86400), // 24 hours
+            reactivate_after_failures: 3,
+            activation_token_file: StorageLayout::default().activation_token_file(),
         }
     }
 }
 
-/// Run the token refresh worker
+/// What the token refresh worker last did
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TokenRefreshState {
+    /// Last successful refresh
+    pub last_refreshed_at: Option<DateTime<Utc>>,
+
+    /// When the worker checks the token next
+    pub next_check_at: Option<DateTime<Utc>>,
+
+    /// Error of the last failed check or refresh
+    pub last_error: Option<String>,
+
+    /// Checks or refreshes failed in a row
+    pub consecutive_failures: u32,
+
+    /// Expiry of the current token
+    pub token_expires_at: Option<DateTime<Utc>>,
+
+    /// Last successful re-activation
+    pub reactivated_at: Option<DateTime<Utc>>,
+
+    /// Refresh was rejected and re-activation is impossible; the device has
+    /// to be re-provisioned by hand
+    pub needs_reprovisioning: bool,
+}
+
+impl TokenRefreshState {
+    /// Seconds until the current token expires (negative once expired)
+    pub fn token_expires_in_secs(&self) -> Option<i64> {
+        self.token_expires_at
+            .map(|expires_at| (expires_at - Utc::now()).num_seconds())
+    }
+
+    fn record_success(&mut self, expires_at: DateTime<Utc>) {
+        self.token_expires_at = Some(expires_at);
+        self.last_error = None;
+        self.consecutive_failures = 0;
+        self.needs_reprovisioning = false;
+    }
+
+    /// Record a successful refresh of the token to one expiring at
+    /// `expires_at`
+    pub(crate) fn record_refresh(&mut self, expires_at: DateTime<Utc>) {
+        self.record_success(expires_at);
+        self.last_refreshed_at = Some(Utc::now());
+    }
+
+    pub(crate) fn record_failure(&mut self, error: String) {
+        self.last_error = Some(error);
+        self.consecutive_failures += 1;
+    }
+}
+
+/// Run the token refresh worker until shutdown, or until the device is
+/// deactivated, in which case the reason is sent on `deactivated`
 pub async fn run<T, S, F>(
     options: &Options,
     token_mngr: &T,
+    health: &HealthRegistry,
+    state: &RwLock<TokenRefreshState>,
+    deactivated: &watch::Sender<Option<String>>,
     sleep_fn: S,
     mut shutdown_signal: Pin<Box<dyn Future<Output = ()> + Send>>,
 ) where
@@ -39,8 +123,17 @@ pub async fn run<T, S, F>(
     F: Future<Output = ()>,
 {
     info!("Token refresh worker starting...");
+    if let Ok(token) = token_mngr.get_token().await {
+        write(state).token_expires_at = Some(token.expires_at());
+    }
+    let mut rejected_refreshes = 0u32;
 
     loop {
+        let next_check_at = chrono::Duration::from_std(options.check_interval)
+            .ok()
+            .map(|interval| Utc::now() + interval);
+        write(state).next_check_at = next_check_at;
+
         // Check for shutdown
         tokio::select! {
             _ = &mut shutdown_signal => {
@@ -59,6 +152,9 @@ pub async fn run<T, S, F>(
             Ok(t) => t,
             Err(e) => {
                 error!("Failed to get token: {}", e);
+                let message = format!("Failed to get token: {}", e);
+                write(state).record_failure(message.clone());
+                health.set_degraded(HEALTH_COMPONENT, message);
                 continue;
             }
         };
@@ -77,10 +173,72 @@ pub async fn run<T, S, F>(
                         "Token refreshed successfully, new expiration: {}",
                         new_token.expires_at()
                     );
+                    rejected_refreshes = 0;
+                    write(state).record_refresh(new_token.expires_at());
+                    health.set_healthy(HEALTH_COMPONENT);
                 }
                 Err(e) => {
                     error!("Failed to refresh token: {}", e);
                     // Will retry on next interval
+                    {
+                        let mut state = write(state);
+                        state.token_expires_at = Some(token.expires_at());
+                        state.record_failure(format!("Failed to refresh token: {}", e));
+                    }
+
+                    // Only a rejected token calls for re-activation; network
+                    // and server errors are retried as usual
+                    if matches!(e, AgentError::AuthError(_)) {
+                        rejected_refreshes += 1;
+                    } else {
+                        rejected_refreshes = 0;
+                    }
+                    if options.reactivate_after_failures > 0
+                        && rejected_refreshes >= options.reactivate_after_failures
+                    {
+                        match reactivate(options, token_mngr).await {
+                            Ok(new_token) => {
+                                rejected_refreshes = 0;
+                                {
+                                    let mut state = write(state);
+                                    state.record_success(new_token.expires_at());
+                                    state.reactivated_at = Some(Utc::now());
+                                }
+                                health.set_healthy(HEALTH_COMPONENT);
+                            }
+                            Err(e) => {
+                                let reason = format!(
+                                    "token refresh was rejected {} times and re-activation \
+                                     failed: {}",
+                                    rejected_refreshes, e
+                                );
+                                error!(
+                                    "DEVICE DEACTIVATED: {}. Re-install with \
+                                     `ajigent --install --token=<activation_token>`",
+                                    reason
+                                );
+                                write(state).needs_reprovisioning = true;
+                                health.set_unhealthy(
+                                    HEALTH_COMPONENT,
+                                    format!("Device needs re-provisioning: {}", e),
+                                );
+                                deactivated.send_replace(Some(reason));
+                                return;
+                            }
+                        }
+                        continue;
+                    }
+
+                    let message = format!(
+                        "Token refresh failed, token expires at {}: {}",
+                        token.expires_at(),
+                        e
+                    );
+                    if token.is_expired() {
+                        health.set_unhealthy(HEALTH_COMPONENT, message);
+                    } else {
+                        health.set_degraded(HEALTH_COMPONENT, message);
+                    }
                 }
             }
         } else {
@@ -88,6 +246,197 @@ pub async fn run<T, S, F>(
                 "Token still valid, expires in {} hours",
                 token.time_until_expiry() / 3600
             );
+            write(state).record_success(token.expires_at());
+            health.set_healthy(HEALTH_COMPONENT);
         }
     }
 }
+
+/// Re-activate with the stored activation token
+async fn reactivate<T: TokenManagerExt>(
+    options: &Options,
+    token_mngr: &T,
+) -> Result<DeviceToken, AgentError> {
+    let file = &options.activation_token_file;
+    if !file.exists().await {
+        return Err(AgentError::AuthError(format!(
+            "no activation token stored at {:?}",
+            file.path()
+        )));
+    }
+    let activation_token = file.read_string().await?;
+    let activation_token = activation_token.trim();
+    if activation_token.is_empty() {
+        return Err(AgentError::AuthError(format!(
+            "activation token file {:?} is empty",
+            file.path()
+        )));
+    }
+
+    warn!("Token refresh keeps being rejected, re-activating the device...");
+    token_mngr.reactivate(activation_token).await
+}
+
+fn write(state: &RwLock<TokenRefreshState>) -> RwLockWriteGuard<'_, TokenRefreshState> {
+    state.write().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    use async_trait::async_trait;
+
+    use crate::filesys::test_utils::TempFs;
+    use crate::health::HealthStatus;
+
+    /// A token expiring in an hour whose refresh always fails
+    struct ExpiringToken {
+        refresh_error: fn() -> AgentError,
+        refreshes: AtomicUsize,
+        reactivated_with: Mutex<Option<String>>,
+    }
+
+    impl ExpiringToken {
+        fn new(refresh_error: fn() -> AgentError) -> Self {
+            Self {
+                refresh_error,
+                refreshes: AtomicUsize::new(0),
+                reactivated_with: Mutex::new(None),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl TokenManagerExt for ExpiringToken {
+        async fn get_token(&self) -> Result<DeviceToken, AgentError> {
+            let mut token = DeviceToken::from_secret("device-1".to_string(), "secret".to_string());
+            token.claims.exp = Utc::now().timestamp() + 3600;
+            Ok(token)
+        }
+
+        async fn refresh_token(&self) -> Result<DeviceToken, AgentError> {
+            self.refreshes.fetch_add(1, Ordering::SeqCst);
+            Err((self.refresh_error)())
+        }
+
+        async fn get_device_id(&self) -> Result<String, AgentError> {
+            Ok("device-1".to_string())
+        }
+
+        async fn reactivate(&self, activation_token: &str) -> Result<DeviceToken, AgentError> {
+            *self.reactivated_with.lock().unwrap() = Some(activation_token.to_string());
+            Ok(DeviceToken::from_secret("device-1".to_string(), "new".to_string()))
+        }
+    }
+
+    fn revoked() -> AgentError {
+        AgentError::AuthError("Token refresh failed: 401 Unauthorized - revoked".to_string())
+    }
+
+    /// Run the worker for `checks` checks, then shut it down. Returns the
+    /// deactivation reason too, if any.
+    async fn run_checks(
+        options: &Options,
+        token_mngr: &ExpiringToken,
+        checks: usize,
+    ) -> (TokenRefreshState, HealthRegistry, Option<String>) {
+        let health = HealthRegistry::new();
+        let state = RwLock::new(TokenRefreshState::default());
+        let (deactivated, _) = watch::channel(None);
+
+        let sleeps = AtomicUsize::new(0);
+        let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+        let shutdown_tx = Mutex::new(Some(shutdown_tx));
+        let sleep_fn = |_: Duration| {
+            let last = sleeps.fetch_add(1, Ordering::SeqCst) >= checks;
+            if last {
+                if let Some(tx) = shutdown_tx.lock().unwrap().take() {
+                    let _ = tx.send(());
+                }
+            }
+            async move {
+                if last {
+                    std::future::pending::<()>().await;
+                }
+            }
+        };
+
+        run(
+            options,
+            token_mngr,
+            &health,
+            &state,
+            &deactivated,
+            sleep_fn,
+            Box::pin(async move {
+                let _ = shutdown_rx.await;
+            }),
+        )
+        .await;
+
+        let state = state.read().unwrap().clone();
+        let reason = deactivated.borrow().clone();
+        (state, health, reason)
+    }
+
+    #[tokio::test]
+    async fn test_failed_refresh_is_observable() {
+        let token_mngr =
+            ExpiringToken::new(|| AgentError::TokenError("Token refresh failed: 503".to_string()));
+        let (state, health, reason) = run_checks(&Options::default(), &token_mngr, 4).await;
+
+        assert_eq!(state.consecutive_failures, 4);
+        assert!(state.last_error.as_deref().unwrap().contains("503"));
+        assert!(state.last_refreshed_at.is_none());
+        assert!(state.next_check_at.is_some());
+        assert!((3500..=3600).contains(&state.token_expires_in_secs().unwrap()));
+
+        // Server errors never trigger re-activation
+        assert!(!state.needs_reprovisioning);
+        assert!(reason.is_none());
+        assert!(token_mngr.reactivated_with.lock().unwrap().is_none());
+        let component = health.get(HEALTH_COMPONENT).unwrap();
+        assert_eq!(component.status, HealthStatus::Degraded);
+    }
+
+    #[tokio::test]
+    async fn test_rejected_refresh_falls_back_to_reactivation() {
+        let fs = TempFs::new();
+        let options = Options {
+            reactivate_after_failures: 2,
+            activation_token_file: File::new(fs.path("activation_token")),
+            ..Default::default()
+        };
+
+        // Without a stored activation token the device is deactivated and
+        // the worker stops refreshing
+        let token_mngr = ExpiringToken::new(revoked);
+        let (state, health, reason) = run_checks(&options, &token_mngr, 5).await;
+        assert_eq!(token_mngr.refreshes.load(Ordering::SeqCst), 2);
+        assert!(reason.unwrap().contains("rejected 2 times"));
+        assert!(state.needs_reprovisioning);
+        assert!(state.reactivated_at.is_none());
+        let component = health.get(HEALTH_COMPONENT).unwrap();
+        assert_eq!(component.status, HealthStatus::Unhealthy);
+        assert!(component.message.unwrap().contains("re-provisioning"));
+
+        // With one it re-activates after the configured number of rejections
+        fs.write("activation_token", "act-123\n");
+        let token_mngr = ExpiringToken::new(revoked);
+        let (state, health, _) = run_checks(&options, &token_mngr, 1).await;
+        assert!(token_mngr.reactivated_with.lock().unwrap().is_none());
+        assert_eq!(health.get(HEALTH_COMPONENT).unwrap().status, HealthStatus::Degraded);
+        assert_eq!(state.consecutive_failures, 1);
+
+        let (state, health, reason) = run_checks(&options, &token_mngr, 2).await;
+        assert!(reason.is_none());
+        assert_eq!(token_mngr.reactivated_with.lock().unwrap().as_deref(), Some("act-123"));
+        assert!(state.reactivated_at.is_some());
+        assert!(!state.needs_reprovisioning);
+        assert_eq!(state.consecutive_failures, 0);
+        assert_eq!(health.get(HEALTH_COMPONENT).unwrap().status, HealthStatus::Healthy);
+    }
+}
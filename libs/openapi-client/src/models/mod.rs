@@ -8,6 +8,10 @@ pub struct ActivateDeviceRequest {
     pub activation_token: String,
     pub device_name: String,
     pub device_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub capabilities: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<serde_json::Value>,
 }
 
 /// Device activation response
@@ -38,6 +38,8 @@ log_error() {
 ACTIVATION_TOKEN=""
 DEVICE_NAME=""
 BACKEND_URL=""
+KEEP_ACTIVATION_TOKEN=""
+DEVICE_LOCATION=""
 
 while [[ $# -gt 0 ]]; do
     case $1 in
@@ -49,6 +51,10 @@ while [[ $# -gt 0 ]]; do
             DEVICE_NAME="${1#*=}"
             shift
             ;;
+        --location=*)
+            DEVICE_LOCATION="${1#*=}"
+            shift
+            ;;
         --backend=*)
             BACKEND_URL="${1#*=}"
             shift
@@ -57,6 +63,10 @@ while [[ $# -gt 0 ]]; do
             AJIME_VERSION="${1#*=}"
             shift
             ;;
+        --keep-activation-token)
+            KEEP_ACTIVATION_TOKEN="1"
+            shift
+            ;;
         --help|-h)
             echo "Ajime Agent Installer"
             echo ""
@@ -65,8 +75,11 @@ while [[ $# -gt 0 ]]; do
             echo "Options:"
             echo "  --token=TOKEN     Activation token (required)"
             echo "  --name=NAME       Device name (optional, defaults to hostname)"
+            echo "  --location=LOC    Device location registered at activation (optional)"
             echo "  --backend=URL     Backend URL (optional)"
             echo "  --version=VER     Agent version (optional, defaults to latest)"
+            echo "  --keep-activation-token"
+            echo "                    Keep the token so the agent can re-activate itself"
             echo "  --help            Show this help message"
             exit 0
             ;;
@@ -267,20 +280,29 @@ EOF
 activate_agent() {
     log_info "Activating agent..."
     
-    local args="--install --token=$ACTIVATION_TOKEN"
+    # One array element per argument, so values with spaces stay whole
+    local args=(--install "--token=$ACTIVATION_TOKEN")
     
     if [ -n "$DEVICE_NAME" ]; then
-        args="$args --name=$DEVICE_NAME"
+        args+=("--name=$DEVICE_NAME")
     fi
     
+    if [ -n "$DEVICE_LOCATION" ]; then
+        args+=("--location=$DEVICE_LOCATION")
+    fi
+
     if [ -n "$BACKEND_URL" ]; then
-        args="$args --backend=$BACKEND_URL"
+        args+=("--backend=$BACKEND_URL")
     fi
     
-    args="$args --type=$DEVICE_TYPE"
+    if [ -n "$KEEP_ACTIVATION_TOKEN" ]; then
+        args+=(--keep-activation-token)
+    fi
+
+    args+=("--type=$DEVICE_TYPE")
     
     # Run activation
-    ${AJIME_INSTALL_DIR}/ajigent $args
+    "${AJIME_INSTALL_DIR}/ajigent" "${args[@]}"
     
     if [ $? -eq 0 ]; then
         log_success "Agent activated successfully"